@@ -0,0 +1,30 @@
+use std::process::Command;
+
+// Exposes the commit and compiler the binary was built from to the
+// `cache_build_info` metric. `MILENA_GIT_SHA` overrides the commit for builds
+// made outside a git checkout, such as in a container from a source tarball.
+fn main() {
+    println!("cargo:rerun-if-env-changed=MILENA_GIT_SHA");
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs");
+
+    let git_sha = std::env::var("MILENA_GIT_SHA")
+        .ok()
+        .or_else(|| command_output("git", &["rev-parse", "--short=12", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = command_output(&rustc, &["--version"])
+        .map(|version| version.trim_start_matches("rustc ").to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=MILENA_GIT_SHA={git_sha}");
+    println!("cargo:rustc-env=MILENA_RUSTC_VERSION={rustc_version}");
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
@@ -0,0 +1,101 @@
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+use lru::LruCache;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::metrics::Metrics;
+
+/// Caps the requests for each bucket that run at once, so a spike in one
+/// bucket is turned away at its own limit instead of taking the key locks and
+/// store calls the other buckets are waiting on. Unlike the router's rate
+/// limit, which bounds the node's total traffic, this only shares it out.
+pub struct BucketLimits {
+    per_bucket: usize,
+    // Least recently used buckets are forgotten first. A forgotten bucket's
+    // requests still in flight keep their permits, so until they finish it can
+    // briefly run more than `per_bucket`.
+    buckets: Mutex<LruCache<String, Arc<Semaphore>>>,
+    metrics: Arc<Metrics>,
+}
+
+/// A bucket's slot for one request, given back when dropped.
+pub struct BucketPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    semaphore: Arc<Semaphore>,
+    bucket: String,
+    per_bucket: usize,
+    metrics: Arc<Metrics>,
+}
+
+impl BucketLimits {
+    pub fn new(per_bucket: usize, max_buckets: NonZeroUsize, metrics: Arc<Metrics>) -> Self {
+        Self {
+            per_bucket,
+            buckets: Mutex::new(LruCache::new(max_buckets)),
+            metrics,
+        }
+    }
+
+    /// A slot for a request to `bucket`, or `None` when `per_bucket` of its
+    /// requests are already running.
+    pub fn try_acquire(&self, bucket: &str) -> Option<BucketPermit> {
+        let semaphore = {
+            let mut buckets = self.buckets.lock().unwrap();
+            match buckets.get(bucket) {
+                Some(semaphore) => semaphore.clone(),
+                None => {
+                    let semaphore = Arc::new(Semaphore::new(self.per_bucket));
+                    buckets.put(bucket.to_string(), semaphore.clone());
+                    semaphore
+                }
+            }
+        };
+        let permit = semaphore.clone().try_acquire_owned().ok()?;
+        let permit = BucketPermit {
+            permit: Some(permit),
+            semaphore,
+            bucket: bucket.to_string(),
+            per_bucket: self.per_bucket,
+            metrics: self.metrics.clone(),
+        };
+        permit.record_in_flight();
+        Some(permit)
+    }
+}
+
+impl BucketPermit {
+    fn record_in_flight(&self) {
+        let in_flight = self.per_bucket - self.semaphore.available_permits();
+        self.metrics
+            .record_bucket_in_flight(&self.bucket, in_flight as u64);
+    }
+}
+
+impl Drop for BucketPermit {
+    fn drop(&mut self) {
+        self.permit.take();
+        self.record_in_flight();
+    }
+}
+
+#[test]
+fn test_bucket_limits_isolate_buckets() {
+    let limits = BucketLimits::new(
+        2,
+        NonZeroUsize::new(2).unwrap(),
+        Arc::new(Metrics::new().unwrap()),
+    );
+
+    let first = limits.try_acquire("busy").unwrap();
+    let second = limits.try_acquire("busy").unwrap();
+    assert!(limits.try_acquire("busy").is_none());
+    // A full bucket does not hold up the others
+    assert!(limits.try_acquire("quiet").is_some());
+
+    drop(first);
+    let third = limits.try_acquire("busy").unwrap();
+    assert!(limits.try_acquire("busy").is_none());
+    drop((second, third));
+    assert!(limits.try_acquire("busy").is_some());
+}
@@ -0,0 +1,40 @@
+/// Share of the limit the disk tier must drop back under before the node
+/// accepts writes again, so it does not flap around the limit.
+const RESUME_RATIO: f64 = 0.9;
+
+/// Tracks whether the disk tier has room for more writes, with hysteresis
+/// between the point it fills up and the point it is writable again.
+pub struct WriteGate {
+    limit_bytes: u64,
+    writable: bool,
+}
+
+impl WriteGate {
+    pub fn new(limit_bytes: u64) -> Self {
+        Self {
+            limit_bytes,
+            writable: true,
+        }
+    }
+
+    /// Updates the state from the disk tier's current size and returns whether
+    /// the node is writable.
+    pub fn update(&mut self, used_bytes: u64) -> bool {
+        self.writable = if self.writable {
+            used_bytes < self.limit_bytes
+        } else {
+            (used_bytes as f64) < self.limit_bytes as f64 * RESUME_RATIO
+        };
+        self.writable
+    }
+}
+
+#[test]
+fn test_write_gate_hysteresis() {
+    let mut gate = WriteGate::new(1000);
+    assert!(gate.update(500));
+    assert!(!gate.update(1000));
+    assert!(!gate.update(950));
+    assert!(gate.update(899));
+    assert!(gate.update(999));
+}
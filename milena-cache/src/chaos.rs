@@ -0,0 +1,162 @@
+use anyhow::{anyhow, bail, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Environment variable that must be exactly `1` for any rule to take effect,
+/// so a stray `CHAOS_RULES` in a production environment does nothing.
+pub const CHAOS_ENV: &str = "MILENA_CHAOS";
+
+pub fn enabled() -> bool {
+    std::env::var(CHAOS_ENV).as_deref() == Ok("1")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperationKind {
+    Get,
+    Put,
+    Delete,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// Fail before touching any tier.
+    Error,
+    /// Sleep before touching any tier.
+    Latency(Duration),
+    /// Run the operation, then report failure as if the response were lost.
+    Drop,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rule {
+    pub operation: OperationKind,
+    pub fault: Fault,
+    pub probability: f64,
+}
+
+/// Parses comma-separated rules of the form `operation:fault:probability`,
+/// with a trailing `:millis` for latency, e.g. `get:error:0.1,put:latency:0.5:200`.
+pub fn parse_rules(spec: &str) -> Result<Vec<Rule>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(parse_rule)
+        .collect()
+}
+
+fn parse_rule(rule: &str) -> Result<Rule> {
+    let parts: Vec<&str> = rule.split(':').collect();
+    let (operation, fault, probability, millis) = match parts.as_slice() {
+        [operation, fault, probability] => (*operation, *fault, *probability, None),
+        [operation, fault, probability, millis] => {
+            (*operation, *fault, *probability, Some(*millis))
+        }
+        _ => bail!("Chaos rule {:?} must be operation:fault:probability", rule),
+    };
+
+    let operation = match operation {
+        "get" => OperationKind::Get,
+        "put" => OperationKind::Put,
+        "delete" => OperationKind::Delete,
+        other => bail!("Unknown chaos operation {:?}", other),
+    };
+    let fault = match (fault, millis) {
+        ("error", None) => Fault::Error,
+        ("drop", None) => Fault::Drop,
+        ("latency", Some(millis)) => Fault::Latency(Duration::from_millis(
+            millis
+                .parse()
+                .map_err(|_| anyhow!("Invalid chaos latency {:?}", millis))?,
+        )),
+        ("latency", None) => bail!("Chaos rule {:?} needs a latency in millis", rule),
+        (other, _) => bail!("Unknown chaos fault in {:?}: {:?}", rule, other),
+    };
+    let probability: f64 = probability
+        .parse()
+        .map_err(|_| anyhow!("Invalid chaos probability {:?}", probability))?;
+    if !(0.0..=1.0).contains(&probability) {
+        bail!("Chaos probability {} must be between 0 and 1", probability);
+    }
+
+    Ok(Rule {
+        operation,
+        fault,
+        probability,
+    })
+}
+
+/// Failure injection for staging. Each matching rule is rolled independently
+/// per call; a seed makes the sequence of injected faults reproducible.
+pub struct Chaos {
+    rules: Vec<Rule>,
+    rng: Mutex<StdRng>,
+}
+
+impl Chaos {
+    pub fn new(rules: Vec<Rule>, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            rules,
+            rng: Mutex::new(rng),
+        }
+    }
+
+    /// Applies error and latency faults for one call of `operation`, and
+    /// returns whether its response should be dropped.
+    pub async fn inject(&self, operation: OperationKind) -> Result<bool> {
+        let faults: Vec<Fault> = {
+            let mut rng = self.rng.lock().unwrap();
+            self.rules
+                .iter()
+                .filter(|rule| rule.operation == operation)
+                .filter(|rule| rng.gen_bool(rule.probability))
+                .map(|rule| rule.fault)
+                .collect()
+        };
+
+        let mut drop_response = false;
+        for fault in faults {
+            match fault {
+                Fault::Latency(delay) => tokio::time::sleep(delay).await,
+                Fault::Error => bail!("Injected {:?} failure", operation),
+                Fault::Drop => drop_response = true,
+            }
+        }
+        Ok(drop_response)
+    }
+}
+
+#[test]
+fn test_parse_rules() {
+    let rules = parse_rules("get:error:0.1, put:latency:0.5:200,delete:drop:1").unwrap();
+    assert_eq!(
+        rules,
+        vec![
+            Rule {
+                operation: OperationKind::Get,
+                fault: Fault::Error,
+                probability: 0.1,
+            },
+            Rule {
+                operation: OperationKind::Put,
+                fault: Fault::Latency(Duration::from_millis(200)),
+                probability: 0.5,
+            },
+            Rule {
+                operation: OperationKind::Delete,
+                fault: Fault::Drop,
+                probability: 1.0,
+            },
+        ]
+    );
+
+    assert!(parse_rules("get:error").is_err());
+    assert!(parse_rules("get:latency:0.5").is_err());
+    assert!(parse_rules("scan:error:0.5").is_err());
+    assert!(parse_rules("get:error:1.5").is_err());
+}
@@ -0,0 +1,84 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Where the stores and `Operation` read the current time, so tests can move
+/// it forward instead of sleeping through TTLs and windows.
+pub trait Clock: Send + Sync {
+    /// Monotonic time, for measuring how long ago something happened.
+    fn now(&self) -> Instant;
+
+    /// Time since the Unix epoch, for timestamps that are stored or sent.
+    fn since_epoch(&self) -> Duration;
+
+    fn unix_secs(&self) -> u64 {
+        self.since_epoch().as_secs()
+    }
+
+    fn unix_millis(&self) -> u64 {
+        self.since_epoch().as_millis() as u64
+    }
+}
+
+/// The real time.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn since_epoch(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+}
+
+/// A clock that starts at the real time and only moves when advanced.
+#[cfg(test)]
+pub struct MockClock {
+    start: Instant,
+    start_since_epoch: Duration,
+    advanced: std::sync::Mutex<Duration>,
+}
+
+#[cfg(test)]
+impl Default for MockClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            start_since_epoch: SystemClock.since_epoch(),
+            advanced: std::sync::Mutex::new(Duration::ZERO),
+        }
+    }
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn advance(&self, by: Duration) {
+        *self.advanced.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + *self.advanced.lock().unwrap()
+    }
+
+    fn since_epoch(&self) -> Duration {
+        self.start_since_epoch + *self.advanced.lock().unwrap()
+    }
+}
+
+#[test]
+fn test_mock_clock_moves_only_when_advanced() {
+    let clock = MockClock::default();
+    let (instant, since_epoch) = (clock.now(), clock.since_epoch());
+    std::thread::sleep(Duration::from_millis(5));
+    assert_eq!(clock.now(), instant);
+
+    clock.advance(Duration::from_secs(90));
+    assert_eq!(clock.now() - instant, Duration::from_secs(90));
+    assert_eq!(clock.since_epoch() - since_epoch, Duration::from_secs(90));
+    assert_eq!(clock.unix_secs(), since_epoch.as_secs() + 90);
+}
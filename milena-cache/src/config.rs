@@ -1,5 +1,19 @@
+use crate::chaos;
+use crate::metrics::ExporterKind;
+use crate::operation::{PartialWritePolicy, WriteMode};
+use crate::store::{
+    DiskCompression, DiskOptions, MemoryCompression, S3Options, ValueCompression, MAX_SHARD_COUNT,
+};
+use crate::ttl::JitterMode;
+use aws_sdk_s3::config::Credentials;
+use aws_sdk_s3::types::StorageClass;
+use milena_protos::normalization::KeyNormalization;
+use milena_protos::validation::{validate_bucket_name_with, BucketNameRules, MAX_VALUE_BYTES};
 use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -17,9 +31,353 @@ pub struct Config {
     pub lru_size: usize,
     pub ttl_seconds: u64,
     pub router_addr: String,
+    /// Node group label sent when joining the router, which places the buckets
+    /// it maps to the group only on that group's nodes. Empty joins the
+    /// default group.
+    #[serde(default)]
+    pub node_group: String,
     pub s3_bucket: String,
     pub log_level: String,
     pub metrics_port: u16,
+    /// Scrapes of `/metrics` taking longer than this are answered with a 500.
+    #[serde(default = "default_metrics_scrape_timeout_ms")]
+    pub metrics_scrape_timeout_ms: u64,
+    /// Largest `/metrics` response served; larger ones are answered with a 500.
+    #[serde(default = "default_metrics_max_bytes")]
+    pub metrics_max_bytes: usize,
+    #[serde(default = "default_self_test_bucket")]
+    pub self_test_bucket: String,
+    /// Size budget for the disk tier; the janitor is disabled when unset.
+    #[serde(default)]
+    pub disk_max_bytes: Option<u64>,
+    #[serde(default = "default_disk_janitor_interval_seconds")]
+    pub disk_janitor_interval_seconds: u64,
+    /// How long to keep retrying when another process holds the disk store's
+    /// lock, e.g. while a previous deploy shuts down; 0 fails at once.
+    #[serde(default)]
+    pub disk_lock_wait_seconds: u64,
+    /// Directory of the disk tier's RocksDB instance. Nodes sharing a host
+    /// each need their own.
+    #[serde(default = "default_disk_path")]
+    pub disk_path: PathBuf,
+    /// RocksDB block cache for the disk tier, in MB; 0 disables it.
+    #[serde(default = "default_disk_block_cache_mb")]
+    pub disk_block_cache_mb: usize,
+    /// How RocksDB compresses the disk tier's files.
+    #[serde(default)]
+    pub disk_compression: DiskCompression,
+    /// Disk tier size at which the node asks the router to send its writes
+    /// elsewhere; never reported full when unset.
+    #[serde(default)]
+    pub disk_full_bytes: Option<u64>,
+    /// In-memory entries unused for this long are evicted by the idle
+    /// sweeper; the sweeper is disabled when unset.
+    #[serde(default)]
+    pub memory_idle_seconds: Option<u64>,
+    #[serde(default = "default_memory_sweep_interval_seconds")]
+    pub memory_sweep_interval_seconds: u64,
+    /// Most entries one sweep evicts, so the LRU lock is not held for long.
+    #[serde(default = "default_memory_sweep_max_evictions")]
+    pub memory_sweep_max_evictions: usize,
+    /// Compression for values held in memory; disk and S3 are unaffected.
+    #[serde(default)]
+    pub memory_compression: MemoryCompression,
+    /// Values shorter than this are held in memory uncompressed.
+    #[serde(default = "default_memory_compression_min_bytes")]
+    pub memory_compression_min_bytes: usize,
+    /// Compression for values on disk and in S3; memory is unaffected.
+    #[serde(default)]
+    pub value_compression: ValueCompression,
+    /// Zstd level for `value_compression`, from 1 (fastest) to 22 (smallest).
+    #[serde(default = "default_value_compression_level")]
+    pub value_compression_level: i32,
+    /// Number of keys to prefetch after each GET; prefetching is disabled at 0.
+    #[serde(default)]
+    pub prefetch_count: usize,
+    #[serde(default = "default_prefetch_concurrency")]
+    pub prefetch_concurrency: usize,
+    /// Idempotency keys remembered for deduplicating retried puts and
+    /// deletes; the keys are ignored at 0.
+    #[serde(default = "default_idempotency_keys")]
+    pub idempotency_keys: usize,
+    /// How long a write's idempotency key is honoured.
+    #[serde(default = "default_idempotency_ttl_seconds")]
+    pub idempotency_ttl_seconds: u64,
+    /// Most `watch` streams open at once; watching is disabled at 0.
+    #[serde(default = "default_max_watchers")]
+    pub max_watchers: usize,
+    /// Changes a watcher may fall behind by before it is dropped.
+    #[serde(default = "default_watch_buffer")]
+    pub watch_buffer: usize,
+    /// Most gets, puts and deletes for one bucket running at once; further ones
+    /// fail with `ResourceExhausted`. Unlimited at 0.
+    #[serde(default)]
+    pub max_requests_per_bucket: usize,
+    /// Buckets whose in-flight requests are tracked for `max_requests_per_bucket`.
+    #[serde(default = "default_max_limited_buckets")]
+    pub max_limited_buckets: usize,
+    /// Disk reads slower than this are abandoned in favour of S3.
+    #[serde(default)]
+    pub disk_read_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub s3_read_timeout_ms: Option<u64>,
+    /// Port for the HTTP/JSON gateway; the gateway is disabled when unset.
+    #[serde(default)]
+    pub http_gateway_port: Option<u16>,
+    /// Largest gRPC message accepted or sent; must leave room above the 5MB value limit.
+    #[serde(default = "default_max_message_bytes")]
+    pub max_message_bytes: usize,
+    /// Gzip responses to callers that accept gzip; compressed requests are always accepted.
+    #[serde(default)]
+    pub grpc_compression: bool,
+    /// Sample one in this many keys for the shard distribution; disabled when unset.
+    #[serde(default)]
+    pub key_shard_sample_every: Option<u64>,
+    /// Length of the sliding window behind the `cache_hit_ratio` gauge.
+    #[serde(default = "default_hit_ratio_window_seconds")]
+    pub hit_ratio_window_seconds: u64,
+    /// Named AWS profile to load S3 credentials from instead of the default
+    /// provider chain.
+    #[serde(default)]
+    pub s3_profile: Option<String>,
+    /// Static S3 credentials, used instead of the default provider chain when
+    /// set; the secret key is required with the access key.
+    #[serde(default)]
+    pub s3_access_key_id: Option<String>,
+    #[serde(default)]
+    pub s3_secret_access_key: Option<String>,
+    #[serde(default)]
+    pub s3_session_token: Option<String>,
+    /// Start with the S3 tier disabled instead of exiting when `s3_bucket` is unreachable.
+    #[serde(default)]
+    pub s3_degraded_mode: bool,
+    /// Storage class for objects the node writes to S3, e.g. `STANDARD_IA`.
+    #[serde(default = "default_s3_storage_class")]
+    pub s3_storage_class: String,
+    /// Comma-separated buckets whose S3 objects are managed externally; puts and
+    /// deletes for them only touch memory and disk.
+    #[serde(default)]
+    pub s3_read_only_buckets: String,
+    /// Reject every client call that would change the cache, for read
+    /// replicas; reads are served as usual.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Characters allowed in bucket names besides alphanumerics and hyphens, e.g. `._`.
+    #[serde(default)]
+    pub bucket_name_extra_chars: String,
+    /// Validate bucket names against S3's naming rules instead, since request
+    /// buckets are used as S3 buckets directly.
+    #[serde(default)]
+    pub s3_bucket_names: bool,
+    /// Lowercase keys before storing them. Must match the router's setting, as
+    /// must the other key normalization options.
+    #[serde(default)]
+    pub key_normalize_lowercase: bool,
+    /// Strip leading and trailing whitespace from keys.
+    #[serde(default)]
+    pub key_normalize_trim: bool,
+    /// Convert keys to Unicode Normalization Form C.
+    #[serde(default)]
+    pub key_normalize_nfc: bool,
+    /// Times an S3 read that finds nothing is retried when this node wrote the
+    /// key to S3 recently, for backends with read-after-write lag; 0 disables.
+    #[serde(default)]
+    pub s3_read_after_write_retries: u32,
+    /// Pause before each read-after-write retry.
+    #[serde(default = "default_s3_read_after_write_delay_ms")]
+    pub s3_read_after_write_delay_ms: u64,
+    /// How long after a write its misses are retried.
+    #[serde(default = "default_s3_read_after_write_window_ms")]
+    pub s3_read_after_write_window_ms: u64,
+    /// Whether DELETE removes the S3 object too; when false it only clears
+    /// memory and disk.
+    #[serde(default = "default_delete_from_s3")]
+    pub delete_from_s3: bool,
+    /// Whether DELETE sends S3 a HEAD request so `existed` also counts keys
+    /// only S3 still holds.
+    #[serde(default)]
+    pub delete_checks_s3: bool,
+    /// Serve an expired disk entry written at most this long ago when S3 reads
+    /// fail, so it only has an effect above the TTL; disabled when unset.
+    #[serde(default)]
+    pub max_stale_seconds: Option<u64>,
+    /// Values larger than this skip the in-memory LRU and are served from disk
+    /// or S3; unlimited when unset.
+    #[serde(default)]
+    pub memory_max_value_bytes: Option<u64>,
+    /// Values larger than this skip the disk tier; unlimited when unset.
+    #[serde(default)]
+    pub disk_max_value_bytes: Option<u64>,
+    /// Order in which puts write the tiers; see `WriteMode` for the trade-offs.
+    #[serde(default)]
+    pub write_mode: WriteMode,
+    /// Whether a put that one tier rejects after another accepted it fails
+    /// and is rolled back, or succeeds with a warning.
+    #[serde(default)]
+    pub partial_write_policy: PartialWritePolicy,
+    /// How often latency-first writes are persisted to S3, and combined writes
+    /// to disk and S3.
+    #[serde(default = "default_write_behind_interval_ms")]
+    pub write_behind_interval_ms: u64,
+    /// Puts to a key written to S3 less than this long ago are combined into
+    /// one later write of the latest value; 0 disables combining. Has no
+    /// effect on latency-first writes, which are always combined.
+    #[serde(default)]
+    pub write_combine_window_ms: u64,
+    /// Cap on the key and value bytes held by pinned keys, which are never evicted.
+    #[serde(default = "default_max_pinned_bytes")]
+    pub max_pinned_bytes: u64,
+    /// How often recently used keys are checked against S3; the reconciler is
+    /// disabled when unset.
+    #[serde(default)]
+    pub reconcile_interval_seconds: Option<u64>,
+    /// Keys checked per reconciler run, and so S3 reads per interval.
+    #[serde(default = "default_reconcile_batch_size")]
+    pub reconcile_batch_size: usize,
+    /// Spread each disk entry's TTL by up to this percentage either way.
+    #[serde(default)]
+    pub ttl_jitter_percent: u8,
+    #[serde(default)]
+    pub ttl_jitter_mode: JitterMode,
+    /// Record how long each tier's calls hold a key lock, in
+    /// `cache_tier_lock_hold_seconds`.
+    #[serde(default)]
+    pub tier_lock_metrics: bool,
+    /// Comma-separated `bucket=seconds` pairs overriding `ttl_seconds` for disk
+    /// entries in those buckets, e.g. `sessions=60,reports=86400`.
+    #[serde(default)]
+    pub bucket_ttl_seconds: String,
+    /// Comma-separated `bucket=bytes` pairs capping the disk bytes of those
+    /// buckets, e.g. `tenant-a=1073741824`; other buckets are unlimited.
+    #[serde(default)]
+    pub bucket_quota_bytes: String,
+    #[serde(default)]
+    pub metrics_exporter: ExporterKind,
+    /// `host:port` of the StatsD agent when `metrics_exporter` is `statsd`.
+    #[serde(default)]
+    pub statsd_addr: Option<String>,
+    /// Mixed into S3 object keys so deployments can share a bucket; changing
+    /// it orphans every object written under the old salt.
+    #[serde(default)]
+    pub key_salt: String,
+    /// The salt in use before `key_salt`. When set, S3 misses are looked up
+    /// under it and moved to the current salt.
+    #[serde(default)]
+    pub previous_key_salt: Option<String>,
+    /// Number of prefixes S3 object keys are spread over. Like the salt,
+    /// changing it orphans every object written under the old count.
+    #[serde(default = "default_shard_count")]
+    pub shard_count: u32,
+    /// Failure-injection rules, e.g. `get:error:0.1`; ignored unless `MILENA_CHAOS=1`.
+    #[serde(default)]
+    pub chaos_rules: Option<String>,
+    /// Seed for reproducible failure injection.
+    #[serde(default)]
+    pub chaos_seed: Option<u64>,
+}
+
+fn default_shard_count() -> u32 {
+    MAX_SHARD_COUNT
+}
+
+fn default_self_test_bucket() -> String {
+    "milena-self-test".to_string()
+}
+
+fn default_metrics_scrape_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_metrics_max_bytes() -> usize {
+    16 * 1024 * 1024
+}
+
+fn default_disk_janitor_interval_seconds() -> u64 {
+    60
+}
+
+fn default_disk_path() -> PathBuf {
+    PathBuf::from("./db")
+}
+
+fn default_disk_block_cache_mb() -> usize {
+    8
+}
+
+fn default_memory_sweep_interval_seconds() -> u64 {
+    60
+}
+
+fn default_memory_sweep_max_evictions() -> usize {
+    1000
+}
+
+fn default_memory_compression_min_bytes() -> usize {
+    256
+}
+
+fn default_value_compression_level() -> i32 {
+    3
+}
+
+fn default_prefetch_concurrency() -> usize {
+    4
+}
+
+fn default_s3_storage_class() -> String {
+    "STANDARD".to_string()
+}
+
+fn default_idempotency_keys() -> usize {
+    10_000
+}
+
+fn default_idempotency_ttl_seconds() -> u64 {
+    300
+}
+
+fn default_max_watchers() -> usize {
+    1024
+}
+
+fn default_watch_buffer() -> usize {
+    16
+}
+
+fn default_max_limited_buckets() -> usize {
+    1024
+}
+
+fn default_max_message_bytes() -> usize {
+    8 * 1024 * 1024
+}
+
+fn default_hit_ratio_window_seconds() -> u64 {
+    300
+}
+
+fn default_s3_read_after_write_delay_ms() -> u64 {
+    50
+}
+
+fn default_s3_read_after_write_window_ms() -> u64 {
+    10_000
+}
+
+fn default_delete_from_s3() -> bool {
+    true
+}
+
+fn default_reconcile_batch_size() -> usize {
+    10
+}
+
+fn default_max_pinned_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_write_behind_interval_ms() -> u64 {
+    100
 }
 
 impl Config {
@@ -45,6 +403,184 @@ impl Config {
                 "TTL must be greater than 0".to_string(),
             ));
         }
+        if self.ttl_jitter_percent >= 100 {
+            return Err(ConfigError::InvalidConfig(
+                "TTL jitter percent must be less than 100".to_string(),
+            ));
+        }
+        // RocksDB creates the disk path itself, but not its parents
+        let disk_parent = match self.disk_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        if let Err(e) = probe_writable(disk_parent) {
+            return Err(ConfigError::InvalidConfig(format!(
+                "Disk path parent {} must be a writable directory: {}",
+                disk_parent.display(),
+                e
+            )));
+        }
+        if self.disk_janitor_interval_seconds == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "Disk janitor interval must be greater than 0".to_string(),
+            ));
+        }
+        if self.memory_idle_seconds == Some(0) {
+            return Err(ConfigError::InvalidConfig(
+                "Memory idle seconds must be greater than 0".to_string(),
+            ));
+        }
+        if self.memory_sweep_interval_seconds == 0 || self.memory_sweep_max_evictions == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "Memory sweep interval and max evictions must be greater than 0".to_string(),
+            ));
+        }
+        if self.write_mode == WriteMode::LatencyFirst && self.write_behind_interval_ms == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "Write-behind interval must be greater than 0 for latency-first writes"
+                    .to_string(),
+            ));
+        }
+        if self.value_compression == ValueCompression::Zstd
+            && !(1..=22).contains(&self.value_compression_level)
+        {
+            return Err(ConfigError::InvalidConfig(
+                "Value compression level must be between 1 and 22".to_string(),
+            ));
+        }
+        if self.reconcile_interval_seconds == Some(0) {
+            return Err(ConfigError::InvalidConfig(
+                "Reconcile interval must be greater than 0".to_string(),
+            ));
+        }
+        if self.memory_max_value_bytes == Some(0) || self.disk_max_value_bytes == Some(0) {
+            return Err(ConfigError::InvalidConfig(
+                "Max value bytes must be greater than 0".to_string(),
+            ));
+        }
+        if self.s3_read_after_write_retries > 0 && self.s3_read_after_write_window_ms == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "S3 read-after-write window must be greater than 0 when retries are enabled"
+                    .to_string(),
+            ));
+        }
+        if self.disk_full_bytes == Some(0) {
+            return Err(ConfigError::InvalidConfig(
+                "Disk full bytes must be greater than 0".to_string(),
+            ));
+        }
+        if self.prefetch_count > 0 && self.prefetch_concurrency == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "Prefetch concurrency must be greater than 0 when prefetching is enabled"
+                    .to_string(),
+            ));
+        }
+        if self.idempotency_keys > 0 && self.idempotency_ttl_seconds == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "Idempotency TTL must be greater than 0 when idempotency keys are enabled"
+                    .to_string(),
+            ));
+        }
+        if self.max_watchers > 0 && self.watch_buffer == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "Watch buffer must be greater than 0 when watching is enabled".to_string(),
+            ));
+        }
+        if self.max_requests_per_bucket > 0 && self.max_limited_buckets == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "Max limited buckets must be greater than 0 when per-bucket limits are enabled"
+                    .to_string(),
+            ));
+        }
+        if self.max_message_bytes <= MAX_VALUE_BYTES {
+            return Err(ConfigError::InvalidConfig(format!(
+                "Max message bytes must be greater than the {} byte value limit",
+                MAX_VALUE_BYTES
+            )));
+        }
+        if self.key_shard_sample_every == Some(0) {
+            return Err(ConfigError::InvalidConfig(
+                "Key shard sample interval must be greater than 0".to_string(),
+            ));
+        }
+        if self.previous_key_salt.as_ref() == Some(&self.key_salt) {
+            return Err(ConfigError::InvalidConfig(
+                "Previous key salt must differ from the key salt".to_string(),
+            ));
+        }
+        if self.shard_count == 0 || self.shard_count > MAX_SHARD_COUNT {
+            return Err(ConfigError::InvalidConfig(format!(
+                "Shard count must be between 1 and {}",
+                MAX_SHARD_COUNT
+            )));
+        }
+        if self.metrics_scrape_timeout_ms == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "Metrics scrape timeout must be greater than 0".to_string(),
+            ));
+        }
+        if self.metrics_max_bytes == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "Metrics max bytes must be greater than 0".to_string(),
+            ));
+        }
+        if self.hit_ratio_window_seconds == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "Hit ratio window must be greater than 0".to_string(),
+            ));
+        }
+        if self.metrics_exporter == ExporterKind::Statsd {
+            if !cfg!(feature = "statsd") {
+                return Err(ConfigError::InvalidConfig(
+                    "StatsD metrics require building with the statsd feature".to_string(),
+                ));
+            }
+            if self.statsd_addr.is_none() {
+                return Err(ConfigError::MissingConfig(
+                    "StatsD address is required when exporting to StatsD".to_string(),
+                ));
+            }
+        }
+        if self.s3_access_key_id.is_some() != self.s3_secret_access_key.is_some() {
+            return Err(ConfigError::InvalidConfig(
+                "S3 access key id and secret access key must be set together".to_string(),
+            ));
+        }
+        if self.s3_session_token.is_some() && self.s3_access_key_id.is_none() {
+            return Err(ConfigError::InvalidConfig(
+                "S3 session token requires an access key id and secret access key".to_string(),
+            ));
+        }
+        if self.s3_profile.is_some() && self.s3_access_key_id.is_some() {
+            return Err(ConfigError::InvalidConfig(
+                "S3 profile cannot be combined with static S3 credentials".to_string(),
+            ));
+        }
+        if self.s3_bucket_names && !self.bucket_name_extra_chars.is_empty() {
+            return Err(ConfigError::InvalidConfig(
+                "Bucket name extra characters cannot be combined with S3 bucket names".to_string(),
+            ));
+        }
+        let bucket_name_rules = self.bucket_name_rules();
+        for bucket in self.read_only_buckets() {
+            validate_bucket_name_with(&bucket, &bucket_name_rules).map_err(|e| {
+                ConfigError::InvalidConfig(format!("S3 read-only bucket {:?}: {}", bucket, e))
+            })?;
+        }
+        for bucket in self.bucket_ttls()?.keys() {
+            validate_bucket_name_with(bucket, &bucket_name_rules).map_err(|e| {
+                ConfigError::InvalidConfig(format!("Bucket TTL bucket {:?}: {}", bucket, e))
+            })?;
+        }
+        for bucket in self.bucket_quotas()?.keys() {
+            validate_bucket_name_with(bucket, &bucket_name_rules).map_err(|e| {
+                ConfigError::InvalidConfig(format!("Bucket quota bucket {:?}: {}", bucket, e))
+            })?;
+        }
+        self.s3_storage_class()?;
+        if let Some(rules) = &self.chaos_rules {
+            chaos::parse_rules(rules).map_err(|e| ConfigError::InvalidConfig(e.to_string()))?;
+        }
         if self.router_addr.is_empty() {
             return Err(ConfigError::MissingConfig(
                 "Router address is required".to_string(),
@@ -52,6 +588,114 @@ impl Config {
         }
         Ok(())
     }
+
+    pub fn s3_storage_class(&self) -> Result<StorageClass, ConfigError> {
+        let name = self.s3_storage_class.trim();
+        if !StorageClass::values().contains(&name) {
+            return Err(ConfigError::InvalidConfig(format!(
+                "S3 storage class {:?} must be one of {}",
+                name,
+                StorageClass::values().join(", ")
+            )));
+        }
+        Ok(StorageClass::from(name))
+    }
+
+    pub fn bucket_name_rules(&self) -> BucketNameRules {
+        if self.s3_bucket_names {
+            BucketNameRules::S3
+        } else {
+            BucketNameRules::Charset(self.bucket_name_extra_chars.clone())
+        }
+    }
+
+    /// Static credentials overriding the default provider chain, if configured.
+    pub fn s3_credentials(&self) -> Option<Credentials> {
+        Some(Credentials::new(
+            self.s3_access_key_id.clone()?,
+            self.s3_secret_access_key.clone()?,
+            self.s3_session_token.clone(),
+            None,
+            "milena-config",
+        ))
+    }
+
+    pub fn disk_options(&self) -> DiskOptions {
+        DiskOptions {
+            path: self.disk_path.clone(),
+            block_cache_mb: self.disk_block_cache_mb,
+            compression: self.disk_compression,
+        }
+    }
+
+    pub fn s3_options(&self) -> Result<S3Options, ConfigError> {
+        Ok(S3Options {
+            key_salt: self.key_salt.clone(),
+            shard_count: self.shard_count,
+            storage_class: self.s3_storage_class()?,
+        })
+    }
+
+    pub fn key_normalization(&self) -> KeyNormalization {
+        KeyNormalization {
+            lowercase: self.key_normalize_lowercase,
+            trim: self.key_normalize_trim,
+            nfc: self.key_normalize_nfc,
+        }
+    }
+
+    pub fn read_only_buckets(&self) -> HashSet<String> {
+        self.s3_read_only_buckets
+            .split(',')
+            .map(str::trim)
+            .filter(|bucket| !bucket.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    pub fn bucket_ttls(&self) -> Result<HashMap<String, Duration>, ConfigError> {
+        let mut ttls = HashMap::new();
+        for pair in self.bucket_ttl_seconds.split(',').map(str::trim) {
+            if pair.is_empty() {
+                continue;
+            }
+            let invalid = || {
+                ConfigError::InvalidConfig(format!(
+                    "Bucket TTL {:?} must be bucket=seconds with seconds greater than 0",
+                    pair
+                ))
+            };
+            let (bucket, seconds) = pair.split_once('=').ok_or_else(invalid)?;
+            let seconds: u64 = seconds.trim().parse().map_err(|_| invalid())?;
+            if seconds == 0 {
+                return Err(invalid());
+            }
+            ttls.insert(bucket.trim().to_string(), Duration::from_secs(seconds));
+        }
+        Ok(ttls)
+    }
+
+    pub fn bucket_quotas(&self) -> Result<HashMap<String, u64>, ConfigError> {
+        let mut quotas = HashMap::new();
+        for pair in self.bucket_quota_bytes.split(',').map(str::trim) {
+            if pair.is_empty() {
+                continue;
+            }
+            let invalid = || {
+                ConfigError::InvalidConfig(format!(
+                    "Bucket quota {:?} must be bucket=bytes with bytes greater than 0",
+                    pair
+                ))
+            };
+            let (bucket, bytes) = pair.split_once('=').ok_or_else(invalid)?;
+            let bytes: u64 = bytes.trim().parse().map_err(|_| invalid())?;
+            if bytes == 0 {
+                return Err(invalid());
+            }
+            quotas.insert(bucket.trim().to_string(), bytes);
+        }
+        Ok(quotas)
+    }
 }
 
 impl Default for Config {
@@ -62,9 +706,125 @@ impl Default for Config {
             lru_size: 100,
             ttl_seconds: 360,
             router_addr: "http://localhost:50052".to_string(),
+            node_group: String::new(),
             s3_bucket: "milena-cache".to_string(),
             log_level: "info".to_string(),
             metrics_port: 9090,
+            metrics_scrape_timeout_ms: default_metrics_scrape_timeout_ms(),
+            metrics_max_bytes: default_metrics_max_bytes(),
+            self_test_bucket: default_self_test_bucket(),
+            disk_max_bytes: None,
+            disk_janitor_interval_seconds: default_disk_janitor_interval_seconds(),
+            disk_lock_wait_seconds: 0,
+            disk_path: default_disk_path(),
+            disk_block_cache_mb: default_disk_block_cache_mb(),
+            disk_compression: DiskCompression::default(),
+            disk_full_bytes: None,
+            memory_idle_seconds: None,
+            memory_sweep_interval_seconds: default_memory_sweep_interval_seconds(),
+            memory_sweep_max_evictions: default_memory_sweep_max_evictions(),
+            memory_compression: MemoryCompression::default(),
+            memory_compression_min_bytes: default_memory_compression_min_bytes(),
+            value_compression: ValueCompression::default(),
+            value_compression_level: default_value_compression_level(),
+            prefetch_count: 0,
+            prefetch_concurrency: default_prefetch_concurrency(),
+            idempotency_keys: default_idempotency_keys(),
+            idempotency_ttl_seconds: default_idempotency_ttl_seconds(),
+            max_watchers: default_max_watchers(),
+            watch_buffer: default_watch_buffer(),
+            max_requests_per_bucket: 0,
+            max_limited_buckets: default_max_limited_buckets(),
+            disk_read_timeout_ms: None,
+            s3_read_timeout_ms: None,
+            http_gateway_port: None,
+            max_message_bytes: default_max_message_bytes(),
+            grpc_compression: false,
+            key_shard_sample_every: None,
+            hit_ratio_window_seconds: default_hit_ratio_window_seconds(),
+            s3_profile: None,
+            s3_access_key_id: None,
+            s3_secret_access_key: None,
+            s3_session_token: None,
+            s3_degraded_mode: false,
+            s3_storage_class: default_s3_storage_class(),
+            s3_read_only_buckets: String::new(),
+            read_only: false,
+            bucket_name_extra_chars: String::new(),
+            s3_bucket_names: false,
+            key_normalize_lowercase: false,
+            key_normalize_trim: false,
+            key_normalize_nfc: false,
+            s3_read_after_write_retries: 0,
+            s3_read_after_write_delay_ms: default_s3_read_after_write_delay_ms(),
+            s3_read_after_write_window_ms: default_s3_read_after_write_window_ms(),
+            delete_from_s3: default_delete_from_s3(),
+            delete_checks_s3: false,
+            max_stale_seconds: None,
+            reconcile_interval_seconds: None,
+            reconcile_batch_size: default_reconcile_batch_size(),
+            max_pinned_bytes: default_max_pinned_bytes(),
+            memory_max_value_bytes: None,
+            disk_max_value_bytes: None,
+            write_mode: WriteMode::default(),
+            partial_write_policy: PartialWritePolicy::default(),
+            write_behind_interval_ms: default_write_behind_interval_ms(),
+            write_combine_window_ms: 0,
+            ttl_jitter_percent: 0,
+            ttl_jitter_mode: JitterMode::default(),
+            tier_lock_metrics: false,
+            bucket_ttl_seconds: String::new(),
+            bucket_quota_bytes: String::new(),
+            metrics_exporter: ExporterKind::default(),
+            statsd_addr: None,
+            key_salt: String::new(),
+            previous_key_salt: None,
+            shard_count: default_shard_count(),
+            chaos_rules: None,
+            chaos_seed: None,
         }
     }
 }
+
+// Creates and removes a file in `dir`, since permission bits say nothing of
+// root, ACLs or read-only mounts.
+fn probe_writable(dir: &Path) -> std::io::Result<()> {
+    let probe = dir.join(format!(".milena-write-probe-{}", std::process::id()));
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    std::fs::remove_file(&probe)
+}
+
+#[test]
+fn test_validate_requires_a_writable_disk_path_parent() {
+    let with_disk_path = |disk_path: PathBuf| Config {
+        disk_path,
+        ..Config::default()
+    };
+    assert!(with_disk_path(default_disk_path()).validate().is_ok());
+
+    let scratch = std::env::temp_dir().join(format!("milena-config-{}", std::process::id()));
+    std::fs::create_dir_all(&scratch).unwrap();
+    assert!(with_disk_path(scratch.join("db")).validate().is_ok());
+    assert!(with_disk_path(scratch.join("missing/db"))
+        .validate()
+        .is_err());
+
+    let file = scratch.join("file");
+    std::fs::write(&file, b"").unwrap();
+    assert!(with_disk_path(file.join("db")).validate().is_err());
+
+    // Not even root can create files in /proc, whatever its permission bits say
+    if cfg!(target_os = "linux") {
+        let rejected = with_disk_path(PathBuf::from("/proc/db")).validate();
+        assert!(
+            matches!(rejected, Err(ConfigError::InvalidConfig(reason)) if reason.contains("writable"))
+        );
+    }
+
+    // The probe leaves nothing behind
+    std::fs::remove_file(&file).unwrap();
+    std::fs::remove_dir(&scratch).unwrap();
+}
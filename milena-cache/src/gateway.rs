@@ -0,0 +1,274 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use milena_protos::normalization::KeyNormalization;
+use milena_protos::validation::{
+    validate_bucket_name_with, validate_key, validate_value, BucketNameRules, MAX_VALUE_BYTES,
+};
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::{Filter, Rejection, Reply};
+
+use crate::metrics::{Metrics, Op};
+use crate::operation::QuotaExceeded;
+use crate::service::SharedOperation;
+use crate::store::{Key, Value};
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+#[derive(Serialize)]
+struct DeleteBody {
+    existed: bool,
+}
+
+#[derive(Clone)]
+struct Gateway {
+    operation: SharedOperation,
+    metrics: Arc<Metrics>,
+    bucket_name_rules: Arc<BucketNameRules>,
+    key_normalization: KeyNormalization,
+    read_only: bool,
+}
+
+/// HTTP façade over `Operation` for clients without gRPC:
+/// `GET`, `PUT` and `DELETE` on `/v1/{bucket}/{key}`, with the raw value as the
+/// body. Keys are percent-decoded from the path. A read-only gateway answers
+/// `PUT` and `DELETE` with 403.
+pub fn routes(
+    operation: SharedOperation,
+    metrics: Arc<Metrics>,
+    bucket_name_rules: BucketNameRules,
+    key_normalization: KeyNormalization,
+    read_only: bool,
+) -> impl Filter<Extract = (Box<dyn Reply>,), Error = Rejection> + Clone {
+    let gateway = Gateway {
+        operation,
+        metrics,
+        bucket_name_rules: Arc::new(bucket_name_rules),
+        key_normalization,
+        read_only,
+    };
+    let entry = warp::path!("v1" / String / String).and(warp::any().map(move || gateway.clone()));
+
+    let get = entry.clone().and(warp::get()).and_then(handle_get);
+    let put = entry
+        .clone()
+        .and(warp::put())
+        .and(warp::body::content_length_limit(MAX_VALUE_BYTES as u64))
+        .and(warp::body::bytes())
+        .and_then(handle_put);
+    let delete = entry.and(warp::delete()).and_then(handle_delete);
+
+    get.or(put).unify().or(delete).unify()
+}
+
+async fn handle_get(
+    bucket: String,
+    key: String,
+    gateway: Gateway,
+) -> Result<Box<dyn Reply>, Infallible> {
+    gateway.metrics.record_request(Op::Get);
+    let key = match parse_entry(&bucket, &key, &gateway) {
+        Ok(key) => key,
+        Err(reply) => return Ok(reply),
+    };
+
+    let result = gateway
+        .operation
+        .get_with_deadline(&bucket, &key, None)
+        .await;
+    match result {
+        Ok(Some(lookup)) => {
+            gateway.metrics.record_get(true);
+            let entry = lookup.entry;
+            Ok(Box::new(warp::reply::with_header(
+                warp::reply::with_header(
+                    warp::reply::with_header(
+                        warp::reply::with_header(
+                            entry.value.0,
+                            "Content-Type",
+                            "application/octet-stream",
+                        ),
+                        "X-Milena-Written-At",
+                        entry.written_at.to_string(),
+                    ),
+                    "X-Milena-Stale",
+                    lookup.stale.to_string(),
+                ),
+                "X-Milena-Served-From",
+                lookup.tier.as_str(),
+            )))
+        }
+        Ok(None) => {
+            gateway.metrics.record_get(false);
+            Ok(error_reply(
+                StatusCode::NOT_FOUND,
+                "Key not found".to_string(),
+            ))
+        }
+        Err(e) => {
+            gateway.metrics.record_error();
+            Ok(error_reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                e.to_string(),
+            ))
+        }
+    }
+}
+
+async fn handle_put(
+    bucket: String,
+    key: String,
+    gateway: Gateway,
+    body: Bytes,
+) -> Result<Box<dyn Reply>, Infallible> {
+    gateway.metrics.record_request(Op::Put);
+    if gateway.read_only {
+        return Ok(read_only_reply());
+    }
+    let key = match parse_entry(&bucket, &key, &gateway) {
+        Ok(key) => key,
+        Err(reply) => return Ok(reply),
+    };
+    if let Err(e) = validate_value(&body) {
+        return Ok(error_reply(StatusCode::BAD_REQUEST, e.to_string()));
+    }
+
+    let value = Value(body.to_vec());
+    match gateway.operation.put(&bucket, &key, &value).await {
+        Ok(()) => Ok(Box::new(StatusCode::NO_CONTENT)),
+        Err(e) => {
+            gateway.metrics.record_error();
+            let status = if e.is::<QuotaExceeded>() {
+                StatusCode::INSUFFICIENT_STORAGE
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Ok(error_reply(status, e.to_string()))
+        }
+    }
+}
+
+async fn handle_delete(
+    bucket: String,
+    key: String,
+    gateway: Gateway,
+) -> Result<Box<dyn Reply>, Infallible> {
+    gateway.metrics.record_request(Op::Delete);
+    if gateway.read_only {
+        return Ok(read_only_reply());
+    }
+    let key = match parse_entry(&bucket, &key, &gateway) {
+        Ok(key) => key,
+        Err(reply) => return Ok(reply),
+    };
+
+    match gateway.operation.delete(&bucket, &key).await {
+        Ok(existed) => Ok(Box::new(warp::reply::json(&DeleteBody { existed }))),
+        Err(e) => {
+            gateway.metrics.record_error();
+            Ok(error_reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                e.to_string(),
+            ))
+        }
+    }
+}
+
+fn parse_entry(bucket: &str, key: &str, gateway: &Gateway) -> Result<Key, Box<dyn Reply>> {
+    let key = gateway
+        .key_normalization
+        .apply(percent_decode_str(key).collect());
+    validate_bucket_name_with(bucket, &gateway.bucket_name_rules)
+        .and_then(|_| validate_key(&key))
+        .map_err(|e| error_reply(StatusCode::BAD_REQUEST, e.to_string()))?;
+    Ok(Key(key))
+}
+
+fn read_only_reply() -> Box<dyn Reply> {
+    error_reply(StatusCode::FORBIDDEN, "Node is read-only".to_string())
+}
+
+fn error_reply(status: StatusCode, error: String) -> Box<dyn Reply> {
+    Box::new(warp::reply::with_status(
+        warp::reply::json(&ErrorBody { error }),
+        status,
+    ))
+}
+
+#[cfg(test)]
+fn test_routes(
+    name: &str,
+    read_only: bool,
+) -> impl Filter<Extract = (Box<dyn Reply>,), Error = Rejection> + Clone {
+    let path = std::env::temp_dir().join(format!("milena-gateway-{}-{}", name, std::process::id()));
+    let metrics = Arc::new(Metrics::new().unwrap());
+    routes(
+        crate::service::test_operation(&path, metrics.clone()),
+        metrics,
+        BucketNameRules::default(),
+        KeyNormalization::default(),
+        read_only,
+    )
+}
+
+#[tokio::test]
+async fn test_gateway_puts_gets_and_deletes() {
+    let routes = test_routes("crud", false);
+    let request = |method: &str| {
+        warp::test::request()
+            .method(method)
+            .path("/v1/users/user%2F42")
+    };
+
+    let missing = request("GET").reply(&routes).await;
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+    let put = request("PUT").body("alice").reply(&routes).await;
+    assert_eq!(put.status(), StatusCode::NO_CONTENT);
+    let found = request("GET").reply(&routes).await;
+    assert_eq!(found.status(), StatusCode::OK);
+    assert_eq!(found.body().as_ref(), b"alice");
+    assert_eq!(found.headers()["X-Milena-Served-From"], "memory");
+
+    let deleted = request("DELETE").reply(&routes).await;
+    assert_eq!(deleted.status(), StatusCode::OK);
+    assert_eq!(deleted.body().as_ref(), br#"{"existed":true}"#);
+    assert_eq!(
+        request("GET").reply(&routes).await.status(),
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn test_gateway_rejects_invalid_entries_and_read_only_writes() {
+    let routes = test_routes("invalid", false);
+    let bad_bucket = warp::test::request()
+        .method("PUT")
+        .path("/v1/Not_A_Bucket/key")
+        .body("value")
+        .reply(&routes)
+        .await;
+    assert_eq!(bad_bucket.status(), StatusCode::BAD_REQUEST);
+
+    let routes = test_routes("read-only", true);
+    for method in ["PUT", "DELETE"] {
+        let refused = warp::test::request()
+            .method(method)
+            .path("/v1/users/key")
+            .body("value")
+            .reply(&routes)
+            .await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+    }
+    let read = warp::test::request()
+        .path("/v1/users/key")
+        .reply(&routes)
+        .await;
+    assert_eq!(read.status(), StatusCode::NOT_FOUND);
+}
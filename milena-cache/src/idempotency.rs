@@ -0,0 +1,146 @@
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use lru::LruCache;
+use tokio::sync::OnceCell;
+
+use crate::metrics::{Metrics, Op};
+use crate::store::Key;
+
+/// What a write did, replayed to retries that carry the same idempotency key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Put,
+    Delete { existed: bool },
+}
+
+/// Recently seen idempotency keys and the outcome of the write each one
+/// named. A retry of a write that succeeded gets the first outcome back
+/// without the write being applied again; a retry of one that failed, or
+/// that arrives after the key has expired or been pushed out, is applied.
+pub struct IdempotencyKeys {
+    seen: Mutex<LruCache<WriteId, Seen>>,
+    ttl: Duration,
+    metrics: Arc<Metrics>,
+}
+
+// Keys are scoped to the write they came with, so a key reused for another
+// key or operation never replays the wrong outcome.
+#[derive(Hash, PartialEq, Eq)]
+struct WriteId {
+    op: Op,
+    bucket: String,
+    key: Vec<u8>,
+    idempotency_key: String,
+}
+
+struct Seen {
+    at: Instant,
+    // Shared so a duplicate that arrives while the first write is still
+    // running waits for its outcome instead of applying it too.
+    outcome: Arc<OnceCell<Outcome>>,
+}
+
+impl IdempotencyKeys {
+    pub fn new(capacity: NonZeroUsize, ttl: Duration, metrics: Arc<Metrics>) -> Self {
+        Self {
+            seen: Mutex::new(LruCache::new(capacity)),
+            ttl,
+            metrics,
+        }
+    }
+
+    /// Runs `write` unless a write with the same `idempotency_key` has
+    /// already succeeded within the TTL, in which case its outcome is returned.
+    pub async fn run<F, Fut>(
+        &self,
+        op: Op,
+        bucket: &str,
+        key: &Key,
+        idempotency_key: &str,
+        write: F,
+    ) -> Result<Outcome>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Outcome>>,
+    {
+        let id = WriteId {
+            op,
+            bucket: bucket.to_string(),
+            key: key.0.clone(),
+            idempotency_key: idempotency_key.to_string(),
+        };
+        let outcome = {
+            let mut seen = self.seen.lock().unwrap();
+            match seen.get(&id) {
+                Some(earlier) if earlier.at.elapsed() < self.ttl => earlier.outcome.clone(),
+                _ => {
+                    let outcome = Arc::new(OnceCell::new());
+                    seen.put(
+                        id,
+                        Seen {
+                            at: Instant::now(),
+                            outcome: outcome.clone(),
+                        },
+                    );
+                    outcome
+                }
+            }
+        };
+
+        let mut applied = false;
+        let result = *outcome
+            .get_or_try_init(|| {
+                applied = true;
+                write()
+            })
+            .await?;
+        if !applied {
+            self.metrics.record_idempotent_replay(op);
+        }
+        Ok(result)
+    }
+}
+
+#[tokio::test]
+async fn test_idempotency_keys_replay_successful_writes() {
+    let keys = IdempotencyKeys::new(
+        NonZeroUsize::new(10).unwrap(),
+        Duration::from_secs(60),
+        Arc::new(Metrics::new().unwrap()),
+    );
+    let key = Key(b"counter".to_vec());
+    let writes = std::sync::atomic::AtomicUsize::new(0);
+    let delete = |existed| {
+        writes.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        async move { Ok(Outcome::Delete { existed }) }
+    };
+
+    // A retry gets the first outcome even though the key is now gone
+    let first = keys.run(Op::Delete, "users", &key, "req-1", || delete(true));
+    assert_eq!(first.await.unwrap(), Outcome::Delete { existed: true });
+    let retry = keys.run(Op::Delete, "users", &key, "req-1", || delete(false));
+    assert_eq!(retry.await.unwrap(), Outcome::Delete { existed: true });
+    assert_eq!(writes.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+    // Other idempotency keys, and the same one on another key, are applied
+    let other = keys.run(Op::Delete, "users", &key, "req-2", || delete(false));
+    assert_eq!(other.await.unwrap(), Outcome::Delete { existed: false });
+    let other_key = Key(b"other".to_vec());
+    let moved = keys.run(Op::Delete, "users", &other_key, "req-1", || delete(false));
+    assert_eq!(moved.await.unwrap(), Outcome::Delete { existed: false });
+    assert_eq!(writes.load(std::sync::atomic::Ordering::SeqCst), 3);
+
+    // Failed writes are not remembered, so their retries are applied
+    let failed = keys.run(Op::Put, "users", &key, "req-3", || async {
+        Err(anyhow::anyhow!("disk unavailable"))
+    });
+    assert!(failed.await.is_err());
+    let retry = keys.run(Op::Put, "users", &key, "req-3", || async {
+        Ok(Outcome::Put)
+    });
+    assert_eq!(retry.await.unwrap(), Outcome::Put);
+}
@@ -1,31 +1,74 @@
+mod bucket_limits;
+mod capacity;
+mod chaos;
+mod clock;
 mod config;
 mod error;
+mod gateway;
+mod idempotency;
 mod metrics;
 mod operation;
+mod prefetch;
+mod reconcile;
 mod service;
+mod shards;
+mod status;
 mod store;
+mod ttl;
+mod watch;
 
-use crate::config::Config;
-use crate::metrics::Metrics;
-use crate::operation::Operation;
+use crate::bucket_limits::BucketLimits;
+use crate::capacity::WriteGate;
+use crate::chaos::Chaos;
+use crate::clock::SystemClock;
+use crate::config::{Config, ConfigError};
+use crate::idempotency::IdempotencyKeys;
+#[cfg(feature = "statsd")]
+use crate::metrics::StatsdExporter;
+use crate::metrics::{ExporterKind, Metrics};
+use crate::operation::{
+    Operation, PartialWritePolicy, ReadAfterWriteRetry, TierTimeouts, TierValueLimits, WriteMode,
+};
+use crate::prefetch::Prefetcher;
+use crate::reconcile::Reconciler;
 use crate::service::CacheService;
-use crate::store::{DiskStore, LRUStore, S3Store};
+use crate::shards::ShardSampler;
+use crate::status::NodeStatus;
+use crate::store::{DiskStore, LRUStore, S3Store, StoreError, ValueCompression};
+use crate::ttl::Ttl;
+use crate::watch::Watchers;
 use aws_config::meta::region::RegionProviderChain;
 use aws_sdk_s3::Client;
 use aws_types::region::Region;
 use cache_server::cache_server::CacheServer;
 use milena_protos::cache_server;
+use milena_protos::rejections::{RejectedRequest, RejectionLayer};
 use milena_protos::router_server::router_client::RouterClient;
 use prometheus::Encoder;
+use std::convert::Infallible;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tonic::codec::CompressionEncoding;
 use tonic::transport::Server;
 use tracing::{error, info, warn};
+use warp::http::StatusCode;
 use warp::Filter;
 
+// Upper bound on entries removed per janitor run so the disk index lock is not
+// held for long.
+const DISK_JANITOR_MAX_EVICTIONS: usize = 1000;
+// How often the disk tier's size is checked against `disk_full_bytes`.
+const DISK_FULL_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+// Pause between attempts to open a disk store locked by another process.
+const DISK_LOCK_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let started = Instant::now();
+
     // Initialize configuration
     let config = Config::from_env()?;
     config.validate()?;
@@ -34,80 +77,487 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt().init();
 
     // Initialize metrics
-    let metrics = Metrics::new()?;
+    let metrics = match config.metrics_exporter {
+        ExporterKind::Prometheus => Metrics::new()?,
+        #[cfg(feature = "statsd")]
+        ExporterKind::Statsd => {
+            let addr = config.statsd_addr.as_deref().unwrap_or_default();
+            info!("Sending metrics to StatsD at {}", addr);
+            Metrics::with_exporter(Arc::new(StatsdExporter::new(addr)?))
+        }
+        #[cfg(not(feature = "statsd"))]
+        ExporterKind::Statsd => unreachable!("rejected by Config::validate"),
+    };
+    let metrics = Arc::new(
+        metrics.with_hit_ratio_window(Duration::from_secs(config.hit_ratio_window_seconds)),
+    );
     let metrics_clone = metrics.clone();
 
     // Initialize AWS S3 client
     let region_provider =
         RegionProviderChain::default_provider().or_else(Region::new(config.aws_region.clone()));
-    let aws_config = aws_config::from_env().region(region_provider).load().await;
+    let mut aws_loader = aws_config::from_env().region(region_provider);
+    if let Some(profile) = &config.s3_profile {
+        info!("Loading S3 credentials from AWS profile {}", profile);
+        aws_loader = aws_loader.profile_name(profile);
+    }
+    if let Some(credentials) = config.s3_credentials() {
+        info!("Using static S3 credentials from configuration");
+        aws_loader = aws_loader.credentials_provider(credentials);
+    }
+    let aws_config = aws_loader.load().await;
     let s3_client = Client::new(&aws_config);
+    let s3_options = config.s3_options()?;
+    info!(
+        "Writing S3 objects with storage class {}",
+        s3_options.storage_class.as_str()
+    );
+
+    // Verify the S3 bucket before serving
+    let s3_enabled = match S3Store::verify_bucket(&s3_client, &config.s3_bucket).await {
+        Ok(()) => {
+            info!("S3 bucket {} is reachable", config.s3_bucket);
+            true
+        }
+        Err(e) if config.s3_degraded_mode => {
+            warn!(
+                "S3 bucket {} is not reachable ({}), starting with the S3 tier disabled",
+                config.s3_bucket, e
+            );
+            false
+        }
+        Err(e) => {
+            error!("S3 bucket {} is not reachable, exiting", config.s3_bucket);
+            return Err(ConfigError::InvalidConfig(format!(
+                "S3 bucket {} is not reachable: {}",
+                config.s3_bucket, e
+            ))
+            .into());
+        }
+    };
+
+    // Initialize cache service, waiting out a previous process that still
+    // holds the disk store's lock when configured to
+    let lock_wait = Duration::from_secs(config.disk_lock_wait_seconds);
+    let disk_options = config.disk_options();
+    let opening = Instant::now();
+    let mut operation = loop {
+        let opened = Operation::<LRUStore, DiskStore, S3Store>::simple_new(
+            config.lru_size as u64,
+            Ttl::new(Duration::from_secs(config.ttl_seconds))
+                .with_jitter(config.ttl_jitter_percent, config.ttl_jitter_mode)
+                .with_bucket_overrides(config.bucket_ttls()?),
+            &disk_options,
+            s3_client.clone(),
+            s3_options.clone(),
+            metrics.clone(),
+        );
+        match opened {
+            Err(StoreError::Locked { path, .. }) if opening.elapsed() < lock_wait => {
+                warn!(
+                    "Disk store at {} is locked by another process, retrying",
+                    path
+                );
+                tokio::time::sleep(DISK_LOCK_RETRY_INTERVAL).await;
+            }
+            opened => break opened,
+        }
+    }
+    .map_err(|e| {
+        error!("Could not open the disk tier, exiting");
+        ConfigError::InvalidConfig(e.to_string())
+    })?
+    .with_max_pinned_bytes(config.max_pinned_bytes)
+    .with_memory_compression(
+        config.memory_compression,
+        config.memory_compression_min_bytes,
+    )
+    .with_timeouts(TierTimeouts {
+        disk: config.disk_read_timeout_ms.map(Duration::from_millis),
+        cloud: config.s3_read_timeout_ms.map(Duration::from_millis),
+    })
+    .with_value_limits(TierValueLimits {
+        memory: config.memory_max_value_bytes,
+        disk: config.disk_max_value_bytes,
+    });
+    if !s3_enabled {
+        operation = operation.without_cloud();
+    }
+    if !config.delete_from_s3 {
+        info!("Deletes will leave S3 objects in place");
+        operation = operation.with_local_deletes();
+    }
+    if config.delete_checks_s3 {
+        operation = operation.with_cloud_delete_checks();
+    }
+    if config.write_mode == WriteMode::LatencyFirst {
+        warn!("Puts will return before reaching disk and S3 (latency-first writes)");
+        operation = operation.with_write_mode(config.write_mode);
+    }
+    let write_combine_window = match config.write_mode {
+        WriteMode::DurabilityFirst if config.write_combine_window_ms > 0 => {
+            let window = Duration::from_millis(config.write_combine_window_ms);
+            info!(
+                "Combining puts to keys written to S3 in the last {}ms",
+                config.write_combine_window_ms
+            );
+            operation = operation.with_write_combining(window);
+            Some(window)
+        }
+        _ => None,
+    };
+    if config.partial_write_policy == PartialWritePolicy::Warn {
+        warn!("Puts rejected by one tier will succeed if another has the value");
+        operation = operation.with_partial_write_policy(config.partial_write_policy);
+    }
+    if config.s3_read_after_write_retries > 0 {
+        info!(
+            "Retrying S3 misses up to {} times for keys written in the last {}ms",
+            config.s3_read_after_write_retries, config.s3_read_after_write_window_ms
+        );
+        operation = operation.with_read_after_write_retry(ReadAfterWriteRetry {
+            attempts: config.s3_read_after_write_retries,
+            delay: Duration::from_millis(config.s3_read_after_write_delay_ms),
+            window: Duration::from_millis(config.s3_read_after_write_window_ms),
+        });
+    }
+    if let Some(max_stale) = config.max_stale_seconds {
+        info!(
+            "Serving entries up to {}s old when S3 reads fail",
+            max_stale
+        );
+        operation = operation.with_max_stale(Duration::from_secs(max_stale));
+    }
+    if let Some(previous_key_salt) = &config.previous_key_salt {
+        info!("Migrating S3 objects from the previous key salt on read");
+        operation = operation.with_key_migration(S3Store {
+            client: s3_client,
+            key_salt: previous_key_salt.clone(),
+            shard_count: s3_options.shard_count,
+            storage_class: s3_options.storage_class,
+            clock: Arc::new(SystemClock),
+            compression: ValueCompression::None,
+            compression_level: 0,
+        });
+    }
+    if config.value_compression == ValueCompression::Zstd {
+        info!(
+            "Compressing values on disk and in S3 with zstd level {}",
+            config.value_compression_level
+        );
+    }
+    operation =
+        operation.with_value_compression(config.value_compression, config.value_compression_level);
+    if config.tier_lock_metrics {
+        info!("Recording time spent in each tier while holding key locks");
+        operation = operation.with_tier_lock_timing();
+    }
+    let bucket_quotas = config.bucket_quotas()?;
+    if !bucket_quotas.is_empty() {
+        info!("Limiting disk bytes for {:?}", bucket_quotas);
+        operation = operation.with_bucket_quotas(bucket_quotas);
+    }
+    let read_only_buckets = config.read_only_buckets();
+    if !read_only_buckets.is_empty() {
+        info!("Writes to {:?} will not be stored in S3", read_only_buckets);
+        operation = operation.with_s3_read_only_buckets(read_only_buckets);
+    }
+    if let Some(rules) = &config.chaos_rules {
+        if chaos::enabled() {
+            warn!("Failure injection enabled with rules: {}", rules);
+            operation = operation.with_chaos(Arc::new(Chaos::new(
+                chaos::parse_rules(rules)?,
+                config.chaos_seed,
+            )));
+        } else {
+            warn!(
+                "Ignoring CHAOS_RULES because {}=1 is not set",
+                chaos::CHAOS_ENV
+            );
+        }
+    }
+    let watchers = (config.max_watchers > 0).then(|| {
+        Arc::new(Watchers::new(
+            config.max_watchers,
+            config.watch_buffer,
+            metrics.clone(),
+        ))
+    });
+    if let Some(watchers) = &watchers {
+        operation = operation.with_watchers(watchers.clone());
+    }
+    let operation = Arc::new(operation);
+    if config.read_only {
+        info!("Read-only mode: puts, deletes, pins and unpins will be rejected");
+    }
+    // Cancelled on shutdown; every background task stops when it is
+    let shutdown = CancellationToken::new();
+    let mut background_tasks = Vec::new();
 
-    // Initialize cache service
     let service = CacheService {
-        operation: Arc::new(Mutex::new(
-            Operation::<LRUStore, DiskStore, S3Store>::simple_new(
-                config.lru_size as u64,
-                Duration::from_secs(config.ttl_seconds),
-                s3_client,
-            ),
-        )),
-        metrics: Arc::new(metrics),
+        operation: operation.clone(),
+        metrics: metrics.clone(),
+        self_test_bucket: config.self_test_bucket.clone(),
+        key_normalization: config.key_normalization(),
+        read_only: config.read_only,
+        prefetcher: (config.prefetch_count > 0).then(|| {
+            Arc::new(
+                Prefetcher::new(
+                    config.prefetch_count,
+                    config.prefetch_concurrency,
+                    metrics.clone(),
+                )
+                .with_shutdown(shutdown.clone()),
+            )
+        }),
+        shard_sampler: config.key_shard_sample_every.map(|every| {
+            Arc::new(ShardSampler::new(
+                every,
+                &config.key_salt,
+                config.shard_count,
+            ))
+        }),
+        reconciler: config.reconcile_interval_seconds.map(|seconds| {
+            Arc::new(Reconciler::new(
+                Duration::from_secs(seconds),
+                config.reconcile_batch_size,
+                metrics.clone(),
+            ))
+        }),
+        idempotency_keys: NonZeroUsize::new(config.idempotency_keys).map(|capacity| {
+            Arc::new(IdempotencyKeys::new(
+                capacity,
+                Duration::from_secs(config.idempotency_ttl_seconds),
+                metrics.clone(),
+            ))
+        }),
+        watchers,
+        bucket_limits: (config.max_requests_per_bucket > 0).then(|| {
+            info!(
+                "Limiting each bucket to {} requests in flight",
+                config.max_requests_per_bucket
+            );
+            Arc::new(BucketLimits::new(
+                config.max_requests_per_bucket,
+                NonZeroUsize::new(config.max_limited_buckets).unwrap(),
+                metrics.clone(),
+            ))
+        }),
     };
 
-    // Setup graceful shutdown
-    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
-    let shutdown_tx_clone = shutdown_tx;
+    // Start reconciler
+    if let Some(reconciler) = &service.reconciler {
+        info!(
+            "Reconciling up to {} keys against S3 per run",
+            config.reconcile_batch_size
+        );
+        background_tasks.push(tokio::spawn(
+            reconciler.clone().run(operation.clone(), shutdown.clone()),
+        ));
+    }
+
+    // Start disk janitor
+    if let Some(budget) = config.disk_max_bytes {
+        let interval = Duration::from_secs(config.disk_janitor_interval_seconds);
+        let operation = operation.clone();
+        let metrics = metrics.clone();
+        let shutdown = shutdown.clone();
+        background_tasks.push(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => return,
+                    _ = ticker.tick() => {}
+                }
+                let result = operation.evict_disk_to_budget(budget, DISK_JANITOR_MAX_EVICTIONS);
+                match result {
+                    Ok(0) => {}
+                    Ok(reclaimed) => {
+                        info!("Disk janitor reclaimed {} bytes", reclaimed);
+                        metrics.record_disk_bytes_reclaimed(reclaimed);
+                    }
+                    Err(e) => warn!("Disk janitor failed: {}", e),
+                }
+            }
+        }));
+    }
+
+    // Start memory idle sweeper
+    if let Some(idle_seconds) = config.memory_idle_seconds {
+        let idle = Duration::from_secs(idle_seconds);
+        let interval = Duration::from_secs(config.memory_sweep_interval_seconds);
+        let max_evictions = config.memory_sweep_max_evictions;
+        let operation = operation.clone();
+        let metrics = metrics.clone();
+        let shutdown = shutdown.clone();
+        background_tasks.push(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => return,
+                    _ = ticker.tick() => {}
+                }
+                let evicted = operation.evict_idle_memory(idle, max_evictions);
+                if evicted > 0 {
+                    info!("Memory sweeper evicted {} idle entries", evicted);
+                    metrics.record_memory_idle_evictions(evicted as u64);
+                }
+            }
+        }));
+    }
+
+    // Start write-behind persistence, which also flushes combined writes
+    let write_behind_interval = match config.write_mode {
+        WriteMode::LatencyFirst => Some(Duration::from_millis(config.write_behind_interval_ms)),
+        WriteMode::DurabilityFirst => write_combine_window,
+    };
+    if let Some(interval) = write_behind_interval {
+        let operation = operation.clone();
+        let shutdown = shutdown.clone();
+        background_tasks.push(tokio::spawn(async move {
+            operation.run_write_behind(interval, shutdown).await
+        }));
+    }
 
     // Handle Ctrl+C
+    let shutdown_on_signal = shutdown.clone();
     tokio::spawn(async move {
         tokio::signal::ctrl_c()
             .await
             .expect("Failed to listen for ctrl+c");
         info!("Received shutdown signal");
-        shutdown_tx_clone
-            .send(())
-            .expect("Failed to send shutdown signal");
+        shutdown_on_signal.cancel();
     });
 
-    // Start metrics server
+    // Start metrics server, which also serves the status page
     let metrics_addr =
         format!("0.0.0.0:{}", config.metrics_port).parse::<std::net::SocketAddr>()?;
-    let metrics_server = warp::serve(warp::path("metrics").boxed().and(warp::get().boxed()).map(
-        move || {
-            let mut buffer = Vec::new();
-            prometheus::TextEncoder::new()
-                .encode(&metrics_clone.registry.gather(), &mut buffer)
-                .unwrap();
-            warp::reply::with_header(
-                buffer,
-                "Content-Type",
-                "text/plain; version=0.0.4; charset=utf-8",
-            )
-        },
-    ))
-    .run(metrics_addr);
+    let scrape_timeout = Duration::from_millis(config.metrics_scrape_timeout_ms);
+    let max_bytes = config.metrics_max_bytes;
+    let joined_router = Arc::new(AtomicBool::new(false));
+    let status = status::route(NodeStatus {
+        operation: operation.clone(),
+        metrics: metrics.clone(),
+        started,
+        hit_ratio_window: Duration::from_secs(config.hit_ratio_window_seconds),
+        joined_router: joined_router.clone(),
+    });
+    let metrics_server = async move {
+        // Other exporters push their metrics, so there is nothing to scrape.
+        if metrics_clone.prometheus().is_none() {
+            return warp::serve(status).run(metrics_addr).await;
+        }
+        warp::serve(
+            warp::path("metrics")
+                .boxed()
+                .and(warp::get().boxed())
+                .and_then(move || scrape_metrics(metrics_clone.clone(), scrape_timeout, max_bytes))
+                .or(status),
+        )
+        .run(metrics_addr)
+        .await
+    };
+
+    // Start HTTP gateway
+    if let Some(port) = config.http_gateway_port {
+        let gateway_addr = format!("0.0.0.0:{}", port).parse::<std::net::SocketAddr>()?;
+        info!("HTTP gateway listening on {}", gateway_addr);
+        let (_, gateway_server) = warp::serve(gateway::routes(
+            operation.clone(),
+            metrics.clone(),
+            config.bucket_name_rules(),
+            config.key_normalization(),
+            config.read_only,
+        ))
+        .try_bind_with_graceful_shutdown(gateway_addr, shutdown.clone().cancelled_owned())?;
+        background_tasks.push(tokio::spawn(gateway_server));
+    }
 
     // Start gRPC server
+    let mut cache_server = CacheServer::new(service)
+        .max_decoding_message_size(config.max_message_bytes)
+        .max_encoding_message_size(config.max_message_bytes)
+        .accept_compressed(CompressionEncoding::Gzip);
+    if config.grpc_compression {
+        // Only used for callers that advertise gzip in grpc-accept-encoding
+        cache_server = cache_server.send_compressed(CompressionEncoding::Gzip);
+    }
+    let rejections_metrics = metrics.clone();
     let grpc_server = Server::builder()
-        .add_service(CacheServer::new(service))
+        .layer(RejectionLayer::new(move |rejected: RejectedRequest| {
+            warn!(
+                "Rejected {} request to {} from {:?}: {}",
+                rejected.rejection.as_str(),
+                rejected.method,
+                rejected.peer,
+                rejected.message
+            );
+            rejections_metrics.record_rejected_request(rejected.rejection);
+        }))
+        .add_service(cache_server)
         .serve(config.listen_addr);
 
     // Join router
     let mut router_client =
         RouterClient::connect(config.router_addr.parse::<tonic::transport::Uri>()?).await?;
-    if let Err(e) = router_client
+    match router_client
         .join(milena_protos::router_server::JoinRequest {
             address: config.listen_addr.to_string(),
+            group: config.node_group.clone(),
         })
         .await
     {
-        warn!("Failed to join router: {}", e);
+        Ok(_) => joined_router.store(true, Ordering::SeqCst),
+        Err(e) => warn!("Failed to join router: {}", e),
+    }
+
+    // Tell the router when the disk fills up so it sends writes elsewhere
+    if let Some(limit) = config.disk_full_bytes {
+        let operation = operation.clone();
+        let mut router_client = router_client.clone();
+        let address = config.listen_addr.to_string();
+        let shutdown = shutdown.clone();
+        background_tasks.push(tokio::spawn(async move {
+            let mut gate = WriteGate::new(limit);
+            // Nodes join writable; a failed report is retried on the next tick.
+            let mut reported = true;
+            let mut ticker = tokio::time::interval(DISK_FULL_CHECK_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => return,
+                    _ = ticker.tick() => {}
+                }
+                let used = operation.disk_size_bytes();
+                let writable = gate.update(used);
+                if writable == reported {
+                    continue;
+                }
+                if writable {
+                    info!("Disk tier down to {} bytes, accepting writes again", used);
+                } else {
+                    warn!(
+                        "Disk tier at {} bytes, asking router to send writes elsewhere",
+                        used
+                    );
+                }
+                if let Err(e) = router_client
+                    .set_node_writable(milena_protos::router_server::SetNodeWritableRequest {
+                        address: address.clone(),
+                        writable,
+                    })
+                    .await
+                {
+                    warn!("Failed to report writable state to router: {}", e);
+                    continue;
+                }
+                reported = writable;
+            }
+        }));
     }
 
     // Wait for shutdown signal
     tokio::select! {
-        _ = shutdown_rx => {
+        _ = shutdown.cancelled() => {
             info!("Shutting down...");
         }
         _ = grpc_server => {
@@ -118,5 +568,63 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    // Also reached when a server exits on its own, so stop the background
+    // tasks explicitly before waiting for them.
+    shutdown.cancel();
+    for task in background_tasks {
+        if let Err(e) = task.await {
+            error!("Background task failed: {}", e);
+        }
+    }
+
+    // Latency-first writes still only in memory would be lost on exit
+    match operation.persist_pending().await {
+        Ok(0) => {}
+        Ok(persisted) => info!("Persisted {} pending writes before exit", persisted),
+        Err(e) => error!("Failed to persist pending writes before exit: {}", e),
+    }
+
     Ok(())
 }
+
+// Gathers and encodes the Prometheus registry on a blocking thread. A failed,
+// slow or oversized scrape is answered with a 500 rather than panicking or
+// holding the connection open.
+async fn scrape_metrics(
+    metrics: Arc<Metrics>,
+    timeout: Duration,
+    max_bytes: usize,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let encode = tokio::task::spawn_blocking(move || {
+        let families = metrics
+            .prometheus()
+            .map(|exporter| exporter.gather())
+            .unwrap_or_default();
+        let mut buffer = Vec::new();
+        prometheus::TextEncoder::new()
+            .encode(&families, &mut buffer)
+            .map(|()| buffer)
+    });
+    let error = match tokio::time::timeout(timeout, encode).await {
+        Ok(Ok(Ok(buffer))) if buffer.len() <= max_bytes => {
+            return Ok(Box::new(warp::reply::with_header(
+                buffer,
+                "Content-Type",
+                "text/plain; version=0.0.4; charset=utf-8",
+            )));
+        }
+        Ok(Ok(Ok(buffer))) => format!(
+            "Metrics response of {} bytes exceeds the {} byte limit",
+            buffer.len(),
+            max_bytes
+        ),
+        Ok(Ok(Err(e))) => format!("Failed to encode metrics: {}", e),
+        Ok(Err(e)) => format!("Metrics scrape failed: {}", e),
+        Err(_) => format!("Metrics scrape timed out after {:?}", timeout),
+    };
+    warn!("{}", error);
+    Ok(Box::new(warp::reply::with_status(
+        error,
+        StatusCode::INTERNAL_SERVER_ERROR,
+    )))
+}
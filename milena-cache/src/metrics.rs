@@ -1,49 +1,681 @@
-use prometheus::{Counter, Histogram, IntCounter, Registry};
-use std::sync::Arc;
+use milena_protos::rejections::Rejection;
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-#[derive(Clone)]
-pub struct Metrics {
-    pub registry: Arc<Registry>,
-    pub request_counter: Counter,
-    pub error_counter: Counter,
-    pub operation_duration: Histogram,
-    pub cache_hits: IntCounter,
-    pub cache_misses: IntCounter,
+const DEFAULT_HIT_RATIO_WINDOW: Duration = Duration::from_secs(300);
+
+const REQUESTS: &str = "cache_requests_total";
+const ERRORS: &str = "cache_errors_total";
+const OPERATION_DURATION: &str = "cache_operation_duration_seconds";
+const TIER_LOCK_HOLD: &str = "cache_tier_lock_hold_seconds";
+const HITS: &str = "cache_hits_total";
+const MISSES: &str = "cache_misses_total";
+const TIER_LOOKUPS: &str = "cache_tier_lookups_total";
+const HIT_RATIO: &str = "cache_hit_ratio";
+const DISK_CORRUPTIONS: &str = "cache_disk_corruptions_total";
+const DISK_BYTES_RECLAIMED: &str = "cache_disk_bytes_reclaimed_total";
+const PREFETCH_HITS: &str = "cache_prefetch_hits_total";
+const PREFETCH_WASTED: &str = "cache_prefetch_wasted_total";
+const TIER_TIMEOUTS: &str = "cache_tier_timeouts_total";
+const KEY_SHARD_SKEW: &str = "cache_key_shard_skew";
+const STALE_SERVED: &str = "cache_stale_served_total";
+const RECONCILED: &str = "cache_reconciled_keys_total";
+const TIER_SKIPPED_VALUES: &str = "cache_tier_skipped_values_total";
+const READ_AFTER_WRITE_RETRIES: &str = "cache_read_after_write_retries_total";
+const MEMORY_IDLE_EVICTIONS: &str = "cache_memory_idle_evictions_total";
+const KEYS_MIGRATED: &str = "cache_keys_migrated_total";
+const MEMORY_LOGICAL_BYTES: &str = "cache_memory_logical_bytes";
+const MEMORY_STORED_BYTES: &str = "cache_memory_stored_bytes";
+const BUCKET_DISK_BYTES: &str = "cache_bucket_disk_bytes";
+const QUOTA_REJECTIONS: &str = "cache_quota_rejections_total";
+const QUOTA_BYTES_RECLAIMED: &str = "cache_quota_bytes_reclaimed_total";
+const WATCHERS: &str = "cache_watchers";
+const BUCKET_IN_FLIGHT: &str = "cache_bucket_in_flight_requests";
+const WATCHERS_DROPPED: &str = "cache_watchers_dropped_total";
+const IDEMPOTENT_REPLAYS: &str = "cache_idempotent_replays_total";
+const PARTIAL_WRITES: &str = "cache_partial_writes_total";
+const COMBINED_WRITES: &str = "cache_combined_writes_total";
+const REJECTED_REQUESTS: &str = "cache_rejected_requests_total";
+const BUILD_INFO: &str = "cache_build_info";
+
+// Per-operation metrics carry one of the fixed `Op` names.
+const OPERATION_LABELS: &[&str] = &["operation"];
+
+// Name, help and label names of every counter.
+const COUNTERS: &[(&str, &str, &[&str])] = &[
+    (REQUESTS, "Total number of cache requests", OPERATION_LABELS),
+    (ERRORS, "Total number of cache errors", &[]),
+    (HITS, "Total number of cache hits", &[]),
+    (MISSES, "Total number of cache misses", &[]),
+    (
+        TIER_LOOKUPS,
+        "Total number of reads by the tier that answered them, or miss",
+        &["tier"],
+    ),
+    (
+        DISK_CORRUPTIONS,
+        "Total number of disk entries that failed checksum verification",
+        &[],
+    ),
+    (
+        DISK_BYTES_RECLAIMED,
+        "Total bytes evicted from the disk tier to stay within its size budget",
+        &[],
+    ),
+    (
+        PREFETCH_HITS,
+        "Total number of prefetched keys that were later read",
+        &[],
+    ),
+    (
+        PREFETCH_WASTED,
+        "Total number of prefetched keys dropped from tracking without being read",
+        &[],
+    ),
+    (
+        TIER_TIMEOUTS,
+        "Total number of reads that timed out and skipped a tier",
+        &["tier"],
+    ),
+    (
+        STALE_SERVED,
+        "Total number of expired entries served because S3 could not be read",
+        &[],
+    ),
+    (
+        RECONCILED,
+        "Total number of keys checked against S3 by the reconciler, by outcome",
+        &["outcome"],
+    ),
+    (
+        TIER_SKIPPED_VALUES,
+        "Total number of values not stored in a tier for exceeding its value size limit",
+        &["tier"],
+    ),
+    (
+        READ_AFTER_WRITE_RETRIES,
+        "Total number of S3 misses on recently written keys that were retried, by outcome",
+        &["outcome"],
+    ),
+    (
+        MEMORY_IDLE_EVICTIONS,
+        "Total number of in-memory entries evicted for going unused past the idle threshold",
+        &[],
+    ),
+    (
+        KEYS_MIGRATED,
+        "Total number of S3 objects found under the previous key salt and moved to the current one",
+        &[],
+    ),
+    (
+        QUOTA_REJECTIONS,
+        "Total number of puts rejected for exceeding their bucket's disk quota",
+        &["bucket"],
+    ),
+    (
+        QUOTA_BYTES_RECLAIMED,
+        "Total bytes evicted from the disk tier to make room within a bucket's quota",
+        &["bucket"],
+    ),
+    (
+        WATCHERS_DROPPED,
+        "Total number of key watches ended for falling too far behind",
+        &[],
+    ),
+    (
+        PARTIAL_WRITES,
+        "Total number of puts a tier rejected after another had accepted the value",
+        &["tier"],
+    ),
+    (
+        IDEMPOTENT_REPLAYS,
+        "Total number of retried writes answered from an earlier outcome",
+        OPERATION_LABELS,
+    ),
+    (
+        COMBINED_WRITES,
+        "Total number of queued puts replaced by a later put before reaching S3",
+        &[],
+    ),
+    (
+        REJECTED_REQUESTS,
+        "Total number of gRPC requests rejected before reaching a handler, by reason",
+        &["reason"],
+    ),
+];
+
+// Name, help and label names of every gauge.
+const GAUGES: &[(&str, &str, &[&str])] = &[
+    (
+        HIT_RATIO,
+        "Ratio of GET hits to all GETs over the configured sliding window",
+        &[],
+    ),
+    (
+        KEY_SHARD_SKEW,
+        "Busiest key shard's share of sampled keys relative to the mean",
+        &[],
+    ),
+    (
+        MEMORY_LOGICAL_BYTES,
+        "Bytes of values held in the in-memory LRU, before compression",
+        &[],
+    ),
+    (
+        MEMORY_STORED_BYTES,
+        "Bytes the in-memory LRU stores for its values, after compression",
+        &[],
+    ),
+    (
+        BUCKET_DISK_BYTES,
+        "Estimated bytes the disk tier holds for each bucket",
+        &["bucket"],
+    ),
+    (WATCHERS, "Number of open key watches", &[]),
+    (
+        BUCKET_IN_FLIGHT,
+        "Gets, puts and deletes running for each bucket, when per-bucket limits are on",
+        &["bucket"],
+    ),
+];
+
+// Name, help, label names and buckets of every histogram.
+const HISTOGRAMS: &[(&str, &str, &[&str], &[f64])] = &[
+    (
+        OPERATION_DURATION,
+        "Duration of cache operations",
+        OPERATION_LABELS,
+        &[0.001, 0.01, 0.1, 0.5, 1.0, 2.0, 5.0],
+    ),
+    (
+        TIER_LOCK_HOLD,
+        "Time spent in each tier's calls while holding a key lock",
+        &["tier", "operation"],
+        &[0.0001, 0.001, 0.01, 0.1, 0.5, 1.0, 5.0],
+    ),
+];
+
+/// Cache operation a request or latency sample is labelled with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Op {
+    Get,
+    Put,
+    Delete,
 }
 
-impl Metrics {
+impl Op {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Op::Get => "get",
+            Op::Put => "put",
+            Op::Delete => "delete",
+        }
+    }
+}
+
+/// Where metrics are sent.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExporterKind {
+    /// Served for scraping on `/metrics`.
+    #[default]
+    Prometheus,
+    /// Pushed over UDP in DogStatsD format; requires the `statsd` feature.
+    Statsd,
+}
+
+/// Backend that metric updates are recorded into. Label values are given in
+/// the order of the metric's declared label names.
+pub trait MetricExporter: Send + Sync {
+    fn record_counter(&self, name: &'static str, labels: &[&str], value: u64);
+    fn observe_histogram(&self, name: &'static str, labels: &[&str], value: f64);
+    fn set_gauge(&self, name: &'static str, labels: &[&str], value: f64);
+}
+
+/// Records into a Prometheus registry for the `/metrics` endpoint.
+pub struct PrometheusExporter {
+    registry: Registry,
+    counters: HashMap<&'static str, IntCounterVec>,
+    histograms: HashMap<&'static str, HistogramVec>,
+    gauges: HashMap<&'static str, GaugeVec>,
+}
+
+impl PrometheusExporter {
     pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
         let registry = Registry::new();
 
-        let request_counter =
-            Counter::new("cache_requests_total", "Total number of cache requests")?;
-        registry.register(Box::new(request_counter.clone()))?;
+        let mut counters = HashMap::new();
+        for &(name, help, labels) in COUNTERS {
+            let counter = IntCounterVec::new(Opts::new(name, help), labels)?;
+            registry.register(Box::new(counter.clone()))?;
+            counters.insert(name, counter);
+        }
 
-        let error_counter = Counter::new("cache_errors_total", "Total number of cache errors")?;
-        registry.register(Box::new(error_counter.clone()))?;
+        let mut histograms = HashMap::new();
+        for &(name, help, labels, buckets) in HISTOGRAMS {
+            let histogram = HistogramVec::new(
+                HistogramOpts::new(name, help).buckets(buckets.to_vec()),
+                labels,
+            )?;
+            registry.register(Box::new(histogram.clone()))?;
+            histograms.insert(name, histogram);
+        }
 
-        let operation_duration = Histogram::with_opts(
-            prometheus::HistogramOpts::new(
-                "cache_operation_duration_seconds",
-                "Duration of cache operations",
-            )
-            .buckets(vec![0.001, 0.01, 0.1, 0.5, 1.0, 2.0, 5.0]),
+        let mut gauges = HashMap::new();
+        for &(name, help, labels) in GAUGES {
+            let gauge = GaugeVec::new(Opts::new(name, help), labels)?;
+            registry.register(Box::new(gauge.clone()))?;
+            gauges.insert(name, gauge);
+        }
+
+        let build_info = IntGaugeVec::new(
+            Opts::new(
+                BUILD_INFO,
+                "Always 1, labelled with the version, commit and compiler of this build",
+            ),
+            &["version", "git_sha", "rustc_version"],
         )?;
-        registry.register(Box::new(operation_duration.clone()))?;
+        build_info
+            .with_label_values(&[
+                env!("CARGO_PKG_VERSION"),
+                env!("MILENA_GIT_SHA"),
+                env!("MILENA_RUSTC_VERSION"),
+            ])
+            .set(1);
+        registry.register(Box::new(build_info))?;
+
+        Ok(Self {
+            registry,
+            counters,
+            histograms,
+            gauges,
+        })
+    }
+
+    pub fn gather(&self) -> Vec<MetricFamily> {
+        self.registry.gather()
+    }
+}
+
+impl MetricExporter for PrometheusExporter {
+    fn record_counter(&self, name: &'static str, labels: &[&str], value: u64) {
+        if let Some(counter) = self.counters.get(name) {
+            counter.with_label_values(labels).inc_by(value);
+        }
+    }
 
-        let cache_hits = IntCounter::new("cache_hits_total", "Total number of cache hits")?;
-        registry.register(Box::new(cache_hits.clone()))?;
+    fn observe_histogram(&self, name: &'static str, labels: &[&str], value: f64) {
+        if let Some(histogram) = self.histograms.get(name) {
+            histogram.with_label_values(labels).observe(value);
+        }
+    }
 
-        let cache_misses = IntCounter::new("cache_misses_total", "Total number of cache misses")?;
-        registry.register(Box::new(cache_misses.clone()))?;
+    fn set_gauge(&self, name: &'static str, labels: &[&str], value: f64) {
+        if let Some(gauge) = self.gauges.get(name) {
+            gauge.with_label_values(labels).set(value);
+        }
+    }
+}
 
+/// Pushes each update as a DogStatsD datagram, with labels sent as tags.
+#[cfg(feature = "statsd")]
+pub struct StatsdExporter {
+    socket: std::net::UdpSocket,
+}
+
+#[cfg(feature = "statsd")]
+impl StatsdExporter {
+    pub fn new(addr: &str) -> std::io::Result<Self> {
+        let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket })
+    }
+
+    fn send(&self, name: &str, value: String, kind: &str, tags: String) {
+        // Metrics are best effort; a dropped datagram must never fail a request.
+        let _ = self
+            .socket
+            .send(format!("{name}:{value}|{kind}{tags}").as_bytes());
+    }
+}
+
+#[cfg(feature = "statsd")]
+impl MetricExporter for StatsdExporter {
+    fn record_counter(&self, name: &'static str, labels: &[&str], value: u64) {
+        let names = COUNTERS
+            .iter()
+            .find(|(counter, _, _)| *counter == name)
+            .map_or(&[][..], |(_, _, names)| *names);
+        self.send(name, value.to_string(), "c", statsd_tags(names, labels));
+    }
+
+    fn observe_histogram(&self, name: &'static str, labels: &[&str], value: f64) {
+        let names = HISTOGRAMS
+            .iter()
+            .find(|(histogram, _, _, _)| *histogram == name)
+            .map_or(&[][..], |(_, _, names, _)| *names);
+        self.send(name, value.to_string(), "h", statsd_tags(names, labels));
+    }
+
+    fn set_gauge(&self, name: &'static str, labels: &[&str], value: f64) {
+        let names = GAUGES
+            .iter()
+            .find(|(gauge, _, _)| *gauge == name)
+            .map_or(&[][..], |(_, _, names)| *names);
+        self.send(name, value.to_string(), "g", statsd_tags(names, labels));
+    }
+}
+
+#[cfg(feature = "statsd")]
+fn statsd_tags(names: &[&str], labels: &[&str]) -> String {
+    let tags: Vec<String> = names
+        .iter()
+        .zip(labels)
+        .map(|(name, value)| format!("{name}:{value}"))
+        .collect();
+    if tags.is_empty() {
+        String::new()
+    } else {
+        format!("|#{}", tags.join(","))
+    }
+}
+
+#[derive(Clone)]
+pub struct Metrics {
+    exporter: Arc<dyn MetricExporter>,
+    prometheus: Option<Arc<PrometheusExporter>>,
+    hit_ratio_window: Arc<Mutex<HitRatioWindow>>,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let prometheus = Arc::new(PrometheusExporter::new()?);
         Ok(Self {
-            registry: Arc::new(registry),
-            request_counter,
-            error_counter,
-            operation_duration,
-            cache_hits,
-            cache_misses,
+            exporter: prometheus.clone(),
+            prometheus: Some(prometheus),
+            hit_ratio_window: Arc::new(Mutex::new(HitRatioWindow::new(DEFAULT_HIT_RATIO_WINDOW))),
         })
     }
+
+    #[cfg(feature = "statsd")]
+    pub fn with_exporter(exporter: Arc<dyn MetricExporter>) -> Self {
+        Self {
+            exporter,
+            prometheus: None,
+            hit_ratio_window: Arc::new(Mutex::new(HitRatioWindow::new(DEFAULT_HIT_RATIO_WINDOW))),
+        }
+    }
+
+    pub fn with_hit_ratio_window(self, window: Duration) -> Self {
+        *self.hit_ratio_window.lock().unwrap() = HitRatioWindow::new(window);
+        self
+    }
+
+    /// The Prometheus exporter, when metrics are served for scraping.
+    pub fn prometheus(&self) -> Option<&PrometheusExporter> {
+        self.prometheus.as_deref()
+    }
+
+    pub fn record_request(&self, op: Op) {
+        self.exporter.record_counter(REQUESTS, &[op.as_str()], 1);
+    }
+
+    pub fn record_error(&self) {
+        self.exporter.record_counter(ERRORS, &[], 1);
+    }
+
+    pub fn record_duration(&self, op: Op, duration: Duration) {
+        self.exporter
+            .observe_histogram(OPERATION_DURATION, &[op.as_str()], duration.as_secs_f64());
+    }
+
+    pub fn record_tier_lock_hold(&self, tier: &str, op: Op, duration: Duration) {
+        self.exporter.observe_histogram(
+            TIER_LOCK_HOLD,
+            &[tier, op.as_str()],
+            duration.as_secs_f64(),
+        );
+    }
+
+    /// Counts a GET as a hit or miss and refreshes the sliding-window hit ratio.
+    pub fn record_get(&self, hit: bool) {
+        self.exporter
+            .record_counter(if hit { HITS } else { MISSES }, &[], 1);
+        let ratio = self.hit_ratio_window.lock().unwrap().record(hit);
+        self.exporter.set_gauge(HIT_RATIO, &[], ratio);
+    }
+
+    /// Counts a read by the tier that answered it, or `miss` when none did.
+    pub fn record_tier_lookup(&self, tier: &str) {
+        self.exporter.record_counter(TIER_LOOKUPS, &[tier], 1);
+    }
+
+    /// Hits and misses within the hit ratio window.
+    pub fn recent_gets(&self) -> (u64, u64) {
+        self.hit_ratio_window.lock().unwrap().counts()
+    }
+
+    pub fn record_disk_corruption(&self) {
+        self.exporter.record_counter(DISK_CORRUPTIONS, &[], 1);
+    }
+
+    pub fn record_disk_bytes_reclaimed(&self, bytes: u64) {
+        self.exporter
+            .record_counter(DISK_BYTES_RECLAIMED, &[], bytes);
+    }
+
+    pub fn record_memory_idle_evictions(&self, count: u64) {
+        self.exporter
+            .record_counter(MEMORY_IDLE_EVICTIONS, &[], count);
+    }
+
+    pub fn record_key_migrated(&self) {
+        self.exporter.record_counter(KEYS_MIGRATED, &[], 1);
+    }
+
+    pub fn record_prefetch_hit(&self) {
+        self.exporter.record_counter(PREFETCH_HITS, &[], 1);
+    }
+
+    pub fn record_prefetch_wasted(&self) {
+        self.exporter.record_counter(PREFETCH_WASTED, &[], 1);
+    }
+
+    pub fn record_tier_timeout(&self, tier: &str) {
+        self.exporter.record_counter(TIER_TIMEOUTS, &[tier], 1);
+    }
+
+    pub fn record_tier_skip(&self, tier: &str) {
+        self.exporter
+            .record_counter(TIER_SKIPPED_VALUES, &[tier], 1);
+    }
+
+    pub fn record_read_after_write_retry(&self, outcome: &str) {
+        self.exporter
+            .record_counter(READ_AFTER_WRITE_RETRIES, &[outcome], 1);
+    }
+
+    pub fn record_stale_served(&self) {
+        self.exporter.record_counter(STALE_SERVED, &[], 1);
+    }
+
+    pub fn record_reconciled(&self, outcome: &str) {
+        self.exporter.record_counter(RECONCILED, &[outcome], 1);
+    }
+
+    pub fn record_key_shard_skew(&self, skew: f64) {
+        self.exporter.set_gauge(KEY_SHARD_SKEW, &[], skew);
+    }
+
+    pub fn record_memory_bytes(&self, logical: u64, stored: u64) {
+        self.exporter
+            .set_gauge(MEMORY_LOGICAL_BYTES, &[], logical as f64);
+        self.exporter
+            .set_gauge(MEMORY_STORED_BYTES, &[], stored as f64);
+    }
+
+    pub fn record_bucket_disk_bytes(&self, bucket: &str, bytes: u64) {
+        self.exporter
+            .set_gauge(BUCKET_DISK_BYTES, &[bucket], bytes as f64);
+    }
+
+    pub fn record_bucket_in_flight(&self, bucket: &str, in_flight: u64) {
+        self.exporter
+            .set_gauge(BUCKET_IN_FLIGHT, &[bucket], in_flight as f64);
+    }
+
+    pub fn record_watchers(&self, count: usize) {
+        self.exporter.set_gauge(WATCHERS, &[], count as f64);
+    }
+
+    pub fn record_watcher_dropped(&self) {
+        self.exporter.record_counter(WATCHERS_DROPPED, &[], 1);
+    }
+
+    pub fn record_partial_write(&self, tier: &str) {
+        self.exporter.record_counter(PARTIAL_WRITES, &[tier], 1);
+    }
+
+    pub fn record_combined_write(&self) {
+        self.exporter.record_counter(COMBINED_WRITES, &[], 1);
+    }
+
+    pub fn record_rejected_request(&self, rejection: Rejection) {
+        self.exporter
+            .record_counter(REJECTED_REQUESTS, &[rejection.as_str()], 1);
+    }
+
+    pub fn record_idempotent_replay(&self, op: Op) {
+        self.exporter
+            .record_counter(IDEMPOTENT_REPLAYS, &[op.as_str()], 1);
+    }
+
+    pub fn record_quota_rejection(&self, bucket: &str) {
+        self.exporter.record_counter(QUOTA_REJECTIONS, &[bucket], 1);
+    }
+
+    pub fn record_quota_bytes_reclaimed(&self, bucket: &str, bytes: u64) {
+        self.exporter
+            .record_counter(QUOTA_BYTES_RECLAIMED, &[bucket], bytes);
+    }
+}
+
+/// Hit and miss counts in one-second buckets covering a sliding window.
+struct HitRatioWindow {
+    start: Instant,
+    window_secs: u64,
+    buckets: VecDeque<(u64, u64, u64)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl HitRatioWindow {
+    fn new(window: Duration) -> Self {
+        Self {
+            start: Instant::now(),
+            window_secs: window.as_secs().max(1),
+            buckets: VecDeque::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn record(&mut self, hit: bool) -> f64 {
+        let now = self.start.elapsed().as_secs();
+        self.record_at(now, hit)
+    }
+
+    fn counts(&mut self) -> (u64, u64) {
+        self.expire(self.start.elapsed().as_secs());
+        (self.hits, self.misses)
+    }
+
+    fn expire(&mut self, now: u64) {
+        while let Some(&(second, hits, misses)) = self.buckets.front() {
+            if second + self.window_secs > now {
+                break;
+            }
+            self.hits -= hits;
+            self.misses -= misses;
+            self.buckets.pop_front();
+        }
+    }
+
+    fn record_at(&mut self, now: u64, hit: bool) -> f64 {
+        self.expire(now);
+        if self
+            .buckets
+            .back()
+            .is_none_or(|&(second, _, _)| second != now)
+        {
+            self.buckets.push_back((now, 0, 0));
+        }
+        let bucket = self.buckets.back_mut().unwrap();
+        if hit {
+            bucket.1 += 1;
+            self.hits += 1;
+        } else {
+            bucket.2 += 1;
+            self.misses += 1;
+        }
+
+        self.hits as f64 / (self.hits + self.misses) as f64
+    }
+}
+
+#[test]
+fn test_hit_ratio_window_slides() {
+    let mut window = HitRatioWindow::new(Duration::from_secs(10));
+    assert_eq!(window.record_at(0, true), 1.0);
+    assert_eq!(window.record_at(5, false), 0.5);
+    // The hit at second 0 has left the window by second 10.
+    assert_eq!(window.record_at(10, false), 0.0);
+    assert_eq!(window.record_at(12, true), 1.0 / 3.0);
+    // Counts also slide when nothing is recorded
+    window.expire(21);
+    assert_eq!((window.hits, window.misses), (1, 0));
+}
+
+#[test]
+fn test_operation_metrics_are_labelled() {
+    let metrics = Metrics::new().unwrap();
+    metrics.record_request(Op::Put);
+    metrics.record_duration(Op::Put, Duration::from_millis(20));
+
+    let families = metrics.prometheus().unwrap().gather();
+    let durations = families
+        .iter()
+        .find(|family| family.get_name() == OPERATION_DURATION)
+        .unwrap();
+    let labels: Vec<&str> = durations
+        .get_metric()
+        .iter()
+        .map(|metric| metric.get_label()[0].get_value())
+        .collect();
+    assert_eq!(labels, vec!["put"]);
+}
+
+#[test]
+fn test_build_info_is_exported() {
+    let metrics = Metrics::new().unwrap();
+    let families = metrics.prometheus().unwrap().gather();
+    let build_info = families
+        .iter()
+        .find(|family| family.get_name() == BUILD_INFO)
+        .unwrap();
+    let metric = &build_info.get_metric()[0];
+    assert_eq!(metric.get_gauge().get_value(), 1.0);
+    let version = metric
+        .get_label()
+        .iter()
+        .find(|label| label.get_name() == "version")
+        .unwrap();
+    assert_eq!(version.get_value(), env!("CARGO_PKG_VERSION"));
 }
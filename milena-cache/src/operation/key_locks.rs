@@ -0,0 +1,38 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::store::Key;
+
+/// Number of lock stripes keys are hashed onto.
+const STRIPES: usize = 1024;
+
+/// Serializes operations on the same key while other keys proceed
+/// concurrently. Keys share a fixed set of striped locks, so memory stays
+/// bounded however many keys are seen; the cost is that two keys hashing to
+/// the same stripe occasionally wait for each other.
+pub struct KeyLocks {
+    stripes: Vec<Mutex<()>>,
+}
+
+impl Default for KeyLocks {
+    fn default() -> Self {
+        KeyLocks {
+            stripes: (0..STRIPES).map(|_| Mutex::new(())).collect(),
+        }
+    }
+}
+
+impl KeyLocks {
+    /// Waits for every other holder of `key` to finish. The key stays locked
+    /// until the guard is dropped, including when the holding future is
+    /// cancelled.
+    pub async fn lock(&self, bucket: &str, key: &Key) -> MutexGuard<'_, ()> {
+        let mut hasher = DefaultHasher::new();
+        (bucket, &key.0).hash(&mut hasher);
+        self.stripes[hasher.finish() as usize % STRIPES]
+            .lock()
+            .await
+    }
+}
@@ -1,175 +1,3191 @@
-use std::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use aws_sdk_s3::Client;
-use rocksdb::Options;
+use lru::LruCache;
+use serde::Deserialize;
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
 
-use crate::store::{DiskStore, Key, LRUStore, S3Store, Store, Value};
+use crate::chaos::{Chaos, OperationKind};
+use crate::clock::{Clock, SystemClock};
+use crate::metrics::{Metrics, Op};
+use crate::store::{
+    CacheKey, DiskOptions, DiskStore, Entry, Key, LRUStore, MemoryCompression, S3Options, S3Store,
+    Store, StoreError, Value, ValueCompression,
+};
+use crate::ttl::Ttl;
+use crate::watch::Watchers;
 
+mod key_locks;
+
+use key_locks::KeyLocks;
+
+/// Latency-first puts beyond this many unpersisted keys are written through
+/// synchronously, so an S3 outage cannot grow the backlog without bound.
+const MAX_PENDING_WRITES: usize = 10_000;
+
+/// Keys remembered as recently written to S3, for `ReadAfterWriteRetry` and
+/// write combining.
+const RECENT_WRITES: usize = 10_000;
+
+/// Outcome of a put/get/delete round trip against a single tier.
+#[derive(Debug)]
+pub struct TierCheck {
+    pub tier: &'static str,
+    pub successful: bool,
+    pub duration: Duration,
+    pub error: Option<String>,
+}
+
+/// Upper bounds on how long `get` waits for a tier before skipping it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TierTimeouts {
+    pub disk: Option<Duration>,
+    pub cloud: Option<Duration>,
+}
+
+/// Largest value, in bytes, each cache tier stores. Larger values skip the
+/// tier and are served from the next one; S3 takes anything under the
+/// request size limit.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TierValueLimits {
+    pub memory: Option<u64>,
+    pub disk: Option<u64>,
+}
+
+/// Retries S3 reads that find nothing for keys this node wrote to S3 within
+/// `window`, for backends where a new object can briefly read as missing.
+#[derive(Clone, Copy, Debug)]
+pub struct ReadAfterWriteRetry {
+    pub attempts: u32,
+    pub delay: Duration,
+    pub window: Duration,
+}
+
+/// A put refused because its bucket would hold more than its quota on disk,
+/// even after evicting the bucket's least recently used entries.
+#[derive(Debug, Error)]
+#[error("Bucket {bucket} would exceed its disk quota of {quota} bytes")]
+pub struct QuotaExceeded {
+    pub bucket: String,
+    pub quota: u64,
+}
+
+/// Order in which `put` writes the tiers.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteMode {
+    /// S3, then disk, then memory, all before the put returns. A successful
+    /// put survives the node failing; a failed put leaves no cached copy, so
+    /// reads fall back to whatever S3 holds.
+    #[default]
+    DurabilityFirst,
+    /// Disk, then memory, before the put returns; S3 is written later by
+    /// `persist_pending`. Reads on this node see the new value at once and it
+    /// survives a restart, though without an fsync, but it is lost if the
+    /// node's disk is, and other readers of the S3 bucket see the old value
+    /// until it is persisted. A write that later fails to persist is retried,
+    /// never reported to the client.
+    LatencyFirst,
+}
+
+/// What a put does when a tier rejects a value that S3 or disk, or the
+/// latency-first write queue, has already accepted. Either way the rejecting
+/// tier is cleared and later tiers are not written, so no tier serves a value
+/// another one rejected.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PartialWritePolicy {
+    /// Fail the put and remove the value from the tiers that accepted it,
+    /// leaving the key absent until it is written again. With local deletes
+    /// an S3 copy is left in place.
+    #[default]
+    Fail,
+    /// Succeed with a warning, leaving the value in the tiers that accepted
+    /// it; reads backfill the rest from them.
+    Warn,
+}
+
+/// What `reconcile` found when comparing a key's cached copies with S3.
+#[derive(Debug, PartialEq)]
+pub enum Reconciled {
+    /// Every cached copy matched S3.
+    Consistent,
+    /// A cached copy differed from S3 and was replaced by the S3 entry.
+    Repaired,
+    /// S3 no longer holds the key, so the cached copies were removed.
+    Evicted,
+    /// Not checked: nothing is cached, S3 is not the source of truth for the
+    /// bucket, or a latency-first write is still pending.
+    Skipped,
+}
+
+impl Reconciled {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Reconciled::Consistent => "consistent",
+            Reconciled::Repaired => "repaired",
+            Reconciled::Evicted => "evicted",
+            Reconciled::Skipped => "skipped",
+        }
+    }
+}
+
+/// A storage tier, such as the one that answered a read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tier {
+    Memory,
+    Disk,
+    Cloud,
+}
+
+impl Tier {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Tier::Memory => "memory",
+            Tier::Disk => "disk",
+            Tier::Cloud => "cloud",
+        }
+    }
+}
+
+/// An entry found by a read and the tier it came from, marked stale when it
+/// is an expired disk copy served because S3 could not be read.
+#[derive(Debug)]
+pub struct Lookup {
+    pub entry: Entry,
+    pub stale: bool,
+    pub tier: Tier,
+}
+
+/// A key's copies across every tier, probed under the key's lock so no write
+/// lands between probes.
+#[derive(Debug, PartialEq)]
+pub struct Inspection {
+    /// What a read would serve: the first copy from memory down to S3. Its
+    /// `written_at` doubles as the version.
+    pub entry: Option<Entry>,
+    pub in_memory: bool,
+    pub on_disk: bool,
+    pub in_cloud: bool,
+    /// Time before the disk copy expires; `None` without one.
+    pub ttl_remaining: Option<Duration>,
+}
+
+/// Shared by every request handler and background task. Operations on one key
+/// are serialized, so a key's tiers are never written by two operations at
+/// once, while operations on different keys run concurrently.
 pub struct Operation<I, O, C> {
     in_memory_store: I,
     on_disk_store: O,
     cloud_store: C,
+    /// The cloud tier under the previous key salt. S3 misses are looked up
+    /// there and moved to the current salt, so a salt change warms over
+    /// gradually instead of emptying the tier.
+    previous_cloud_store: Option<C>,
+    timeouts: TierTimeouts,
+    value_limits: TierValueLimits,
+    cloud_enabled: bool,
+    /// Buckets whose S3 objects are written by an external process; writes and
+    /// deletes leave S3 alone and only touch memory and disk.
+    s3_read_only_buckets: HashSet<String>,
+    /// Whether `delete` removes the S3 object as well as the cached copies.
+    delete_from_cloud: bool,
+    /// Whether `delete` asks S3 if it held the key, at the cost of a HEAD
+    /// request per delete. Otherwise only memory and disk decide `existed`.
+    delete_checks_cloud: bool,
+    /// Oldest expired disk entry that may be served when S3 reads fail.
+    max_stale: Option<Duration>,
+    /// Puts accepted per bucket since startup.
+    bucket_puts: Mutex<HashMap<String, u64>>,
+    /// Most bytes each listed bucket may hold on disk.
+    bucket_quotas: HashMap<String, u64>,
+    write_mode: WriteMode,
+    partial_write_policy: PartialWritePolicy,
+    /// Latency-first writes held in memory but not yet on disk or in S3.
+    pending_writes: Mutex<HashMap<(String, Vec<u8>), Entry>>,
+    key_locks: KeyLocks,
+    read_after_write: Option<ReadAfterWriteRetry>,
+    /// Puts to a key written to S3 less than this long ago are queued like
+    /// latency-first writes, coalescing bursts into one S3 write.
+    write_combine_window: Option<Duration>,
+    /// When each key was last written to S3; only kept with `read_after_write`
+    /// or `write_combine_window`.
+    recent_cloud_writes: Mutex<LruCache<(String, Vec<u8>), Instant>>,
+    chaos: Option<Arc<Chaos>>,
+    /// Whether time spent in each tier while holding a key lock is recorded.
+    time_tier_locks: bool,
+    /// Notified of every accepted put and of deletes that removed something.
+    watchers: Option<Arc<Watchers>>,
+    clock: Arc<dyn Clock>,
+    metrics: Arc<Metrics>,
 }
 
 impl<I: Store, O: Store, C: Store> Operation<I, O, C> {
-    pub fn simple_new(
-        in_memory_lru_capacity: u64,
-        disk_store_ttl: Duration,
-        client: Client,
-    ) -> Operation<LRUStore, DiskStore, S3Store> {
-        let in_memory_store = LRUStore::new(in_memory_lru_capacity);
-        let mut ops = Options::default();
-        // enable blobstore (key value separation)
-        ops.set_enable_blob_files(true);
-        // Blob files are kept until their total size exceeds this amount.
-        ops.set_blob_file_size(128 * 1024 * 1024); // 128 MB
-
-        // Minimum size of value to be stored in blob file. Values smaller than this threshold are stored inline.
-        ops.set_min_blob_size(1024); // 1KB
-
-        // If enable_blob_garbage_collection is true, then blob files are eligible to be
-        // garbage collected and compacted when their expiration time is reached.
-        ops.set_enable_blob_gc(true);
-
-        // Minimum ratio of live data size to total data size for a blob file to be considered for garbage collection.
-        ops.set_blob_gc_age_cutoff(0.5);
-        ops.create_if_missing(true);
-        let on_disk_store = DiskStore::new(&ops, disk_store_ttl, "./db");
-        let cloud_store = S3Store { client };
-
+    pub fn new(
+        in_memory_store: I,
+        on_disk_store: O,
+        cloud_store: C,
+        metrics: Arc<Metrics>,
+    ) -> Self {
         Operation {
             in_memory_store,
             on_disk_store,
             cloud_store,
+            previous_cloud_store: None,
+            timeouts: TierTimeouts::default(),
+            value_limits: TierValueLimits::default(),
+            cloud_enabled: true,
+            s3_read_only_buckets: HashSet::new(),
+            delete_from_cloud: true,
+            delete_checks_cloud: false,
+            max_stale: None,
+            bucket_puts: Mutex::new(HashMap::new()),
+            bucket_quotas: HashMap::new(),
+            write_mode: WriteMode::default(),
+            partial_write_policy: PartialWritePolicy::default(),
+            pending_writes: Mutex::new(HashMap::new()),
+            key_locks: KeyLocks::default(),
+            read_after_write: None,
+            write_combine_window: None,
+            recent_cloud_writes: Mutex::new(LruCache::new(
+                NonZeroUsize::new(RECENT_WRITES).unwrap(),
+            )),
+            chaos: None,
+            time_tier_locks: false,
+            watchers: None,
+            clock: Arc::new(SystemClock),
+            metrics,
+        }
+    }
+
+    /// Reads the time that entries are stamped with and that staleness and the
+    /// read-after-write and write combining windows are measured against. The
+    /// stores keep their own clocks, set when they are built.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: TierTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn with_value_limits(mut self, value_limits: TierValueLimits) -> Self {
+        self.value_limits = value_limits;
+        self
+    }
+
+    /// Degraded mode: every operation skips the cloud tier and is served by
+    /// memory and disk alone.
+    pub fn without_cloud(mut self) -> Self {
+        self.cloud_enabled = false;
+        self
+    }
+
+    pub fn with_s3_read_only_buckets(mut self, buckets: HashSet<String>) -> Self {
+        self.s3_read_only_buckets = buckets;
+        self
+    }
+
+    /// Makes `delete` a local purge: memory and disk are cleared and the S3
+    /// object is left in place.
+    pub fn with_local_deletes(mut self) -> Self {
+        self.delete_from_cloud = false;
+        self
+    }
+
+    /// Makes `delete` report keys that only S3 still holds as existing, by
+    /// checking S3 before removing them.
+    pub fn with_cloud_delete_checks(mut self) -> Self {
+        self.delete_checks_cloud = true;
+        self
+    }
+
+    /// Serves an expired disk entry written less than `max_stale` ago when
+    /// reading S3 fails, instead of failing the read.
+    pub fn with_max_stale(mut self, max_stale: Duration) -> Self {
+        self.max_stale = Some(max_stale);
+        self
+    }
+
+    /// Caps the disk bytes of each listed bucket. A put that would take its
+    /// bucket over first evicts the bucket's least recently used disk entries,
+    /// and fails with `QuotaExceeded` when that cannot make room. Values too
+    /// large for the disk tier never reach it and are not counted.
+    pub fn with_bucket_quotas(mut self, quotas: HashMap<String, u64>) -> Self {
+        self.bucket_quotas = quotas;
+        self
+    }
+
+    pub fn with_write_mode(mut self, write_mode: WriteMode) -> Self {
+        self.write_mode = write_mode;
+        self
+    }
+
+    pub fn with_partial_write_policy(mut self, policy: PartialWritePolicy) -> Self {
+        self.partial_write_policy = policy;
+        self
+    }
+
+    pub fn with_key_migration(mut self, previous_cloud_store: C) -> Self {
+        self.previous_cloud_store = Some(previous_cloud_store);
+        self
+    }
+
+    pub fn with_read_after_write_retry(mut self, retry: ReadAfterWriteRetry) -> Self {
+        self.read_after_write = Some(retry);
+        self
+    }
+
+    /// Queues puts to keys written to S3 less than `window` ago instead of
+    /// writing them through, so a burst of updates to one key reaches disk
+    /// and S3 as one write of the latest value while memory has each update
+    /// at once. `persist_pending` must run about every `window` to flush them.
+    pub fn with_write_combining(mut self, window: Duration) -> Self {
+        self.write_combine_window = Some(window);
+        self
+    }
+
+    fn writes_to_cloud(&self, bucket: &str) -> bool {
+        self.cloud_enabled && !self.s3_read_only_buckets.contains(bucket)
+    }
+
+    /// Staging only: injects the faults described by `chaos` into get, put and delete.
+    pub fn with_chaos(mut self, chaos: Arc<Chaos>) -> Self {
+        self.chaos = Some(chaos);
+        self
+    }
+
+    pub fn with_watchers(mut self, watchers: Arc<Watchers>) -> Self {
+        self.watchers = Some(watchers);
+        self
+    }
+
+    /// Records how long each tier's calls take while a key lock is held, to
+    /// show which tier keeps other operations on the same lock stripe waiting.
+    pub fn with_tier_lock_timing(mut self) -> Self {
+        self.time_tier_locks = true;
+        self
+    }
+
+    pub fn simple_new(
+        in_memory_lru_capacity: u64,
+        disk_store_ttl: Ttl,
+        disk: &DiskOptions,
+        client: Client,
+        s3: S3Options,
+        metrics: Arc<Metrics>,
+    ) -> std::result::Result<Operation<LRUStore, DiskStore, S3Store>, StoreError> {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let in_memory_store = LRUStore::new(in_memory_lru_capacity)
+            .with_metrics(metrics.clone())
+            .with_clock(clock.clone());
+        let on_disk_store = DiskStore::open(
+            &disk.rocksdb_options(),
+            disk_store_ttl,
+            &disk.path,
+            metrics.clone(),
+        )?
+        .with_clock(clock.clone());
+        let cloud_store = S3Store {
+            client,
+            key_salt: s3.key_salt,
+            shard_count: s3.shard_count,
+            storage_class: s3.storage_class,
+            clock: clock.clone(),
+            compression: ValueCompression::None,
+            compression_level: 0,
+        };
+
+        Ok(Operation::new(in_memory_store, on_disk_store, cloud_store, metrics).with_clock(clock))
+    }
+
+    #[cfg(test)]
+    pub async fn get(&self, bucket: &str, key: &Key) -> Result<Option<Entry>> {
+        Ok(self
+            .get_with_deadline(bucket, key, None)
+            .await?
+            .map(|lookup| lookup.entry))
+    }
+
+    /// Like `get`, but a disk read slower than its timeout is abandoned in favour
+    /// of S3, and the S3 read is bounded by both its own timeout and `deadline`.
+    pub async fn get_with_deadline(
+        &self,
+        bucket: &str,
+        key: &Key,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Lookup>> {
+        let drop_response = self.inject_chaos(OperationKind::Get).await?;
+        let _key_lock = self.key_locks.lock(bucket, key).await;
+        let result = self.read_tiers(&CacheKey::new(bucket, key), deadline).await;
+        if let Ok(lookup) = &result {
+            self.metrics
+                .record_tier_lookup(lookup.as_ref().map_or("miss", |l| l.tier.as_str()));
         }
+        dropped(result, drop_response)
     }
-    pub async fn get(&mut self, bucket: &str, key: &Key) -> Result<Option<Value>> {
+
+    async fn read_tiers(
+        &self,
+        key: &CacheKey<'_>,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Lookup>> {
         // Check in-memory store first
-        if let Some(data) = self.in_memory_store.get(bucket, key).await? {
-            return Ok(Some(data));
+        let memory = self.in_tier(Tier::Memory, Op::Get, self.in_memory_store.get(key));
+        if let Some(data) = memory.await? {
+            return Ok(Some(fresh(data, Tier::Memory)));
+        }
+
+        // A latency-first write evicted from memory before it was persisted
+        // is newer than anything on disk or in S3
+        if let Some(data) = self.pending_write(key) {
+            self.cache_in_memory(key, &data).await?;
+            return Ok(Some(fresh(data, Tier::Memory)));
         }
 
-        // Check on-disk store next
-        if let Some(data) = self.on_disk_store.get(bucket, key).await? {
-            // Store data in in-memory store before returning it
-            self.in_memory_store.put(bucket, key, &data).await?;
-            return Ok(Some(data));
+        // Check on-disk store next, keeping an expired copy in case S3 fails
+        let disk_read = with_timeout(self.timeouts.disk, async {
+            if self.max_stale.is_some() {
+                self.on_disk_store.get_including_expired(key).await
+            } else {
+                Ok(self.on_disk_store.get(key).await?.map(|e| (e, false)))
+            }
+        });
+        let disk_read = self.in_tier(Tier::Disk, Op::Get, disk_read).await;
+        let mut expired = None;
+        let disk_responsive = match disk_read {
+            Some(result) => {
+                match result? {
+                    Some((data, false)) => {
+                        // Store data in in-memory store before returning it
+                        self.cache_in_memory(key, &data).await?;
+                        return Ok(Some(fresh(data, Tier::Disk)));
+                    }
+                    Some((data, true)) => expired = Some(data),
+                    None => {}
+                }
+                true
+            }
+            None => {
+                warn!("Disk read timed out, falling through to S3");
+                self.metrics.record_tier_timeout("disk");
+                false
+            }
+        };
+
+        if !self.cloud_enabled {
+            return Ok(None);
         }
 
         // Check cloud store if data is not found in cache
-        if let Some(data) = self.cloud_store.get(bucket, key).await? {
+        let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        let cloud_timeout = match (self.timeouts.cloud, remaining) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let cloud_read = with_timeout(cloud_timeout, self.cloud_store.get(key));
+        let cloud_read = match self.in_tier(Tier::Cloud, Op::Get, cloud_read).await {
+            Some(result) => result.map_err(anyhow::Error::from),
+            None => {
+                self.metrics.record_tier_timeout("cloud");
+                Err(anyhow!("S3 read timed out"))
+            }
+        };
+        let data = match cloud_read {
+            Ok(None) => match self.retry_recent_miss(key, deadline).await {
+                Ok(data) => data,
+                Err(e) => return self.serve_stale(key.bucket, expired, e),
+            },
+            Ok(data) => data,
+            Err(e) => return self.serve_stale(key.bucket, expired, e),
+        };
+        let data = match data {
+            Some(data) => Some(data),
+            None => match self.migrate_key(key).await {
+                Ok(data) => data,
+                Err(e) => return self.serve_stale(key.bucket, expired, e),
+            },
+        };
+        if let Some(data) = data {
             // Store data in in-memory and on-disk stores before returning it
-            self.in_memory_store.put(bucket, key, &data).await?;
-            self.on_disk_store.put(bucket, key, &data).await?;
-            return Ok(Some(data));
+            self.cache_in_memory(key, &data).await?;
+            if disk_responsive {
+                self.cache_on_disk(key, &data, false).await?;
+            }
+            return Ok(Some(fresh(data, Tier::Cloud)));
         }
 
+        // The object is gone from S3, so the expired copy must never be served.
+        if expired.is_some() {
+            self.in_tier(Tier::Disk, Op::Delete, self.on_disk_store.delete(key))
+                .await?;
+        }
         Ok(None)
     }
 
-    pub async fn put(&mut self, bucket: &str, key: &Key, value: &Value) -> Result<()> {
-        self.cloud_store.put(bucket, key, value).await?;
-        self.on_disk_store.put(bucket, key, value).await?;
-        self.in_memory_store.put(bucket, key, value).await
+    // Moves `key` from the previous key salt to the current one. The object is
+    // written under the new salt before the old one is deleted, so a failure
+    // in between leaves a copy for the next read to find.
+    async fn migrate_key(&self, key: &CacheKey<'_>) -> Result<Option<Entry>> {
+        let Some(previous) = &self.previous_cloud_store else {
+            return Ok(None);
+        };
+        let Some(entry) = self
+            .in_tier(Tier::Cloud, Op::Get, previous.get(key))
+            .await?
+        else {
+            return Ok(None);
+        };
+        if self.writes_to_cloud(key.bucket) {
+            self.in_tier(Tier::Cloud, Op::Put, self.cloud_store.put(key, &entry))
+                .await?;
+            self.note_cloud_write(key);
+            self.in_tier(Tier::Cloud, Op::Delete, previous.delete(key))
+                .await?;
+            self.metrics.record_key_migrated();
+        }
+        Ok(Some(entry))
     }
 
-    pub async fn delete(&mut self, bucket: &str, key: &Key) -> Result<()> {
-        self.cloud_store.delete(bucket, key).await?;
-        self.on_disk_store.delete(bucket, key).await?;
-        self.in_memory_store.delete(bucket, key).await
+    fn serve_stale(
+        &self,
+        bucket: &str,
+        expired: Option<Entry>,
+        error: anyhow::Error,
+    ) -> Result<Option<Lookup>> {
+        match (expired, self.max_stale) {
+            (Some(entry), Some(max_stale)) if entry.age(&*self.clock) <= max_stale => {
+                warn!(
+                    "S3 read failed for bucket {} ({}), serving stale disk entry",
+                    bucket, error
+                );
+                self.metrics.record_stale_served();
+                Ok(Some(Lookup {
+                    entry,
+                    stale: true,
+                    tier: Tier::Disk,
+                }))
+            }
+            _ => Err(error),
+        }
     }
-}
 
-mod tests {
+    /// Loads `key` from S3 into disk and memory unless a faster tier already
+    /// holds it. Returns whether anything was loaded.
+    pub async fn prefetch(&self, bucket: &str, key: &Key) -> Result<bool> {
+        let _key_lock = self.key_locks.lock(bucket, key).await;
+        let key = CacheKey::new(bucket, key);
+        if self.pending_write(&key).is_some()
+            || self
+                .in_tier(Tier::Memory, Op::Get, self.in_memory_store.get(&key))
+                .await?
+                .is_some()
+            || self
+                .in_tier(Tier::Disk, Op::Get, self.on_disk_store.get(&key))
+                .await?
+                .is_some()
+        {
+            return Ok(false);
+        }
+        if !self.cloud_enabled {
+            return Ok(false);
+        }
 
-    use super::*;
-    use std::collections::HashMap;
-    use tonic::async_trait;
+        match self
+            .in_tier(Tier::Cloud, Op::Get, self.cloud_store.get(&key))
+            .await?
+        {
+            Some(data) => {
+                self.cache_on_disk(&key, &data, false).await?;
+                self.cache_in_memory(&key, &data).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
 
-    pub struct MockStore {
-        map: HashMap<Vec<u8>, Vec<u8>>,
+    /// Cancellation safe: the faster tiers are invalidated before S3 is written,
+    /// so if the future is dropped at any await point memory and disk hold either
+    /// nothing or the new value, and reads fall through to S3 and backfill.
+    /// In latency-first mode the write is queued for persistence before disk
+    /// and memory are updated, so a dropped put is still persisted.
+    ///
+    /// Every tier stores the same write timestamp, taken once here.
+    ///
+    /// For S3 read-only buckets the value is cached but never reaches S3, so it
+    /// lasts only until evicted or expired, after which reads see S3 again.
+    pub async fn put(&self, bucket: &str, key: &Key, value: &Value) -> Result<()> {
+        self.put_with_durability(bucket, key, value, false, None)
+            .await
     }
 
-    impl MockStore {
-        pub fn new() -> Self {
-            Self {
-                map: HashMap::new(),
+    /// A durable put is written through to every tier before returning, even
+    /// in latency-first mode, and its disk write is fsynced. That costs an
+    /// fsync on top of the usual write, typically milliseconds, so it is meant
+    /// for the few writes that need it.
+    ///
+    /// With a `ttl` the value expires that long after the write in every
+    /// tier, disk entries at whichever of it and the disk TTL ends first.
+    pub async fn put_with_durability(
+        &self,
+        bucket: &str,
+        key: &Key,
+        value: &Value,
+        durable: bool,
+        ttl: Option<Duration>,
+    ) -> Result<()> {
+        self.put_in_tiers(bucket, key, value, durable, ttl, true)
+            .await
+    }
+
+    /// Stores a replica copy sent by the router in memory and on disk only,
+    /// since the owner's put already wrote S3. It is written through in every
+    /// write mode, so nothing queued for persistence can reach S3 from here.
+    pub async fn put_replica(
+        &self,
+        bucket: &str,
+        key: &Key,
+        value: &Value,
+        durable: bool,
+        ttl: Option<Duration>,
+    ) -> Result<()> {
+        self.put_in_tiers(bucket, key, value, durable, ttl, false)
+            .await
+    }
+
+    async fn put_in_tiers(
+        &self,
+        bucket: &str,
+        key: &Key,
+        value: &Value,
+        durable: bool,
+        ttl: Option<Duration>,
+        cloud: bool,
+    ) -> Result<()> {
+        let drop_response = self.inject_chaos(OperationKind::Put).await?;
+        let _key_lock = self.key_locks.lock(bucket, key).await;
+        let mut entry = Entry::new(value.clone(), &*self.clock);
+        if let Some(ttl) = ttl {
+            entry = entry.with_ttl(ttl);
+        }
+        let result = self
+            .write_tiers(&CacheKey::new(bucket, key), &entry, durable, cloud)
+            .await;
+        if result.is_ok() {
+            *self
+                .bucket_puts
+                .lock()
+                .unwrap()
+                .entry(bucket.to_string())
+                .or_default() += 1;
+            // Still under the key lock, so watchers see changes in write order
+            if let Some(watchers) = &self.watchers {
+                watchers.publish(bucket, key, Some(entry));
             }
         }
+        dropped(result, drop_response)
     }
-    #[async_trait]
-    impl Store for MockStore {
-        async fn get(&mut self, _bucket: &str, key: &Key) -> Result<Option<Value>> {
-            Ok(self.map.get(&key.0).cloned().map(Value))
+
+    /// Whether reads and writes go to S3, which is false after starting in degraded mode.
+    pub fn cloud_enabled(&self) -> bool {
+        self.cloud_enabled
+    }
+
+    /// Buckets this node has accepted puts for, with the number of puts each.
+    pub fn bucket_puts(&self) -> HashMap<String, u64> {
+        self.bucket_puts.lock().unwrap().clone()
+    }
+
+    async fn write_tiers(
+        &self,
+        key: &CacheKey<'_>,
+        entry: &Entry,
+        durable: bool,
+        cloud: bool,
+    ) -> Result<()> {
+        self.check_quota(key, entry).await?;
+        if self.queue_write(key, entry, durable || !cloud) {
+            // Combined writes leave disk to the write-behind loop too
+            let mut accepted = Vec::new();
+            if self.write_mode == WriteMode::LatencyFirst {
+                if let Err(e) = self.cache_on_disk(key, entry, false).await {
+                    return self.partial_write(key, Tier::Disk, &[], e).await;
+                }
+                accepted.push(Tier::Disk);
+            }
+            return match self.cache_in_memory(key, entry).await {
+                Err(e) => self.partial_write(key, Tier::Memory, &accepted, e).await,
+                ok => ok,
+            };
         }
 
-        async fn put(&mut self, _bucket: &str, key: &Key, value: &Value) -> Result<()> {
-            self.map.insert(key.0.clone(), value.0.clone());
-            Ok(())
+        self.in_tier(Tier::Memory, Op::Delete, self.in_memory_store.delete(key))
+            .await?;
+        self.in_tier(Tier::Disk, Op::Delete, self.on_disk_store.delete(key))
+            .await?;
+        let mut accepted = Vec::new();
+        if cloud && self.writes_to_cloud(key.bucket) {
+            self.in_tier(Tier::Cloud, Op::Put, self.cloud_store.put(key, entry))
+                .await?;
+            self.note_cloud_write(key);
+            accepted.push(Tier::Cloud);
+        }
+        if let Err(e) = self.cache_on_disk(key, entry, durable).await {
+            return self.partial_write(key, Tier::Disk, &accepted, e).await;
+        }
+        accepted.push(Tier::Disk);
+        if let Err(e) = self.cache_in_memory(key, entry).await {
+            return self.partial_write(key, Tier::Memory, &accepted, e).await;
+        }
+        Ok(())
+    }
+
+    /// Handles `failed` rejecting a put that the `accepted` tiers, or the
+    /// write queue, already hold, as the partial write policy says.
+    async fn partial_write(
+        &self,
+        key: &CacheKey<'_>,
+        failed: Tier,
+        accepted: &[Tier],
+        error: anyhow::Error,
+    ) -> Result<()> {
+        // Whatever the failed write left behind must not be served
+        if let Err(e) = self.delete_from(failed, key).await {
+            warn!(
+                "Could not clear the {} tier after a failed put: {}",
+                failed.as_str(),
+                e
+            );
+        }
+        let queued = self.pending_write(key).is_some();
+        if accepted.is_empty() && !queued {
+            return Err(error);
         }
+        self.metrics.record_partial_write(failed.as_str());
 
-        async fn delete(&mut self, _bucket: &str, key: &Key) -> Result<()> {
-            self.map.remove(&key.0);
-            Ok(())
+        if self.partial_write_policy == PartialWritePolicy::Warn {
+            warn!(
+                "Put to bucket {} was rejected by the {} tier, keeping it in the others: {}",
+                key.bucket,
+                failed.as_str(),
+                error
+            );
+            return Ok(());
+        }
+        self.pending_writes
+            .lock()
+            .unwrap()
+            .remove(&(key.bucket.to_string(), key.key.0.clone()));
+        // With local deletes S3 is never deleted from, even to roll back
+        for &tier in accepted {
+            if tier == Tier::Cloud && !self.delete_from_cloud {
+                continue;
+            }
+            if let Err(e) = self.delete_from(tier, key).await {
+                warn!(
+                    "Could not roll back a failed put in the {} tier: {}",
+                    tier.as_str(),
+                    e
+                );
+            }
         }
+        Err(error.context(format!(
+            "Put was rejected by the {} tier and rolled back",
+            failed.as_str()
+        )))
     }
 
-    #[tokio::test]
-    async fn test_get() -> Result<()> {
-        let mut operation = Operation {
-            in_memory_store: MockStore::new(),
-            on_disk_store: MockStore::new(),
-            cloud_store: MockStore::new(),
+    async fn delete_from(&self, tier: Tier, key: &CacheKey<'_>) -> Result<()> {
+        match tier {
+            Tier::Memory => {
+                self.in_tier(tier, Op::Delete, self.in_memory_store.delete(key))
+                    .await?
+            }
+            Tier::Disk => {
+                self.in_tier(tier, Op::Delete, self.on_disk_store.delete(key))
+                    .await?
+            }
+            Tier::Cloud => {
+                self.in_tier(tier, Op::Delete, self.cloud_store.delete(key))
+                    .await?
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes room on disk for `entry` within its bucket's quota, failing
+    /// before any tier is touched when there is none.
+    async fn check_quota(&self, key: &CacheKey<'_>, entry: &Entry) -> Result<()> {
+        let Some(&quota) = self.bucket_quotas.get(key.bucket) else {
+            return Ok(());
         };
+        if exceeds(self.value_limits.disk, entry) {
+            return Ok(());
+        }
+        match self.on_disk_store.make_room(key, entry, quota).await? {
+            Some(0) => Ok(()),
+            Some(reclaimed) => {
+                self.metrics
+                    .record_quota_bytes_reclaimed(key.bucket, reclaimed);
+                Ok(())
+            }
+            None => {
+                self.metrics.record_quota_rejection(key.bucket);
+                Err(QuotaExceeded {
+                    bucket: key.bucket.to_string(),
+                    quota,
+                }
+                .into())
+            }
+        }
+    }
 
-        let bucket = "bucket";
-        let key = Key(vec![1, 2, 3]);
-        let value = Value(vec![4, 5, 6]);
+    /// Cancellation safe: tiers are cleared fastest first, so a dropped delete
+    /// never leaves a cached copy of a value that is already gone from S3.
+    ///
+    /// Returns whether the key was present in any tier beforehand; deleting a
+    /// missing key still succeeds. With local deletes, or for S3 read-only
+    /// buckets, only the cached copies are removed, so the next read loads the
+    /// key from S3 again.
+    pub async fn delete(&self, bucket: &str, key: &Key) -> Result<bool> {
+        let drop_response = self.inject_chaos(OperationKind::Delete).await?;
+        let _key_lock = self.key_locks.lock(bucket, key).await;
+        let result = self.delete_tiers(&CacheKey::new(bucket, key)).await;
+        if let (Ok(true), Some(watchers)) = (&result, &self.watchers) {
+            watchers.publish(bucket, key, None);
+        }
+        dropped(result, drop_response)
+    }
 
-        // Initially, get should return None.
-        assert!(operation.get(bucket, &key).await?.is_none());
+    async fn delete_tiers(&self, key: &CacheKey<'_>) -> Result<bool> {
+        let was_pending = self
+            .pending_writes
+            .lock()
+            .unwrap()
+            .remove(&(key.bucket.to_string(), key.key.0.clone()))
+            .is_some();
+        let existed = was_pending
+            || self
+                .in_tier(Tier::Memory, Op::Get, self.in_memory_store.exists(key))
+                .await?
+            || self
+                .in_tier(Tier::Disk, Op::Get, self.on_disk_store.exists(key))
+                .await?
+            || (self.cloud_enabled
+                && self.delete_checks_cloud
+                && (self
+                    .in_tier(Tier::Cloud, Op::Get, self.cloud_store.exists(key))
+                    .await?
+                    || self.exists_under_previous_salt(key).await?));
 
-        // After putting a value, get should return the value.
-        operation.put(bucket, &key, &value).await?;
-        assert_eq!(operation.get(bucket, &key).await?, Some(value.clone()));
+        self.in_tier(Tier::Memory, Op::Delete, self.in_memory_store.delete(key))
+            .await?;
+        self.in_tier(Tier::Disk, Op::Delete, self.on_disk_store.delete(key))
+            .await?;
+        if self.delete_from_cloud && self.writes_to_cloud(key.bucket) {
+            // Otherwise the next read would migrate the deleted key back
+            if let Some(previous) = &self.previous_cloud_store {
+                self.in_tier(Tier::Cloud, Op::Delete, previous.delete(key))
+                    .await?;
+            }
+            self.in_tier(Tier::Cloud, Op::Delete, self.cloud_store.delete(key))
+                .await?;
+            self.recent_cloud_writes
+                .lock()
+                .unwrap()
+                .pop(&(key.bucket.to_string(), key.key.0.clone()));
+        }
+        Ok(existed)
+    }
 
-        Ok(())
+    /// Whether a get of `key` would find it, checking memory, disk and S3 in
+    /// turn and stopping at the first that holds it. Values are never read
+    /// back, so nothing is copied between tiers.
+    pub async fn exists(&self, bucket: &str, key: &Key) -> Result<bool> {
+        let _key_lock = self.key_locks.lock(bucket, key).await;
+        let key = CacheKey::new(bucket, key);
+        Ok(self
+            .in_tier(Tier::Memory, Op::Get, self.in_memory_store.exists(&key))
+            .await?
+            || self.pending_write(&key).is_some()
+            || self
+                .in_tier(Tier::Disk, Op::Get, self.on_disk_store.exists(&key))
+                .await?
+            || (self.cloud_enabled
+                && (self
+                    .in_tier(Tier::Cloud, Op::Get, self.cloud_store.exists(&key))
+                    .await?
+                    || self.exists_under_previous_salt(&key).await?)))
     }
 
-    #[tokio::test]
-    async fn test_put() -> Result<()> {
-        let mut operation = Operation {
-            in_memory_store: MockStore::new(),
-            on_disk_store: MockStore::new(),
-            cloud_store: MockStore::new(),
+    async fn exists_under_previous_salt(&self, key: &CacheKey<'_>) -> Result<bool> {
+        match &self.previous_cloud_store {
+            Some(previous) => Ok(self
+                .in_tier(Tier::Cloud, Op::Get, previous.exists(key))
+                .await?),
+            None => Ok(false),
+        }
+    }
+
+    /// Compares the memory and disk copies of `key` with S3, which is
+    /// authoritative, and replaces or removes copies that differ. Makes at most
+    /// one S3 request, and none when nothing is cached.
+    pub async fn reconcile(&self, bucket: &str, key: &Key) -> Result<Reconciled> {
+        let _key_lock = self.key_locks.lock(bucket, key).await;
+        let key = CacheKey::new(bucket, key);
+        // Copies in S3 read-only buckets may be local-only writes that never reach S3
+        if !self.writes_to_cloud(bucket) || self.pending_write(&key).is_some() {
+            return Ok(Reconciled::Skipped);
+        }
+        let memory = self
+            .in_tier(Tier::Memory, Op::Get, self.in_memory_store.get(&key))
+            .await?;
+        let disk = self
+            .in_tier(Tier::Disk, Op::Get, self.on_disk_store.get(&key))
+            .await?;
+        if memory.is_none() && disk.is_none() {
+            return Ok(Reconciled::Skipped);
+        }
+
+        let cloud = match self
+            .in_tier(Tier::Cloud, Op::Get, self.cloud_store.get(&key))
+            .await?
+        {
+            Some(cloud) => Some(cloud),
+            None => self.retry_recent_miss(&key, None).await?,
+        };
+        let Some(cloud) = cloud else {
+            self.in_tier(Tier::Memory, Op::Delete, self.in_memory_store.delete(&key))
+                .await?;
+            self.in_tier(Tier::Disk, Op::Delete, self.on_disk_store.delete(&key))
+                .await?;
+            return Ok(Reconciled::Evicted);
         };
+        let mut outcome = Reconciled::Consistent;
+        if disk.is_some_and(|disk| disk != cloud) {
+            self.cache_on_disk(&key, &cloud, false).await?;
+            outcome = Reconciled::Repaired;
+        }
+        if memory.is_some_and(|memory| memory != cloud) {
+            self.cache_in_memory(&key, &cloud).await?;
+            outcome = Reconciled::Repaired;
+        }
+        Ok(outcome)
+    }
 
-        let bucket = "bucket";
-        let key = Key(vec![1, 2, 3]);
-        let value = Value(vec![4, 5, 6]);
+    /// Reports which tiers hold `key` and what a read would serve, probing
+    /// each tier under a single lock acquisition. Nothing is copied between
+    /// tiers, so the snapshot shows divergence as it is.
+    pub async fn inspect(&self, bucket: &str, key: &Key) -> Result<Inspection> {
+        let _key_lock = self.key_locks.lock(bucket, key).await;
+        let key = CacheKey::new(bucket, key);
+        let memory = self
+            .in_tier(Tier::Memory, Op::Get, self.in_memory_store.get(&key))
+            .await?;
+        let disk = self
+            .in_tier(Tier::Disk, Op::Get, self.on_disk_store.get(&key))
+            .await?;
+        let ttl_remaining = match disk {
+            Some(_) => {
+                self.in_tier(Tier::Disk, Op::Get, self.on_disk_store.ttl_remaining(&key))
+                    .await?
+            }
+            None => None,
+        };
+        let cloud = if self.cloud_enabled {
+            self.in_tier(Tier::Cloud, Op::Get, self.cloud_store.get(&key))
+                .await?
+        } else {
+            None
+        };
 
-        operation.put(bucket, &key, &value).await?;
-        assert_eq!(operation.get(bucket, &key).await?, Some(value));
+        Ok(Inspection {
+            in_memory: memory.is_some(),
+            on_disk: disk.is_some(),
+            in_cloud: cloud.is_some(),
+            // A latency-first write evicted from memory is still what a read serves
+            entry: memory
+                .or_else(|| self.pending_write(&key))
+                .or(disk)
+                .or(cloud),
+            ttl_remaining,
+        })
+    }
 
-        Ok(())
+    /// Writes latency-first puts through to S3 and disk, stopping at the first
+    /// failure. Entries leave the queue only once persisted, so failed or
+    /// cancelled runs are retried by the next call. Returns how many were persisted.
+    pub async fn persist_pending(&self) -> Result<usize> {
+        let keys: Vec<(String, Vec<u8>)> = self
+            .pending_writes
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        let mut persisted = 0;
+        for pending_key in keys {
+            let bucket = &pending_key.0;
+            let key = Key(pending_key.1.clone());
+            let _key_lock = self.key_locks.lock(bucket, &key).await;
+            // Deleted or written through since the keys were listed
+            let Some(entry) = self
+                .pending_writes
+                .lock()
+                .unwrap()
+                .get(&pending_key)
+                .cloned()
+            else {
+                continue;
+            };
+            self.persist(&CacheKey::new(bucket, &key), &entry).await?;
+            self.pending_writes.lock().unwrap().remove(&pending_key);
+            persisted += 1;
+        }
+        Ok(persisted)
     }
 
-    #[tokio::test]
-    async fn test_delete() -> Result<()> {
-        let mut operation = Operation {
-            in_memory_store: MockStore::new(),
-            on_disk_store: MockStore::new(),
-            cloud_store: MockStore::new(),
+    /// Persists pending writes every `interval` until `shutdown` is cancelled.
+    /// A run in progress is abandoned on cancellation; its unpersisted entries
+    /// stay queued for a final `persist_pending`.
+    pub async fn run_write_behind(&self, interval: Duration, shutdown: CancellationToken) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = ticker.tick() => {}
+            }
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                result = self.persist_pending() => {
+                    if let Err(e) = result {
+                        warn!("Write-behind persistence failed: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn persist(&self, key: &CacheKey<'_>, entry: &Entry) -> Result<()> {
+        if self.writes_to_cloud(key.bucket) {
+            self.in_tier(Tier::Cloud, Op::Put, self.cloud_store.put(key, entry))
+                .await?;
+            self.note_cloud_write(key);
+        }
+        // Latency-first puts wrote disk before returning
+        if self.write_mode == WriteMode::LatencyFirst {
+            return Ok(());
+        }
+        self.cache_on_disk(key, entry, false).await
+    }
+
+    /// Caches `entry` in memory unless it is over the memory value limit, in
+    /// which case any older copy is dropped so it cannot be served instead.
+    async fn cache_in_memory(&self, key: &CacheKey<'_>, entry: &Entry) -> Result<()> {
+        if exceeds(self.value_limits.memory, entry) {
+            self.metrics.record_tier_skip("memory");
+            return Ok(self
+                .in_tier(Tier::Memory, Op::Delete, self.in_memory_store.delete(key))
+                .await?);
+        }
+        Ok(self
+            .in_tier(Tier::Memory, Op::Put, self.in_memory_store.put(key, entry))
+            .await?)
+    }
+
+    /// Disk counterpart of `cache_in_memory`. A durable write returns only
+    /// once the entry is on stable storage.
+    async fn cache_on_disk(&self, key: &CacheKey<'_>, entry: &Entry, durable: bool) -> Result<()> {
+        if exceeds(self.value_limits.disk, entry) {
+            self.metrics.record_tier_skip("disk");
+            return Ok(self
+                .in_tier(Tier::Disk, Op::Delete, self.on_disk_store.delete(key))
+                .await?);
+        }
+        if durable {
+            return Ok(self
+                .in_tier(
+                    Tier::Disk,
+                    Op::Put,
+                    self.on_disk_store.put_durable(key, entry),
+                )
+                .await?);
+        }
+        Ok(self
+            .in_tier(Tier::Disk, Op::Put, self.on_disk_store.put(key, entry))
+            .await?)
+    }
+
+    // Runs a call into `tier` made while holding a key lock, timing it when
+    // tier lock timing is on.
+    async fn in_tier<T>(&self, tier: Tier, op: Op, call: impl Future<Output = T>) -> T {
+        if !self.time_tier_locks {
+            return call.await;
+        }
+        let started = Instant::now();
+        let result = call.await;
+        self.metrics
+            .record_tier_lock_hold(tier.as_str(), op, started.elapsed());
+        result
+    }
+
+    fn note_cloud_write(&self, key: &CacheKey<'_>) {
+        if self.read_after_write.is_some() || self.write_combine_window.is_some() {
+            self.recent_cloud_writes.lock().unwrap().put(
+                (key.bucket.to_string(), key.key.0.clone()),
+                self.clock.now(),
+            );
+        }
+    }
+
+    /// Re-reads S3 after a miss on a key this node wrote to S3 recently, giving
+    /// up after the configured attempts, at `deadline`, or when a read times out.
+    async fn retry_recent_miss(
+        &self,
+        key: &CacheKey<'_>,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Entry>> {
+        let Some(retry) = self.read_after_write else {
+            return Ok(None);
         };
+        let written_at = self
+            .recent_cloud_writes
+            .lock()
+            .unwrap()
+            .get(&(key.bucket.to_string(), key.key.0.clone()))
+            .copied();
+        let now = self.clock.now();
+        if written_at
+            .is_none_or(|written_at| now.saturating_duration_since(written_at) > retry.window)
+        {
+            return Ok(None);
+        }
+        for _ in 0..retry.attempts {
+            if deadline.is_some_and(|deadline| Instant::now() + retry.delay >= deadline) {
+                break;
+            }
+            tokio::time::sleep(retry.delay).await;
+            let cloud_read = with_timeout(self.timeouts.cloud, self.cloud_store.get(key));
+            match self.in_tier(Tier::Cloud, Op::Get, cloud_read).await {
+                Some(Ok(Some(entry))) => {
+                    self.metrics.record_read_after_write_retry("found");
+                    return Ok(Some(entry));
+                }
+                Some(Ok(None)) => {}
+                Some(Err(e)) => return Err(e.into()),
+                None => break,
+            }
+        }
+        self.metrics.record_read_after_write_retry("missing");
+        Ok(None)
+    }
 
-        let bucket = "bucket";
-        let key = Key(vec![1, 2, 3]);
-        let value = Value(vec![4, 5, 6]);
+    fn pending_write(&self, key: &CacheKey<'_>) -> Option<Entry> {
+        self.pending_writes
+            .lock()
+            .unwrap()
+            .get(&(key.bucket.to_string(), key.key.0.clone()))
+            .filter(|entry| !entry.is_expired(&*self.clock))
+            .cloned()
+    }
 
-        operation.put(bucket, &key, &value).await?;
-        operation.delete(bucket, &key).await?;
-        assert!(operation.get(bucket, &key).await?.is_none());
+    /// Queues a latency-first or combined write for `persist_pending`,
+    /// returning false when it must be written through instead, as durable
+    /// writes always are.
+    fn queue_write(&self, key: &CacheKey<'_>, entry: &Entry, durable: bool) -> bool {
+        let pending_key = (key.bucket.to_string(), key.key.0.clone());
+        let queued = !durable
+            && (self.write_mode == WriteMode::LatencyFirst || self.combines_write(&pending_key));
+        let mut pending_writes = self.pending_writes.lock().unwrap();
+        if queued
+            && (pending_writes.len() < MAX_PENDING_WRITES
+                || pending_writes.contains_key(&pending_key))
+        {
+            if pending_writes.insert(pending_key, entry.clone()).is_some() {
+                self.metrics.record_combined_write();
+            }
+            return true;
+        }
+
+        // Written through, so an older queued write must not overwrite it later
+        pending_writes.remove(&pending_key);
+        false
+    }
+
+    // Whether a put to this key lands within the combining window of its last
+    // S3 write. The first put of a burst is written through, starting the window.
+    fn combines_write(&self, pending_key: &(String, Vec<u8>)) -> bool {
+        let Some(window) = self.write_combine_window else {
+            return false;
+        };
+        self.recent_cloud_writes
+            .lock()
+            .unwrap()
+            .peek(pending_key)
+            .is_some_and(|written_at| {
+                self.clock.now().saturating_duration_since(*written_at) < window
+            })
+    }
+
+    async fn inject_chaos(&self, operation: OperationKind) -> Result<bool> {
+        match &self.chaos {
+            Some(chaos) => chaos.inject(operation).await,
+            None => Ok(false),
+        }
+    }
+
+    /// Writes, reads back and deletes `value` in every tier independently,
+    /// reporting timing and success per tier.
+    pub async fn self_test(&self, bucket: &str, key: &Key, value: &Value) -> Vec<TierCheck> {
+        let entry = Entry::new(value.clone(), &*self.clock);
+        let _key_lock = self.key_locks.lock(bucket, key).await;
+        let key = CacheKey::new(bucket, key);
+        vec![
+            round_trip("memory", &self.in_memory_store, &key, &entry).await,
+            round_trip("disk", &self.on_disk_store, &key, &entry).await,
+            if self.cloud_enabled {
+                round_trip("cloud", &self.cloud_store, &key, &entry).await
+            } else {
+                TierCheck {
+                    tier: "cloud",
+                    successful: false,
+                    duration: Duration::ZERO,
+                    error: Some("S3 tier disabled (degraded mode)".to_string()),
+                }
+            },
+        ]
+    }
+}
+
+impl<I: Store, C: Store> Operation<I, DiskStore, C> {
+    /// Evicts least recently used disk entries until the disk tier fits in
+    /// `budget` bytes, touching at most `max_evictions` entries per call.
+    pub fn evict_disk_to_budget(&self, budget: u64, max_evictions: usize) -> Result<u64> {
+        self.on_disk_store.evict_to_budget(budget, max_evictions)
+    }
+
+    pub fn disk_size_bytes(&self) -> u64 {
+        self.on_disk_store.size_bytes()
+    }
+}
+
+impl<I: Store> Operation<I, DiskStore, S3Store> {
+    /// Compresses values written to disk and S3, including S3 writes made
+    /// while migrating keys from a previous salt. Memory is unaffected.
+    pub fn with_value_compression(mut self, compression: ValueCompression, level: i32) -> Self {
+        self.on_disk_store = self.on_disk_store.with_compression(compression, level);
+        self.cloud_store.compression = compression;
+        self.cloud_store.compression_level = level;
+        if let Some(previous) = &mut self.previous_cloud_store {
+            previous.compression = compression;
+            previous.compression_level = level;
+        }
+        self
+    }
+}
+
+impl<O: Store, C: Store> Operation<LRUStore, O, C> {
+    pub fn with_max_pinned_bytes(mut self, max_pinned_bytes: u64) -> Self {
+        self.in_memory_store = self.in_memory_store.with_max_pinned_bytes(max_pinned_bytes);
+        self
+    }
+
+    pub fn with_memory_compression(
+        mut self,
+        compression: MemoryCompression,
+        min_bytes: usize,
+    ) -> Self {
+        self.in_memory_store = self
+            .in_memory_store
+            .with_compression(compression, min_bytes);
+        self
+    }
+
+    /// Keeps `key` in memory whatever the access pattern, loading it from disk
+    /// or S3 now if it is not already there. Returns whether it is resident.
+    pub async fn pin(&self, bucket: &str, key: &Key) -> Result<bool> {
+        let _key_lock = self.key_locks.lock(bucket, key).await;
+        let key = CacheKey::new(bucket, key);
+        self.in_memory_store.pin(&key)?;
+        Ok(self.read_tiers(&key, None).await?.is_some())
+    }
+
+    /// Returns whether `key` was pinned.
+    pub fn unpin(&self, bucket: &str, key: &Key) -> bool {
+        self.in_memory_store.unpin(&CacheKey::new(bucket, key))
+    }
+
+    /// Evicts memory entries unused for longer than `idle`, touching at most
+    /// `max_evictions` entries per call. Returns how many were evicted.
+    pub fn evict_idle_memory(&self, idle: Duration, max_evictions: usize) -> usize {
+        self.in_memory_store.evict_idle(idle, max_evictions)
+    }
+
+    /// Entries held in memory, pinned ones included, and the LRU's capacity.
+    pub fn memory_entries(&self) -> (usize, usize) {
+        self.in_memory_store.entries()
+    }
+}
+
+fn fresh(entry: Entry, tier: Tier) -> Lookup {
+    Lookup {
+        entry,
+        stale: false,
+        tier,
+    }
+}
+
+fn exceeds(limit: Option<u64>, entry: &Entry) -> bool {
+    limit.is_some_and(|limit| entry.value.0.len() as u64 > limit)
+}
+
+// A dropped response still lets the operation take effect, then reports failure.
+fn dropped<T>(result: Result<T>, drop_response: bool) -> Result<T> {
+    if drop_response {
+        result?;
+        bail!("Injected dropped response");
+    }
+    result
+}
+
+async fn with_timeout<F: Future>(timeout: Option<Duration>, future: F) -> Option<F::Output> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+async fn round_trip<S: Store>(
+    tier: &'static str,
+    store: &S,
+    key: &CacheKey<'_>,
+    entry: &Entry,
+) -> TierCheck {
+    let start = Instant::now();
+    let result = async {
+        store.put(key, entry).await?;
+        let read = store.get(key).await?;
+        store.delete(key).await?;
+        if read.as_ref() != Some(entry) {
+            bail!("read back value did not match written value");
+        }
+        Ok(())
+    }
+    .await;
+
+    TierCheck {
+        tier,
+        successful: result.is_ok(),
+        duration: start.elapsed(),
+        error: result.err().map(|e| e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::store::StoreResult;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use tonic::async_trait;
+
+    pub struct MockStore {
+        map: Mutex<HashMap<Vec<u8>, Entry>>,
+        durable_puts: AtomicU32,
+    }
+
+    impl MockStore {
+        pub fn new() -> Self {
+            Self {
+                map: Mutex::new(HashMap::new()),
+                durable_puts: AtomicU32::new(0),
+            }
+        }
+    }
+    #[async_trait]
+    impl Store for MockStore {
+        async fn get(&self, key: &CacheKey<'_>) -> StoreResult<Option<Entry>> {
+            Ok(self.map.lock().unwrap().get(&key.key.0).cloned())
+        }
+
+        async fn put(&self, key: &CacheKey<'_>, entry: &Entry) -> StoreResult<()> {
+            self.map
+                .lock()
+                .unwrap()
+                .insert(key.key.0.clone(), entry.clone());
+            Ok(())
+        }
+
+        async fn delete(&self, key: &CacheKey<'_>) -> StoreResult<()> {
+            self.map.lock().unwrap().remove(&key.key.0);
+            Ok(())
+        }
+
+        async fn put_durable(&self, key: &CacheKey<'_>, entry: &Entry) -> StoreResult<()> {
+            self.durable_puts.fetch_add(1, Ordering::SeqCst);
+            self.put(key, entry).await
+        }
+    }
+
+    fn test_metrics() -> Arc<Metrics> {
+        Arc::new(Metrics::new().unwrap())
+    }
+
+    /// Store whose next get or put never completes, standing in for a stalled
+    /// tier or a request that is cancelled mid-write.
+    pub struct HangingStore {
+        inner: MockStore,
+        hang_next_get: AtomicBool,
+        hang_next_put: AtomicBool,
+    }
+
+    impl HangingStore {
+        pub fn new() -> Self {
+            Self {
+                inner: MockStore::new(),
+                hang_next_get: AtomicBool::new(false),
+                hang_next_put: AtomicBool::new(false),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Store for HangingStore {
+        async fn get(&self, key: &CacheKey<'_>) -> StoreResult<Option<Entry>> {
+            if self.hang_next_get.swap(false, Ordering::SeqCst) {
+                std::future::pending::<()>().await;
+            }
+            self.inner.get(key).await
+        }
+
+        async fn put(&self, key: &CacheKey<'_>, entry: &Entry) -> StoreResult<()> {
+            if self.hang_next_put.swap(false, Ordering::SeqCst) {
+                std::future::pending::<()>().await;
+            }
+            self.inner.put(key, entry).await
+        }
+
+        async fn delete(&self, key: &CacheKey<'_>) -> StoreResult<()> {
+            self.inner.delete(key).await
+        }
+    }
+
+    /// Store whose entries can all be marked past their TTL, standing in for a
+    /// disk tier holding expired copies.
+    pub struct ExpiringStore {
+        inner: MockStore,
+        expired: AtomicBool,
+    }
+
+    impl ExpiringStore {
+        pub fn new() -> Self {
+            Self {
+                inner: MockStore::new(),
+                expired: AtomicBool::new(false),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Store for ExpiringStore {
+        async fn get(&self, key: &CacheKey<'_>) -> StoreResult<Option<Entry>> {
+            if self.expired.load(Ordering::SeqCst) {
+                return Ok(None);
+            }
+            self.inner.get(key).await
+        }
+
+        async fn put(&self, key: &CacheKey<'_>, entry: &Entry) -> StoreResult<()> {
+            self.inner.put(key, entry).await
+        }
+
+        async fn delete(&self, key: &CacheKey<'_>) -> StoreResult<()> {
+            self.inner.delete(key).await
+        }
+
+        async fn get_including_expired(
+            &self,
+            key: &CacheKey<'_>,
+        ) -> StoreResult<Option<(Entry, bool)>> {
+            Ok(self
+                .inner
+                .get(key)
+                .await?
+                .map(|entry| (entry, self.expired.load(Ordering::SeqCst))))
+        }
+    }
+
+    /// Store whose reads miss for a while after every write, like an S3
+    /// backend with read-after-write lag.
+    pub struct LaggingStore {
+        inner: MockStore,
+        misses_after_put: u32,
+        misses_left: AtomicU32,
+    }
+
+    impl LaggingStore {
+        pub fn new(misses_after_put: u32) -> Self {
+            Self {
+                inner: MockStore::new(),
+                misses_after_put,
+                misses_left: AtomicU32::new(0),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Store for LaggingStore {
+        async fn get(&self, key: &CacheKey<'_>) -> StoreResult<Option<Entry>> {
+            let lagging = self
+                .misses_left
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                    left.checked_sub(1)
+                })
+                .is_ok();
+            if lagging {
+                return Ok(None);
+            }
+            self.inner.get(key).await
+        }
+
+        async fn put(&self, key: &CacheKey<'_>, entry: &Entry) -> StoreResult<()> {
+            self.inner.put(key, entry).await?;
+            self.misses_left
+                .store(self.misses_after_put, Ordering::SeqCst);
+            Ok(())
+        }
+
+        async fn delete(&self, key: &CacheKey<'_>) -> StoreResult<()> {
+            self.inner.delete(key).await
+        }
+    }
+
+    /// Store that yields to the scheduler a random number of times before
+    /// every call, so concurrent operations interleave as they would on real I/O.
+    pub struct YieldingStore {
+        inner: MockStore,
+    }
+
+    impl YieldingStore {
+        pub fn new() -> Self {
+            Self {
+                inner: MockStore::new(),
+            }
+        }
+
+        async fn yield_randomly(&self) {
+            for _ in 0..rand::random::<u8>() % 4 {
+                tokio::task::yield_now().await;
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Store for YieldingStore {
+        async fn get(&self, key: &CacheKey<'_>) -> StoreResult<Option<Entry>> {
+            self.yield_randomly().await;
+            self.inner.get(key).await
+        }
+
+        async fn put(&self, key: &CacheKey<'_>, entry: &Entry) -> StoreResult<()> {
+            self.yield_randomly().await;
+            self.inner.put(key, entry).await
+        }
+
+        async fn delete(&self, key: &CacheKey<'_>) -> StoreResult<()> {
+            self.yield_randomly().await;
+            self.inner.delete(key).await
+        }
+    }
+
+    /// Disk tier with room for at most `room` bytes per bucket, evicting
+    /// nothing to make it.
+    pub struct QuotaStore {
+        inner: MockStore,
+        room: u64,
+    }
+
+    impl QuotaStore {
+        pub fn new(room: u64) -> Self {
+            Self {
+                inner: MockStore::new(),
+                room,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Store for QuotaStore {
+        async fn get(&self, key: &CacheKey<'_>) -> StoreResult<Option<Entry>> {
+            self.inner.get(key).await
+        }
+
+        async fn put(&self, key: &CacheKey<'_>, entry: &Entry) -> StoreResult<()> {
+            self.inner.put(key, entry).await
+        }
+
+        async fn delete(&self, key: &CacheKey<'_>) -> StoreResult<()> {
+            self.inner.delete(key).await
+        }
+
+        async fn make_room(
+            &self,
+            _key: &CacheKey<'_>,
+            entry: &Entry,
+            quota: u64,
+        ) -> StoreResult<Option<u64>> {
+            let size = entry.value.0.len() as u64;
+            Ok((size <= self.room.min(quota)).then_some(0))
+        }
+    }
+
+    /// Store that rejects every put once `fail_puts` is set, standing in for a
+    /// tier that refuses a value the others accept.
+    pub struct FailingStore {
+        inner: MockStore,
+        fail_puts: AtomicBool,
+    }
+
+    impl FailingStore {
+        pub fn new() -> Self {
+            Self {
+                inner: MockStore::new(),
+                fail_puts: AtomicBool::new(false),
+            }
+        }
+
+        fn value(&self, key: &Key) -> Option<Value> {
+            let map = self.inner.map.lock().unwrap();
+            map.get(&key.0).map(|entry| entry.value.clone())
+        }
+    }
+
+    #[async_trait]
+    impl Store for FailingStore {
+        async fn get(&self, key: &CacheKey<'_>) -> StoreResult<Option<Entry>> {
+            self.inner.get(key).await
+        }
+
+        async fn put(&self, key: &CacheKey<'_>, entry: &Entry) -> StoreResult<()> {
+            if self.fail_puts.load(Ordering::SeqCst) {
+                return Err(StoreError::Backend {
+                    tier: "disk",
+                    reason: "put rejected".to_string(),
+                });
+            }
+            self.inner.put(key, entry).await
+        }
+
+        async fn delete(&self, key: &CacheKey<'_>) -> StoreResult<()> {
+            self.inner.delete(key).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        );
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let value = Value(vec![4, 5, 6]);
+
+        // Initially, get should return None.
+        assert!(operation.get(bucket, &key).await?.is_none());
+
+        // After putting a value, get should return the value.
+        operation.put(bucket, &key, &value).await?;
+        assert_eq!(
+            operation.get(bucket, &key).await?.map(|e| e.value),
+            Some(value.clone())
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        );
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let value = Value(vec![4, 5, 6]);
+
+        operation.put(bucket, &key, &value).await?;
+        assert_eq!(
+            operation.get(bucket, &key).await?.map(|e| e.value),
+            Some(value)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bucket_puts() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        );
+
+        let key = Key(vec![1, 2, 3]);
+        let value = Value(vec![4, 5, 6]);
+
+        operation.put("a", &key, &value).await?;
+        operation.put("a", &key, &value).await?;
+        operation.put("b", &key, &value).await?;
+        // Reads and deletes do not make a bucket known.
+        operation.get("c", &key).await?;
+        operation.delete("d", &key).await?;
+
+        assert_eq!(
+            operation.bucket_puts(),
+            HashMap::from([("a".to_string(), 2), ("b".to_string(), 1)])
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bucket_quota_rejects_puts_without_room() -> Result<()> {
+        let metrics = test_metrics();
+        let operation = Operation::new(
+            MockStore::new(),
+            QuotaStore::new(4),
+            MockStore::new(),
+            metrics.clone(),
+        )
+        .with_bucket_quotas(HashMap::from([("tenant".to_string(), 8)]))
+        .with_value_limits(TierValueLimits {
+            memory: None,
+            disk: Some(6),
+        });
+
+        let key = Key(vec![1, 2, 3]);
+        operation.put("tenant", &key, &Value(vec![1; 4])).await?;
+
+        // Refused before any tier is written, leaving the previous value
+        let error = operation
+            .put("tenant", &key, &Value(vec![2; 5]))
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<QuotaExceeded>().is_some());
+        assert_eq!(
+            operation.get("tenant", &key).await?.map(|e| e.value),
+            Some(Value(vec![1; 4]))
+        );
+        assert_eq!(operation.bucket_puts().get("tenant"), Some(&1));
+
+        // Buckets without a quota, and values kept off disk, are not limited
+        operation.put("other", &key, &Value(vec![3; 5])).await?;
+        operation.put("tenant", &key, &Value(vec![4; 7])).await?;
+
+        let rejections = metrics
+            .prometheus()
+            .unwrap()
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "cache_quota_rejections_total")
+            .unwrap();
+        let rejected = &rejections.get_metric()[0];
+        assert_eq!(rejected.get_label()[0].get_value(), "tenant");
+        assert_eq!(rejected.get_counter().get_value(), 1.0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_partial_write_policies() -> Result<()> {
+        use PartialWritePolicy::{Fail, Warn};
+
+        let key = Key(vec![1, 2, 3]);
+        let old = Some(Value(vec![1]));
+        let new = Some(Value(vec![2]));
+        // Memory, disk and S3 contents after the second put, by policy and by
+        // the tier that rejects it
+        let cases = [
+            (Fail, Tier::Cloud, [None, None, old.clone()]),
+            (Fail, Tier::Disk, [None, None, None]),
+            (Fail, Tier::Memory, [None, None, None]),
+            (Warn, Tier::Cloud, [None, None, old.clone()]),
+            (Warn, Tier::Disk, [None, None, new.clone()]),
+            (Warn, Tier::Memory, [None, new.clone(), new.clone()]),
+        ];
+        for (policy, failing, expected) in cases {
+            let operation = Operation::new(
+                FailingStore::new(),
+                FailingStore::new(),
+                FailingStore::new(),
+                test_metrics(),
+            )
+            .with_partial_write_policy(policy);
+            operation.put("bucket", &key, &Value(vec![1])).await?;
+
+            let failing_store = match failing {
+                Tier::Memory => &operation.in_memory_store,
+                Tier::Disk => &operation.on_disk_store,
+                Tier::Cloud => &operation.cloud_store,
+            };
+            failing_store.fail_puts.store(true, Ordering::SeqCst);
+            let result = operation.put("bucket", &key, &Value(vec![2])).await;
+
+            let succeeded = policy == Warn && failing != Tier::Cloud;
+            assert_eq!(result.is_ok(), succeeded, "{policy:?} on {failing:?}");
+            let held = [
+                operation.in_memory_store.value(&key),
+                operation.on_disk_store.value(&key),
+                operation.cloud_store.value(&key),
+            ];
+            assert_eq!(held, expected, "{policy:?} on {failing:?}");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_partial_write_policy_covers_queued_writes() -> Result<()> {
+        let key = Key(vec![1, 2, 3]);
+        for (policy, queued) in [
+            (PartialWritePolicy::Fail, false),
+            (PartialWritePolicy::Warn, true),
+        ] {
+            let operation = Operation::new(
+                FailingStore::new(),
+                FailingStore::new(),
+                FailingStore::new(),
+                test_metrics(),
+            )
+            .with_write_mode(WriteMode::LatencyFirst)
+            .with_partial_write_policy(policy);
+            operation
+                .in_memory_store
+                .fail_puts
+                .store(true, Ordering::SeqCst);
+
+            let result = operation.put("bucket", &key, &Value(vec![2])).await;
+            assert_eq!(result.is_ok(), queued);
+            assert_eq!(operation.in_memory_store.value(&key), None);
+            // A kept write still reaches disk and S3
+            assert_eq!(operation.persist_pending().await?, usize::from(queued));
+            let persisted = queued.then(|| Value(vec![2]));
+            assert_eq!(operation.cloud_store.value(&key), persisted);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tier_lock_timing_records_each_tier_call() -> Result<()> {
+        let metrics = test_metrics();
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            metrics.clone(),
+        )
+        .with_tier_lock_timing();
+
+        let key = Key(vec![1, 2, 3]);
+        operation.put("bucket", &key, &Value(vec![4])).await?;
+        operation.get("bucket", &key).await?;
+
+        let holds = metrics
+            .prometheus()
+            .unwrap()
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "cache_tier_lock_hold_seconds")
+            .unwrap();
+        let count = |tier: &str, op: &str| {
+            holds
+                .get_metric()
+                .iter()
+                .find(|metric| {
+                    let labels = metric.get_label();
+                    labels
+                        .iter()
+                        .any(|l| l.get_name() == "tier" && l.get_value() == tier)
+                        && labels
+                            .iter()
+                            .any(|l| l.get_name() == "operation" && l.get_value() == op)
+                })
+                .map_or(0, |metric| metric.get_histogram().get_sample_count())
+        };
+        // The put invalidates memory and disk, writes S3 and then refills both
+        assert_eq!(count("memory", "delete"), 1);
+        assert_eq!(count("disk", "delete"), 1);
+        assert_eq!(count("cloud", "put"), 1);
+        assert_eq!(count("disk", "put"), 1);
+        assert_eq!(count("memory", "put"), 1);
+        // The get is answered from memory without touching the other tiers
+        assert_eq!(count("memory", "get"), 1);
+        assert_eq!(count("disk", "get"), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_watchers_see_puts_and_deletes() -> Result<()> {
+        use futures::StreamExt;
+
+        let metrics = test_metrics();
+        let watchers = Arc::new(Watchers::new(1, 4, metrics.clone()));
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            metrics,
+        )
+        .with_watchers(watchers.clone());
+
+        let key = Key(vec![1, 2, 3]);
+        let mut changes = watchers.subscribe("bucket", &key).unwrap();
+        operation.put("bucket", &key, &Value(vec![4])).await?;
+        operation.delete("bucket", &key).await?;
+        // Deleting a missing key changes nothing
+        operation.delete("bucket", &key).await?;
+        operation.put("bucket", &key, &Value(vec![5])).await?;
+
+        let change = changes.next().await.unwrap().unwrap();
+        assert_eq!(change.map(|entry| entry.value), Some(Value(vec![4])));
+        assert_eq!(changes.next().await, Some(Ok(None)));
+        let change = changes.next().await.unwrap().unwrap();
+        assert_eq!(change.map(|entry| entry.value), Some(Value(vec![5])));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_latency_first_writes_persist_later() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        )
+        .with_write_mode(WriteMode::LatencyFirst);
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let value = Value(vec![4, 5, 6]);
+
+        operation.put(bucket, &key, &value).await?;
+        assert!(operation
+            .cloud_store
+            .get(&CacheKey::new(bucket, &key))
+            .await?
+            .is_none());
+        assert_eq!(
+            operation
+                .on_disk_store
+                .get(&CacheKey::new(bucket, &key))
+                .await?
+                .map(|e| e.value),
+            Some(value.clone())
+        );
+
+        // Still readable after leaving memory and disk, before it is persisted.
+        operation
+            .in_memory_store
+            .delete(&CacheKey::new(bucket, &key))
+            .await?;
+        operation
+            .on_disk_store
+            .delete(&CacheKey::new(bucket, &key))
+            .await?;
+        assert_eq!(
+            operation.get(bucket, &key).await?.map(|e| e.value),
+            Some(value.clone())
+        );
+
+        assert_eq!(operation.persist_pending().await?, 1);
+        assert_eq!(
+            operation
+                .cloud_store
+                .get(&CacheKey::new(bucket, &key))
+                .await?
+                .map(|e| e.value),
+            Some(value.clone())
+        );
+        assert_eq!(operation.persist_pending().await?, 0);
+
+        // A delete cancels a write that has not been persisted yet.
+        operation.put(bucket, &Key(vec![7]), &value).await?;
+        assert!(operation.delete(bucket, &Key(vec![7])).await?);
+        assert_eq!(operation.persist_pending().await?, 0);
+        assert!(operation
+            .cloud_store
+            .get(&CacheKey::new(bucket, &Key(vec![7])))
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_combining_coalesces_rapid_puts() -> Result<()> {
+        let clock = Arc::new(MockClock::default());
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        )
+        .with_write_combining(Duration::from_secs(60))
+        .with_clock(clock.clone());
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let cache_key = CacheKey::new(bucket, &key);
+
+        // The first put starts the window and is written through
+        operation.put(bucket, &key, &Value(vec![1])).await?;
+        assert_eq!(
+            operation
+                .cloud_store
+                .get(&cache_key)
+                .await?
+                .map(|e| e.value),
+            Some(Value(vec![1]))
+        );
+
+        // Later ones are combined, but reads see each at once
+        for i in 2..=5 {
+            operation.put(bucket, &key, &Value(vec![i])).await?;
+            assert_eq!(
+                operation.get(bucket, &key).await?.map(|e| e.value),
+                Some(Value(vec![i]))
+            );
+        }
+        assert_eq!(
+            operation
+                .cloud_store
+                .get(&cache_key)
+                .await?
+                .map(|e| e.value),
+            Some(Value(vec![1]))
+        );
+
+        // Only the latest value is persisted
+        assert_eq!(operation.persist_pending().await?, 1);
+        assert_eq!(
+            operation
+                .cloud_store
+                .get(&cache_key)
+                .await?
+                .map(|e| e.value),
+            Some(Value(vec![5]))
+        );
+
+        // Durable puts are never combined
+        operation
+            .put_with_durability(bucket, &key, &Value(vec![6]), true, None)
+            .await?;
+        assert_eq!(
+            operation
+                .cloud_store
+                .get(&cache_key)
+                .await?
+                .map(|e| e.value),
+            Some(Value(vec![6]))
+        );
+        assert_eq!(operation.persist_pending().await?, 0);
+
+        // Once the window has passed, the next put is written through again
+        clock.advance(Duration::from_secs(61));
+        operation.put(bucket, &key, &Value(vec![7])).await?;
+        assert_eq!(
+            operation
+                .cloud_store
+                .get(&cache_key)
+                .await?
+                .map(|e| e.value),
+            Some(Value(vec![7]))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_durable_put_writes_through() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        )
+        .with_write_mode(WriteMode::LatencyFirst);
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let queued = Value(vec![4, 5, 6]);
+        let durable = Value(vec![7, 8, 9]);
+
+        operation.put(bucket, &key, &queued).await?;
+        assert_eq!(
+            operation.on_disk_store.durable_puts.load(Ordering::SeqCst),
+            0
+        );
+
+        operation
+            .put_with_durability(bucket, &key, &durable, true, None)
+            .await?;
+        assert_eq!(
+            operation.on_disk_store.durable_puts.load(Ordering::SeqCst),
+            1
+        );
+        for tier in [&operation.on_disk_store, &operation.cloud_store] {
+            assert_eq!(
+                tier.get(&CacheKey::new(bucket, &key))
+                    .await?
+                    .map(|e| e.value),
+                Some(durable.clone())
+            );
+        }
+
+        // The write queued before it must not be persisted over it later
+        assert_eq!(operation.persist_pending().await?, 0);
+        assert_eq!(
+            operation
+                .cloud_store
+                .get(&CacheKey::new(bucket, &key))
+                .await?
+                .map(|e| e.value),
+            Some(durable)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_behind_stops_on_cancellation() -> Result<()> {
+        let operation = Arc::new(
+            Operation::new(
+                MockStore::new(),
+                MockStore::new(),
+                MockStore::new(),
+                test_metrics(),
+            )
+            .with_write_mode(WriteMode::LatencyFirst),
+        );
+        let shutdown = CancellationToken::new();
+        let flusher = tokio::spawn({
+            let operation = operation.clone();
+            let shutdown = shutdown.clone();
+            async move {
+                operation
+                    .run_write_behind(Duration::from_millis(10), shutdown)
+                    .await
+            }
+        });
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        operation.put(bucket, &key, &Value(vec![4, 5, 6])).await?;
+        let cache_key = CacheKey::new(bucket, &key);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while operation.pending_write(&cache_key).is_some() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await?;
+        assert!(operation
+            .cloud_store
+            .get(&CacheKey::new(bucket, &key))
+            .await?
+            .is_some());
+
+        shutdown.cancel();
+        tokio::time::timeout(Duration::from_secs(5), flusher).await??;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_repairs_divergent_copies() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        );
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let current = Entry::new(Value(vec![4, 5, 6]), &SystemClock);
+        let outdated = Entry {
+            value: Value(vec![7, 8, 9]),
+            written_at: current.written_at - 1,
+            expires_at: None,
+        };
+
+        assert_eq!(
+            operation.reconcile(bucket, &key).await?,
+            Reconciled::Skipped
+        );
+
+        // S3 took the write but the disk write failed, leaving the old value.
+        operation
+            .cloud_store
+            .put(&CacheKey::new(bucket, &key), &current)
+            .await?;
+        operation
+            .on_disk_store
+            .put(&CacheKey::new(bucket, &key), &outdated)
+            .await?;
+        assert_eq!(
+            operation.reconcile(bucket, &key).await?,
+            Reconciled::Repaired
+        );
+        assert_eq!(operation.get(bucket, &key).await?, Some(current.clone()));
+        assert_eq!(
+            operation.reconcile(bucket, &key).await?,
+            Reconciled::Consistent
+        );
+
+        operation
+            .cloud_store
+            .delete(&CacheKey::new(bucket, &key))
+            .await?;
+        assert_eq!(
+            operation.reconcile(bucket, &key).await?,
+            Reconciled::Evicted
+        );
+        assert!(operation.get(bucket, &key).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_inspect_probes_every_tier_without_promotion() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        );
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let value = Value(vec![4, 5, 6]);
+        assert_eq!(operation.inspect(bucket, &key).await?.entry, None);
+
+        operation.put(bucket, &key, &value).await?;
+        let cache_key = CacheKey::new(bucket, &key);
+        operation.in_memory_store.delete(&cache_key).await?;
+        operation.on_disk_store.delete(&cache_key).await?;
+
+        // Only S3 holds the key, and inspecting it twice leaves it that way
+        for _ in 0..2 {
+            let inspection = operation.inspect(bucket, &key).await?;
+            assert_eq!(inspection.entry.map(|e| e.value), Some(value.clone()));
+            assert!(!inspection.in_memory);
+            assert!(!inspection.on_disk);
+            assert!(inspection.in_cloud);
+            assert_eq!(inspection.ttl_remaining, None);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_exists_checks_tiers_without_reading_values() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        );
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let value = Value(vec![4, 5, 6]);
+        assert!(!operation.exists(bucket, &key).await?);
+
+        operation.put(bucket, &key, &value).await?;
+        assert!(operation.exists(bucket, &key).await?);
+
+        // Found on disk alone, and not promoted to memory by looking
+        let cache_key = CacheKey::new(bucket, &key);
+        operation.in_memory_store.delete(&cache_key).await?;
+        operation.cloud_store.delete(&cache_key).await?;
+        assert!(operation.exists(bucket, &key).await?);
+        assert!(operation.in_memory_store.get(&cache_key).await?.is_none());
+
+        operation.delete(bucket, &key).await?;
+        assert!(!operation.exists(bucket, &key).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        );
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let other = Key(vec![7, 8, 9]);
+        let value = Value(vec![4, 5, 6]);
+
+        operation.put(bucket, &key, &value).await?;
+        operation.put(bucket, &other, &value).await?;
+        assert!(operation.delete(bucket, &key).await?);
+        assert!(operation.get(bucket, &key).await?.is_none());
+
+        // Only the named key is removed.
+        assert_eq!(
+            operation.get(bucket, &other).await?.map(|e| e.value),
+            Some(value)
+        );
+
+        // Deleting again is still successful but reports the key as absent.
+        assert!(!operation.delete(bucket, &key).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_self_test() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        );
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let value = Value(vec![4, 5, 6]);
+
+        let checks = operation.self_test(bucket, &key, &value).await;
+        assert_eq!(checks.len(), 3);
+        assert!(checks.iter().all(|c| c.successful && c.error.is_none()));
+
+        // The sentinel must not be left behind in any tier.
+        assert!(operation.get(bucket, &key).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_cancelled_after_cloud_write() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            HangingStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        );
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let old_value = Value(vec![4, 5, 6]);
+        let new_value = Value(vec![7, 8, 9]);
+
+        operation.put(bucket, &key, &old_value).await?;
+
+        // Drop the put while it is blocked on the disk write, after S3 has the new value.
+        operation
+            .on_disk_store
+            .hang_next_put
+            .store(true, Ordering::SeqCst);
+        let put = operation.put(bucket, &key, &new_value);
+        assert!(tokio::time::timeout(Duration::from_millis(10), put)
+            .await
+            .is_err());
+
+        assert_eq!(
+            operation.get(bucket, &key).await?.map(|e| e.value),
+            Some(new_value)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_puts_leave_tiers_consistent() -> Result<()> {
+        let operation = Operation::new(
+            YieldingStore::new(),
+            YieldingStore::new(),
+            YieldingStore::new(),
+            test_metrics(),
+        );
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let values: Vec<Value> = (0..16u8).map(|i| Value(vec![i])).collect();
+
+        let puts = values
+            .iter()
+            .map(|value| operation.put(bucket, &key, value));
+        for result in futures::future::join_all(puts).await {
+            result?;
+        }
+
+        // Every tier holds the entry of whichever put ran last.
+        let memory = operation
+            .in_memory_store
+            .get(&CacheKey::new(bucket, &key))
+            .await?;
+        assert!(memory.is_some());
+        assert_eq!(
+            operation
+                .on_disk_store
+                .get(&CacheKey::new(bucket, &key))
+                .await?,
+            memory
+        );
+        assert_eq!(
+            operation
+                .cloud_store
+                .get(&CacheKey::new(bucket, &key))
+                .await?,
+            memory
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stalled_key_does_not_block_other_keys() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            HangingStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        );
+
+        let bucket = "bucket";
+        let stalled_key = Key(vec![1, 2, 3]);
+        let other_key = Key(vec![7, 8, 9]);
+        let value = Value(vec![4, 5, 6]);
+
+        // Polled first, the put blocks on its disk write while holding its key.
+        operation
+            .on_disk_store
+            .hang_next_put
+            .store(true, Ordering::SeqCst);
+        let stalled = operation.put(bucket, &stalled_key, &value);
+        tokio::pin!(stalled);
+
+        tokio::select! {
+            biased;
+            _ = &mut stalled => panic!("put did not stall"),
+            result = operation.put(bucket, &other_key, &value) => result?,
+        }
+        tokio::select! {
+            biased;
+            _ = &mut stalled => panic!("put did not stall"),
+            read = tokio::time::timeout(
+                Duration::from_millis(10),
+                operation.get(bucket, &stalled_key),
+            ) => assert!(read.is_err()),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_skips_stalled_disk() -> Result<()> {
+        let metrics = test_metrics();
+        let operation = Operation::new(
+            MockStore::new(),
+            HangingStore::new(),
+            MockStore::new(),
+            metrics.clone(),
+        )
+        .with_timeouts(TierTimeouts {
+            disk: Some(Duration::from_millis(10)),
+            cloud: None,
+        });
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let value = Value(vec![4, 5, 6]);
+
+        operation
+            .cloud_store
+            .put(
+                &CacheKey::new(bucket, &key),
+                &Entry::new(value.clone(), &SystemClock),
+            )
+            .await?;
+        operation
+            .on_disk_store
+            .hang_next_get
+            .store(true, Ordering::SeqCst);
+
+        assert_eq!(
+            operation.get(bucket, &key).await?.map(|e| e.value),
+            Some(value)
+        );
+        let timeouts = metrics
+            .prometheus()
+            .unwrap()
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "cache_tier_timeouts_total")
+            .unwrap();
+        assert_eq!(timeouts.get_metric()[0].get_counter().get_value(), 1.0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_large_values_skip_memory() -> Result<()> {
+        let metrics = test_metrics();
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            metrics.clone(),
+        )
+        .with_value_limits(TierValueLimits {
+            memory: Some(4),
+            disk: None,
+        });
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let small = Value(vec![4, 5, 6]);
+        let large = Value(vec![7; 8]);
+
+        operation.put(bucket, &key, &small).await?;
+        assert!(operation
+            .in_memory_store
+            .get(&CacheKey::new(bucket, &key))
+            .await?
+            .is_some());
+
+        // The small copy must not outlive the write that replaced it
+        operation.put(bucket, &key, &large).await?;
+        assert!(operation
+            .in_memory_store
+            .get(&CacheKey::new(bucket, &key))
+            .await?
+            .is_none());
+        assert_eq!(
+            operation
+                .on_disk_store
+                .get(&CacheKey::new(bucket, &key))
+                .await?
+                .map(|e| e.value),
+            Some(large.clone())
+        );
+
+        // Served from disk without being promoted
+        assert_eq!(
+            operation.get(bucket, &key).await?.map(|e| e.value),
+            Some(large)
+        );
+        assert!(operation
+            .in_memory_store
+            .get(&CacheKey::new(bucket, &key))
+            .await?
+            .is_none());
+
+        let skipped = metrics
+            .prometheus()
+            .unwrap()
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "cache_tier_skipped_values_total")
+            .unwrap();
+        assert_eq!(skipped.get_metric()[0].get_counter().get_value(), 2.0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_after_write_retry() -> Result<()> {
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let value = Value(vec![4, 5, 6]);
+        let retry = ReadAfterWriteRetry {
+            attempts: 3,
+            delay: Duration::from_millis(1),
+            window: Duration::from_secs(60),
+        };
+
+        // Without retries the lagging miss is returned as is
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            LaggingStore::new(2),
+            test_metrics(),
+        );
+        operation.put(bucket, &key, &value).await?;
+        operation
+            .in_memory_store
+            .delete(&CacheKey::new(bucket, &key))
+            .await?;
+        operation
+            .on_disk_store
+            .delete(&CacheKey::new(bucket, &key))
+            .await?;
+        assert!(operation.get(bucket, &key).await?.is_none());
+
+        let metrics = test_metrics();
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            LaggingStore::new(2),
+            metrics.clone(),
+        )
+        .with_read_after_write_retry(retry);
+        operation.put(bucket, &key, &value).await?;
+        operation
+            .in_memory_store
+            .delete(&CacheKey::new(bucket, &key))
+            .await?;
+        operation
+            .on_disk_store
+            .delete(&CacheKey::new(bucket, &key))
+            .await?;
+        assert_eq!(
+            operation.get(bucket, &key).await?.map(|e| e.value),
+            Some(value.clone())
+        );
+
+        // Keys this node did not write are not retried
+        let other = Key(vec![7, 8, 9]);
+        operation
+            .cloud_store
+            .put(
+                &CacheKey::new(bucket, &other),
+                &Entry::new(value.clone(), &SystemClock),
+            )
+            .await?;
+        assert!(operation.get(bucket, &other).await?.is_none());
+
+        // Nor are keys it deleted since
+        operation.put(bucket, &key, &value).await?;
+        operation.delete(bucket, &key).await?;
+        assert!(operation.get(bucket, &key).await?.is_none());
+
+        let retries = metrics
+            .prometheus()
+            .unwrap()
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "cache_read_after_write_retries_total")
+            .unwrap();
+        assert_eq!(retries.get_metric().len(), 1);
+        assert_eq!(retries.get_metric()[0].get_counter().get_value(), 1.0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stale_served_when_cloud_fails() -> Result<()> {
+        let metrics = test_metrics();
+        let timeouts = TierTimeouts {
+            disk: None,
+            cloud: Some(Duration::from_millis(10)),
+        };
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let value = Value(vec![4, 5, 6]);
+
+        let strict = Operation::new(
+            MockStore::new(),
+            ExpiringStore::new(),
+            HangingStore::new(),
+            metrics.clone(),
+        )
+        .with_timeouts(timeouts);
+        strict.put(bucket, &key, &value).await?;
+        strict
+            .in_memory_store
+            .delete(&CacheKey::new(bucket, &key))
+            .await?;
+        strict.on_disk_store.expired.store(true, Ordering::SeqCst);
+        strict
+            .cloud_store
+            .hang_next_get
+            .store(true, Ordering::SeqCst);
+        assert!(strict.get(bucket, &key).await.is_err());
+
+        let operation = Operation::new(
+            MockStore::new(),
+            ExpiringStore::new(),
+            HangingStore::new(),
+            metrics.clone(),
+        )
+        .with_timeouts(timeouts)
+        .with_max_stale(Duration::from_secs(3600));
+        operation.put(bucket, &key, &value).await?;
+        operation
+            .in_memory_store
+            .delete(&CacheKey::new(bucket, &key))
+            .await?;
+        operation
+            .on_disk_store
+            .expired
+            .store(true, Ordering::SeqCst);
+        operation
+            .cloud_store
+            .hang_next_get
+            .store(true, Ordering::SeqCst);
+
+        let lookup = operation
+            .get_with_deadline(bucket, &key, None)
+            .await?
+            .unwrap();
+        assert!(lookup.stale);
+        assert_eq!(lookup.tier, Tier::Disk);
+        assert_eq!(lookup.entry.value, value);
+        // A stale copy is not promoted, so the next read tries S3 again.
+        assert!(operation
+            .in_memory_store
+            .get(&CacheKey::new(bucket, &key))
+            .await?
+            .is_none());
+        let lookup = operation
+            .get_with_deadline(bucket, &key, None)
+            .await?
+            .unwrap();
+        assert!(!lookup.stale);
+        assert_eq!(lookup.tier, Tier::Cloud);
+
+        let served = metrics
+            .prometheus()
+            .unwrap()
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "cache_stale_served_total")
+            .unwrap();
+        assert_eq!(served.get_metric()[0].get_counter().get_value(), 1.0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_degraded_mode_skips_cloud() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        )
+        .without_cloud();
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let value = Value(vec![4, 5, 6]);
+
+        operation.put(bucket, &key, &value).await?;
+        assert!(operation
+            .cloud_store
+            .get(&CacheKey::new(bucket, &key))
+            .await?
+            .is_none());
+        assert_eq!(
+            operation.get(bucket, &key).await?.map(|e| e.value),
+            Some(value)
+        );
+        assert!(operation.delete(bucket, &key).await?);
+
+        let checks = operation.self_test(bucket, &key, &Value(vec![7])).await;
+        assert!(!checks[2].successful);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replica_puts_skip_cloud() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        )
+        .with_write_mode(WriteMode::LatencyFirst);
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let value = Value(vec![4, 5, 6]);
+
+        operation
+            .put_replica(bucket, &key, &value, false, None)
+            .await?;
+        assert_eq!(operation.persist_pending().await?, 0);
+        assert!(operation
+            .cloud_store
+            .get(&CacheKey::new(bucket, &key))
+            .await?
+            .is_none());
+        assert_eq!(
+            operation
+                .on_disk_store
+                .get(&CacheKey::new(bucket, &key))
+                .await?
+                .map(|e| e.value),
+            Some(value.clone())
+        );
+        assert_eq!(
+            operation.get(bucket, &key).await?.map(|e| e.value),
+            Some(value)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_s3_read_only_bucket_skips_cloud_writes() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        )
+        .with_s3_read_only_buckets(HashSet::from(["external".to_string()]));
+
+        let key = Key(vec![1, 2, 3]);
+        let external = Entry::new(Value(vec![1]), &SystemClock);
+        operation
+            .cloud_store
+            .put(&CacheKey::new("external", &key), &external)
+            .await?;
+
+        // Writes are cached but S3 keeps the externally written value
+        operation.put("external", &key, &Value(vec![2])).await?;
+        assert_eq!(
+            operation.get("external", &key).await?.map(|e| e.value),
+            Some(Value(vec![2]))
+        );
+        assert_eq!(
+            operation
+                .cloud_store
+                .get(&CacheKey::new("external", &key))
+                .await?,
+            Some(external.clone())
+        );
+
+        // Deletes only drop the cached copies, so reads fall back to S3
+        assert!(operation.delete("external", &key).await?);
+        assert_eq!(operation.get("external", &key).await?, Some(external));
+
+        // Other buckets still write through
+        operation.put("bucket", &key, &Value(vec![3])).await?;
+        assert!(operation
+            .cloud_store
+            .get(&CacheKey::new("bucket", &key))
+            .await?
+            .is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_checks_cloud_only_when_enabled() -> Result<()> {
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let cache_key = CacheKey::new(bucket, &key);
+        let entry = Entry::new(Value(vec![4, 5, 6]), &SystemClock);
+        for checks_cloud in [false, true] {
+            let mut operation = Operation::new(
+                MockStore::new(),
+                MockStore::new(),
+                MockStore::new(),
+                test_metrics(),
+            );
+            if checks_cloud {
+                operation = operation.with_cloud_delete_checks();
+            }
+            // Held only by S3, as after the cached copies were evicted
+            operation.cloud_store.put(&cache_key, &entry).await?;
+
+            assert_eq!(operation.delete(bucket, &key).await?, checks_cloud);
+            assert!(operation.cloud_store.get(&cache_key).await?.is_none());
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_local_deletes_keep_cloud_object() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        )
+        .with_local_deletes();
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let value = Value(vec![4, 5, 6]);
+
+        operation.put(bucket, &key, &value).await?;
+        assert!(operation.delete(bucket, &key).await?);
+        assert!(operation
+            .in_memory_store
+            .get(&CacheKey::new(bucket, &key))
+            .await?
+            .is_none());
+        assert!(operation
+            .on_disk_store
+            .get(&CacheKey::new(bucket, &key))
+            .await?
+            .is_none());
+        assert_eq!(
+            operation.get(bucket, &key).await?.map(|e| e.value),
+            Some(value)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_written_at_survives_promotion() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        );
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let entry = Entry {
+            value: Value(vec![4, 5, 6]),
+            written_at: 42,
+            expires_at: None,
+        };
+
+        operation
+            .cloud_store
+            .put(&CacheKey::new(bucket, &key), &entry)
+            .await?;
+        assert_eq!(operation.get(bucket, &key).await?, Some(entry.clone()));
+        assert_eq!(
+            operation
+                .on_disk_store
+                .get(&CacheKey::new(bucket, &key))
+                .await?,
+            Some(entry.clone())
+        );
+        assert_eq!(
+            operation
+                .in_memory_store
+                .get(&CacheKey::new(bucket, &key))
+                .await?,
+            Some(entry)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lookup_reports_tier() -> Result<()> {
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        );
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let cache_key = CacheKey::new(bucket, &key);
+        operation
+            .cloud_store
+            .put(&cache_key, &Entry::new(Value(vec![4, 5, 6]), &SystemClock))
+            .await?;
+
+        let tier = |lookup: Option<Lookup>| lookup.map(|lookup| lookup.tier);
+        let read = || operation.get_with_deadline(bucket, &key, None);
+        assert_eq!(tier(read().await?), Some(Tier::Cloud));
+        assert_eq!(tier(read().await?), Some(Tier::Memory));
+        operation.in_memory_store.delete(&cache_key).await?;
+        assert_eq!(tier(read().await?), Some(Tier::Disk));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reads_are_counted_by_tier() -> Result<()> {
+        let metrics = test_metrics();
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            metrics.clone(),
+        );
+
+        let bucket = "bucket";
+        let entry = Entry::new(Value(vec![4, 5, 6]), &SystemClock);
+        let in_memory = Key(vec![1]);
+        let on_disk = Key(vec![2]);
+        let in_cloud = Key(vec![3]);
+        operation
+            .in_memory_store
+            .put(&CacheKey::new(bucket, &in_memory), &entry)
+            .await?;
+        operation
+            .on_disk_store
+            .put(&CacheKey::new(bucket, &on_disk), &entry)
+            .await?;
+        operation
+            .cloud_store
+            .put(&CacheKey::new(bucket, &in_cloud), &entry)
+            .await?;
+
+        for key in [&in_memory, &on_disk, &in_cloud, &Key(vec![4])] {
+            operation.get(bucket, key).await?;
+        }
+        // Both lower-tier hits were promoted to memory
+        operation.get(bucket, &on_disk).await?;
+        operation.get(bucket, &in_cloud).await?;
+
+        let lookups = metrics
+            .prometheus()
+            .unwrap()
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "cache_tier_lookups_total")
+            .unwrap();
+        let count = |tier: &str| {
+            lookups
+                .get_metric()
+                .iter()
+                .find(|metric| metric.get_label().iter().any(|l| l.get_value() == tier))
+                .map_or(0.0, |metric| metric.get_counter().get_value())
+        };
+        assert_eq!(count("memory"), 3.0);
+        assert_eq!(count("disk"), 1.0);
+        assert_eq!(count("cloud"), 1.0);
+        assert_eq!(count("miss"), 1.0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_disk_hit_promotes_to_lru() -> Result<()> {
+        let operation = Operation::new(
+            LRUStore::new(10),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        );
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let cache_key = CacheKey::new(bucket, &key);
+        let entry = Entry::new(Value(vec![4, 5, 6]), &SystemClock);
+        operation.on_disk_store.put(&cache_key, &entry).await?;
+
+        let lookup = operation.get_with_deadline(bucket, &key, None).await?;
+        assert_eq!(lookup.map(|lookup| lookup.tier), Some(Tier::Disk));
+        // The LRU is keyed by the same digest as the disk tier
+        assert_eq!(
+            operation.in_memory_store.get(&cache_key).await?,
+            Some(entry.clone())
+        );
+
+        let lookup = operation
+            .get_with_deadline(bucket, &key, None)
+            .await?
+            .unwrap();
+        assert_eq!(lookup.tier, Tier::Memory);
+        assert_eq!(lookup.entry, entry);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_key_migration_moves_objects_to_current_salt() -> Result<()> {
+        let metrics = test_metrics();
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            metrics.clone(),
+        )
+        .with_key_migration(MockStore::new());
+        let previous = operation.previous_cloud_store.as_ref().unwrap();
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let cache_key = CacheKey::new(bucket, &key);
+        let entry = Entry::new(Value(vec![4, 5, 6]), &SystemClock);
+        previous.put(&cache_key, &entry).await?;
+
+        assert_eq!(operation.get(bucket, &key).await?, Some(entry.clone()));
+        assert_eq!(operation.cloud_store.get(&cache_key).await?, Some(entry));
+        assert!(previous.get(&cache_key).await?.is_none());
+
+        // A delete reaches objects not yet migrated, so they cannot come back
+        let other = Key(vec![7, 8, 9]);
+        previous
+            .put(
+                &CacheKey::new(bucket, &other),
+                &Entry::new(Value(vec![1]), &SystemClock),
+            )
+            .await?;
+        // Only S3 held it, which deletes do not check by default
+        assert!(!operation.delete(bucket, &other).await?);
+        assert!(operation.get(bucket, &other).await?.is_none());
+
+        let migrated = metrics
+            .prometheus()
+            .unwrap()
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "cache_keys_migrated_total")
+            .unwrap();
+        assert_eq!(migrated.get_metric()[0].get_counter().get_value(), 1.0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_chaos_faults() -> Result<()> {
+        let rules = crate::chaos::parse_rules("put:drop:1,delete:error:1")?;
+        let operation = Operation::new(
+            MockStore::new(),
+            MockStore::new(),
+            MockStore::new(),
+            test_metrics(),
+        )
+        .with_chaos(Arc::new(Chaos::new(rules, Some(7))));
+
+        let bucket = "bucket";
+        let key = Key(vec![1, 2, 3]);
+        let value = Value(vec![4, 5, 6]);
+
+        // The dropped put still lands; the failing delete never runs.
+        assert!(operation.put(bucket, &key, &value).await.is_err());
+        assert!(operation.delete(bucket, &key).await.is_err());
+        assert_eq!(
+            operation.get(bucket, &key).await?.map(|e| e.value),
+            Some(value)
+        );
 
         Ok(())
     }
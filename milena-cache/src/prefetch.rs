@@ -0,0 +1,141 @@
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+use lru::LruCache;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+use crate::metrics::Metrics;
+use crate::service::SharedOperation;
+use crate::store::Key;
+
+/// Number of prefetched keys remembered while waiting to be read. Keys pushed
+/// out of this window without a read are counted as wasted.
+const TRACKED_PREFETCHES: usize = 10_000;
+
+/// Speculatively loads keys that are likely to be read next from S3 into the
+/// disk and memory tiers.
+pub struct Prefetcher {
+    count: usize,
+    permits: Arc<Semaphore>,
+    pending: Mutex<LruCache<(String, Vec<u8>), ()>>,
+    metrics: Arc<Metrics>,
+    shutdown: CancellationToken,
+}
+
+impl Prefetcher {
+    pub fn new(count: usize, concurrency: usize, metrics: Arc<Metrics>) -> Self {
+        Self {
+            count,
+            permits: Arc::new(Semaphore::new(concurrency)),
+            pending: Mutex::new(LruCache::new(
+                NonZeroUsize::new(TRACKED_PREFETCHES).unwrap(),
+            )),
+            metrics,
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    /// Stops scheduling loads and abandons those in flight once `shutdown` is cancelled.
+    pub fn with_shutdown(mut self, shutdown: CancellationToken) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Records a client read, counting it as a prefetch hit if the key was
+    /// loaded speculatively.
+    pub fn record_access(&self, bucket: &str, key: &Key) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.pop(&(bucket.to_string(), key.0.clone())).is_some() {
+            self.metrics.record_prefetch_hit();
+        }
+    }
+
+    /// Keys to prefetch after a read of `key`: the client hints when given,
+    /// otherwise the keys following a trailing decimal number in `key`.
+    pub fn candidates(&self, key: &Key, hints: Vec<Vec<u8>>) -> Vec<Key> {
+        let keys = if hints.is_empty() {
+            numeric_successors(&key.0, self.count)
+        } else {
+            hints
+        };
+        keys.into_iter()
+            .filter(|k| !k.is_empty() && *k != key.0)
+            .take(self.count)
+            .map(Key)
+            .collect()
+    }
+
+    /// Starts background loads for `keys`. Keys that find no free permit are
+    /// skipped rather than queued, and failures never reach the caller.
+    pub fn schedule(self: &Arc<Self>, operation: SharedOperation, bucket: &str, keys: Vec<Key>) {
+        if self.shutdown.is_cancelled() {
+            return;
+        }
+        for key in keys {
+            let Ok(permit) = self.permits.clone().try_acquire_owned() else {
+                return;
+            };
+            let prefetcher = self.clone();
+            let operation = operation.clone();
+            let bucket = bucket.to_string();
+            tokio::spawn(async move {
+                let _permit = permit;
+                let result = tokio::select! {
+                    _ = prefetcher.shutdown.cancelled() => return,
+                    result = operation.prefetch(&bucket, &key) => result,
+                };
+                match result {
+                    Ok(true) => prefetcher.track(bucket, key),
+                    Ok(false) => {}
+                    Err(e) => debug!("Prefetch failed: {}", e),
+                }
+            });
+        }
+    }
+
+    fn track(&self, bucket: String, key: Key) {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some((evicted, _)) = pending.push((bucket, key.0), ()) {
+            // `push` also returns the entry it replaced when the key was already tracked.
+            if !pending.contains(&evicted) {
+                self.metrics.record_prefetch_wasted();
+            }
+        }
+    }
+}
+
+/// `obj:9` -> `obj:10`, `obj:11`, ...; zero padding is kept (`a07` -> `a08`).
+fn numeric_successors(key: &[u8], count: usize) -> Vec<Vec<u8>> {
+    let digits = key.iter().rev().take_while(|b| b.is_ascii_digit()).count();
+    if digits == 0 {
+        return vec![];
+    }
+    let (prefix, suffix) = key.split_at(key.len() - digits);
+    let Some(n) = std::str::from_utf8(suffix)
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+    else {
+        return vec![];
+    };
+
+    (1..=count as u64)
+        .filter_map(|i| n.checked_add(i))
+        .map(|next| {
+            let mut successor = prefix.to_vec();
+            successor.extend(format!("{:0width$}", next, width = digits).into_bytes());
+            successor
+        })
+        .collect()
+}
+
+#[test]
+fn test_numeric_successors() {
+    assert_eq!(
+        numeric_successors(b"obj:9", 2),
+        vec![b"obj:10".to_vec(), b"obj:11".to_vec()]
+    );
+    assert_eq!(numeric_successors(b"a07", 1), vec![b"a08".to_vec()]);
+    assert!(numeric_successors(b"obj", 3).is_empty());
+}
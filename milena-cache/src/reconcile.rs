@@ -0,0 +1,114 @@
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lru::LruCache;
+use rand::seq::IteratorRandom;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::metrics::Metrics;
+use crate::service::SharedOperation;
+use crate::store::Key;
+
+/// Number of recently used keys the reconciler samples from.
+const CANDIDATES: usize = 10_000;
+
+/// Periodically checks the memory and disk copies of recently used keys
+/// against S3, repairing copies that diverged after a partial write failure.
+pub struct Reconciler {
+    interval: Duration,
+    /// Keys checked per run, which bounds the S3 reads made per interval.
+    batch_size: usize,
+    candidates: Mutex<LruCache<(String, Vec<u8>), ()>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Reconciler {
+    pub fn new(interval: Duration, batch_size: usize, metrics: Arc<Metrics>) -> Self {
+        Self {
+            interval,
+            batch_size,
+            candidates: Mutex::new(LruCache::new(NonZeroUsize::new(CANDIDATES).unwrap())),
+            metrics,
+        }
+    }
+
+    /// Makes a key that was just read or written eligible for sampling.
+    pub fn record_access(&self, bucket: &str, key: &Key) {
+        self.candidates
+            .lock()
+            .unwrap()
+            .put((bucket.to_string(), key.0.clone()), ());
+    }
+
+    fn sample(&self) -> Vec<(String, Vec<u8>)> {
+        let candidates = self.candidates.lock().unwrap();
+        candidates
+            .iter()
+            .map(|(candidate, _)| candidate.clone())
+            .choose_multiple(&mut rand::thread_rng(), self.batch_size)
+    }
+
+    /// Reconciles one sampled batch per interval until `shutdown` is cancelled.
+    /// Only the key being checked is locked, so client requests on other keys
+    /// proceed.
+    pub async fn run(self: Arc<Self>, operation: SharedOperation, shutdown: CancellationToken) {
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = ticker.tick() => {}
+            }
+            for (bucket, key) in self.sample() {
+                if shutdown.is_cancelled() {
+                    return;
+                }
+                let result = operation.reconcile(&bucket, &Key(key)).await;
+                match result {
+                    Ok(outcome) => {
+                        debug!("Reconciled key in bucket {}: {:?}", bucket, outcome);
+                        self.metrics.record_reconciled(outcome.as_str());
+                    }
+                    Err(e) => {
+                        warn!("Reconciling key in bucket {} failed: {}", bucket, e);
+                        self.metrics.record_reconciled("error");
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_reconciler_samples_at_most_a_batch_of_recorded_keys() {
+    let reconciler = Reconciler::new(
+        Duration::from_secs(60),
+        3,
+        Arc::new(Metrics::new().unwrap()),
+    );
+    assert!(reconciler.sample().is_empty());
+
+    // A key used repeatedly is still one candidate
+    for i in 0..2 {
+        reconciler.record_access("users", &Key(vec![i]));
+        reconciler.record_access("users", &Key(vec![i]));
+    }
+    let mut sample = reconciler.sample();
+    sample.sort();
+    assert_eq!(
+        sample,
+        vec![
+            ("users".to_string(), vec![0]),
+            ("users".to_string(), vec![1])
+        ]
+    );
+
+    for i in 0..10 {
+        reconciler.record_access("orders", &Key(vec![i]));
+    }
+    let sample = reconciler.sample();
+    assert_eq!(sample.len(), 3);
+    let unique: std::collections::HashSet<_> = sample.into_iter().collect();
+    assert_eq!(unique.len(), 3);
+}
@@ -1,110 +1,699 @@
 use crate::{
-    metrics::Metrics,
-    operation::Operation,
-    store::{DiskStore, Key, LRUStore, S3Store, Value},
+    bucket_limits::{BucketLimits, BucketPermit},
+    idempotency::{IdempotencyKeys, Outcome},
+    metrics::{Metrics, Op},
+    operation::{Operation, QuotaExceeded, Tier},
+    prefetch::Prefetcher,
+    reconcile::Reconciler,
+    shards::{self, ShardSampler},
+    store::{DiskStore, Key, LRUStore, S3Store, StoreError, Value},
+    watch::{Lagged, Watchers},
 };
+use futures::future::try_join_all;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use std::future::Future;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tonic::Response;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tonic::{metadata::MetadataMap, Response};
 
 use milena_protos::cache_server::{
-    cache_server::Cache, DeleteRequest, DeleteResponse, GetRequest, GetResponse,
-    PutResponse,
+    cache_server::Cache, BatchGetRequest, BatchGetResponse, BatchPutRequest, BatchPutResponse,
+    BucketInfo, DeleteRequest, DeleteResponse, ExistsRequest, ExistsResponse, GetRequest,
+    GetResponse, HealthRequest, HealthResponse, InspectRequest, InspectResponse, KeyShardsRequest,
+    KeyShardsResponse, ListBucketsRequest, ListBucketsResponse, PinRequest, PinResponse,
+    PutRequest, PutResponse, SelfTestRequest, SelfTestResponse, ServedFrom, ServingStatus,
+    TierSelfTest, UnpinRequest, UnpinResponse, WatchEvent, WatchRequest,
 };
+use milena_protos::normalization::KeyNormalization;
+
+/// Response metadata set when a GET was served from an expired disk entry.
+pub const STALE_METADATA: &str = "x-milena-stale";
+
+pub type SharedOperation = Arc<Operation<LRUStore, DiskStore, S3Store>>;
+
+/// A mutating call made to a read-only node.
+#[derive(Debug, Error)]
+#[error("{0} is not available on a read-only node")]
+pub struct ReadOnly(&'static str);
+
+impl From<ReadOnly> for tonic::Status {
+    fn from(e: ReadOnly) -> Self {
+        tonic::Status::permission_denied(e.to_string())
+    }
+}
+
+/// A call turned away because its bucket has no request slot free.
+#[derive(Debug, Error)]
+#[error("Too many requests in flight for bucket {0}")]
+pub struct BucketBusy(String);
+
+impl From<BucketBusy> for tonic::Status {
+    fn from(e: BucketBusy) -> Self {
+        tonic::Status::resource_exhausted(e.to_string())
+    }
+}
 
 pub struct CacheService {
-    pub operation: Arc<Mutex<Operation<LRUStore, DiskStore, S3Store>>>,
+    pub operation: SharedOperation,
     pub metrics: Arc<Metrics>,
+    /// Internal bucket used by `self_test`, kept apart from user buckets.
+    pub self_test_bucket: String,
+    pub prefetcher: Option<Arc<Prefetcher>>,
+    pub shard_sampler: Option<Arc<ShardSampler>>,
+    pub reconciler: Option<Arc<Reconciler>>,
+    /// Deduplicates retried writes carrying an idempotency key; `None` when
+    /// disabled, in which case the keys are ignored.
+    pub idempotency_keys: Option<Arc<IdempotencyKeys>>,
+    /// Serves `watch`; `None` when watching is disabled.
+    pub watchers: Option<Arc<Watchers>>,
+    /// Caps concurrent gets, puts and deletes per bucket; `None` when unlimited.
+    pub bucket_limits: Option<Arc<BucketLimits>>,
+    pub key_normalization: KeyNormalization,
+    /// Refuse puts, deletes, pins and unpins without touching the stores.
+    pub read_only: bool,
 }
 
-#[tonic::async_trait]
-impl Cache for CacheService {
-    async fn get(
+impl CacheService {
+    fn key(&self, key: Vec<u8>) -> Key {
+        Key(self.key_normalization.apply(key))
+    }
+
+    fn check_writable(&self, method: &'static str) -> Result<(), ReadOnly> {
+        if self.read_only {
+            return Err(ReadOnly(method));
+        }
+        Ok(())
+    }
+
+    // Holds one of `bucket`'s request slots until the returned permit is dropped.
+    fn admit(&self, bucket: &str) -> Result<Option<BucketPermit>, BucketBusy> {
+        let Some(limits) = &self.bucket_limits else {
+            return Ok(None);
+        };
+        match limits.try_acquire(bucket) {
+            Some(permit) => Ok(Some(permit)),
+            None => Err(BucketBusy(bucket.to_string())),
+        }
+    }
+
+    // Applies a put or delete, or replays its outcome to a retry.
+    async fn write_once<F, Fut>(
         &self,
-        request: tonic::Request<GetRequest>,
-    ) -> std::result::Result<Response<GetResponse>, tonic::Status> {
-        let timer = self.metrics.operation_duration.start_timer();
-        self.metrics.request_counter.inc();
+        op: Op,
+        bucket: &str,
+        key: &Key,
+        idempotency_key: &str,
+        write: F,
+    ) -> anyhow::Result<Outcome>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<Outcome>>,
+    {
+        match &self.idempotency_keys {
+            Some(keys) if !idempotency_key.is_empty() => {
+                keys.run(op, bucket, key, idempotency_key, write).await
+            }
+            _ => write().await,
+        }
+    }
 
-        let request_ref = request.into_inner();
-        let key = Key(request_ref.key);
+    fn sample_key_shard(&self, bucket: &str, key: &Key) {
+        if let Some(skew) = self
+            .shard_sampler
+            .as_ref()
+            .and_then(|sampler| sampler.record(bucket, key))
+        {
+            self.metrics.record_key_shard_skew(skew);
+        }
+    }
+
+    // Serves one get, also reporting whether the value came from an expired
+    // disk entry.
+    async fn get_one(
+        &self,
+        request_ref: GetRequest,
+        deadline: Option<Instant>,
+    ) -> Result<(GetResponse, bool), tonic::Status> {
+        let timer = Instant::now();
+        self.metrics.record_request(Op::Get);
+
+        let epoch = request_ref.epoch;
+        let raw_key = self.key(request_ref.key);
+        let key = raw_key.clone().in_epoch(epoch);
         let bucket = &request_ref.bucket;
+        let _permit = self.admit(bucket)?;
+
+        if let Some(prefetcher) = &self.prefetcher {
+            prefetcher.record_access(bucket, &key);
+        }
+        self.sample_key_shard(bucket, &key);
+        if let Some(reconciler) = &self.reconciler {
+            reconciler.record_access(bucket, &key);
+        }
 
         let result = self
             .operation
-            .lock()
-            .await
-            .get(bucket, &key)
+            .get_with_deadline(bucket, &key, deadline)
             .await
             .map_err(|e| {
-                self.metrics.error_counter.inc();
-                tonic::Status::new(tonic::Code::Internal, format!("{e}"))
+                self.metrics.record_error();
+                error_status(e)
             })?;
-        timer.observe_duration();
+        self.metrics.record_duration(Op::Get, timer.elapsed());
 
-        if let Some(v) = result {
-            self.metrics.cache_hits.inc();
-            Ok(Response::new(GetResponse {
+        if let Some(prefetcher) = &self.prefetcher {
+            let keys = prefetcher
+                .candidates(
+                    &raw_key,
+                    request_ref
+                        .prefetch_keys
+                        .into_iter()
+                        .map(|key| self.key_normalization.apply(key))
+                        .collect(),
+                )
+                .into_iter()
+                .map(|k| k.in_epoch(epoch))
+                .collect();
+            prefetcher.schedule(self.operation.clone(), bucket, keys);
+        }
+
+        if let Some(lookup) = result {
+            self.metrics.record_get(true);
+            let response = GetResponse {
                 successful: true,
-                value: v.0,
-            }))
+                value: lookup.entry.value.0,
+                written_at_millis: lookup.entry.written_at,
+                served_from: served_from(lookup.tier) as i32,
+                found: true,
+            };
+            Ok((response, lookup.stale))
         } else {
-            self.metrics.cache_misses.inc();
-            Ok(Response::new(GetResponse {
+            self.metrics.record_get(false);
+            let response = GetResponse {
                 successful: true,
                 value: vec![],
-            }))
+                written_at_millis: 0,
+                served_from: ServedFrom::Miss as i32,
+                found: false,
+            };
+            Ok((response, false))
+        }
+    }
+
+    async fn put_one(&self, request_ref: PutRequest) -> Result<PutResponse, tonic::Status> {
+        let timer = Instant::now();
+        self.metrics.record_request(Op::Put);
+
+        let key = self.key(request_ref.key).in_epoch(request_ref.epoch);
+        let bucket = &request_ref.bucket;
+        let _permit = self.admit(bucket)?;
+        let value = Value(request_ref.value);
+        let ttl =
+            (request_ref.ttl_seconds > 0).then_some(Duration::from_secs(request_ref.ttl_seconds));
+        self.sample_key_shard(bucket, &key);
+        if let Some(reconciler) = &self.reconciler {
+            reconciler.record_access(bucket, &key);
+        }
+
+        self.write_once(
+            Op::Put,
+            bucket,
+            &key,
+            &request_ref.idempotency_key,
+            || async {
+                if request_ref.replica {
+                    self.operation
+                        .put_replica(bucket, &key, &value, request_ref.durable, ttl)
+                        .await
+                } else {
+                    self.operation
+                        .put_with_durability(bucket, &key, &value, request_ref.durable, ttl)
+                        .await
+                }
+                .map(|()| Outcome::Put)
+            },
+        )
+        .await
+        .map_err(|e| {
+            self.metrics.record_error();
+            error_status(e)
+        })?;
+        self.metrics.record_duration(Op::Put, timer.elapsed());
+
+        Ok(PutResponse {
+            successful: true,
+            value: if request_ref.return_value {
+                value.0
+            } else {
+                Vec::new()
+            },
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl Cache for CacheService {
+    type WatchStream = BoxStream<'static, Result<WatchEvent, tonic::Status>>;
+
+    async fn get(
+        &self,
+        request: tonic::Request<GetRequest>,
+    ) -> std::result::Result<Response<GetResponse>, tonic::Status> {
+        let deadline = request_deadline(request.metadata());
+        let (response, stale) = self.get_one(request.into_inner(), deadline).await?;
+        let mut response = Response::new(response);
+        if stale {
+            response
+                .metadata_mut()
+                .insert(STALE_METADATA, "true".parse().unwrap());
         }
+        Ok(response)
     }
 
     async fn put(
         &self,
-        request: tonic::Request<milena_protos::cache_server::PutRequest>,
+        request: tonic::Request<PutRequest>,
     ) -> std::result::Result<Response<PutResponse>, tonic::Status> {
-        let timer = self.metrics.operation_duration.start_timer();
-        self.metrics.request_counter.inc();
+        self.check_writable("Put")?;
+        Ok(Response::new(self.put_one(request.into_inner()).await?))
+    }
+
+    // Stale reads are not flagged, since the metadata covers the whole batch.
+    async fn batch_get(
+        &self,
+        request: tonic::Request<BatchGetRequest>,
+    ) -> std::result::Result<Response<BatchGetResponse>, tonic::Status> {
+        let deadline = request_deadline(request.metadata());
+        let responses = try_join_all(
+            request
+                .into_inner()
+                .requests
+                .into_iter()
+                .map(|get| self.get_one(get, deadline)),
+        )
+        .await?;
+        Ok(Response::new(BatchGetResponse {
+            responses: responses
+                .into_iter()
+                .map(|(response, _stale)| response)
+                .collect(),
+        }))
+    }
+
+    // Applied one at a time so that later puts to a key win.
+    async fn batch_put(
+        &self,
+        request: tonic::Request<BatchPutRequest>,
+    ) -> std::result::Result<Response<BatchPutResponse>, tonic::Status> {
+        self.check_writable("BatchPut")?;
+        let mut responses = Vec::new();
+        for put in request.into_inner().requests {
+            responses.push(self.put_one(put).await?);
+        }
+        Ok(Response::new(BatchPutResponse { responses }))
+    }
+
+    async fn delete(
+        &self,
+        request: tonic::Request<DeleteRequest>,
+    ) -> std::result::Result<Response<DeleteResponse>, tonic::Status> {
+        self.check_writable("Delete")?;
+        let timer = Instant::now();
+        self.metrics.record_request(Op::Delete);
 
         let request_ref = request.into_inner();
-        let key = Key(request_ref.key);
+        let key = self.key(request_ref.key).in_epoch(request_ref.epoch);
         let bucket = &request_ref.bucket;
-        let value = request_ref.value;
+        let _permit = self.admit(bucket)?;
 
-        self.operation
-            .lock()
-            .await
-            .put(bucket, &key, &Value(value))
+        let outcome = self
+            .write_once(
+                Op::Delete,
+                bucket,
+                &key,
+                &request_ref.idempotency_key,
+                || async {
+                    let existed = self.operation.delete(bucket, &key).await?;
+                    Ok(Outcome::Delete { existed })
+                },
+            )
             .await
             .map_err(|e| {
-                self.metrics.error_counter.inc();
-                tonic::Status::new(tonic::Code::Internal, format!("{e}"))
+                self.metrics.record_error();
+                error_status(e)
             })?;
-        timer.observe_duration();
+        let existed = matches!(outcome, Outcome::Delete { existed: true });
+        self.metrics.record_duration(Op::Delete, timer.elapsed());
 
-        Ok(Response::new(PutResponse { successful: true }))
+        Ok(Response::new(DeleteResponse {
+            successful: true,
+            existed,
+        }))
     }
 
-    async fn delete(
+    async fn exists(
         &self,
-        request: tonic::Request<DeleteRequest>,
-    ) -> std::result::Result<Response<DeleteResponse>, tonic::Status> {
-        let timer = self.metrics.operation_duration.start_timer();
-        self.metrics.request_counter.inc();
-
+        request: tonic::Request<ExistsRequest>,
+    ) -> std::result::Result<Response<ExistsResponse>, tonic::Status> {
         let request_ref = request.into_inner();
-        let key = request_ref.key;
+        let key = self.key(request_ref.key).in_epoch(request_ref.epoch);
         let bucket = &request_ref.bucket;
+        let _permit = self.admit(bucket)?;
+
+        let exists = self.operation.exists(bucket, &key).await.map_err(|e| {
+            self.metrics.record_error();
+            error_status(e)
+        })?;
+        Ok(Response::new(ExistsResponse { exists }))
+    }
+
+    async fn self_test(
+        &self,
+        _request: tonic::Request<SelfTestRequest>,
+    ) -> std::result::Result<Response<SelfTestResponse>, tonic::Status> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let key = Key(format!("__self_test__/{nanos}").into_bytes());
+        let value = Value(key.0.clone());
+
+        let checks = self
+            .operation
+            .self_test(&self.self_test_bucket, &key, &value)
+            .await;
+
+        let tiers: Vec<TierSelfTest> = checks
+            .into_iter()
+            .map(|c| TierSelfTest {
+                tier: c.tier.to_string(),
+                successful: c.successful,
+                duration_micros: c.duration.as_micros() as u64,
+                error: c.error.unwrap_or_default(),
+            })
+            .collect();
+
+        Ok(Response::new(SelfTestResponse {
+            successful: tiers.iter().all(|t| t.successful),
+            tiers,
+        }))
+    }
+
+    async fn key_shards(
+        &self,
+        _request: tonic::Request<KeyShardsRequest>,
+    ) -> std::result::Result<Response<KeyShardsResponse>, tonic::Status> {
+        let Some(sampler) = &self.shard_sampler else {
+            return Err(tonic::Status::failed_precondition(
+                "Key shard sampling is disabled",
+            ));
+        };
+        let counts = sampler.counts();
+        Ok(Response::new(KeyShardsResponse {
+            skew: shards::skew(&counts),
+            counts,
+        }))
+    }
+
+    async fn list_buckets(
+        &self,
+        _request: tonic::Request<ListBucketsRequest>,
+    ) -> std::result::Result<Response<ListBucketsResponse>, tonic::Status> {
+        let mut buckets: Vec<BucketInfo> = self
+            .operation
+            .bucket_puts()
+            .into_iter()
+            .map(|(name, puts)| BucketInfo { name, puts })
+            .collect();
+        buckets.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Response::new(ListBucketsResponse { buckets }))
+    }
 
-        self.operation
-            .lock()
+    async fn pin(
+        &self,
+        request: tonic::Request<PinRequest>,
+    ) -> std::result::Result<Response<PinResponse>, tonic::Status> {
+        self.check_writable("Pin")?;
+        let request_ref = request.into_inner();
+        let key = self.key(request_ref.key).in_epoch(request_ref.epoch);
+
+        let resident = self
+            .operation
+            .pin(&request_ref.bucket, &key)
             .await
-            .delete(bucket, &Key(key))
+            .map_err(error_status)?;
+
+        Ok(Response::new(PinResponse {
+            successful: true,
+            resident,
+        }))
+    }
+
+    async fn unpin(
+        &self,
+        request: tonic::Request<UnpinRequest>,
+    ) -> std::result::Result<Response<UnpinResponse>, tonic::Status> {
+        self.check_writable("Unpin")?;
+        let request_ref = request.into_inner();
+        let key = self.key(request_ref.key).in_epoch(request_ref.epoch);
+
+        let was_pinned = self.operation.unpin(&request_ref.bucket, &key);
+
+        Ok(Response::new(UnpinResponse {
+            successful: true,
+            was_pinned,
+        }))
+    }
+
+    async fn watch(
+        &self,
+        request: tonic::Request<WatchRequest>,
+    ) -> std::result::Result<Response<Self::WatchStream>, tonic::Status> {
+        let Some(watchers) = &self.watchers else {
+            return Err(tonic::Status::failed_precondition(
+                "Watching is disabled on this node",
+            ));
+        };
+        let request_ref = request.into_inner();
+        let key = self.key(request_ref.key).in_epoch(request_ref.epoch);
+
+        let changes = watchers
+            .subscribe(&request_ref.bucket, &key)
+            .ok_or_else(|| tonic::Status::resource_exhausted("Too many open watches"))?;
+        let events = changes
+            .map_ok(|change| match change {
+                Some(entry) => WatchEvent {
+                    deleted: false,
+                    value: entry.value.0,
+                    written_at_millis: entry.written_at,
+                },
+                None => WatchEvent {
+                    deleted: true,
+                    ..Default::default()
+                },
+            })
+            .map_err(|Lagged| {
+                tonic::Status::resource_exhausted("Watcher fell behind and was dropped")
+            });
+        Ok(Response::new(events.boxed()))
+    }
+
+    async fn inspect(
+        &self,
+        request: tonic::Request<InspectRequest>,
+    ) -> std::result::Result<Response<InspectResponse>, tonic::Status> {
+        let request_ref = request.into_inner();
+        let key = self.key(request_ref.key).in_epoch(request_ref.epoch);
+
+        let inspection = self
+            .operation
+            .inspect(&request_ref.bucket, &key)
             .await
-            .map_err(|e| {
-                self.metrics.error_counter.inc();
-                tonic::Status::new(tonic::Code::Internal, format!("{e}"))
-            })?;
-        timer.observe_duration();
+            .map_err(error_status)?;
+
+        let mut response = InspectResponse {
+            in_memory: inspection.in_memory,
+            on_disk: inspection.on_disk,
+            in_cloud: inspection.in_cloud,
+            ttl_remaining_millis: inspection
+                .ttl_remaining
+                .map_or(0, |ttl| ttl.as_millis() as u64),
+            ..Default::default()
+        };
+        if let Some(entry) = inspection.entry {
+            response.found = true;
+            response.value = entry.value.0;
+            response.written_at_millis = entry.written_at;
+        }
+        Ok(Response::new(response))
+    }
+
+    // Answered without touching the stores, so it only shows the node is
+    // up and accepting requests.
+    async fn health(
+        &self,
+        _request: tonic::Request<HealthRequest>,
+    ) -> std::result::Result<Response<HealthResponse>, tonic::Status> {
+        Ok(Response::new(HealthResponse {
+            status: ServingStatus::Serving as i32,
+        }))
+    }
+}
 
-        Ok(Response::new(DeleteResponse { successful: true }))
+fn served_from(tier: Tier) -> ServedFrom {
+    match tier {
+        Tier::Memory => ServedFrom::Memory,
+        Tier::Disk => ServedFrom::Disk,
+        Tier::Cloud => ServedFrom::Cloud,
     }
 }
+
+// Deadline from the client's `grpc-timeout` header, e.g. `250m` or `2S`.
+fn request_deadline(metadata: &MetadataMap) -> Option<Instant> {
+    let value = metadata.get("grpc-timeout")?.to_str().ok()?;
+    let (amount, unit) = value.split_at(value.len().checked_sub(1)?);
+    let amount: u64 = amount.parse().ok()?;
+    let timeout = match unit {
+        "H" => Duration::from_secs(amount.saturating_mul(3600)),
+        "M" => Duration::from_secs(amount.saturating_mul(60)),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    };
+    Some(Instant::now() + timeout)
+}
+
+// Quotas and throttled tiers ask the caller to back off, and values that no
+// longer decode are lost; anything else is an internal failure.
+fn error_status(error: anyhow::Error) -> tonic::Status {
+    let code = match error.downcast_ref::<StoreError>() {
+        Some(StoreError::Throttled { .. }) => tonic::Code::ResourceExhausted,
+        Some(StoreError::Corrupt { .. }) => tonic::Code::DataLoss,
+        _ if error.is::<QuotaExceeded>() => tonic::Code::ResourceExhausted,
+        _ => tonic::Code::Internal,
+    };
+    tonic::Status::new(code, format!("{error}"))
+}
+
+#[test]
+fn test_request_deadline() {
+    let mut metadata = MetadataMap::new();
+    assert!(request_deadline(&metadata).is_none());
+
+    metadata.insert("grpc-timeout", "250m".parse().unwrap());
+    let remaining = request_deadline(&metadata).unwrap() - Instant::now();
+    assert!(remaining <= Duration::from_millis(250));
+    assert!(remaining > Duration::from_millis(200));
+
+    metadata.insert("grpc-timeout", "5x".parse().unwrap());
+    assert!(request_deadline(&metadata).is_none());
+}
+
+#[test]
+fn test_error_status_by_store_error() {
+    let code = |error: anyhow::Error| error_status(error).code();
+    let throttled = StoreError::Throttled {
+        tier: "cloud",
+        reason: "SlowDown".to_string(),
+    };
+    // Context added on the way up does not hide the cause
+    assert_eq!(
+        code(anyhow::Error::from(throttled).context("Put was rejected")),
+        tonic::Code::ResourceExhausted
+    );
+    let corrupt = StoreError::Corrupt {
+        tier: "memory",
+        reason: "invalid lz4 block".to_string(),
+    };
+    assert_eq!(code(corrupt.into()), tonic::Code::DataLoss);
+    let backend = StoreError::Backend {
+        tier: "disk",
+        reason: "IO error".to_string(),
+    };
+    assert_eq!(code(backend.into()), tonic::Code::Internal);
+    let quota = QuotaExceeded {
+        bucket: "users".to_string(),
+        quota: 1024,
+    };
+    assert_eq!(code(quota.into()), tonic::Code::ResourceExhausted);
+    assert_eq!(
+        code(anyhow::anyhow!("S3 read timed out")),
+        tonic::Code::Internal
+    );
+}
+
+/// An operation over a disk store at `path`, with the S3 tier switched off.
+#[cfg(test)]
+pub fn test_operation(path: &std::path::Path, metrics: Arc<Metrics>) -> SharedOperation {
+    let mut opts = rocksdb::Options::default();
+    opts.create_if_missing(true);
+    let disk = DiskStore::open(
+        &opts,
+        crate::ttl::Ttl::new(Duration::from_secs(60)),
+        path,
+        metrics.clone(),
+    )
+    .unwrap();
+    let s3_config = aws_sdk_s3::Config::builder()
+        .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
+        .region(aws_sdk_s3::config::Region::new("us-east-1"))
+        .build();
+    let cloud = S3Store {
+        client: aws_sdk_s3::Client::from_conf(s3_config),
+        key_salt: String::new(),
+        shard_count: crate::store::MAX_SHARD_COUNT,
+        storage_class: aws_sdk_s3::types::StorageClass::Standard,
+        clock: Arc::new(crate::clock::SystemClock),
+        compression: crate::store::ValueCompression::None,
+        compression_level: 0,
+    };
+    Arc::new(Operation::new(LRUStore::new(10), disk, cloud, metrics).without_cloud())
+}
+
+#[tokio::test]
+async fn test_get_reports_whether_the_key_was_found() {
+    let path = std::env::temp_dir().join(format!("milena-service-found-{}", std::process::id()));
+    let metrics = Arc::new(Metrics::new().unwrap());
+    let operation = test_operation(&path, metrics.clone());
+    let service = CacheService {
+        operation,
+        metrics,
+        self_test_bucket: "self-test".to_string(),
+        prefetcher: None,
+        shard_sampler: None,
+        reconciler: None,
+        idempotency_keys: None,
+        watchers: None,
+        bucket_limits: None,
+        key_normalization: KeyNormalization::default(),
+        read_only: false,
+    };
+    let get = |key: &[u8]| {
+        service.get(tonic::Request::new(GetRequest {
+            key: key.to_vec(),
+            bucket: "users".to_string(),
+            ..Default::default()
+        }))
+    };
+
+    let put = PutRequest {
+        key: b"empty".to_vec(),
+        bucket: "users".to_string(),
+        value: Vec::new(),
+        ..Default::default()
+    };
+    service.put(tonic::Request::new(put)).await.unwrap();
+    let stored = get(b"empty").await.unwrap().into_inner();
+    assert!(stored.found);
+    assert!(stored.value.is_empty());
+
+    let missing = get(b"missing").await.unwrap().into_inner();
+    assert!(!missing.found);
+    assert_eq!(missing.served_from, ServedFrom::Miss as i32);
+
+    drop(service);
+    let _ = std::fs::remove_dir_all(&path);
+}
@@ -0,0 +1,119 @@
+use crate::store::{key_shard, Key};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Most shards counted apart. Beyond that, shards are folded together by
+/// their prefix modulo this, which keeps the buckets even when the shard count
+/// is a multiple of it, as the default of 65536 is.
+pub const MAX_SAMPLED_SHARDS: u32 = 256;
+
+// Number of sampled keys the distribution is computed over.
+const WINDOW: usize = 10_000;
+
+/// Shards of the most recently sampled keys and how many fell in each.
+struct ShardWindow {
+    recent: VecDeque<usize>,
+    counts: Vec<u64>,
+}
+
+/// Samples one in every `sample_every` keys to show how evenly recent traffic
+/// spreads over the S3 prefixes their object keys get under `key_salt` and
+/// `shard_count`.
+pub struct ShardSampler {
+    sample_every: u64,
+    key_salt: Vec<u8>,
+    shard_count: u32,
+    seen: AtomicU64,
+    window: Mutex<ShardWindow>,
+}
+
+impl ShardSampler {
+    pub fn new(sample_every: u64, key_salt: &str, shard_count: u32) -> Self {
+        Self {
+            sample_every,
+            key_salt: key_salt.as_bytes().to_vec(),
+            shard_count,
+            seen: AtomicU64::new(0),
+            window: Mutex::new(ShardWindow {
+                recent: VecDeque::with_capacity(WINDOW),
+                counts: vec![0; shard_count.min(MAX_SAMPLED_SHARDS) as usize],
+            }),
+        }
+    }
+
+    /// Records the key if it is due to be sampled, returning the new skew.
+    pub fn record(&self, bucket: &str, key: &Key) -> Option<f64> {
+        if !self
+            .seen
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(self.sample_every)
+        {
+            return None;
+        }
+        let shard = key_shard(&self.key_salt, self.shard_count, bucket, key);
+        let mut window = self.window.lock().unwrap();
+        let shard = shard as usize % window.counts.len();
+        if window.recent.len() == WINDOW {
+            let evicted = window.recent.pop_front().unwrap();
+            window.counts[evicted] -= 1;
+        }
+        window.recent.push_back(shard);
+        window.counts[shard] += 1;
+        Some(skew(&window.counts))
+    }
+
+    /// Per-shard counts over the sampled window, folded as described on
+    /// `MAX_SAMPLED_SHARDS`.
+    pub fn counts(&self) -> Vec<u64> {
+        self.window.lock().unwrap().counts.clone()
+    }
+}
+
+/// Busiest shard's count relative to the mean; 1.0 is perfectly even.
+pub fn skew(counts: &[u64]) -> f64 {
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+        return 1.0;
+    }
+    let mean = total as f64 / counts.len() as f64;
+    counts.iter().copied().max().unwrap_or_default() as f64 / mean
+}
+
+#[test]
+fn test_shard_distribution_is_uniform() {
+    let sequential = (0..100_000).map(|i| Key(format!("key-{i}").into_bytes()));
+    let structured = (0..1_000).flat_map(|tenant| {
+        (0..100).map(move |user| Key(format!("tenant-{tenant}/user-{user}/profile").into_bytes()))
+    });
+
+    for keys in [
+        sequential.collect::<Vec<_>>(),
+        structured.collect::<Vec<_>>(),
+    ] {
+        let mut counts = [0u64; 256];
+        for key in &keys {
+            counts[key_shard(b"", 256, "bucket", key) as usize] += 1;
+        }
+        // About 390 keys per shard; a standard deviation is about 20.
+        let mean = keys.len() as f64 / counts.len() as f64;
+        for count in counts {
+            assert!((count as f64 - mean).abs() < mean * 0.25);
+        }
+        assert!(skew(&counts) < 1.25);
+    }
+}
+
+#[test]
+fn test_sampler_window() {
+    let sampler = ShardSampler::new(2, "", 65_536);
+    for i in 0..(WINDOW as u64 * 4) {
+        sampler.record("bucket", &Key(i.to_be_bytes().to_vec()));
+    }
+    let counts = sampler.counts();
+    assert_eq!(counts.len(), MAX_SAMPLED_SHARDS as usize);
+    assert_eq!(counts.iter().sum::<u64>(), WINDOW as u64);
+
+    // Fewer shards than that are counted one by one
+    assert_eq!(ShardSampler::new(1, "", 16).counts().len(), 16);
+}
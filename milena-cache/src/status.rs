@@ -0,0 +1,107 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use warp::{Filter, Rejection, Reply};
+
+use crate::metrics::Metrics;
+use crate::service::SharedOperation;
+
+#[derive(Serialize)]
+struct StatusBody {
+    uptime_seconds: u64,
+    memory_entries: usize,
+    memory_capacity: usize,
+    disk_bytes: u64,
+    hits: u64,
+    misses: u64,
+    hit_ratio_window_seconds: u64,
+    joined_router: bool,
+    s3_enabled: bool,
+}
+
+/// What the node knows about itself, served as JSON by `route`.
+#[derive(Clone)]
+pub struct NodeStatus {
+    pub operation: SharedOperation,
+    pub metrics: Arc<Metrics>,
+    pub started: Instant,
+    pub hit_ratio_window: Duration,
+    /// Set once the router has accepted this node's join.
+    pub joined_router: Arc<AtomicBool>,
+}
+
+/// `GET /status`: a JSON summary for checking a node with curl. Everything in
+/// it is already held in memory, so it never touches disk or S3; `s3_enabled`
+/// is whether the S3 tier is in use, not a fresh reachability check.
+pub fn route(
+    status: NodeStatus,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("status")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || warp::reply::json(&status.body()))
+}
+
+impl NodeStatus {
+    fn body(&self) -> StatusBody {
+        let (memory_entries, memory_capacity) = self.operation.memory_entries();
+        let (hits, misses) = self.metrics.recent_gets();
+        StatusBody {
+            uptime_seconds: self.started.elapsed().as_secs(),
+            memory_entries,
+            memory_capacity,
+            disk_bytes: self.operation.disk_size_bytes(),
+            hits,
+            misses,
+            hit_ratio_window_seconds: self.hit_ratio_window.as_secs(),
+            joined_router: self.joined_router.load(Ordering::SeqCst),
+            s3_enabled: self.operation.cloud_enabled(),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_status_summarizes_the_node() {
+    use crate::store::{Key, Value};
+
+    let path = std::env::temp_dir().join(format!("milena-status-{}", std::process::id()));
+    let metrics = Arc::new(Metrics::new().unwrap());
+    let operation = crate::service::test_operation(&path, metrics.clone());
+    operation
+        .put("users", &Key(b"42".to_vec()), &Value(b"alice".to_vec()))
+        .await
+        .unwrap();
+    let joined_router = Arc::new(AtomicBool::new(false));
+    let status = route(NodeStatus {
+        operation,
+        metrics,
+        started: Instant::now(),
+        hit_ratio_window: Duration::from_secs(60),
+        joined_router: joined_router.clone(),
+    });
+    let body = || async {
+        let response = warp::test::request().path("/status").reply(&status).await;
+        assert_eq!(response.status(), 200);
+        String::from_utf8(response.body().to_vec()).unwrap()
+    };
+
+    let before_join = body().await;
+    assert!(before_join.contains(r#""memory_entries":1,"memory_capacity":10"#));
+    assert!(before_join.contains(r#""joined_router":false"#));
+    assert!(before_join.contains(r#""s3_enabled":false"#));
+    joined_router.store(true, Ordering::SeqCst);
+    assert!(body().await.contains(r#""joined_router":true"#));
+
+    // Read-only: nothing but GET is routed
+    let posted = warp::test::request()
+        .method("POST")
+        .path("/status")
+        .reply(&status)
+        .await;
+    assert_eq!(posted.status(), 405);
+
+    drop(status);
+    let _ = std::fs::remove_dir_all(&path);
+}
@@ -1,188 +1,1985 @@
-use anyhow::Result;
+use crate::clock::{Clock, SystemClock};
+use crate::metrics::Metrics;
+use crate::ttl::Ttl;
+use anyhow::{bail, Result};
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata};
+use aws_sdk_s3::operation::get_object::GetObjectError;
+use aws_sdk_s3::types::StorageClass;
+use crc::{Crc, CRC_32_ISCSI};
 use lru::LruCache;
+use serde::Deserialize;
+use thiserror::Error;
+use tracing::warn;
 
 use tonic::async_trait;
 
-use std::{num::NonZeroUsize, path::Path, time::Duration};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
-use rocksdb::Options;
+use rocksdb::{BlockBasedOptions, Cache, DBCompressionType, IteratorMode, Options, WriteOptions};
+
+const CHECKSUM: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+const CHECKSUM_LEN: usize = 4;
+const EXPIRY_LEN: usize = 8;
+const WRITTEN_AT_LEN: usize = 8;
+const ENTRY_EXPIRY_LEN: usize = 8;
+const FLAGS_LEN: usize = 1;
+// Set in a frame's flags when its value is zstd-compressed.
+const FLAG_ZSTD: u8 = 1;
+// Mixed into each frame's checksum, so frames in an older layout fail
+// verification instead of being decoded as this one.
+const FRAME_VERSION: u8 = 3;
+// Memory and disk are private to this node, so only S3 object keys are salted.
+const UNSALTED: &[u8] = b"";
+// S3 user metadata holding an object's write timestamp.
+const WRITTEN_AT_METADATA: &str = "written-at";
+// S3 user metadata holding when an object put with its own TTL expires.
+const EXPIRES_AT_METADATA: &str = "expires-at";
+// `Content-Encoding` of S3 objects whose body is zstd-compressed.
+const ZSTD_ENCODING: &str = "zstd";
+const DEFAULT_MAX_PINNED_BYTES: u64 = 64 * 1024 * 1024;
 #[derive(Clone, Debug, PartialEq)]
 pub struct Key(pub Vec<u8>);
 #[derive(Clone, Debug, PartialEq)]
 pub struct Value(pub Vec<u8>);
 
+/// A value and when it was written, carried unchanged from tier to tier.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub value: Value,
+    /// Milliseconds since the Unix epoch at which this node accepted the write.
+    pub written_at: u64,
+    /// Milliseconds since the Unix epoch after which no tier serves the entry,
+    /// for values put with their own TTL.
+    pub expires_at: Option<u64>,
+}
+
+impl Entry {
+    /// Stamps `value` with the clock's current time.
+    pub fn new(value: Value, clock: &dyn Clock) -> Self {
+        Self {
+            value,
+            written_at: clock.unix_millis(),
+            expires_at: None,
+        }
+    }
+
+    /// Expires the entry `ttl` after its write.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.expires_at = Some(self.written_at.saturating_add(ttl.as_millis() as u64));
+        self
+    }
+
+    pub fn is_expired(&self, clock: &dyn Clock) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| clock.unix_millis() >= expires_at)
+    }
+
+    /// Time since the write; entries without a recorded timestamp are as old as the epoch.
+    pub fn age(&self, clock: &dyn Clock) -> Duration {
+        Duration::from_millis(clock.unix_millis().saturating_sub(self.written_at))
+    }
+}
+
+/// A key in a bucket, with the digest memory and disk index it by computed
+/// once, so an operation touching several tiers hashes the key a single time.
+/// S3 object keys are salted, so the S3 tier derives its own.
+#[derive(Clone, Debug)]
+pub struct CacheKey<'a> {
+    pub bucket: &'a str,
+    pub key: &'a Key,
+    digest: Vec<u8>,
+}
+
+impl<'a> CacheKey<'a> {
+    pub fn new(bucket: &'a str, key: &'a Key) -> Self {
+        CacheKey {
+            bucket,
+            key,
+            digest: build_cache_key(UNSALTED, MAX_SHARD_COUNT, bucket.as_bytes(), key).into_bytes(),
+        }
+    }
+
+    pub fn digest(&self) -> &[u8] {
+        &self.digest
+    }
+}
+
+impl Key {
+    /// Namespaces the key under a bucket epoch. Epoch 0 is the original,
+    /// unprefixed namespace, so entries written before staging existed stay readable.
+    pub fn in_epoch(self, epoch: u64) -> Key {
+        if epoch == 0 {
+            return self;
+        }
+        let mut namespaced = format!("__epoch__/{epoch}/").into_bytes();
+        namespaced.extend(self.0);
+        Key(namespaced)
+    }
+}
+
 #[tonic::async_trait]
-pub trait Store {
-    async fn get(&mut self, bucket: &str, key: &Key) -> Result<Option<Value>>;
-    async fn put(&mut self, bucket: &str, key: &Key, value: &Value) -> Result<()>;
-    async fn delete(&mut self, bucket: &str, key: &Key) -> Result<()>;
+pub trait Store: Send + Sync {
+    async fn get(&self, key: &CacheKey<'_>) -> StoreResult<Option<Entry>>;
+    async fn put(&self, key: &CacheKey<'_>, entry: &Entry) -> StoreResult<()>;
+    async fn delete(&self, key: &CacheKey<'_>) -> StoreResult<()>;
+
+    /// Like `put`, but returns only once the entry is on stable storage. Tiers
+    /// that are never durable, or always are, just `put`.
+    async fn put_durable(&self, key: &CacheKey<'_>, entry: &Entry) -> StoreResult<()> {
+        self.put(key, entry).await
+    }
+
+    async fn exists(&self, key: &CacheKey<'_>) -> StoreResult<bool> {
+        Ok(self.get(key).await?.is_some())
+    }
+
+    /// Like `get`, but an entry past its TTL is returned, flagged `true`, and
+    /// kept rather than dropped. Tiers without expiry never flag one.
+    async fn get_including_expired(
+        &self,
+        key: &CacheKey<'_>,
+    ) -> StoreResult<Option<(Entry, bool)>> {
+        Ok(self.get(key).await?.map(|entry| (entry, false)))
+    }
+
+    /// Time left before the entry under `key` expires, or `None` when there is
+    /// no live entry or the tier has no expiry. Does not count as a use.
+    async fn ttl_remaining(&self, _key: &CacheKey<'_>) -> StoreResult<Option<Duration>> {
+        Ok(None)
+    }
+
+    /// Evicts the least recently used entries of the key's bucket until
+    /// storing `entry` under `key` keeps the bucket within `quota` bytes.
+    /// Returns the bytes evicted, or `None`, evicting nothing, when the entry
+    /// cannot fit however much is evicted. Tiers that do not account by bucket
+    /// always have room.
+    async fn make_room(
+        &self,
+        _key: &CacheKey<'_>,
+        _entry: &Entry,
+        _quota: u64,
+    ) -> StoreResult<Option<u64>> {
+        Ok(Some(0))
+    }
+}
+
+/// How the in-memory tier compresses values, independently of the disk and
+/// S3 tiers, which follow `ValueCompression`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryCompression {
+    #[default]
+    None,
+    Lz4,
+}
+
+/// How the disk and S3 tiers compress values, independently of memory. Each
+/// value is flagged as compressed or not, so values written with any setting
+/// read back under any other.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueCompression {
+    #[default]
+    None,
+    Zstd,
+}
+
+// `value` compressed at `level`, or `None` when compression is off or would
+// not make it smaller.
+fn compress(value: &[u8], compression: ValueCompression, level: i32) -> Option<Vec<u8>> {
+    if compression != ValueCompression::Zstd {
+        return None;
+    }
+    zstd::bulk::compress(value, level)
+        .ok()
+        .filter(|compressed| compressed.len() < value.len())
+}
+
+fn decompress(value: &[u8], tier: &'static str) -> std::result::Result<Vec<u8>, StoreError> {
+    zstd::stream::decode_all(value).map_err(|e| StoreError::Corrupt {
+        tier,
+        reason: e.to_string(),
+    })
 }
 
 pub struct LRUStore {
-    cache: LruCache<Vec<u8>, Vec<u8>>,
+    inner: Mutex<LruInner>,
+    /// Receives the logical and stored size of the LRU after each change.
+    metrics: Option<Arc<Metrics>>,
+    clock: Arc<dyn Clock>,
+}
+
+struct LruInner {
+    /// Each entry with when it was last read or written.
+    cache: LruCache<Vec<u8>, (Resident, Instant)>,
+    /// Keys exempt from eviction. Their values live here instead of in
+    /// `cache`, and are `None` until the key is next written or loaded.
+    pinned: HashMap<Vec<u8>, Option<Entry>>,
+    pinned_bytes: u64,
+    max_pinned_bytes: u64,
+    compression: MemoryCompression,
+    /// Values shorter than this are not worth compressing.
+    compression_min_bytes: usize,
+    /// Value bytes held in `cache` before compression, and as stored.
+    logical_bytes: u64,
+    stored_bytes: u64,
+}
+
+/// An entry as the LRU holds it, its value compressed when that saved space.
+struct Resident {
+    entry: Entry,
+    compressed: bool,
+    logical_len: u64,
+}
+
+impl Resident {
+    fn stored_len(&self) -> u64 {
+        self.entry.value.0.len() as u64
+    }
+
+    fn entry(&self) -> StoreResult<Entry> {
+        if !self.compressed {
+            return Ok(self.entry.clone());
+        }
+        Ok(Entry {
+            value: Value(
+                lz4_flex::decompress_size_prepended(&self.entry.value.0).map_err(|e| {
+                    StoreError::Corrupt {
+                        tier: "memory",
+                        reason: e.to_string(),
+                    }
+                })?,
+            ),
+            written_at: self.entry.written_at,
+            expires_at: self.entry.expires_at,
+        })
+    }
+}
+
+impl LruInner {
+    fn resident(&self, entry: Entry) -> Resident {
+        let logical_len = entry.value.0.len() as u64;
+        if self.compression == MemoryCompression::Lz4
+            && entry.value.0.len() >= self.compression_min_bytes
+        {
+            let compressed = lz4_flex::compress_prepend_size(&entry.value.0);
+            if compressed.len() < entry.value.0.len() {
+                return Resident {
+                    entry: Entry {
+                        value: Value(compressed),
+                        written_at: entry.written_at,
+                        expires_at: entry.expires_at,
+                    },
+                    compressed: true,
+                    logical_len,
+                };
+            }
+        }
+        Resident {
+            entry,
+            compressed: false,
+            logical_len,
+        }
+    }
+
+    // Caches `entry`, accounting for whatever it replaced or evicted.
+    fn insert(&mut self, cache_key: Vec<u8>, entry: Entry, now: Instant) {
+        let resident = self.resident(entry);
+        self.logical_bytes += resident.logical_len;
+        self.stored_bytes += resident.stored_len();
+        if let Some((_, (displaced, _))) = self.cache.push(cache_key, (resident, now)) {
+            self.forget(&displaced);
+        }
+    }
+
+    fn remove(&mut self, cache_key: &[u8]) -> Option<Resident> {
+        let (_, (resident, _)) = self.cache.pop_entry(cache_key)?;
+        self.forget(&resident);
+        Some(resident)
+    }
+
+    fn forget(&mut self, resident: &Resident) {
+        self.logical_bytes -= resident.logical_len;
+        self.stored_bytes -= resident.stored_len();
+    }
 }
 
 impl LRUStore {
     pub fn new(capacity: u64) -> Self {
         let cache = LruCache::new(NonZeroUsize::new(capacity.try_into().unwrap()).unwrap());
-        LRUStore { cache }
+        LRUStore {
+            inner: Mutex::new(LruInner {
+                cache,
+                pinned: HashMap::new(),
+                pinned_bytes: 0,
+                max_pinned_bytes: DEFAULT_MAX_PINNED_BYTES,
+                compression: MemoryCompression::None,
+                compression_min_bytes: 0,
+                logical_bytes: 0,
+                stored_bytes: 0,
+            }),
+            metrics: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Compresses values of at least `min_bytes` as they enter the LRU,
+    /// keeping the compressed form only when it is smaller. Pinned values are
+    /// kept as given.
+    pub fn with_compression(mut self, compression: MemoryCompression, min_bytes: usize) -> Self {
+        let inner = self.inner.get_mut().unwrap();
+        inner.compression = compression;
+        inner.compression_min_bytes = min_bytes;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    fn record_sizes(&self, inner: &LruInner) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_memory_bytes(inner.logical_bytes, inner.stored_bytes);
+        }
+    }
+
+    /// Caps the bytes of keys and values held by pinned entries.
+    pub fn with_max_pinned_bytes(mut self, max_pinned_bytes: u64) -> Self {
+        self.inner.get_mut().unwrap().max_pinned_bytes = max_pinned_bytes;
+        self
+    }
+
+    /// Exempts `key` from eviction, moving any cached copy out of the LRU.
+    /// Fails when the key and its cached value would exceed the pinned budget.
+    pub fn pin(&self, key: &CacheKey<'_>) -> Result<()> {
+        let cache_key = key.digest().to_vec();
+        let mut inner = self.inner.lock().unwrap();
+        if inner.pinned.contains_key(&cache_key) {
+            return Ok(());
+        }
+        let cached = match inner.remove(&cache_key) {
+            Some(resident) => Some(resident.entry()?),
+            None => None,
+        };
+        let size = pinned_size(&cache_key, cached.as_ref());
+        if inner.pinned_bytes + size > inner.max_pinned_bytes {
+            if let Some(cached) = cached {
+                inner.insert(cache_key, cached, self.clock.now());
+            }
+            bail!(
+                "Pinning would exceed the {} byte pinned budget",
+                inner.max_pinned_bytes
+            );
+        }
+        inner.pinned_bytes += size;
+        inner.pinned.insert(cache_key, cached);
+        self.record_sizes(&inner);
+        Ok(())
+    }
+
+    /// Makes `key` evictable again, returning its value to the LRU. Returns
+    /// whether it was pinned.
+    pub fn unpin(&self, key: &CacheKey<'_>) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(entry) = inner.pinned.remove(key.digest()) else {
+            return false;
+        };
+        inner.pinned_bytes -= pinned_size(key.digest(), entry.as_ref());
+        if let Some(entry) = entry {
+            inner.insert(key.digest().to_vec(), entry, self.clock.now());
+            self.record_sizes(&inner);
+        }
+        true
+    }
+
+    /// Entries held, pinned ones included, and the capacity of the LRU.
+    pub fn entries(&self) -> (usize, usize) {
+        let inner = self.inner.lock().unwrap();
+        (
+            inner.cache.len() + inner.pinned.len(),
+            inner.cache.cap().get(),
+        )
+    }
+
+    /// Evicts entries not read or written for longer than `idle`, removing at
+    /// most `max_evictions`. An entry last used exactly `idle` ago is kept.
+    /// Pinned keys are never evicted. Returns how many entries were evicted.
+    pub fn evict_idle(&self, idle: Duration, max_evictions: usize) -> usize {
+        let now = self.clock.now();
+        let mut inner = self.inner.lock().unwrap();
+        let mut evicted = 0;
+        // Least recently used first, so the first entry still in use ends the sweep.
+        while evicted < max_evictions {
+            match inner.cache.peek_lru() {
+                Some((_, (_, last_access)))
+                    if now.saturating_duration_since(*last_access) > idle =>
+                {
+                    if let Some((_, (resident, _))) = inner.cache.pop_lru() {
+                        inner.forget(&resident);
+                    }
+                    evicted += 1;
+                }
+                _ => break,
+            }
+        }
+        if evicted > 0 {
+            self.record_sizes(&inner);
+        }
+        evicted
     }
 }
 
 #[tonic::async_trait]
 impl Store for LRUStore {
-    async fn get(&mut self, bucket: &str, key: &Key) -> Result<Option<Value>> {
-        let data = self.cache.get(&build_cache_key(bucket.as_bytes(), key).0);
-        Ok(data.map(|x| Value(x.clone())))
+    async fn get(&self, key: &CacheKey<'_>) -> StoreResult<Option<Entry>> {
+        let mut guard = self.inner.lock().unwrap();
+        let inner = &mut *guard;
+        let cache_key = key.digest();
+        let expired = |entry: &Entry| entry.is_expired(&*self.clock);
+        // Expired entries are dropped as they are found, like the disk tier does
+        if let Some(slot) = inner.pinned.get_mut(cache_key) {
+            if slot.as_ref().is_some_and(expired) {
+                inner.pinned_bytes -=
+                    pinned_size(cache_key, slot.take().as_ref()) - pinned_size(cache_key, None);
+                return Ok(None);
+            }
+            if let Some(entry) = slot {
+                return Ok(Some(entry.clone()));
+            }
+        }
+        if inner
+            .cache
+            .peek(cache_key)
+            .is_some_and(|(resident, _)| expired(&resident.entry))
+        {
+            inner.remove(cache_key);
+            self.record_sizes(inner);
+            return Ok(None);
+        }
+        match inner.cache.get_mut(cache_key) {
+            Some((resident, last_access)) => {
+                *last_access = self.clock.now();
+                Ok(Some(resident.entry()?))
+            }
+            None => Ok(None),
+        }
     }
 
-    async fn put(&mut self, bucket: &str, key: &Key, value: &Value) -> Result<()> {
-        self.cache
-            .put(build_cache_key(bucket.as_bytes(), key).0, value.clone().0);
+    async fn put(&self, key: &CacheKey<'_>, entry: &Entry) -> StoreResult<()> {
+        let cache_key = key.digest().to_vec();
+        let mut guard = self.inner.lock().unwrap();
+        let inner = &mut *guard;
+        if let Some(slot) = inner.pinned.get_mut(&cache_key) {
+            let unpinned_bytes = inner.pinned_bytes - pinned_size(&cache_key, slot.as_ref());
+            let size = pinned_size(&cache_key, Some(entry));
+            if unpinned_bytes + size <= inner.max_pinned_bytes {
+                inner.pinned_bytes = unpinned_bytes + size;
+                *slot = Some(entry.clone());
+                return Ok(());
+            }
+            // Too large to keep resident; cache it like any other value.
+            warn!(
+                "Value for pinned key in bucket {} exceeds the pinned budget, caching it unpinned",
+                key.bucket
+            );
+            inner.pinned_bytes = unpinned_bytes + pinned_size(&cache_key, None);
+            *slot = None;
+        }
+        inner.insert(cache_key, entry.clone(), self.clock.now());
+        self.record_sizes(inner);
 
         Ok(())
     }
 
-    async fn delete(&mut self, bucket: &str, key: &Key) -> Result<()> {
-        self.cache
-            .pop_entry(&build_cache_key(bucket.as_bytes(), key).0);
+    async fn delete(&self, key: &CacheKey<'_>) -> StoreResult<()> {
+        let cache_key = key.digest();
+        let mut guard = self.inner.lock().unwrap();
+        let inner = &mut *guard;
+        // The key stays pinned, so its next value is kept resident too.
+        if let Some(slot) = inner.pinned.get_mut(cache_key) {
+            inner.pinned_bytes -=
+                pinned_size(cache_key, slot.take().as_ref()) - pinned_size(cache_key, None);
+        }
+        if inner.remove(cache_key).is_some() {
+            self.record_sizes(inner);
+        }
         Ok(())
     }
+
+    async fn exists(&self, key: &CacheKey<'_>) -> StoreResult<bool> {
+        let inner = self.inner.lock().unwrap();
+        let live = |entry: &Entry| !entry.is_expired(&*self.clock);
+        Ok(inner
+            .pinned
+            .get(key.digest())
+            .and_then(Option::as_ref)
+            .is_some_and(live)
+            || inner
+                .cache
+                .peek(key.digest())
+                .is_some_and(|(resident, _)| live(&resident.entry)))
+    }
+}
+
+// Bytes a pinned entry counts against the pinned budget.
+fn pinned_size(cache_key: &[u8], entry: Option<&Entry>) -> u64 {
+    (cache_key.len() + entry.map_or(0, |entry| entry.value.0.len())) as u64
+}
+
+/// Access order, stored size and bucket of disk entries, used to evict by
+/// size overall and within a bucket's quota.
+#[derive(Default)]
+struct RecencyIndex {
+    tick: u64,
+    by_tick: BTreeMap<u64, Vec<u8>>,
+    entries: HashMap<Vec<u8>, IndexedEntry>,
+    total_bytes: u64,
+    buckets: HashMap<Arc<str>, BucketUsage>,
+}
+
+/// An entry's place in the access order and the bytes it counts against.
+struct IndexedEntry {
+    tick: u64,
+    size: u64,
+    /// Entries found on disk at startup belong to no bucket until they are
+    /// next read or written.
+    bucket: Option<Arc<str>>,
+}
+
+/// Bytes a bucket holds on disk and the ticks of its entries.
+#[derive(Default)]
+struct BucketUsage {
+    bytes: u64,
+    ticks: BTreeSet<u64>,
+}
+
+impl RecencyIndex {
+    fn touch(&mut self, key: &[u8], size: u64, bucket: Option<&str>) {
+        self.remove(key);
+        self.tick += 1;
+        let bucket = bucket.map(|bucket| {
+            let name = self
+                .buckets
+                .get_key_value(bucket)
+                .map_or_else(|| Arc::from(bucket), |(name, _)| name.clone());
+            let usage = self.buckets.entry(name.clone()).or_default();
+            usage.bytes += size;
+            usage.ticks.insert(self.tick);
+            name
+        });
+        self.by_tick.insert(self.tick, key.to_vec());
+        let tick = self.tick;
+        self.entries
+            .insert(key.to_vec(), IndexedEntry { tick, size, bucket });
+        self.total_bytes += size;
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<u64> {
+        let IndexedEntry { tick, size, bucket } = self.entries.remove(key)?;
+        self.by_tick.remove(&tick);
+        self.total_bytes -= size;
+        if let Some(bucket) = bucket {
+            let usage = self
+                .buckets
+                .get_mut(&bucket)
+                .expect("entries with a bucket are counted in its usage");
+            usage.bytes -= size;
+            usage.ticks.remove(&tick);
+            if usage.ticks.is_empty() {
+                self.buckets.remove(&bucket);
+            }
+        }
+        Some(size)
+    }
+
+    fn oldest(&self) -> Option<&Vec<u8>> {
+        self.by_tick.values().next()
+    }
+
+    fn bucket_of(&self, key: &[u8]) -> Option<Arc<str>> {
+        self.entries.get(key).and_then(|entry| entry.bucket.clone())
+    }
+
+    fn bucket_bytes(&self, bucket: &str) -> u64 {
+        self.buckets.get(bucket).map_or(0, |usage| usage.bytes)
+    }
+
+    /// Entries of `bucket` to evict, least recently used first, so that `size`
+    /// bytes stored under `key` keep the bucket within `quota`. `None` when
+    /// the entry is larger than the quota on its own.
+    fn evictions_for_quota(
+        &self,
+        bucket: &str,
+        key: &[u8],
+        size: u64,
+        quota: u64,
+    ) -> Option<Vec<Vec<u8>>> {
+        if size > quota {
+            return None;
+        }
+        // The entry replaces whatever the key holds now
+        let replaced = match self.entries.get(key) {
+            Some(IndexedEntry {
+                size,
+                bucket: Some(owner),
+                ..
+            }) if **owner == *bucket => *size,
+            _ => 0,
+        };
+        let mut excess = (self.bucket_bytes(bucket) - replaced + size).saturating_sub(quota);
+        let mut evictions = Vec::new();
+        let ticks = self.buckets.get(bucket).map(|usage| &usage.ticks);
+        for tick in ticks.into_iter().flatten() {
+            if excess == 0 {
+                break;
+            }
+            let victim = &self.by_tick[tick];
+            if victim.as_slice() == key {
+                continue;
+            }
+            excess = excess.saturating_sub(self.entries[victim].size);
+            evictions.push(victim.clone());
+        }
+        Some(evictions)
+    }
+}
+
+/// What every tier returns, so a failure keeps its class on the way to the service.
+pub type StoreResult<T> = std::result::Result<T, StoreError>;
+
+/// Why the disk tier could not be opened, with a hint for the operator, or
+/// why a tier failed a request. The service picks the gRPC status by variant.
+/// A key that is not there is a miss, `Ok(None)`, never an error.
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error(
+        "Disk store at {path} is locked by another process ({reason}); stop any other \
+         milena-cache using this directory, or remove a stale LOCK file left by a crash"
+    )]
+    Locked { path: String, reason: String },
+    #[error(
+        "Permission denied opening disk store at {path} ({reason}); make the directory \
+         readable and writable by the milena-cache user"
+    )]
+    PermissionDenied { path: String, reason: String },
+    #[error("Could not open disk store at {path}: {reason}")]
+    Open { path: String, reason: String },
+    /// The tier turned the request away for exceeding its request rate.
+    #[error("The {tier} tier is throttling requests: {reason}")]
+    Throttled { tier: &'static str, reason: String },
+    /// A value the tier holds could not be decoded.
+    #[error("Could not decode a value in the {tier} tier: {reason}")]
+    Corrupt { tier: &'static str, reason: String },
+    /// Any other failure of the storage behind the tier.
+    #[error("The {tier} tier failed: {reason}")]
+    Backend { tier: &'static str, reason: String },
+}
+
+impl StoreError {
+    fn from_rocksdb(path: &Path, err: rocksdb::Error) -> Self {
+        Self::from_message(path, err.into_string())
+    }
+
+    fn disk(err: rocksdb::Error) -> Self {
+        StoreError::Backend {
+            tier: "disk",
+            reason: err.into_string(),
+        }
+    }
+
+    // S3 answers with one of these codes when a prefix gets more requests
+    // than it will serve; the SDK has already retried them with backoff.
+    fn cloud<E: ProvideErrorMetadata + std::error::Error>(err: E) -> Self {
+        let reason = DisplayErrorContext(&err).to_string();
+        match err.code() {
+            Some("SlowDown" | "Throttling" | "ThrottlingException" | "RequestLimitExceeded") => {
+                StoreError::Throttled {
+                    tier: "cloud",
+                    reason,
+                }
+            }
+            _ => StoreError::Backend {
+                tier: "cloud",
+                reason,
+            },
+        }
+    }
+
+    // RocksDB reports a held LOCK file as "While lock file" when another
+    // process has it, or "lock hold by current process" when this one does.
+    // A corrupt file can mention a block, so corruption is ruled out first.
+    fn from_message(path: &Path, reason: String) -> Self {
+        let path = path.display().to_string();
+        if reason.starts_with("Corruption:") {
+            StoreError::Open { path, reason }
+        } else if reason.contains("While lock file")
+            || reason.contains("lock hold by current process")
+            || reason.starts_with("IO error: lock")
+        {
+            StoreError::Locked { path, reason }
+        } else if reason.contains("Permission denied") {
+            StoreError::PermissionDenied { path, reason }
+        } else {
+            StoreError::Open { path, reason }
+        }
+    }
+}
+
+/// How RocksDB compresses the disk tier's files, on top of any
+/// `ValueCompression` applied to each value.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiskCompression {
+    None,
+    #[default]
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+/// Where the disk tier lives and how its RocksDB instance is tuned.
+#[derive(Clone, Debug)]
+pub struct DiskOptions {
+    pub path: PathBuf,
+    /// RocksDB block cache size; 0 disables the block cache.
+    pub block_cache_mb: usize,
+    pub compression: DiskCompression,
+}
+
+impl DiskOptions {
+    pub fn rocksdb_options(&self) -> Options {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        // Keep large values in blob files, apart from the keys
+        opts.set_enable_blob_files(true);
+        // Blob files are kept until their total size exceeds this amount.
+        opts.set_blob_file_size(128 * 1024 * 1024); // 128 MB
+
+        // Minimum size of value to be stored in blob file. Values smaller than this threshold are stored inline.
+        opts.set_min_blob_size(1024); // 1KB
+
+        // If enable_blob_garbage_collection is true, then blob files are eligible to be
+        // garbage collected and compacted when their expiration time is reached.
+        opts.set_enable_blob_gc(true);
+
+        // Minimum ratio of live data size to total data size for a blob file to be considered for garbage collection.
+        opts.set_blob_gc_age_cutoff(0.5);
+
+        let compression = match self.compression {
+            DiskCompression::None => DBCompressionType::None,
+            DiskCompression::Snappy => DBCompressionType::Snappy,
+            DiskCompression::Lz4 => DBCompressionType::Lz4,
+            DiskCompression::Zstd => DBCompressionType::Zstd,
+        };
+        opts.set_compression_type(compression);
+        opts.set_blob_compression_type(compression);
+
+        let mut table = BlockBasedOptions::default();
+        if self.block_cache_mb == 0 {
+            table.disable_cache();
+        } else {
+            table.set_block_cache(&Cache::new_lru_cache(self.block_cache_mb * 1024 * 1024));
+        }
+        opts.set_block_based_table_factory(&table);
+        opts
+    }
 }
 
 pub struct DiskStore {
-    db: rocksdb::DB,
+    db: Arc<rocksdb::DB>,
+    ttl: Ttl,
+    metrics: Arc<Metrics>,
+    index: Mutex<RecencyIndex>,
+    clock: Arc<dyn Clock>,
+    compression: ValueCompression,
+    compression_level: i32,
 }
 
 impl DiskStore {
-    pub fn new<P: AsRef<Path>>(opts: &Options, ttl: Duration, path: P) -> Self {
-        let db = rocksdb::DB::open_with_ttl(opts, path, ttl)
-            .expect("could not open rocksdb for path given");
-        DiskStore { db }
+    pub fn open<P: AsRef<Path>>(
+        opts: &Options,
+        ttl: Ttl,
+        path: P,
+        metrics: Arc<Metrics>,
+    ) -> std::result::Result<Self, StoreError> {
+        let path = path.as_ref();
+        // Each entry carries its own expiry, checked on every read, so buckets can
+        // have different TTLs and an entry is never served late. RocksDB only drops
+        // entries older than its TTL when compacting, so that just reclaims space
+        // once even the longest has passed.
+        let db = rocksdb::DB::open_with_ttl(opts, path, ttl.max())
+            .map_err(|e| StoreError::from_rocksdb(path, e))?;
+
+        // Entries written before this process started have no recorded access
+        // time, so they are seeded as the oldest.
+        let mut index = RecencyIndex::default();
+        for (key, frame) in db.iterator(IteratorMode::Start).flatten() {
+            index.touch(&key, (key.len() + frame.len()) as u64, None);
+        }
+
+        Ok(DiskStore {
+            db: Arc::new(db),
+            ttl,
+            metrics,
+            index: Mutex::new(index),
+            clock: Arc::new(SystemClock),
+            compression: ValueCompression::None,
+            compression_level: 0,
+        })
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Compresses values as they are written, keeping the compressed form
+    /// only when it is smaller.
+    pub fn with_compression(mut self, compression: ValueCompression, level: i32) -> Self {
+        self.compression = compression;
+        self.compression_level = level;
+        self
+    }
+
+    /// Estimated bytes held by the disk tier.
+    pub fn size_bytes(&self) -> u64 {
+        self.index.lock().unwrap().total_bytes
+    }
+
+    /// Evicts least recently used entries until the estimated size is within
+    /// `budget`, removing at most `max_evictions` entries. Returns bytes reclaimed.
+    pub fn evict_to_budget(&self, budget: u64, max_evictions: usize) -> Result<u64> {
+        let mut index = self.index.lock().unwrap();
+        let mut reclaimed = 0;
+        let mut buckets = Vec::new();
+        for _ in 0..max_evictions {
+            if index.total_bytes <= budget {
+                break;
+            }
+            let Some(key) = index.oldest().cloned() else {
+                break;
+            };
+            self.db.delete(&key).map_err(StoreError::disk)?;
+            buckets.extend(index.bucket_of(&key));
+            reclaimed += index.remove(&key).unwrap_or_default();
+        }
+        buckets.sort();
+        buckets.dedup();
+        for bucket in buckets {
+            self.record_bucket_size(&index, &bucket);
+        }
+        Ok(reclaimed)
+    }
+
+    fn record_bucket_size(&self, index: &RecencyIndex, bucket: &str) {
+        self.metrics
+            .record_bucket_disk_bytes(bucket, index.bucket_bytes(bucket));
+    }
+
+    async fn read(
+        &self,
+        key: &CacheKey<'_>,
+        keep_expired: bool,
+    ) -> StoreResult<Option<(Entry, bool)>> {
+        let cache_key = key.digest();
+        // Read on the blocking pool so a stalled disk can be abandoned by a timeout.
+        let db = self.db.clone();
+        let lookup_key = cache_key.to_vec();
+        let Some(frame) = tokio::task::spawn_blocking(move || db.get(lookup_key))
+            .await
+            .map_err(|e| StoreError::Backend {
+                tier: "disk",
+                reason: e.to_string(),
+            })?
+            .map_err(StoreError::disk)?
+        else {
+            return Ok(None);
+        };
+
+        match StoredValue::decode(&frame) {
+            Some(stored) => {
+                let expired = stored.is_expired(self.clock.unix_millis());
+                let mut index = self.index.lock().unwrap();
+                if expired && !keep_expired {
+                    self.db.delete(cache_key).map_err(StoreError::disk)?;
+                    index.remove(cache_key);
+                    self.record_bucket_size(&index, key.bucket);
+                    return Ok(None);
+                }
+                index.touch(
+                    cache_key,
+                    (cache_key.len() + frame.len()) as u64,
+                    Some(key.bucket),
+                );
+                self.record_bucket_size(&index, key.bucket);
+                let value = if stored.compressed {
+                    decompress(stored.value, "disk")?
+                } else {
+                    stored.value.to_vec()
+                };
+                let entry = Entry {
+                    value: Value(value),
+                    written_at: stored.written_at,
+                    expires_at: stored.entry_expires_at,
+                };
+                Ok(Some((entry, expired)))
+            }
+            None => {
+                // Serve a miss so the read falls through to S3 instead of returning garbage.
+                warn!(
+                    "Checksum mismatch for disk entry in bucket {}, evicting",
+                    key.bucket
+                );
+                self.metrics.record_disk_corruption();
+                let mut index = self.index.lock().unwrap();
+                self.db.delete(cache_key).map_err(StoreError::disk)?;
+                index.remove(cache_key);
+                self.record_bucket_size(&index, key.bucket);
+                Ok(None)
+            }
+        }
+    }
+
+    // A synced write is fsynced to the write-ahead log before returning.
+    fn write(&self, key: &CacheKey<'_>, entry: &Entry, sync: bool) -> StoreResult<()> {
+        let cache_key = key.digest();
+        let compressed = compress(&entry.value.0, self.compression, self.compression_level);
+        let stored = StoredValue {
+            expires_at: self.clock.unix_secs() + self.ttl.for_key(key.bucket, cache_key).as_secs(),
+            written_at: entry.written_at,
+            entry_expires_at: entry.expires_at,
+            compressed: compressed.is_some(),
+            value: compressed.as_deref().unwrap_or(&entry.value.0),
+        };
+        let frame = stored.encode();
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(sync);
+        // Held across the write so eviction never sees the index and RocksDB disagree.
+        let mut index = self.index.lock().unwrap();
+        self.db
+            .put_opt(cache_key, &frame, &write_options)
+            .map_err(StoreError::disk)?;
+        index.touch(
+            cache_key,
+            (cache_key.len() + frame.len()) as u64,
+            Some(key.bucket),
+        );
+        self.record_bucket_size(&index, key.bucket);
+        Ok(())
     }
 }
+
 #[tonic::async_trait]
 impl Store for DiskStore {
-    async fn get(&mut self, bucket: &str, key: &Key) -> Result<Option<Value>> {
-        let result = self
-            .db
-            .get(build_cache_key(bucket.as_bytes(), key).0)?
-            .map(Value);
+    async fn get(&self, key: &CacheKey<'_>) -> StoreResult<Option<Entry>> {
+        Ok(self.read(key, false).await?.map(|(entry, _)| entry))
+    }
 
-        Ok(result)
+    async fn get_including_expired(
+        &self,
+        key: &CacheKey<'_>,
+    ) -> StoreResult<Option<(Entry, bool)>> {
+        self.read(key, true).await
     }
 
-    async fn put(&mut self, bucket: &str, key: &Key, value: &Value) -> Result<()> {
-        self.db
-            .put(build_cache_key(bucket.as_bytes(), key).0, &value.0)?;
-        Ok(())
+    async fn put(&self, key: &CacheKey<'_>, entry: &Entry) -> StoreResult<()> {
+        self.write(key, entry, false)
+    }
+
+    async fn put_durable(&self, key: &CacheKey<'_>, entry: &Entry) -> StoreResult<()> {
+        self.write(key, entry, true)
     }
 
-    async fn delete(&mut self, bucket: &str, key: &Key) -> Result<()> {
-        self.db.delete(build_cache_key(bucket.as_bytes(), key).0)?;
+    async fn delete(&self, key: &CacheKey<'_>) -> StoreResult<()> {
+        let mut index = self.index.lock().unwrap();
+        self.db.delete(key.digest()).map_err(StoreError::disk)?;
+        index.remove(key.digest());
+        self.record_bucket_size(&index, key.bucket);
         Ok(())
     }
+
+    async fn make_room(
+        &self,
+        key: &CacheKey<'_>,
+        entry: &Entry,
+        quota: u64,
+    ) -> StoreResult<Option<u64>> {
+        let size = (key.digest().len() + StoredValue::FRAME_OVERHEAD + entry.value.0.len()) as u64;
+        let mut index = self.index.lock().unwrap();
+        let Some(evictions) = index.evictions_for_quota(key.bucket, key.digest(), size, quota)
+        else {
+            return Ok(None);
+        };
+        let mut reclaimed = 0;
+        for victim in evictions {
+            self.db.delete(&victim).map_err(StoreError::disk)?;
+            reclaimed += index.remove(&victim).unwrap_or_default();
+        }
+        if reclaimed > 0 {
+            self.record_bucket_size(&index, key.bucket);
+        }
+        Ok(Some(reclaimed))
+    }
+
+    async fn exists(&self, key: &CacheKey<'_>) -> StoreResult<bool> {
+        Ok(self
+            .db
+            .get(key.digest())
+            .map_err(StoreError::disk)?
+            .and_then(|frame| {
+                StoredValue::decode(&frame).map(|s| !s.is_expired(self.clock.unix_millis()))
+            })
+            .unwrap_or(false))
+    }
+
+    async fn ttl_remaining(&self, key: &CacheKey<'_>) -> StoreResult<Option<Duration>> {
+        Ok(self
+            .db
+            .get(key.digest())
+            .map_err(StoreError::disk)?
+            .and_then(|frame| {
+                StoredValue::decode(&frame).and_then(|s| s.remaining(self.clock.unix_millis()))
+            }))
+    }
+}
+
+/// How the S3 tier names and writes its objects.
+#[derive(Clone, Debug)]
+pub struct S3Options {
+    pub key_salt: String,
+    pub shard_count: u32,
+    pub storage_class: StorageClass,
 }
 
 pub struct S3Store {
     pub client: aws_sdk_s3::Client,
+    /// Mixed into every object key so deployments sharing a bucket do not collide.
+    pub key_salt: String,
+    /// Number of distinct prefixes object keys are spread over, at most
+    /// `MAX_SHARD_COUNT`. Changing it moves every object key, like the salt.
+    pub shard_count: u32,
+    /// Storage class of the objects this store writes.
+    pub storage_class: StorageClass,
+    /// Checked against the expiry of objects put with their own TTL.
+    pub clock: Arc<dyn Clock>,
+    /// Compression for the objects this store writes; objects are read
+    /// back by their `Content-Encoding` whatever this is set to.
+    pub compression: ValueCompression,
+    pub compression_level: i32,
+}
+
+impl S3Store {
+    fn object_key(&self, key: &CacheKey<'_>) -> String {
+        build_cache_key(
+            self.key_salt.as_bytes(),
+            self.shard_count,
+            key.bucket.as_bytes(),
+            key.key,
+        )
+    }
+
+    /// Fails unless `bucket` exists and is reachable with the client's credentials.
+    pub async fn verify_bucket(client: &aws_sdk_s3::Client, bucket: &str) -> Result<()> {
+        client
+            .head_bucket()
+            .bucket(bucket)
+            .send()
+            .await
+            .map_err(|e| e.into_service_error())?;
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl Store for S3Store {
-    async fn get(&mut self, bucket: &str, key: &Key) -> Result<Option<Value>> {
+    async fn get(&self, key: &CacheKey<'_>) -> StoreResult<Option<Entry>> {
         let data = self
             .client
             .get_object()
-            .bucket(bucket)
-            .key(std::str::from_utf8(build_cache_key(bucket.as_bytes(), key).0.as_slice()).unwrap())
+            .bucket(key.bucket)
+            .key(self.object_key(key))
             .send()
             .await;
         match data {
-            Ok(v) => Ok(Some(Value(v.body.collect().await.unwrap().to_vec()))),
-            Err(e) => {
-                let error = e.into_service_error();
-
-                Err(error.into())
+            Ok(v) => {
+                // Objects written before timestamps were recorded report 0.
+                let written_at = v
+                    .metadata()
+                    .and_then(|m| m.get(WRITTEN_AT_METADATA))
+                    .and_then(|t| t.parse().ok())
+                    .unwrap_or_default();
+                let expires_at = object_expiry(v.metadata());
+                if expires_at.is_some_and(|expires_at| self.clock.unix_millis() >= expires_at) {
+                    return Ok(None);
+                }
+                let compressed = v.content_encoding() == Some(ZSTD_ENCODING);
+                let body = v
+                    .body
+                    .collect()
+                    .await
+                    .map_err(|e| StoreError::Backend {
+                        tier: "cloud",
+                        reason: e.to_string(),
+                    })?
+                    .to_vec();
+                Ok(Some(Entry {
+                    value: Value(if compressed {
+                        decompress(&body, "cloud")?
+                    } else {
+                        body
+                    }),
+                    written_at,
+                    expires_at,
+                }))
             }
+            Err(e) => miss_or_error(e.into_service_error()),
         }
     }
 
-    async fn put(&mut self, bucket: &str, key: &Key, value: &Value) -> Result<()> {
-        let result = self
+    async fn put(&self, key: &CacheKey<'_>, entry: &Entry) -> StoreResult<()> {
+        let mut request = self
             .client
             .put_object()
-            .bucket(bucket)
-            .key(std::str::from_utf8(build_cache_key(bucket.as_bytes(), key).0.as_slice()).unwrap())
-            .body(aws_sdk_s3::primitives::ByteStream::from(value.clone().0))
+            .bucket(key.bucket)
+            .key(self.object_key(key))
+            .metadata(WRITTEN_AT_METADATA, entry.written_at.to_string());
+        if let Some(expires_at) = entry.expires_at {
+            request = request.metadata(EXPIRES_AT_METADATA, expires_at.to_string());
+        }
+        let body = match compress(&entry.value.0, self.compression, self.compression_level) {
+            Some(compressed) => {
+                request = request.content_encoding(ZSTD_ENCODING);
+                compressed
+            }
+            None => entry.value.0.clone(),
+        };
+        let result = request
+            .storage_class(self.storage_class.clone())
+            .body(aws_sdk_s3::primitives::ByteStream::from(body))
             .send()
             .await;
         match result {
             Ok(_) => Ok(()),
-            Err(e) => Err(e.into_service_error().into()),
+            Err(e) => Err(StoreError::cloud(e.into_service_error())),
         }
     }
 
-    async fn delete(&mut self, bucket: &str, key: &Key) -> Result<()> {
+    async fn delete(&self, key: &CacheKey<'_>) -> StoreResult<()> {
         let result = self
             .client
             .delete_object()
-            .bucket(bucket)
-            .key(std::str::from_utf8(build_cache_key(bucket.as_bytes(), key).0.as_slice()).unwrap())
+            .bucket(key.bucket)
+            .key(self.object_key(key))
             .send()
             .await;
         match result {
             Ok(_) => Ok(()),
-            Err(e) => Err(e.into_service_error().into()),
+            Err(e) => Err(StoreError::cloud(e.into_service_error())),
+        }
+    }
+
+    async fn exists(&self, key: &CacheKey<'_>) -> StoreResult<bool> {
+        let result = self
+            .client
+            .head_object()
+            .bucket(key.bucket)
+            .key(self.object_key(key))
+            .send()
+            .await;
+        match result {
+            Ok(head) => Ok(object_expiry(head.metadata())
+                .is_none_or(|expires_at| self.clock.unix_millis() < expires_at)),
+            Err(e) => {
+                let error = e.into_service_error();
+                if error.is_not_found() {
+                    Ok(false)
+                } else {
+                    Err(StoreError::cloud(error))
+                }
+            }
         }
     }
 }
 
-fn build_cache_key(bucket: &[u8], key: &Key) -> Key {
-    let mut key_vec = vec![];
+// An absent object is a miss; anything else is a failure of the S3 tier.
+fn miss_or_error(error: GetObjectError) -> StoreResult<Option<Entry>> {
+    if error.is_no_such_key() {
+        Ok(None)
+    } else {
+        Err(StoreError::cloud(error))
+    }
+}
 
-    let mut key_to_md5 = key_vec.clone();
-    key_to_md5.extend(&key.0);
-    key_to_md5.extend(bucket);
+// Expiry of an object put with its own TTL. Objects keep it as metadata, so
+// an expired one is a miss until it is overwritten or deleted.
+fn object_expiry(metadata: Option<&HashMap<String, String>>) -> Option<u64> {
+    metadata
+        .and_then(|m| m.get(EXPIRES_AT_METADATA))
+        .and_then(|t| t.parse().ok())
+}
+
+/// A disk entry: its expiry under the disk TTL in seconds, its write time in
+/// milliseconds and, when it was put with its own TTL, that expiry in
+/// milliseconds, all since the Unix epoch, and its value, compressed or not.
+///
+/// Stored as a big-endian CRC32 of the rest of the frame, then the disk
+/// expiry, write time and entry expiry as big-endian u64s, the last 0 when
+/// there is none, then a flags byte, then the value.
+struct StoredValue<'a> {
+    expires_at: u64,
+    written_at: u64,
+    entry_expires_at: Option<u64>,
+    compressed: bool,
+    value: &'a [u8],
+}
+
+impl<'a> StoredValue<'a> {
+    /// Bytes a frame adds to its value.
+    const FRAME_OVERHEAD: usize =
+        CHECKSUM_LEN + EXPIRY_LEN + WRITTEN_AT_LEN + ENTRY_EXPIRY_LEN + FLAGS_LEN;
 
-    let digest = format!("{:x}", md5::compute(&key_to_md5))
-        .as_bytes()
-        .to_vec();
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::with_capacity(Self::FRAME_OVERHEAD - CHECKSUM_LEN + self.value.len());
+        body.extend(self.expires_at.to_be_bytes());
+        body.extend(self.written_at.to_be_bytes());
+        body.extend(self.entry_expires_at.unwrap_or(0).to_be_bytes());
+        body.push(if self.compressed { FLAG_ZSTD } else { 0 });
+        body.extend(self.value);
 
-    key_vec.extend(&digest[0..4]);
-    key_vec.extend("/".as_bytes());
+        let mut frame = Vec::with_capacity(CHECKSUM_LEN + body.len());
+        frame.extend(frame_checksum(&body).to_be_bytes());
+        frame.extend(body);
+        frame
+    }
 
-    key_vec.extend(digest);
-    Key(key_vec)
+    fn decode(frame: &'a [u8]) -> Option<Self> {
+        let (checksum, body) = frame.split_at_checked(CHECKSUM_LEN)?;
+        if frame_checksum(body) != u32::from_be_bytes(checksum.try_into().ok()?) {
+            return None;
+        }
+        let (expires_at, rest) = body.split_at_checked(EXPIRY_LEN)?;
+        let (written_at, rest) = rest.split_at_checked(WRITTEN_AT_LEN)?;
+        let (entry_expires_at, rest) = rest.split_at_checked(ENTRY_EXPIRY_LEN)?;
+        let (flags, value) = rest.split_first()?;
+        let entry_expires_at = u64::from_be_bytes(entry_expires_at.try_into().ok()?);
+        Some(StoredValue {
+            expires_at: u64::from_be_bytes(expires_at.try_into().ok()?),
+            written_at: u64::from_be_bytes(written_at.try_into().ok()?),
+            entry_expires_at: (entry_expires_at > 0).then_some(entry_expires_at),
+            compressed: flags & FLAG_ZSTD != 0,
+            value,
+        })
+    }
+
+    /// Time left until whichever expiry comes first, or `None` once one has passed.
+    fn remaining(&self, now_millis: u64) -> Option<Duration> {
+        let disk = self
+            .expires_at
+            .checked_sub(now_millis / 1000)
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs)?;
+        match self.entry_expires_at {
+            Some(expires_at) => expires_at
+                .checked_sub(now_millis)
+                .filter(|&millis| millis > 0)
+                .map(|millis| Duration::from_millis(millis).min(disk)),
+            None => Some(disk),
+        }
+    }
+
+    fn is_expired(&self, now_millis: u64) -> bool {
+        self.remaining(now_millis).is_none()
+    }
+}
+
+fn frame_checksum(body: &[u8]) -> u32 {
+    let mut digest = CHECKSUM.digest();
+    digest.update(&[FRAME_VERSION]);
+    digest.update(body);
+    digest.finalize()
+}
+
+/// Shard of a key: the prefix of its S3 object key under `salt` and
+/// `shard_count`, so one of `shard_count` values.
+pub fn key_shard(salt: &[u8], shard_count: u32, bucket: &str, key: &Key) -> u32 {
+    shard_of(&cache_key_digest(salt, bucket.as_bytes(), key), shard_count)
+}
+
+/// Prefixes a cache key can start with: every four hex digit value, so the
+/// prefix is the first two digest bytes unchanged.
+pub const MAX_SHARD_COUNT: u32 = 1 << 16;
+
+// Only hex digits and a slash, whatever bytes the key holds, so the result is
+// always a valid S3 object key.
+fn build_cache_key(salt: &[u8], shard_count: u32, bucket: &[u8], key: &Key) -> String {
+    let digest = cache_key_digest(salt, bucket, key);
+    format!("{:04x}/{:x}", shard_of(&digest, shard_count), digest)
+}
+
+fn cache_key_digest(salt: &[u8], bucket: &[u8], key: &Key) -> md5::Digest {
+    let mut key_to_md5 = vec![];
+    // Length-prefixed so no salt and key pair can hash like another. An empty
+    // salt adds nothing, keeping unsalted keys where they have always been.
+    if !salt.is_empty() {
+        key_to_md5.extend((salt.len() as u32).to_be_bytes());
+        key_to_md5.extend(salt);
+    }
+    key_to_md5.extend(&key.0);
+    key_to_md5.extend(bucket);
+    md5::compute(&key_to_md5)
+}
+
+// The first two digest bytes folded into `shard_count` values.
+fn shard_of(digest: &md5::Digest, shard_count: u32) -> u32 {
+    u32::from(u16::from_be_bytes([digest.0[0], digest.0[1]])) % shard_count
 }
 
 #[test]
 fn test_build_cache() {
     let a = "topic".as_bytes().to_vec();
     let b = "some_key".as_bytes().to_vec();
-    let result = build_cache_key(&a, &Key(b));
+    let cases = [
+        (MAX_SHARD_COUNT, "0d08/0d08c5418603c1ec5755b6f4754bf3d4"),
+        (256, "0008/0d08c5418603c1ec5755b6f4754bf3d4"),
+        (1000, "0150/0d08c5418603c1ec5755b6f4754bf3d4"),
+    ];
+    for (shard_count, expected) in cases {
+        let result = build_cache_key(UNSALTED, shard_count, &a, &Key(b.clone()));
+        assert_eq!(result, expected);
+    }
+
+    let result = build_cache_key(UNSALTED, MAX_SHARD_COUNT, &a, &Key(b.clone()));
+    let key = Key(b.clone());
+    assert_eq!(CacheKey::new("topic", &key).digest(), result.as_bytes());
+
+    let staging = build_cache_key(b"staging", MAX_SHARD_COUNT, &a, &Key(b.clone()));
+    let prod = build_cache_key(b"prod", MAX_SHARD_COUNT, &a, &Key(b));
+    assert_ne!(staging, result);
+    assert_ne!(staging, prod);
+}
+
+#[test]
+fn test_build_cache_key_stays_within_the_shard_count() {
+    let prefixes: std::collections::HashSet<String> = (0..4096u32)
+        .map(|i| {
+            build_cache_key(UNSALTED, 16, b"topic", &Key(i.to_be_bytes().to_vec()))[0..4]
+                .to_string()
+        })
+        .collect();
+    assert_eq!(prefixes.len(), 16);
+    assert!(prefixes
+        .iter()
+        .all(|p| u32::from_str_radix(p, 16).unwrap() < 16));
+}
+
+#[test]
+fn test_build_cache_key_is_url_safe_for_binary_keys() {
+    let key = Key(vec![0xff, 0x00, 0x80]);
+    let salts: [&[u8]; 3] = [UNSALTED, b"staging", &[0xfe, 0x01]];
+    for salt in salts {
+        let object_key = build_cache_key(salt, MAX_SHARD_COUNT, "topic".as_bytes(), &key);
+        assert_eq!(object_key.len(), 37);
+        assert_eq!(&object_key[4..5], "/");
+        assert!(object_key
+            .chars()
+            .all(|c| matches!(c, '0'..='9' | 'a'..='f' | '/')));
+    }
+}
+
+#[test]
+fn test_build_cache_key_spreads_over_more_than_256_prefixes() {
+    let prefixes: std::collections::HashSet<String> = (0..4096u32)
+        .map(|i| {
+            build_cache_key(
+                UNSALTED,
+                MAX_SHARD_COUNT,
+                b"topic",
+                &Key(i.to_be_bytes().to_vec()),
+            )[0..4]
+                .to_string()
+        })
+        .collect();
+    assert!(prefixes.len() > 256 * 4);
+}
+
+#[test]
+fn test_s3_missing_object_is_a_miss() {
+    use aws_sdk_s3::types::error::{InvalidObjectState, NoSuchKey};
+
+    let missing = GetObjectError::NoSuchKey(NoSuchKey::builder().build());
+    assert_eq!(miss_or_error(missing).unwrap(), None);
+    let archived = GetObjectError::InvalidObjectState(InvalidObjectState::builder().build());
+    assert!(miss_or_error(archived).is_err());
+}
+
+#[test]
+fn test_key_shard_matches_cache_key_prefix() {
+    let key = Key(b"some_key".to_vec());
+    for (salt, shard_count) in [(UNSALTED, MAX_SHARD_COUNT), (b"prod".as_slice(), 1000)] {
+        let cache_key = build_cache_key(salt, shard_count, b"topic", &key);
+        assert_eq!(
+            format!("{:04x}", key_shard(salt, shard_count, "topic", &key)),
+            &cache_key[0..4]
+        );
+    }
+}
+
+#[test]
+fn test_key_in_epoch() {
+    let key = Key(b"config".to_vec());
+    assert_eq!(key.clone().in_epoch(0), key);
+    assert_eq!(key.clone().in_epoch(3).0, b"__epoch__/3/config".to_vec());
+    assert_ne!(key.clone().in_epoch(3), key.in_epoch(4));
+}
+
+#[test]
+fn test_frame_round_trip() {
+    let frame = StoredValue {
+        expires_at: 100,
+        written_at: 42,
+        entry_expires_at: None,
+        compressed: false,
+        value: b"value",
+    }
+    .encode();
+    let stored = StoredValue::decode(&frame).unwrap();
+    assert_eq!(stored.value, b"value");
+    assert_eq!(stored.written_at, 42);
+    assert_eq!(stored.entry_expires_at, None);
+    assert!(!stored.is_expired(99_999));
+    assert!(stored.is_expired(100_000));
 
+    // An entry's own TTL applies when it ends before the disk TTL
+    let expiring = StoredValue {
+        expires_at: 100,
+        written_at: 42,
+        entry_expires_at: Some(50_500),
+        compressed: true,
+        value: b"value",
+    }
+    .encode();
+    let stored = StoredValue::decode(&expiring).unwrap();
+    assert_eq!(stored.entry_expires_at, Some(50_500));
+    assert!(stored.compressed);
+    assert!(!stored.is_expired(50_499));
+    assert!(stored.is_expired(50_500));
+
+    let mut corrupted = frame.clone();
+    *corrupted.last_mut().unwrap() ^= 0xff;
+    assert!(StoredValue::decode(&corrupted).is_none());
+    assert!(StoredValue::decode(&frame[..2]).is_none());
+
+    // Frames from before entry expiries were stored fail verification
+    let mut body = Vec::new();
+    body.extend(100u64.to_be_bytes());
+    body.extend(42u64.to_be_bytes());
+    body.extend(b"value 12345");
+    let mut old = CHECKSUM.checksum(&body).to_be_bytes().to_vec();
+    old.extend(body);
+    assert!(StoredValue::decode(&old).is_none());
+
+    // As do frames from before values were flagged as compressed
+    let mut body = Vec::new();
+    body.extend(100u64.to_be_bytes());
+    body.extend(42u64.to_be_bytes());
+    body.extend(0u64.to_be_bytes());
+    body.extend(b"value");
+    let mut digest = CHECKSUM.digest();
+    digest.update(&[2]);
+    digest.update(&body);
+    let mut unflagged = digest.finalize().to_be_bytes().to_vec();
+    unflagged.extend(body);
+    assert!(StoredValue::decode(&unflagged).is_none());
+}
+
+#[test]
+fn test_store_error_classifies_rocksdb_failures() {
+    let path = Path::new("./db");
+    let classify = |reason: &str| {
+        // rocksdb::Error has no public constructor, so classify the message directly.
+        match StoreError::from_message(path, reason.to_string()) {
+            StoreError::Locked { .. } => "locked",
+            StoreError::PermissionDenied { .. } => "permission",
+            StoreError::Open { .. } => "open",
+            other => panic!("not an open failure: {other}"),
+        }
+    };
+    assert_eq!(
+        classify("IO error: While lock file: ./db/LOCK: Resource temporarily unavailable"),
+        "locked"
+    );
+    assert_eq!(
+        classify("IO error: lock hold by current process, acquire time 1700000000 acquiring thread 1: ./db/LOCK: No locks available"),
+        "locked"
+    );
+    assert_eq!(
+        classify("IO error: While mkdir if missing: ./db: Permission denied"),
+        "permission"
+    );
+    assert_eq!(classify("Corruption: bad block"), "open");
+    assert_eq!(
+        classify("Corruption: block checksum mismatch in ./db/000012.sst"),
+        "open"
+    );
+    assert_eq!(classify("IO error: No space left on device"), "open");
+}
+
+#[test]
+fn test_recency_index_evicts_oldest_first() {
+    let mut index = RecencyIndex::default();
+    index.touch(b"a", 10, None);
+    index.touch(b"b", 20, None);
+    index.touch(b"a", 15, None);
+    assert_eq!(index.total_bytes, 35);
+    assert_eq!(index.oldest(), Some(&b"b".to_vec()));
+
+    assert_eq!(index.remove(b"b"), Some(20));
+    assert_eq!(index.oldest(), Some(&b"a".to_vec()));
+    assert_eq!(index.total_bytes, 15);
+    assert_eq!(index.remove(b"b"), None);
+}
+
+#[test]
+fn test_recency_index_evicts_within_bucket_quota() {
+    let mut index = RecencyIndex::default();
+    index.touch(b"seeded", 50, None);
+    index.touch(b"a1", 10, Some("a"));
+    index.touch(b"b1", 40, Some("b"));
+    index.touch(b"a2", 20, Some("a"));
+    index.touch(b"a3", 30, Some("a"));
+    assert_eq!(index.bucket_bytes("a"), 60);
+    assert_eq!(index.bucket_bytes("b"), 40);
+
+    // Fits without evicting, counting a replaced entry only once
+    assert_eq!(index.evictions_for_quota("a", b"a4", 40, 100), Some(vec![]));
+    assert_eq!(index.evictions_for_quota("a", b"a3", 70, 100), Some(vec![]));
+    // Evicts the bucket's own least recently used entries, never another's
+    assert_eq!(
+        index.evictions_for_quota("a", b"a4", 65, 100),
+        Some(vec![b"a1".to_vec(), b"a2".to_vec()])
+    );
+    assert_eq!(
+        index.evictions_for_quota("a", b"a1", 100, 100),
+        Some(vec![b"a2".to_vec(), b"a3".to_vec()])
+    );
+    assert_eq!(index.evictions_for_quota("a", b"a4", 101, 100), None);
+
+    index.remove(b"a1");
+    index.touch(b"seeded", 50, Some("a"));
+    assert_eq!(index.bucket_bytes("a"), 100);
+    assert_eq!(index.bucket_of(b"seeded").as_deref(), Some("a"));
+    index.remove(b"b1");
+    assert_eq!(index.bucket_bytes("b"), 0);
+    assert!(!index.buckets.contains_key("b"));
+}
+
+#[tokio::test]
+async fn test_disk_store_expires_entries_on_read() {
+    let path = std::env::temp_dir().join(format!("milena-disk-ttl-{}", std::process::id()));
+    let mut opts = Options::default();
+    opts.create_if_missing(true);
+    let clock = Arc::new(crate::clock::MockClock::default());
+    let store = DiskStore::open(
+        &opts,
+        Ttl::new(Duration::from_secs(60)),
+        &path,
+        Arc::new(Metrics::new().unwrap()),
+    )
+    .unwrap()
+    .with_clock(clock.clone());
+
+    let key = Key(b"session".to_vec());
+    let key = CacheKey::new("users", &key);
+    let entry = Entry {
+        value: Value(b"token".to_vec()),
+        written_at: 0,
+        expires_at: None,
+    };
+    store.put(&key, &entry).await.unwrap();
+    clock.advance(Duration::from_secs(59));
+    assert_eq!(store.get(&key).await.unwrap(), Some(entry.clone()));
+    assert_eq!(
+        store.ttl_remaining(&key).await.unwrap(),
+        Some(Duration::from_secs(1))
+    );
+
+    // Expired long before RocksDB would compact it away
+    clock.advance(Duration::from_secs(1));
+    assert!(!store.exists(&key).await.unwrap());
+    assert_eq!(store.get(&key).await.unwrap(), None);
+    // The read deleted it, so it is gone even to reads that keep expired entries
+    assert_eq!(store.get_including_expired(&key).await.unwrap(), None);
+    assert_eq!(store.size_bytes(), 0);
+
+    drop(store);
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+#[tokio::test]
+async fn test_disk_stores_at_different_paths_are_independent() {
+    let open = |name: &str| {
+        let disk = DiskOptions {
+            path: std::env::temp_dir().join(format!("milena-disk-{}-{}", name, std::process::id())),
+            block_cache_mb: 1,
+            compression: DiskCompression::Lz4,
+        };
+        let store = DiskStore::open(
+            &disk.rocksdb_options(),
+            Ttl::new(Duration::from_secs(60)),
+            &disk.path,
+            Arc::new(Metrics::new().unwrap()),
+        )
+        .unwrap();
+        (store, disk.path)
+    };
+    // Both open at once, which a shared path would refuse with a lock error
+    let (first, first_path) = open("first");
+    let (second, second_path) = open("second");
+
+    let key = Key(b"session".to_vec());
+    let key = CacheKey::new("users", &key);
+    let entry = |value: &[u8]| Entry {
+        value: Value(value.to_vec()),
+        written_at: 0,
+        expires_at: None,
+    };
+    first.put(&key, &entry(b"first")).await.unwrap();
+    assert_eq!(second.get(&key).await.unwrap(), None);
+    second.put(&key, &entry(b"second")).await.unwrap();
+    assert_eq!(first.get(&key).await.unwrap(), Some(entry(b"first")));
+    first.delete(&key).await.unwrap();
+    assert_eq!(second.get(&key).await.unwrap(), Some(entry(b"second")));
+
+    drop((first, second));
+    let _ = std::fs::remove_dir_all(&first_path);
+    let _ = std::fs::remove_dir_all(&second_path);
+}
+
+#[test]
+fn test_disk_options_apply_block_cache_and_compression() {
+    let cases = [
+        (8, DiskCompression::Zstd, "kZSTD"),
+        (0, DiskCompression::None, "kNoCompression"),
+    ];
+    for (block_cache_mb, compression, persisted_name) in cases {
+        let disk = DiskOptions {
+            path: std::env::temp_dir().join(format!(
+                "milena-disk-options-{}-{}",
+                persisted_name,
+                std::process::id()
+            )),
+            block_cache_mb,
+            compression,
+        };
+        let store = DiskStore::open(
+            &disk.rocksdb_options(),
+            Ttl::new(Duration::from_secs(60)),
+            &disk.path,
+            Arc::new(Metrics::new().unwrap()),
+        )
+        .unwrap();
+
+        // RocksDB records the options it opened with in its OPTIONS file
+        let options_file = std::fs::read_dir(&disk.path)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .starts_with("OPTIONS-")
+            })
+            .max()
+            .unwrap();
+        let persisted = std::fs::read_to_string(options_file).unwrap();
+        assert!(persisted.contains(&format!("compression={}", persisted_name)));
+        assert!(persisted.contains(&format!("no_block_cache={}", block_cache_mb == 0)));
+        if block_cache_mb > 0 {
+            assert_eq!(
+                store
+                    .db
+                    .property_int_value("rocksdb.block-cache-capacity")
+                    .unwrap(),
+                Some(block_cache_mb as u64 * 1024 * 1024)
+            );
+        }
+
+        drop(store);
+        let _ = std::fs::remove_dir_all(&disk.path);
+    }
+}
+
+#[tokio::test]
+async fn test_disk_store_compression_round_trip() {
+    let path = std::env::temp_dir().join(format!("milena-disk-zstd-{}", std::process::id()));
+    let mut opts = Options::default();
+    opts.create_if_missing(true);
+    let store = DiskStore::open(
+        &opts,
+        Ttl::new(Duration::from_secs(60)),
+        &path,
+        Arc::new(Metrics::new().unwrap()),
+    )
+    .unwrap()
+    .with_compression(ValueCompression::Zstd, 3);
+    let stored_len = |key: &CacheKey<'_>| store.db.get(key.digest()).unwrap().unwrap().len();
+
+    // A JSON-like value shrinks and is stored compressed
+    let json = Key(b"json".to_vec());
+    let json = CacheKey::new("users", &json);
+    let compressible = Entry {
+        value: Value(br#"{"name":"milena","tags":["cache","cache","cache"]}"#.repeat(50)),
+        written_at: 42,
+        expires_at: None,
+    };
+    store.put(&json, &compressible).await.unwrap();
+    assert!(stored_len(&json) < compressible.value.0.len());
+    assert_eq!(store.get(&json).await.unwrap(), Some(compressible));
+
+    // Bytes that do not compress are stored as given
+    let noise = Key(b"noise".to_vec());
+    let noise = CacheKey::new("users", &noise);
+    let incompressible = Entry {
+        value: Value(
+            (0..64u32)
+                .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+                .collect(),
+        ),
+        written_at: 42,
+        expires_at: None,
+    };
+    store.put(&noise, &incompressible).await.unwrap();
     assert_eq!(
-        String::from_utf8_lossy(result.0.as_slice()),
-        "0d08/0d08c5418603c1ec5755b6f4754bf3d4"
+        stored_len(&noise),
+        StoredValue::FRAME_OVERHEAD + incompressible.value.0.len()
     );
+    assert_eq!(store.get(&noise).await.unwrap(), Some(incompressible));
+
+    // Values written before compression was enabled still read back
+    let plain = Key(b"plain".to_vec());
+    let plain = CacheKey::new("users", &plain);
+    let store = store.with_compression(ValueCompression::None, 0);
+    let uncompressed = Entry::new(Value(b"abc".repeat(100)), &SystemClock);
+    store.put(&plain, &uncompressed).await.unwrap();
+    let store = store.with_compression(ValueCompression::Zstd, 3);
+    assert_eq!(store.get(&plain).await.unwrap(), Some(uncompressed));
+
+    drop(store);
+    let _ = std::fs::remove_dir_all(&path);
 }
+
 #[tokio::test]
 async fn test_lru_store_methods() {
-    let mut store = LRUStore::new(100);
+    let store = LRUStore::new(100);
     let bucket = "bucket";
     let key = Key("key".as_bytes().to_vec());
-    let value = Value("value".as_bytes().to_vec());
+    let key = CacheKey::new(bucket, &key);
+    let value = Entry::new(Value("value".as_bytes().to_vec()), &SystemClock);
 
     // Test put
-    store.put(bucket, &key, &value).await.unwrap();
-    assert_eq!(store.cache.len(), 1);
+    store.put(&key, &value).await.unwrap();
+    assert_eq!(store.inner.lock().unwrap().cache.len(), 1);
 
     // Test get
-    let result = store.get(bucket, &key).await.unwrap();
+    let result = store.get(&key).await.unwrap();
     assert_eq!(result.unwrap(), value.clone());
 
     // Test delete
-    store.delete(bucket, &key).await.unwrap();
-    assert_eq!(store.cache.len(), 0);
+    store.delete(&key).await.unwrap();
+    assert_eq!(store.inner.lock().unwrap().cache.len(), 0);
+}
+
+#[tokio::test]
+async fn test_lru_store_pinned_keys_survive_eviction() {
+    let store = LRUStore::new(1).with_max_pinned_bytes(100);
+    let bucket = "bucket";
+    let hot = Key(b"hot".to_vec());
+    let hot = CacheKey::new(bucket, &hot);
+    let value = Entry::new(Value(b"value".to_vec()), &SystemClock);
+
+    store.put(&hot, &value).await.unwrap();
+    store.pin(&hot).unwrap();
+    for i in 0..10u8 {
+        let key = Key(vec![i]);
+        store
+            .put(&CacheKey::new(bucket, &key), &value)
+            .await
+            .unwrap();
+    }
+    assert_eq!(store.get(&hot).await.unwrap(), Some(value.clone()));
+
+    // Pins that would overrun the budget are refused and stay cached.
+    let big = Key(b"big".to_vec());
+    let big = CacheKey::new(bucket, &big);
+    let big_value = Entry::new(Value(vec![0; 64]), &SystemClock);
+    store.put(&big, &big_value).await.unwrap();
+    assert!(store.pin(&big).is_err());
+    assert_eq!(store.get(&big).await.unwrap(), Some(big_value));
+
+    assert!(store.unpin(&hot));
+    assert!(!store.unpin(&hot));
+    assert_eq!(store.inner.lock().unwrap().pinned_bytes, 0);
+    let key = Key(vec![42]);
+    store
+        .put(&CacheKey::new(bucket, &key), &value)
+        .await
+        .unwrap();
+    assert!(store.get(&hot).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_lru_store_evicts_idle_entries() {
+    let clock = Arc::new(crate::clock::MockClock::default());
+    let store = LRUStore::new(10).with_clock(clock.clone());
+    let bucket = "bucket";
+    let cold = Key(b"cold".to_vec());
+    let cold = CacheKey::new(bucket, &cold);
+    let warm = Key(b"warm".to_vec());
+    let warm = CacheKey::new(bucket, &warm);
+    let hot = Key(b"hot".to_vec());
+    let hot = CacheKey::new(bucket, &hot);
+    let value = Entry::new(Value(b"value".to_vec()), &SystemClock);
+
+    store.put(&cold, &value).await.unwrap();
+    store.put(&warm, &value).await.unwrap();
+    store.put(&hot, &value).await.unwrap();
+    store.pin(&hot).unwrap();
+    clock.advance(Duration::from_secs(50));
+    // Reading an entry keeps it from going idle
+    store.get(&warm).await.unwrap();
+
+    assert_eq!(store.evict_idle(Duration::from_secs(40), 10), 1);
+    assert!(store.get(&cold).await.unwrap().is_none());
+    assert!(store.get(&warm).await.unwrap().is_some());
+    assert!(store.get(&hot).await.unwrap().is_some());
+
+    // Each sweep removes at most the given number of entries. An entry is
+    // idle only once strictly more than `idle` has passed since its last use.
+    store.put(&cold, &value).await.unwrap();
+    assert_eq!(store.evict_idle(Duration::ZERO, 1), 0);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(store.evict_idle(Duration::ZERO, 1), 1);
+    assert_eq!(store.inner.lock().unwrap().cache.len(), 1);
+}
+
+#[tokio::test]
+async fn test_lru_store_drops_expired_entries_on_read() {
+    let clock = Arc::new(crate::clock::MockClock::default());
+    let store = LRUStore::new(10).with_clock(clock.clone());
+    let bucket = "bucket";
+    let short = Key(b"short".to_vec());
+    let short = CacheKey::new(bucket, &short);
+    let long = Key(b"long".to_vec());
+    let long = CacheKey::new(bucket, &long);
+    let pinned = Key(b"pinned".to_vec());
+    let pinned = CacheKey::new(bucket, &pinned);
+    let value = Value(b"value".to_vec());
+
+    let expiring = Entry::new(value.clone(), &*clock).with_ttl(Duration::from_secs(10));
+    store.put(&short, &expiring).await.unwrap();
+    store.pin(&pinned).unwrap();
+    store.put(&pinned, &expiring).await.unwrap();
+    let lasting = Entry::new(value, &*clock).with_ttl(Duration::from_secs(86400));
+    store.put(&long, &lasting).await.unwrap();
+
+    clock.advance(Duration::from_secs(9));
+    assert_eq!(store.get(&short).await.unwrap(), Some(expiring.clone()));
+
+    clock.advance(Duration::from_secs(1));
+    assert!(!store.exists(&short).await.unwrap());
+    assert!(store.get(&short).await.unwrap().is_none());
+    assert!(store.get(&pinned).await.unwrap().is_none());
+    assert_eq!(store.get(&long).await.unwrap(), Some(lasting));
+    // The read evicted it rather than just hiding it
+    assert_eq!(store.inner.lock().unwrap().cache.len(), 1);
+    assert_eq!(store.entries().0, 2);
+}
+
+#[tokio::test]
+async fn test_lru_store_compression() {
+    let store = LRUStore::new(10).with_compression(MemoryCompression::Lz4, 64);
+    let bucket = "bucket";
+    let large = Key(b"large".to_vec());
+    let large = CacheKey::new(bucket, &large);
+    let small = Key(b"small".to_vec());
+    let small = CacheKey::new(bucket, &small);
+    let repetitive = Entry::new(Value(b"abcd".repeat(1024)), &SystemClock);
+    let short = Entry::new(Value(b"value".to_vec()), &SystemClock);
+
+    store.put(&large, &repetitive).await.unwrap();
+    store.put(&small, &short).await.unwrap();
+    assert_eq!(store.get(&large).await.unwrap(), Some(repetitive.clone()));
+    assert_eq!(store.get(&small).await.unwrap(), Some(short.clone()));
+    {
+        let inner = store.inner.lock().unwrap();
+        assert_eq!(inner.logical_bytes, 4096 + 5);
+        assert!(inner.stored_bytes < 1024);
+        assert!(!inner.cache.peek(small.digest()).unwrap().0.compressed);
+    }
+
+    // Pinned values are held as given, and compressed again once unpinned
+    store.pin(&large).unwrap();
+    assert_eq!(store.inner.lock().unwrap().logical_bytes, 5);
+    assert_eq!(store.get(&large).await.unwrap(), Some(repetitive));
+    assert!(store.unpin(&large));
+    store.delete(&small).await.unwrap();
+    let inner = store.inner.lock().unwrap();
+    assert_eq!(inner.logical_bytes, 4096);
+    assert!(inner.stored_bytes < 1024);
+}
+
+// Compares deriving the key once per operation with deriving it at each of the
+// three tiers a read touches, as every store did before keys were precomputed:
+// cargo test -p milena-cache --release -- --ignored --nocapture bench_cache_key_reuse
+#[tokio::test]
+#[ignore]
+async fn bench_cache_key_reuse() {
+    const OPERATIONS: usize = 500_000;
+
+    let tiers: Vec<LRUStore> = (0..3).map(|_| LRUStore::new(1024)).collect();
+    let keys: Vec<Key> = (0..1024)
+        .map(|i| Key(format!("user:{i}").into_bytes()))
+        .collect();
+    let bucket = "sessions";
+
+    let start = std::time::Instant::now();
+    for i in 0..OPERATIONS {
+        let key = &keys[i % keys.len()];
+        for tier in &tiers {
+            let found = tier.get(&CacheKey::new(bucket, key)).await.unwrap();
+            std::hint::black_box(found);
+        }
+    }
+    let per_tier_elapsed = start.elapsed();
+
+    let start = std::time::Instant::now();
+    for i in 0..OPERATIONS {
+        let key = CacheKey::new(bucket, &keys[i % keys.len()]);
+        for tier in &tiers {
+            std::hint::black_box(tier.get(&key).await.unwrap());
+        }
+    }
+    let once_elapsed = start.elapsed();
+
+    let per_operation = |elapsed: Duration| elapsed / OPERATIONS as u32;
+    println!(
+        "{} operations: keyed per tier {:?} ({:?}/op), keyed once {:?} ({:?}/op)",
+        OPERATIONS,
+        per_tier_elapsed,
+        per_operation(per_tier_elapsed),
+        once_elapsed,
+        per_operation(once_elapsed)
+    );
 }
@@ -0,0 +1,123 @@
+use crc::{Crc, CRC_32_ISCSI};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const KEY_HASH: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+
+/// How the jitter applied to each entry's TTL is chosen.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JitterMode {
+    /// A fresh offset on every write.
+    #[default]
+    Random,
+    /// An offset derived from the key, so a key always gets the same TTL.
+    Deterministic,
+}
+
+/// Disk entry lifetime: a base TTL, optionally overridden per bucket, spread by
+/// up to `jitter_percent` in either direction so entries written together do
+/// not all expire together.
+#[derive(Clone, Debug)]
+pub struct Ttl {
+    base: Duration,
+    bucket_overrides: HashMap<String, Duration>,
+    jitter_percent: u8,
+    mode: JitterMode,
+}
+
+impl Ttl {
+    pub fn new(base: Duration) -> Self {
+        Self {
+            base,
+            bucket_overrides: HashMap::new(),
+            jitter_percent: 0,
+            mode: JitterMode::default(),
+        }
+    }
+
+    pub fn with_jitter(mut self, jitter_percent: u8, mode: JitterMode) -> Self {
+        self.jitter_percent = jitter_percent;
+        self.mode = mode;
+        self
+    }
+
+    /// Replaces the base TTL for entries in the given buckets.
+    pub fn with_bucket_overrides(mut self, bucket_overrides: HashMap<String, Duration>) -> Self {
+        self.bucket_overrides = bucket_overrides;
+        self
+    }
+
+    fn base_for(&self, bucket: &str) -> Duration {
+        self.bucket_overrides
+            .get(bucket)
+            .copied()
+            .unwrap_or(self.base)
+    }
+
+    /// Longest TTL any entry can get, in any bucket.
+    pub fn max(&self) -> Duration {
+        let longest = self
+            .bucket_overrides
+            .values()
+            .copied()
+            .fold(self.base, Duration::max);
+        longest.mul_f64(1.0 + f64::from(self.jitter_percent) / 100.0)
+    }
+
+    pub fn for_key(&self, bucket: &str, key: &[u8]) -> Duration {
+        let base = self.base_for(bucket);
+        if self.jitter_percent == 0 {
+            return base;
+        }
+        let position = match self.mode {
+            JitterMode::Random => rand::random::<f64>(),
+            JitterMode::Deterministic => f64::from(KEY_HASH.checksum(key)) / f64::from(u32::MAX),
+        };
+        // Map [0, 1] onto [-jitter, +jitter]
+        let offset = (2.0 * position - 1.0) * f64::from(self.jitter_percent) / 100.0;
+        base.mul_f64(1.0 + offset)
+    }
+}
+
+#[test]
+fn test_ttl_jitter_bounds() {
+    let base = Duration::from_secs(3600);
+    assert_eq!(Ttl::new(base).for_key("bucket", b"key"), base);
+
+    let min = Duration::from_secs(3240);
+    let max = Duration::from_secs(3960);
+    let random = Ttl::new(base).with_jitter(10, JitterMode::Random);
+    assert_eq!(random.max(), max);
+    for i in 0..100u32 {
+        let ttl = random.for_key("bucket", &i.to_be_bytes());
+        assert!(ttl >= min && ttl <= max);
+    }
+
+    let deterministic = Ttl::new(base).with_jitter(10, JitterMode::Deterministic);
+    let ttl = deterministic.for_key("bucket", b"key");
+    assert!(ttl >= min && ttl <= max);
+    assert_eq!(deterministic.for_key("bucket", b"key"), ttl);
+    assert_ne!(deterministic.for_key("bucket", b"other"), ttl);
+}
+
+#[test]
+fn test_ttl_bucket_overrides() {
+    let ttl = Ttl::new(Duration::from_secs(3600)).with_bucket_overrides(HashMap::from([
+        ("sessions".to_string(), Duration::from_secs(60)),
+        ("reports".to_string(), Duration::from_secs(86400)),
+    ]));
+    assert_eq!(ttl.for_key("sessions", b"key"), Duration::from_secs(60));
+    assert_eq!(ttl.for_key("reports", b"key"), Duration::from_secs(86400));
+    assert_eq!(ttl.for_key("other", b"key"), Duration::from_secs(3600));
+    assert_eq!(ttl.max(), Duration::from_secs(86400));
+
+    // Jitter spreads each bucket around its own TTL
+    let jittered = ttl.with_jitter(10, JitterMode::Deterministic);
+    let short = jittered.for_key("sessions", b"key");
+    assert!(short >= Duration::from_secs(54) && short <= Duration::from_secs(66));
+    let long = jittered.for_key("reports", b"key");
+    assert!(long >= Duration::from_secs(77760) && long <= Duration::from_secs(95040));
+    assert_eq!(jittered.max(), Duration::from_secs(95040));
+}
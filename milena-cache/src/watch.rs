@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use futures::stream::{BoxStream, StreamExt};
+use tokio::sync::broadcast;
+
+use crate::metrics::Metrics;
+use crate::store::{Entry, Key};
+
+/// A watched key's new entry, or `None` once it is deleted.
+pub type KeyChange = Option<Entry>;
+
+/// Per-key channels through which puts and deletes on this node reach the
+/// clients watching those keys. A watcher that falls more than `buffer`
+/// changes behind is dropped, so a slow client never holds up writes or
+/// other watchers of the same key.
+pub struct Watchers {
+    channels: Mutex<Channels>,
+    max_watchers: usize,
+    buffer: usize,
+    metrics: Arc<Metrics>,
+}
+
+#[derive(Default)]
+struct Channels {
+    by_key: HashMap<(String, Vec<u8>), broadcast::Sender<KeyChange>>,
+    watching: usize,
+}
+
+/// Why a watch ended early.
+#[derive(Debug, PartialEq)]
+pub struct Lagged;
+
+impl Watchers {
+    pub fn new(max_watchers: usize, buffer: usize, metrics: Arc<Metrics>) -> Self {
+        Self {
+            channels: Mutex::new(Channels::default()),
+            max_watchers,
+            buffer,
+            metrics,
+        }
+    }
+
+    /// Changes to `key` from now on, ending with `Lagged` if the caller falls
+    /// behind. `None` when `max_watchers` watches are already open.
+    pub fn subscribe(
+        self: &Arc<Self>,
+        bucket: &str,
+        key: &Key,
+    ) -> Option<BoxStream<'static, Result<KeyChange, Lagged>>> {
+        let mut channels = self.channels.lock().unwrap();
+        if channels.watching >= self.max_watchers {
+            return None;
+        }
+        let watch_key = (bucket.to_string(), key.0.clone());
+        let receiver = channels
+            .by_key
+            .entry(watch_key.clone())
+            .or_insert_with(|| broadcast::channel(self.buffer).0)
+            .subscribe();
+        channels.watching += 1;
+        self.metrics.record_watchers(channels.watching);
+
+        let watch = Watch {
+            receiver,
+            watchers: self.clone(),
+            key: watch_key,
+        };
+        let changes = futures::stream::unfold(Some(watch), |watch| async move {
+            let mut watch = watch?;
+            match watch.receiver.recv().await {
+                Ok(change) => Some((Ok(change), Some(watch))),
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    watch.watchers.metrics.record_watcher_dropped();
+                    Some((Err(Lagged), None))
+                }
+                Err(broadcast::error::RecvError::Closed) => None,
+            }
+        });
+        Some(changes.boxed())
+    }
+
+    /// Sends `change` to everyone watching `key`.
+    pub fn publish(&self, bucket: &str, key: &Key, change: KeyChange) {
+        let channels = self.channels.lock().unwrap();
+        if channels.by_key.is_empty() {
+            return;
+        }
+        if let Some(sender) = channels.by_key.get(&(bucket.to_string(), key.0.clone())) {
+            // Fails only when every receiver has just gone
+            let _ = sender.send(change);
+        }
+    }
+}
+
+/// One open watch; closing it frees its slot, and the key's channel with the
+/// last watcher.
+struct Watch {
+    receiver: broadcast::Receiver<KeyChange>,
+    watchers: Arc<Watchers>,
+    key: (String, Vec<u8>),
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        let mut channels = self.watchers.channels.lock().unwrap();
+        channels.watching -= 1;
+        self.watchers.metrics.record_watchers(channels.watching);
+        // Subscriptions take the same lock, so a count of one is this watch
+        let last = channels
+            .by_key
+            .get(&self.key)
+            .is_some_and(|sender| sender.receiver_count() <= 1);
+        if last {
+            channels.by_key.remove(&self.key);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_watchers_receive_changes_until_dropped() {
+    let watchers = Arc::new(Watchers::new(2, 2, Arc::new(Metrics::new().unwrap())));
+    let key = Key(b"config".to_vec());
+    let entry = Entry::new(
+        crate::store::Value(b"v1".to_vec()),
+        &crate::clock::SystemClock,
+    );
+
+    let mut watch = watchers.subscribe("users", &key).unwrap();
+    let mut slow = watchers.subscribe("users", &key).unwrap();
+    assert!(watchers.subscribe("users", &key).is_none());
+
+    // Changes to other keys and buckets are not delivered
+    watchers.publish("users", &Key(b"other".to_vec()), None);
+    watchers.publish("groups", &key, None);
+    watchers.publish("users", &key, Some(entry.clone()));
+    assert_eq!(watch.next().await, Some(Ok(Some(entry.clone()))));
+    watchers.publish("users", &key, None);
+    assert_eq!(watch.next().await, Some(Ok(None)));
+
+    // The watcher that never read has missed more changes than its buffer holds
+    watchers.publish("users", &key, Some(entry));
+    assert_eq!(slow.next().await, Some(Err(Lagged)));
+    assert_eq!(slow.next().await, None);
+
+    drop(slow);
+    assert!(watchers.subscribe("users", &key).is_some());
+    drop(watch);
+    assert!(watchers.channels.lock().unwrap().by_key.is_empty());
+}
@@ -0,0 +1,467 @@
+//! Async client for a Milena router.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), milena_client::ClientError> {
+//! let client = milena_client::Client::connect("http://localhost:50050").await?;
+//! client.put("default", b"my_key", b"my_value".to_vec()).await?;
+//! assert_eq!(client.get("default", b"my_key").await?, Some(b"my_value".to_vec()));
+//! client.delete("default", b"my_key").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use milena_protos::router_server::router_client::RouterClient;
+use milena_protos::router_server::{DeleteRequest, ExistsRequest, GetRequest, PutRequest};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tokio::time::Instant;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Code, Request, Status};
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Connection error: {0}")]
+    Connection(#[from] tonic::transport::Error),
+    #[error("Request failed: {0}")]
+    Status(#[from] Status),
+    #[error("Deadline of {0:?} exceeded")]
+    DeadlineExceeded(Duration),
+}
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    /// Total time a call may take, across all of its attempts.
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    /// Attempts after the first for calls that fail with a transient status.
+    pub max_retries: u32,
+    /// Delay before the first retry; doubled for each one after.
+    pub retry_backoff: Duration,
+    pub max_message_bytes: usize,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            connect_timeout: Duration::from_secs(5),
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(50),
+            max_message_bytes: 8 * 1024 * 1024,
+        }
+    }
+}
+
+/// Handle to a router. Clones share one HTTP/2 connection, so create one
+/// client per process and clone it freely.
+#[derive(Clone)]
+pub struct Client {
+    inner: RouterClient<Channel>,
+    config: ClientConfig,
+}
+
+impl Client {
+    pub async fn connect(endpoint: impl Into<String>) -> Result<Self> {
+        Self::connect_with(endpoint, ClientConfig::default()).await
+    }
+
+    pub async fn connect_with(endpoint: impl Into<String>, config: ClientConfig) -> Result<Self> {
+        let channel = Endpoint::from_shared(endpoint.into())?
+            .connect_timeout(config.connect_timeout)
+            .connect()
+            .await?;
+        let inner = RouterClient::new(channel)
+            .max_decoding_message_size(config.max_message_bytes)
+            .max_encoding_message_size(config.max_message_bytes);
+        Ok(Self { inner, config })
+    }
+
+    /// Returns the value stored under `key`, or `None` on a miss.
+    pub async fn get(&self, bucket: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let response = self
+            .call(|mut client, timeout| {
+                let request = with_timeout(
+                    GetRequest {
+                        key: key.to_vec(),
+                        bucket: bucket.to_string(),
+                        ..Default::default()
+                    },
+                    timeout,
+                );
+                async move { client.get(request).await }
+            })
+            .await?;
+        // Routers that predate `found` never set it, but every value they
+        // return carries a write time.
+        if !response.found && response.written_at_millis == 0 {
+            return Ok(None);
+        }
+        Ok(Some(response.value))
+    }
+
+    /// Returns whether a `get` would find `key`, without transferring its value.
+    pub async fn exists(&self, bucket: &str, key: &[u8]) -> Result<bool> {
+        let response = self
+            .call(|mut client, timeout| {
+                let request = with_timeout(
+                    ExistsRequest {
+                        key: key.to_vec(),
+                        bucket: bucket.to_string(),
+                        ..Default::default()
+                    },
+                    timeout,
+                );
+                async move { client.exists(request).await }
+            })
+            .await?;
+        Ok(response.exists)
+    }
+
+    pub async fn put(&self, bucket: &str, key: &[u8], value: impl Into<Vec<u8>>) -> Result<()> {
+        self.put_with(bucket, key, value.into(), false).await
+    }
+
+    /// Like `put`, but returns only once the value is fsynced on the cache
+    /// node's disk and written through to S3. Slower than `put`; use it for
+    /// writes that must survive a node crash.
+    pub async fn put_durable(
+        &self,
+        bucket: &str,
+        key: &[u8],
+        value: impl Into<Vec<u8>>,
+    ) -> Result<()> {
+        self.put_with(bucket, key, value.into(), true).await
+    }
+
+    async fn put_with(
+        &self,
+        bucket: &str,
+        key: &[u8],
+        value: Vec<u8>,
+        durable: bool,
+    ) -> Result<()> {
+        let idempotency_key = idempotency_key();
+        self.call(|mut client, timeout| {
+            let request = with_timeout(
+                PutRequest {
+                    key: key.to_vec(),
+                    bucket: bucket.to_string(),
+                    value: value.clone(),
+                    durable,
+                    idempotency_key: idempotency_key.clone(),
+                    ..Default::default()
+                },
+                timeout,
+            );
+            async move { client.put(request).await }
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Returns whether the key existed before it was deleted.
+    pub async fn delete(&self, bucket: &str, key: &[u8]) -> Result<bool> {
+        let idempotency_key = idempotency_key();
+        let response = self
+            .call(|mut client, timeout| {
+                let request = with_timeout(
+                    DeleteRequest {
+                        key: key.to_vec(),
+                        bucket: bucket.to_string(),
+                        idempotency_key: idempotency_key.clone(),
+                        ..Default::default()
+                    },
+                    timeout,
+                );
+                async move { client.delete(request).await }
+            })
+            .await?;
+        Ok(response.existed)
+    }
+
+    // Runs `attempt` until it succeeds, fails with a status that is not worth
+    // retrying, runs out of retries, or the call's deadline passes. Each attempt
+    // is told how much of the deadline is left so the router can honour it.
+    async fn call<T, F, Fut>(&self, mut attempt: F) -> Result<T>
+    where
+        F: FnMut(RouterClient<Channel>, Duration) -> Fut,
+        Fut: Future<Output = std::result::Result<tonic::Response<T>, Status>>,
+    {
+        let deadline = Instant::now() + self.config.timeout;
+        let mut retries = 0;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(ClientError::DeadlineExceeded(self.config.timeout));
+            }
+
+            let status =
+                match tokio::time::timeout(remaining, attempt(self.inner.clone(), remaining)).await
+                {
+                    Ok(Ok(response)) => return Ok(response.into_inner()),
+                    Ok(Err(status)) => status,
+                    Err(_) => return Err(ClientError::DeadlineExceeded(self.config.timeout)),
+                };
+            if !is_retryable(status.code()) || retries >= self.config.max_retries {
+                return Err(status.into());
+            }
+
+            let delay = backoff(self.config.retry_backoff, retries);
+            if Instant::now() + delay >= deadline {
+                return Err(status.into());
+            }
+            tokio::time::sleep(delay).await;
+            retries += 1;
+        }
+    }
+}
+
+fn with_timeout<T>(message: T, timeout: Duration) -> Request<T> {
+    let mut request = Request::new(message);
+    request.set_timeout(timeout);
+    request
+}
+
+// Every attempt of a put or delete carries the same idempotency key, so any
+// failure that may clear up on its own is retried.
+fn is_retryable(code: Code) -> bool {
+    matches!(
+        code,
+        Code::Unavailable | Code::ResourceExhausted | Code::Aborted
+    )
+}
+
+// Unique per write: the process, the time and a per-process sequence number.
+fn idempotency_key() -> String {
+    static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!(
+        "{:x}-{:x}-{:x}",
+        std::process::id(),
+        nanos,
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+fn backoff(base: Duration, retries: u32) -> Duration {
+    base.saturating_mul(2u32.saturating_pow(retries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use milena_protos::router_server::router_server::{Router, RouterServer};
+    use milena_protos::router_server::*;
+    use std::sync::atomic::AtomicU32;
+    use std::sync::Arc;
+    use tonic::Response;
+
+    #[test]
+    fn test_idempotency_keys_are_unique() {
+        assert_ne!(idempotency_key(), idempotency_key());
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        let base = Duration::from_millis(50);
+        assert_eq!(backoff(base, 0), Duration::from_millis(50));
+        assert_eq!(backoff(base, 3), Duration::from_millis(400));
+    }
+
+    /// Router that fails the first `failures` gets with `Unavailable`.
+    struct FlakyRouter {
+        failures: u32,
+        calls: Arc<AtomicU32>,
+    }
+
+    #[tonic::async_trait]
+    impl Router for FlakyRouter {
+        type GetOrComputeStream =
+            tokio_stream::Empty<std::result::Result<GetOrComputeResponse, Status>>;
+        type WatchStream = tokio_stream::Empty<std::result::Result<WatchEvent, Status>>;
+
+        async fn join(
+            &self,
+            _: Request<JoinRequest>,
+        ) -> std::result::Result<Response<JoinResponse>, Status> {
+            Err(Status::unimplemented("join"))
+        }
+
+        async fn leave(
+            &self,
+            _: Request<LeaveRequest>,
+        ) -> std::result::Result<Response<LeaveResponse>, Status> {
+            Err(Status::unimplemented("leave"))
+        }
+
+        async fn get(
+            &self,
+            request: Request<GetRequest>,
+        ) -> std::result::Result<Response<GetResponse>, Status> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(Status::unavailable("node restarting"));
+            }
+            if request.into_inner().key == b"missing" {
+                return Ok(Response::new(GetResponse {
+                    successful: true,
+                    ..Default::default()
+                }));
+            }
+            Ok(Response::new(GetResponse {
+                successful: true,
+                value: b"value".to_vec(),
+                written_at_millis: 1,
+                found: true,
+                ..Default::default()
+            }))
+        }
+
+        async fn put(
+            &self,
+            _: Request<PutRequest>,
+        ) -> std::result::Result<Response<PutResponse>, Status> {
+            Err(Status::invalid_argument("read only"))
+        }
+
+        async fn batch_get(
+            &self,
+            _: Request<BatchGetRequest>,
+        ) -> std::result::Result<Response<BatchGetResponse>, Status> {
+            Err(Status::unimplemented("batch_get"))
+        }
+
+        async fn batch_put(
+            &self,
+            _: Request<BatchPutRequest>,
+        ) -> std::result::Result<Response<BatchPutResponse>, Status> {
+            Err(Status::unimplemented("batch_put"))
+        }
+
+        async fn delete(
+            &self,
+            _: Request<DeleteRequest>,
+        ) -> std::result::Result<Response<DeleteResponse>, Status> {
+            Err(Status::unimplemented("delete"))
+        }
+
+        async fn exists(
+            &self,
+            _: Request<ExistsRequest>,
+        ) -> std::result::Result<Response<ExistsResponse>, Status> {
+            Err(Status::unimplemented("exists"))
+        }
+
+        async fn begin_stage(
+            &self,
+            _: Request<BeginStageRequest>,
+        ) -> std::result::Result<Response<BeginStageResponse>, Status> {
+            Err(Status::unimplemented("begin_stage"))
+        }
+
+        async fn promote_bucket(
+            &self,
+            _: Request<PromoteBucketRequest>,
+        ) -> std::result::Result<Response<PromoteBucketResponse>, Status> {
+            Err(Status::unimplemented("promote_bucket"))
+        }
+
+        async fn set_node_writable(
+            &self,
+            _: Request<SetNodeWritableRequest>,
+        ) -> std::result::Result<Response<SetNodeWritableResponse>, Status> {
+            Err(Status::unimplemented("set_node_writable"))
+        }
+
+        async fn list_nodes(
+            &self,
+            _: Request<ListNodesRequest>,
+        ) -> std::result::Result<Response<ListNodesResponse>, Status> {
+            Err(Status::unimplemented("list_nodes"))
+        }
+
+        async fn get_or_compute(
+            &self,
+            _: Request<tonic::Streaming<GetOrComputeRequest>>,
+        ) -> std::result::Result<Response<Self::GetOrComputeStream>, Status> {
+            Err(Status::unimplemented("get_or_compute"))
+        }
+
+        async fn pin(
+            &self,
+            _: Request<PinRequest>,
+        ) -> std::result::Result<Response<PinResponse>, Status> {
+            Err(Status::unimplemented("pin"))
+        }
+
+        async fn unpin(
+            &self,
+            _: Request<UnpinRequest>,
+        ) -> std::result::Result<Response<UnpinResponse>, Status> {
+            Err(Status::unimplemented("unpin"))
+        }
+
+        async fn watch(
+            &self,
+            _: Request<WatchRequest>,
+        ) -> std::result::Result<Response<Self::WatchStream>, Status> {
+            Err(Status::unimplemented("watch"))
+        }
+    }
+
+    async fn serve(failures: u32) -> (Client, Arc<AtomicU32>) {
+        let calls = Arc::new(AtomicU32::new(0));
+        let router = FlakyRouter {
+            failures,
+            calls: calls.clone(),
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(RouterServer::new(router))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+
+        let config = ClientConfig {
+            retry_backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let client = Client::connect_with(format!("http://{addr}"), config)
+            .await
+            .unwrap();
+        (client, calls)
+    }
+
+    #[tokio::test]
+    async fn test_get_retries_transient_failures() {
+        let (client, calls) = serve(2).await;
+        assert_eq!(
+            client.get("bucket", b"key").await.unwrap(),
+            Some(b"value".to_vec())
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(client.get("bucket", b"missing").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_retries() {
+        let (client, calls) = serve(u32::MAX).await;
+        let err = client.get("bucket", b"key").await.unwrap_err();
+        assert!(matches!(err, ClientError::Status(s) if s.code() == Code::Unavailable));
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_permanent_failures() {
+        let (client, _) = serve(0).await;
+        let err = client.put("bucket", b"key", b"value".to_vec()).await;
+        assert!(matches!(err, Err(ClientError::Status(s)) if s.code() == Code::InvalidArgument));
+    }
+}
@@ -5,3 +5,7 @@ pub mod cache_server {
 pub mod router_server {
     tonic::include_proto!("router_server");
 }
+
+pub mod normalization;
+pub mod rejections;
+pub mod validation;
@@ -0,0 +1,58 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// Rewrites applied to a key before it is placed on the ring or hashed into a
+/// cache key. Keys that differ only in what an enabled rewrite removes alias
+/// the same entry. All rewrites are off by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KeyNormalization {
+    /// Lowercase the key.
+    pub lowercase: bool,
+    /// Strip leading and trailing whitespace.
+    pub trim: bool,
+    /// Convert the key to Unicode Normalization Form C.
+    pub nfc: bool,
+}
+
+impl KeyNormalization {
+    pub fn is_enabled(&self) -> bool {
+        self.lowercase || self.trim || self.nfc
+    }
+
+    /// Keys that are not valid UTF-8 are returned unchanged. Normalizing an
+    /// already normalized key is a no-op, so router and cache may both apply it.
+    pub fn apply(&self, key: Vec<u8>) -> Vec<u8> {
+        if !self.is_enabled() {
+            return key;
+        }
+        let Ok(text) = std::str::from_utf8(&key) else {
+            return key;
+        };
+        let mut text = if self.trim { text.trim() } else { text }.to_string();
+        if self.lowercase {
+            text = text.to_lowercase();
+        }
+        if self.nfc {
+            text = text.nfc().collect();
+        }
+        text.into_bytes()
+    }
+}
+
+#[test]
+fn test_key_normalization() {
+    let key = "  Cafe\u{301} ".as_bytes().to_vec();
+    assert_eq!(KeyNormalization::default().apply(key.clone()), key);
+
+    let all = KeyNormalization {
+        lowercase: true,
+        trim: true,
+        nfc: true,
+    };
+    let normalized = all.apply(key);
+    assert_eq!(normalized, "café".as_bytes());
+    assert_eq!(all.apply(normalized.clone()), normalized);
+    assert_eq!(all.apply("CAFÉ".as_bytes().to_vec()), normalized);
+
+    let binary = vec![0xff, b'A', b' '];
+    assert_eq!(all.apply(binary.clone()), binary);
+}
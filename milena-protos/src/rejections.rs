@@ -0,0 +1,149 @@
+use http::{Request, Response};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tonic::transport::server::TcpConnectInfo;
+use tonic::{Code, Status};
+use tower::{Layer, Service};
+
+/// Why tonic refused a request before it reached a handler.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rejection {
+    /// Larger than the server's maximum decoding message size.
+    Oversized,
+    /// Not a valid encoding of the method's request message.
+    Malformed,
+}
+
+impl Rejection {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Rejection::Oversized => "oversized",
+            Rejection::Malformed => "malformed",
+        }
+    }
+
+    /// Recognizes the statuses tonic answers undecodable requests with. Only
+    /// tonic's own wording matches, so handler errors are never counted.
+    pub fn from_status(status: &Status) -> Option<Self> {
+        match status.code() {
+            Code::OutOfRange if status.message().contains("message length too large") => {
+                Some(Rejection::Oversized)
+            }
+            Code::Internal
+                if status
+                    .message()
+                    .starts_with("failed to decode Protobuf message") =>
+            {
+                Some(Rejection::Malformed)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A request tonic refused before it reached a handler.
+#[derive(Debug)]
+pub struct RejectedRequest {
+    pub rejection: Rejection,
+    /// The gRPC method, e.g. `/cache_server.Cache/Put`.
+    pub method: String,
+    pub peer: Option<SocketAddr>,
+    /// Tonic's description, which gives the size of an oversized message.
+    pub message: String,
+}
+
+/// Reports requests that tonic rejects while decoding them, which otherwise
+/// never reach a handler or its metrics. Messages of client-streaming calls
+/// are decoded inside the handler, so only their first is seen here.
+#[derive(Clone)]
+pub struct RejectionLayer<F> {
+    on_rejection: F,
+}
+
+impl<F> RejectionLayer<F> {
+    pub fn new(on_rejection: F) -> Self {
+        Self { on_rejection }
+    }
+}
+
+impl<S, F: Clone> Layer<S> for RejectionLayer<F> {
+    type Service = Rejections<S, F>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Rejections {
+            inner,
+            on_rejection: self.on_rejection.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Rejections<S, F> {
+    inner: S,
+    on_rejection: F,
+}
+
+impl<S, F, B, R> Service<Request<B>> for Rejections<S, F>
+where
+    S: Service<Request<B>, Response = Response<R>>,
+    S::Future: Send + 'static,
+    F: Fn(RejectedRequest) + Clone + Send + 'static,
+{
+    type Response = Response<R>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let method = request.uri().path().to_string();
+        let peer = request
+            .extensions()
+            .get::<TcpConnectInfo>()
+            .and_then(TcpConnectInfo::remote_addr);
+        let on_rejection = self.on_rejection.clone();
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await?;
+            // Rejections are trailers-only responses, so the status is in the headers
+            if let Some(status) = Status::from_header_map(response.headers())
+                && let Some(rejection) = Rejection::from_status(&status)
+            {
+                on_rejection(RejectedRequest {
+                    rejection,
+                    method,
+                    peer,
+                    message: status.message().to_string(),
+                });
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[test]
+fn test_rejection_from_status() {
+    let oversized = Status::out_of_range(
+        "Error, message length too large: found 4194309 bytes, the limit is: 4194304 bytes",
+    );
+    assert_eq!(
+        Rejection::from_status(&oversized),
+        Some(Rejection::Oversized)
+    );
+    let malformed = Status::internal("failed to decode Protobuf message: invalid wire type value");
+    assert_eq!(
+        Rejection::from_status(&malformed),
+        Some(Rejection::Malformed)
+    );
+
+    // Errors returned by handlers are left alone
+    assert_eq!(
+        Rejection::from_status(&Status::internal("disk unavailable")),
+        None
+    );
+    assert_eq!(Rejection::from_status(&Status::ok("")), None);
+}
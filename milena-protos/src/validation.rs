@@ -0,0 +1,276 @@
+use thiserror::Error;
+
+/// Largest value accepted by `validate_value`.
+pub const MAX_VALUE_BYTES: usize = 5 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum ValidationError {
+    #[error("Invalid bucket name: {0}")]
+    InvalidBucketName(String),
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+    #[error("Invalid value: {0}")]
+    InvalidValue(String),
+    #[error("Invalid address: {0}")]
+    InvalidAddress(String),
+    #[error("Invalid routing key: {0}")]
+    InvalidRoutingKey(String),
+}
+
+/// Which bucket names `validate_bucket_name_with` accepts.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BucketNameRules {
+    /// Alphanumeric characters, hyphens and any of the given extra characters.
+    Charset(String),
+    /// Amazon S3's bucket naming rules, for buckets used as S3 buckets directly.
+    S3,
+}
+
+impl Default for BucketNameRules {
+    fn default() -> Self {
+        BucketNameRules::Charset(String::new())
+    }
+}
+
+pub fn validate_bucket_name(name: &str) -> Result<(), ValidationError> {
+    validate_bucket_name_with(name, &BucketNameRules::default())
+}
+
+pub fn validate_bucket_name_with(
+    name: &str,
+    rules: &BucketNameRules,
+) -> Result<(), ValidationError> {
+    if name.is_empty() {
+        return Err(ValidationError::InvalidBucketName(
+            "Bucket name cannot be empty".to_string(),
+        ));
+    }
+    if name.len() > 63 {
+        return Err(ValidationError::InvalidBucketName(
+            "Bucket name cannot be longer than 63 characters".to_string(),
+        ));
+    }
+    let extra = match rules {
+        BucketNameRules::Charset(extra) => extra,
+        BucketNameRules::S3 => return validate_s3_bucket_name(name),
+    };
+    if let Some(c) = name
+        .chars()
+        .find(|&c| !(c.is_alphanumeric() || c == '-' || extra.contains(c)))
+    {
+        let allowed = if extra.is_empty() {
+            "alphanumeric characters and hyphens".to_string()
+        } else {
+            format!("alphanumeric characters, hyphens and any of {:?}", extra)
+        };
+        return Err(ValidationError::InvalidBucketName(format!(
+            "Bucket name cannot contain {:?}; only {} are allowed",
+            c, allowed
+        )));
+    }
+    Ok(())
+}
+
+// https://docs.aws.amazon.com/AmazonS3/latest/userguide/bucketnamingrules.html
+fn validate_s3_bucket_name(name: &str) -> Result<(), ValidationError> {
+    let invalid = |reason: String| Err(ValidationError::InvalidBucketName(reason));
+    if name.len() < 3 {
+        return invalid("S3 bucket names must be at least 3 characters".to_string());
+    }
+    if let Some(c) = name.chars().find(|&c| {
+        !(c.is_ascii_lowercase() || c.is_ascii_digit() || c == '.' || c == '-')
+    }) {
+        return invalid(format!(
+            "S3 bucket names cannot contain {:?}; only lowercase letters, digits, dots and hyphens are allowed",
+            c
+        ));
+    }
+    let edge_ok = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric());
+    if !edge_ok(name.chars().next()) || !edge_ok(name.chars().last()) {
+        return invalid("S3 bucket names must begin and end with a letter or digit".to_string());
+    }
+    if name.contains("..") {
+        return invalid("S3 bucket names cannot contain adjacent dots".to_string());
+    }
+    if name.parse::<std::net::Ipv4Addr>().is_ok() {
+        return invalid("S3 bucket names cannot be formatted as IP addresses".to_string());
+    }
+    if let Some(prefix) = ["xn--", "sthree-", "amzn-s3-demo-"]
+        .into_iter()
+        .find(|prefix| name.starts_with(prefix))
+    {
+        return invalid(format!("S3 bucket names cannot start with {:?}", prefix));
+    }
+    if let Some(suffix) = ["-s3alias", "--ol-s3", ".mrap", "--x-s3"]
+        .into_iter()
+        .find(|suffix| name.ends_with(suffix))
+    {
+        return invalid(format!("S3 bucket names cannot end with {:?}", suffix));
+    }
+    Ok(())
+}
+
+pub fn validate_key(key: &[u8]) -> Result<(), ValidationError> {
+    if key.is_empty() {
+        return Err(ValidationError::InvalidKey(
+            "Key cannot be empty".to_string(),
+        ));
+    }
+    if key.len() > 1024 {
+        return Err(ValidationError::InvalidKey(
+            "Key cannot be longer than 1024 bytes".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub fn validate_routing_key(routing_key: &[u8]) -> Result<(), ValidationError> {
+    if routing_key.is_empty() {
+        return Err(ValidationError::InvalidRoutingKey(
+            "Routing key cannot be empty".to_string(),
+        ));
+    }
+    if routing_key.len() > 1024 {
+        return Err(ValidationError::InvalidRoutingKey(
+            "Routing key cannot be longer than 1024 bytes".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub fn validate_value(value: &[u8]) -> Result<(), ValidationError> {
+    if value.len() > MAX_VALUE_BYTES {
+        return Err(ValidationError::InvalidValue(
+            "Value cannot be larger than 5MB".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub fn validate_address(addr: &str) -> Result<(), ValidationError> {
+    if addr.is_empty() {
+        return Err(ValidationError::InvalidAddress(
+            "Address cannot be empty".to_string(),
+        ));
+    }
+    canonical_address(addr).map(|_| ())
+}
+
+/// The form of a node address used to identify the node: scheme and host
+/// lowercased, trailing slashes dropped and the scheme's default port
+/// omitted, so `HTTP://Cache-1:80/` and `http://cache-1` are one node.
+/// Hostnames are not resolved, so a node reached by IP and by name still
+/// has two addresses.
+pub fn canonical_address(addr: &str) -> Result<String, ValidationError> {
+    let invalid = |reason: &str| ValidationError::InvalidAddress(reason.to_string());
+    if addr.is_empty() {
+        return Err(invalid("Address cannot be empty"));
+    }
+    let (scheme, rest) = addr
+        .split_once("://")
+        .ok_or_else(|| invalid("Address must start with http:// or https://"))?;
+    let scheme = scheme.to_ascii_lowercase();
+    let default_port = match scheme.as_str() {
+        "http" => 80,
+        "https" => 443,
+        _ => return Err(invalid("Address must start with http:// or https://")),
+    };
+    let authority = rest.trim_end_matches('/');
+    if authority.contains(['/', '?', '#']) {
+        return Err(invalid("Address cannot have a path, query or fragment"));
+    }
+    if authority.contains('@') {
+        return Err(invalid("Address cannot have user information"));
+    }
+
+    // IPv6 hosts are bracketed, so only a colon after the host starts a port
+    let host_end = if authority.starts_with('[') {
+        authority
+            .find(']')
+            .ok_or_else(|| invalid("Address has an unclosed IPv6 bracket"))?
+            + 1
+    } else {
+        authority.find(':').unwrap_or(authority.len())
+    };
+    let (host, port) = authority.split_at(host_end);
+    if host.is_empty() || host == "[]" {
+        return Err(invalid("Address must have a host"));
+    }
+    let host = host.to_ascii_lowercase();
+    let port = match port.strip_prefix(':') {
+        None if port.is_empty() => default_port,
+        Some(port) => port
+            .parse::<u16>()
+            .map_err(|_| invalid("Address has an invalid port"))?,
+        None => return Err(invalid("Address has an invalid port")),
+    };
+    if port == default_port {
+        Ok(format!("{scheme}://{host}"))
+    } else {
+        Ok(format!("{scheme}://{host}:{port}"))
+    }
+}
+
+#[test]
+fn test_bucket_name_charset() {
+    assert!(validate_bucket_name("legacy-bucket-1").is_ok());
+    let err = validate_bucket_name("legacy.bucket").unwrap_err();
+    assert!(err.to_string().contains("'.'"));
+
+    let rules = BucketNameRules::Charset("._".to_string());
+    assert!(validate_bucket_name_with("legacy.bucket_1", &rules).is_ok());
+    let err = validate_bucket_name_with("legacy/bucket", &rules).unwrap_err();
+    assert!(err.to_string().contains("'/'"));
+}
+
+#[test]
+fn test_s3_bucket_name_rules() {
+    let rules = BucketNameRules::S3;
+    assert!(validate_bucket_name_with("my.logs-2024", &rules).is_ok());
+    for name in [
+        "ab",
+        "My-Bucket",
+        "my_bucket",
+        "-bucket",
+        "bucket.",
+        "my..bucket",
+        "192.168.5.4",
+        "xn--bucket",
+        "bucket-s3alias",
+    ] {
+        assert!(validate_bucket_name_with(name, &rules).is_err(), "{name}");
+    }
+}
+
+#[test]
+fn test_canonical_address() {
+    for (addr, canonical) in [
+        ("http://cache-1:50051", "http://cache-1:50051"),
+        ("http://cache-1:50051/", "http://cache-1:50051"),
+        ("HTTP://Cache-1:50051//", "http://cache-1:50051"),
+        ("http://cache-1:80", "http://cache-1"),
+        ("https://cache-1:443/", "https://cache-1"),
+        ("https://cache-1:80", "https://cache-1:80"),
+        ("http://10.0.0.7:050051", "http://10.0.0.7:50051"),
+        ("http://[FE80::1]:50051", "http://[fe80::1]:50051"),
+        ("http://[::1]:80", "http://[::1]"),
+    ] {
+        assert_eq!(canonical_address(addr).unwrap(), canonical, "{addr}");
+    }
+    for addr in [
+        "",
+        "cache-1:50051",
+        "grpc://cache-1:50051",
+        "http://",
+        "http://:50051",
+        "http://cache-1:port",
+        "http://cache-1:70000",
+        "http://cache-1:50051/path",
+        "http://cache-1:50051?query",
+        "http://user@cache-1:50051",
+        "http://[::1:50051",
+        "http://[::1]50051",
+    ] {
+        assert!(canonical_address(addr).is_err(), "{addr}");
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+type LeaseKey = (String, Vec<u8>);
+
+/// Outcome of asking to fetch a missing key from origin.
+pub enum Claim {
+    /// The caller must compute the value and fill the key.
+    Leader(Lease),
+    /// Another caller holds the lease; the receiver closes when it is released.
+    Follower(watch::Receiver<()>),
+}
+
+/// Hands out one lease per missing key so that concurrent `GetOrCompute`
+/// callers fetch from origin once instead of all at the same time.
+#[derive(Default)]
+pub struct LeaseTable {
+    next_token: AtomicU64,
+    leases: Mutex<HashMap<LeaseKey, (u64, watch::Sender<()>)>>,
+}
+
+impl LeaseTable {
+    pub fn claim(self: &Arc<Self>, bucket: &str, key: &[u8]) -> Claim {
+        let lease_key = (bucket.to_string(), key.to_vec());
+        let mut leases = self.leases.lock().unwrap();
+        if let Some((_, holder)) = leases.get(&lease_key) {
+            return Claim::Follower(holder.subscribe());
+        }
+        // Tokens start at 1 so a fill with an unset token never matches.
+        let token = self.next_token.fetch_add(1, Ordering::Relaxed) + 1;
+        leases.insert(lease_key.clone(), (token, watch::channel(()).0));
+        Claim::Leader(Lease {
+            table: self.clone(),
+            key: lease_key,
+            token,
+        })
+    }
+}
+
+/// Held by the caller computing a key; waiters are released when it drops,
+/// whether or not the key was filled.
+pub struct Lease {
+    table: Arc<LeaseTable>,
+    key: LeaseKey,
+    token: u64,
+}
+
+impl Lease {
+    pub fn token(&self) -> u64 {
+        self.token
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let mut leases = self.table.leases.lock().unwrap();
+        if leases
+            .get(&self.key)
+            .is_some_and(|(token, _)| *token == self.token)
+        {
+            // Dropping the sender wakes every follower.
+            leases.remove(&self.key);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_lease_table_hands_out_one_lease_per_key() {
+    let table = Arc::new(LeaseTable::default());
+    let Claim::Leader(lease) = table.claim("users", b"42") else {
+        panic!("the first caller should get the lease");
+    };
+    let Claim::Follower(mut waiter) = table.claim("users", b"42") else {
+        panic!("a second caller should wait on the lease");
+    };
+    // Other keys and buckets are leased separately
+    assert!(matches!(table.claim("users", b"43"), Claim::Leader(_)));
+    assert!(matches!(table.claim("orders", b"42"), Claim::Leader(_)));
+
+    drop(lease);
+    assert!(waiter.changed().await.is_err());
+}
+
+#[test]
+fn test_abandoned_lease_goes_to_the_next_caller() {
+    let table = Arc::new(LeaseTable::default());
+    let Claim::Leader(abandoned) = table.claim("users", b"42") else {
+        panic!("the first caller should get the lease");
+    };
+    let token = abandoned.token();
+    assert_ne!(token, 0);
+    // The leader failing to compute drops the lease without a fill
+    drop(abandoned);
+
+    let Claim::Leader(lease) = table.claim("users", b"42") else {
+        panic!("the lease should be free again");
+    };
+    assert_ne!(lease.token(), token);
+}
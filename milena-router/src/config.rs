@@ -0,0 +1,248 @@
+use milena_protos::normalization::KeyNormalization;
+use milena_protos::validation::{BucketNameRules, MAX_VALUE_BYTES};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use thiserror::Error;
+
+/// Comfortably above the 5MB value limit plus key, bucket and framing overhead.
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub listen_addr: SocketAddr,
+    pub rate_limit: u32,
+    pub log_level: String,
+    /// Port serving Prometheus metrics on `/metrics`.
+    pub metrics_port: u16,
+    /// When enabled, requests must carry a routing key and placement on the
+    /// hash ring uses it instead of the full key.
+    pub routing_keys_enabled: bool,
+    /// When enabled, requests may carry a routing key, and those that do are
+    /// placed by it; the rest are placed by their full key.
+    pub routing_keys_optional: bool,
+    /// Number of join/leave calls allowed to mutate the ring at once; the rest queue.
+    pub max_concurrent_membership_changes: usize,
+    /// Nodes allowed on the ring before further joins are rejected; 0 means unlimited.
+    pub max_nodes: usize,
+    /// Comma-separated `bucket=group` pairs placing those buckets only on nodes
+    /// that joined with that group label, e.g. `checkout=premium`; other buckets
+    /// use the nodes that joined without one.
+    pub bucket_node_groups: String,
+    /// Accept gRPC-Web requests (read-only methods only) alongside native gRPC.
+    pub grpc_web_enabled: bool,
+    /// Comma-separated CORS origins allowed to make gRPC-Web calls; empty allows any.
+    pub grpc_web_allowed_origins: String,
+    /// Largest gRPC message accepted or sent, on both the client and cache node
+    /// hops. Must leave room above the 5MB value limit.
+    pub max_message_bytes: usize,
+    /// Gzip messages sent to cache nodes, and responses to clients that accept
+    /// gzip. Compressed messages are always accepted.
+    pub grpc_compression: bool,
+    /// Requests allowed in flight to a single cache node before the router
+    /// rejects with `ResourceExhausted`; 0 means unlimited.
+    pub max_in_flight_per_node: usize,
+    /// How long a request waits for a pooled connection to a cache node before
+    /// failing with `ResourceExhausted`; 0 fails at once.
+    pub pool_wait_timeout_ms: u64,
+    /// How long a pooled connection is reused before it is health checked again.
+    pub pool_health_check_interval_ms: u64,
+    /// How long a health check waits for the cache node before the pooled
+    /// connection is discarded.
+    pub pool_health_check_timeout_ms: u64,
+    /// Times a failed cache node call may be retried, budget permitting.
+    pub max_retries: u32,
+    /// Capacity of the router-wide retry budget; each retry spends one token.
+    pub retry_budget_max_tokens: u32,
+    /// Fraction of a token each successful cache node call returns to the budget.
+    pub retry_budget_token_ratio: f64,
+    /// How long a `GetOrCompute` caller holding a lease has to send the value,
+    /// and how long each waiter waits on it.
+    pub compute_lease_timeout_ms: u64,
+    /// Characters allowed in bucket names besides alphanumerics and hyphens, e.g. `._`.
+    pub bucket_name_extra_chars: String,
+    /// Validate bucket names against S3's naming rules instead, for buckets
+    /// that are used as S3 buckets directly.
+    pub s3_bucket_names: bool,
+    /// Lowercase keys before placing and storing them. Must match the cache
+    /// nodes' setting, as must the other key normalization options.
+    pub key_normalize_lowercase: bool,
+    /// Strip leading and trailing whitespace from keys.
+    pub key_normalize_trim: bool,
+    /// Convert keys to Unicode Normalization Form C.
+    pub key_normalize_nfc: bool,
+    /// Where to write the audit log of puts and deletes: `stdout`, or a file
+    /// path to append to. Disabled when unset.
+    pub audit_log: Option<String>,
+    /// Refuse to remove the last node on the ring unless the leave is forced,
+    /// since an empty ring fails every request.
+    pub protect_last_node: bool,
+    /// Track nodes by their canonical address (lowercase scheme and host,
+    /// default port dropped), so a node can't join twice under two spellings.
+    pub canonicalize_node_addresses: bool,
+    /// Reject every call that would write to cache nodes, for read replicas
+    /// exposed to less-trusted clients. Membership calls are still accepted.
+    pub read_only: bool,
+    /// GET hits the router keeps to answer repeated reads of hot keys itself;
+    /// 0 disables the result cache.
+    pub result_cache_size: usize,
+    /// How long a result cache entry is served before the node is asked again.
+    pub result_cache_ttl_ms: u64,
+    /// Distinct nodes each key is written to. Gets try them in order, so a
+    /// key stays readable from the cache while one of its nodes is down.
+    pub replication_factor: usize,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let config = config::Config::builder()
+            .add_source(config::Environment::default())
+            .build()
+            .map_err(|e| ConfigError::InvalidConfig(e.to_string()))?;
+
+        config
+            .try_deserialize()
+            .map_err(|e| ConfigError::InvalidConfig(e.to_string()))
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.rate_limit == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "Rate limit must be greater than 0".to_string(),
+            ));
+        }
+        if self.max_message_bytes <= MAX_VALUE_BYTES {
+            return Err(ConfigError::InvalidConfig(format!(
+                "Max message bytes must be greater than the {} byte value limit",
+                MAX_VALUE_BYTES
+            )));
+        }
+        if self.max_concurrent_membership_changes == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "Max concurrent membership changes must be greater than 0".to_string(),
+            ));
+        }
+        if !(self.retry_budget_token_ratio > 0.0 && self.retry_budget_token_ratio <= 1.0) {
+            return Err(ConfigError::InvalidConfig(
+                "Retry budget token ratio must be greater than 0 and at most 1".to_string(),
+            ));
+        }
+        if self.pool_health_check_timeout_ms == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "Pool health check timeout must be greater than 0".to_string(),
+            ));
+        }
+        if self.replication_factor == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "Replication factor must be greater than 0".to_string(),
+            ));
+        }
+        if self.compute_lease_timeout_ms == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "Compute lease timeout must be greater than 0".to_string(),
+            ));
+        }
+        if self.result_cache_size > 0 && self.result_cache_ttl_ms == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "Result cache TTL must be greater than 0 when the result cache is enabled"
+                    .to_string(),
+            ));
+        }
+        if self.routing_keys_enabled && self.routing_keys_optional {
+            return Err(ConfigError::InvalidConfig(
+                "Routing keys cannot be both required and optional".to_string(),
+            ));
+        }
+        self.bucket_node_groups()?;
+        if self.s3_bucket_names && !self.bucket_name_extra_chars.is_empty() {
+            return Err(ConfigError::InvalidConfig(
+                "Bucket name extra characters cannot be combined with S3 bucket names".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn bucket_node_groups(&self) -> Result<HashMap<String, String>, ConfigError> {
+        let mut groups = HashMap::new();
+        for pair in self.bucket_node_groups.split(',').map(str::trim) {
+            if pair.is_empty() {
+                continue;
+            }
+            let invalid = || {
+                ConfigError::InvalidConfig(format!(
+                    "Bucket node group {:?} must be bucket=group with a non-empty group",
+                    pair
+                ))
+            };
+            let (bucket, group) = pair.split_once('=').ok_or_else(invalid)?;
+            if group.trim().is_empty() {
+                return Err(invalid());
+            }
+            groups.insert(bucket.trim().to_string(), group.trim().to_string());
+        }
+        Ok(groups)
+    }
+
+    pub fn bucket_name_rules(&self) -> BucketNameRules {
+        if self.s3_bucket_names {
+            BucketNameRules::S3
+        } else {
+            BucketNameRules::Charset(self.bucket_name_extra_chars.clone())
+        }
+    }
+
+    pub fn key_normalization(&self) -> KeyNormalization {
+        KeyNormalization {
+            lowercase: self.key_normalize_lowercase,
+            trim: self.key_normalize_trim,
+            nfc: self.key_normalize_nfc,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen_addr: "[::1]:50052".parse().unwrap(),
+            rate_limit: 100,
+            log_level: "info".to_string(),
+            metrics_port: 9091,
+            routing_keys_enabled: false,
+            routing_keys_optional: false,
+            max_concurrent_membership_changes: 1,
+            max_nodes: 256,
+            bucket_node_groups: String::new(),
+            grpc_web_enabled: false,
+            grpc_web_allowed_origins: String::new(),
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            grpc_compression: false,
+            max_in_flight_per_node: 0,
+            pool_wait_timeout_ms: 100,
+            pool_health_check_interval_ms: 1_000,
+            pool_health_check_timeout_ms: 200,
+            max_retries: 1,
+            retry_budget_max_tokens: 100,
+            retry_budget_token_ratio: 0.1,
+            compute_lease_timeout_ms: 10_000,
+            bucket_name_extra_chars: String::new(),
+            s3_bucket_names: false,
+            key_normalize_lowercase: false,
+            key_normalize_trim: false,
+            key_normalize_nfc: false,
+            audit_log: None,
+            protect_last_node: true,
+            canonicalize_node_addresses: true,
+            read_only: false,
+            result_cache_size: 0,
+            result_cache_ttl_ms: 100,
+            replication_factor: 1,
+        }
+    }
+}
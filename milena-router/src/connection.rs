@@ -1,6 +1,16 @@
-use deadpool::managed::{Manager, Object, RecycleResult};
+use arc_swap::{ArcSwap, Guard};
+use deadpool::Runtime;
+use deadpool::managed::{Manager, Object, RecycleError, RecycleResult};
 use milena_protos::cache_server::cache_client::CacheClient;
+use milena_protos::cache_server::{HealthRequest, ServingStatus};
+use prometheus::IntGauge;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use tokio::sync::{Mutex, MutexGuard, OwnedSemaphorePermit, Semaphore};
+use tonic::codec::CompressionEncoding;
 use tonic::transport::Channel;
 
 #[derive(Debug, Error)]
@@ -11,49 +21,304 @@ pub enum ConnectionError {
     RecycleError(String),
 }
 
+/// How pooled connections are checked before reuse. A connection is only
+/// checked once `interval` has passed since its last check, so a busy pool
+/// does not pay a round trip per request.
+#[derive(Clone, Copy, Debug)]
+pub struct HealthCheck {
+    pub interval: Duration,
+    /// Longest a check may take before the connection is discarded.
+    pub timeout: Duration,
+}
+
 pub struct CacheClientManager {
     endpoint: String,
+    max_message_bytes: usize,
+    compression: bool,
+    health_check: HealthCheck,
 }
 
 impl CacheClientManager {
-    pub fn new(endpoint: String) -> Self {
-        Self { endpoint }
+    pub fn new(
+        endpoint: String,
+        max_message_bytes: usize,
+        compression: bool,
+        health_check: HealthCheck,
+    ) -> Self {
+        Self {
+            endpoint,
+            max_message_bytes,
+            compression,
+            health_check,
+        }
     }
 }
 
+/// A pooled client and when it last proved the node was answering.
+pub struct CacheConnection {
+    client: CacheClient<Channel>,
+    checked_at: Instant,
+}
+
 #[async_trait::async_trait]
 impl Manager for CacheClientManager {
-    type Type = CacheClient<Channel>;
+    type Type = CacheConnection;
     type Error = ConnectionError;
 
     async fn create(&self) -> Result<Self::Type, Self::Error> {
-        CacheClient::connect(self.endpoint.clone())
+        let client = CacheClient::connect(self.endpoint.clone())
             .await
-            .map_err(|e| ConnectionError::CreateError(e.to_string()))
+            .map_err(|e| ConnectionError::CreateError(e.to_string()))?;
+        // Always offer to take gzip responses; only compress requests when
+        // enabled, since a node that predates compression cannot read them.
+        let client = client
+            .max_decoding_message_size(self.max_message_bytes)
+            .max_encoding_message_size(self.max_message_bytes)
+            .accept_compressed(CompressionEncoding::Gzip);
+        let client = if self.compression {
+            client.send_compressed(CompressionEncoding::Gzip)
+        } else {
+            client
+        };
+        Ok(CacheConnection {
+            client,
+            checked_at: Instant::now(),
+        })
     }
 
-    async fn recycle(&self, client: &mut Self::Type) -> RecycleResult<Self::Error> {
-        // Check if the connection is still valid by making a health check
-        // For now, we'll just assume the connection is valid if it exists
+    // A connection to a node that crashed or went away fails its check and is
+    // dropped by the pool, which connects afresh, instead of being handed to a
+    // request that would hang on it.
+    async fn recycle(&self, connection: &mut Self::Type) -> RecycleResult<Self::Error> {
+        if connection.checked_at.elapsed() < self.health_check.interval {
+            return Ok(());
+        }
+        let health = connection.client.health(HealthRequest {});
+        let status = match tokio::time::timeout(self.health_check.timeout, health).await {
+            Ok(Ok(response)) => response.into_inner().status,
+            Ok(Err(e)) => {
+                return Err(RecycleError::Backend(ConnectionError::RecycleError(
+                    e.to_string(),
+                )));
+            }
+            Err(_) => {
+                return Err(RecycleError::Backend(ConnectionError::RecycleError(
+                    format!("No health response within {:?}", self.health_check.timeout),
+                )));
+            }
+        };
+        if status != ServingStatus::Serving as i32 {
+            return Err(RecycleError::Backend(ConnectionError::RecycleError(
+                format!("{} is not serving", self.endpoint),
+            )));
+        }
+        connection.checked_at = Instant::now();
         Ok(())
     }
 }
 
 // Create a wrapper type for Object<CacheClientManager> that simplifies access to the client
-pub struct PooledClient(pub Object<CacheClientManager>);
+pub struct PooledClient {
+    connection: Object<CacheClientManager>,
+    _in_flight: InFlight,
+}
 
 impl PooledClient {
+    pub fn new(connection: Object<CacheClientManager>, in_flight: InFlight) -> Self {
+        Self {
+            connection,
+            _in_flight: in_flight,
+        }
+    }
+
     pub fn client(&mut self) -> &mut CacheClient<Channel> {
-        &mut self.0
+        &mut self.connection.client
     }
 }
 
 pub type Pool = deadpool::managed::Pool<CacheClientManager>;
 
-pub async fn create_pool(endpoint: String, max_size: usize) -> Result<Pool, ConnectionError> {
-    let manager = CacheClientManager::new(endpoint);
+/// A cache node's connection pool, the cap on requests in flight to it,
+/// whether it currently accepts writes, and the node group it serves.
+#[derive(Clone)]
+pub struct NodeConn {
+    pub pool: Pool,
+    pub in_flight: Arc<Semaphore>,
+    pub writable: bool,
+    pub group: String,
+}
+
+/// Connections to every joined node, consulted on every request. Lookups
+/// read the current table without taking a lock; joins, leaves and writable
+/// changes edit a copy one at a time and publish it, so requests never wait
+/// on a membership change.
+#[derive(Default)]
+pub struct NodeConns {
+    table: ArcSwap<HashMap<String, NodeConn>>,
+    /// Held by the edit in progress.
+    changes: Mutex<()>,
+}
+
+impl NodeConns {
+    pub fn load(&self) -> Guard<Arc<HashMap<String, NodeConn>>> {
+        self.table.load()
+    }
+
+    /// Waits for any other edit to finish and returns a copy of the table.
+    pub async fn edit(&self) -> NodeConnsEdit<'_> {
+        let changes = self.changes.lock().await;
+        NodeConnsEdit {
+            table: HashMap::clone(&self.table.load()),
+            node_conns: self,
+            _changes: changes,
+        }
+    }
+}
+
+/// A copy of the connection table being changed. Other edits wait until it
+/// is dropped; its changes are discarded unless it is published.
+pub struct NodeConnsEdit<'a> {
+    table: HashMap<String, NodeConn>,
+    node_conns: &'a NodeConns,
+    _changes: MutexGuard<'a, ()>,
+}
+
+impl NodeConnsEdit<'_> {
+    /// Makes the edited table the one requests see.
+    pub fn publish(&mut self) {
+        self.node_conns.table.store(Arc::new(self.table.clone()));
+    }
+}
+
+impl Deref for NodeConnsEdit<'_> {
+    type Target = HashMap<String, NodeConn>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.table
+    }
+}
+
+impl DerefMut for NodeConnsEdit<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.table
+    }
+}
+
+/// Holds one of a node's in-flight slots and keeps its gauge current until dropped.
+pub struct InFlight {
+    _permit: OwnedSemaphorePermit,
+    gauge: IntGauge,
+}
+
+impl InFlight {
+    pub fn new(permit: OwnedSemaphorePermit, gauge: IntGauge) -> Self {
+        gauge.inc();
+        Self {
+            _permit: permit,
+            gauge,
+        }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.gauge.dec();
+    }
+}
+
+/// Pool of up to `max_size` connections to `endpoint`. A request waits at
+/// most `wait_timeout` for a connection to free up.
+pub fn create_pool(
+    endpoint: String,
+    max_size: usize,
+    max_message_bytes: usize,
+    compression: bool,
+    health_check: HealthCheck,
+    wait_timeout: Duration,
+) -> Result<Pool, ConnectionError> {
+    let manager = CacheClientManager::new(endpoint, max_message_bytes, compression, health_check);
     Pool::builder(manager)
         .max_size(max_size)
+        .wait_timeout(Some(wait_timeout))
+        .runtime(Runtime::Tokio1)
         .build()
         .map_err(|e| ConnectionError::CreateError(e.to_string()))
 }
+
+#[tokio::test]
+async fn test_recycle_discards_connections_to_a_closed_endpoint() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+    let manager = |interval| {
+        CacheClientManager::new(
+            endpoint.clone(),
+            1024,
+            false,
+            HealthCheck {
+                interval,
+                timeout: Duration::from_secs(1),
+            },
+        )
+    };
+    let channel = Channel::from_shared(endpoint.clone())
+        .unwrap()
+        .connect_lazy();
+    let mut connection = CacheConnection {
+        client: CacheClient::new(channel),
+        checked_at: Instant::now(),
+    };
+
+    // Recently checked connections are reused without a round trip
+    assert!(
+        manager(Duration::from_secs(60))
+            .recycle(&mut connection)
+            .await
+            .is_ok()
+    );
+    assert!(
+        manager(Duration::ZERO)
+            .recycle(&mut connection)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_node_conns_edits_are_invisible_until_published() {
+    let node_conns = NodeConns::default();
+    let node_conn = NodeConn {
+        pool: create_pool(
+            "http://cache-1:50051".to_string(),
+            1,
+            1024,
+            false,
+            HealthCheck {
+                interval: Duration::from_secs(1),
+                timeout: Duration::from_secs(1),
+            },
+            Duration::from_millis(10),
+        )
+        .unwrap(),
+        in_flight: Arc::new(Semaphore::new(1)),
+        writable: true,
+        group: String::new(),
+    };
+
+    let mut edit = node_conns.edit().await;
+    edit.insert("http://cache-1:50051".to_string(), node_conn);
+    assert!(node_conns.load().is_empty());
+    // Only one edit at a time
+    assert!(
+        tokio::time::timeout(Duration::from_millis(10), node_conns.edit())
+            .await
+            .is_err()
+    );
+    edit.publish();
+    assert!(node_conns.load().contains_key("http://cache-1:50051"));
+
+    // Dropping an edit discards it
+    drop(edit);
+    node_conns.edit().await.clear();
+    assert_eq!(node_conns.load().len(), 1);
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use tokio::sync::{Mutex, MutexGuard};
+
+/// Key, in each bucket, of the marker recording that bucket's epochs. It is
+/// stored through the cache like any other entry, so it persists in S3.
+pub const EPOCH_MARKER_KEY: &[u8] = b"__milena_epochs__";
+
+/// Which key namespace of a bucket is live, and the next one to hand out for
+/// staging. Epoch 0 is the namespace every bucket starts with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BucketEpochs {
+    pub live: u64,
+    pub next: u64,
+}
+
+impl Default for BucketEpochs {
+    fn default() -> Self {
+        Self { live: 0, next: 1 }
+    }
+}
+
+impl BucketEpochs {
+    /// Whether `epoch` was handed out by `BeginStage` and not yet promoted.
+    pub fn is_staged(&self, epoch: u64) -> bool {
+        epoch > self.live && epoch < self.next
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut marker = self.live.to_be_bytes().to_vec();
+        marker.extend(self.next.to_be_bytes());
+        marker
+    }
+
+    /// An empty marker means the bucket has never been staged.
+    pub fn decode(marker: &[u8]) -> Option<Self> {
+        if marker.is_empty() {
+            return Some(Self::default());
+        }
+        let (live, next) = marker.split_at_checked(8)?;
+        Some(Self {
+            live: u64::from_be_bytes(live.try_into().ok()?),
+            next: u64::from_be_bytes(next.try_into().ok()?),
+        })
+    }
+}
+
+/// Epochs of every bucket seen so far, loaded from each bucket's marker on
+/// first use. Lookups only take a read lock that is never held across a
+/// call, so loading one bucket's marker never holds up requests to another.
+#[derive(Default)]
+pub struct EpochTable {
+    cached: RwLock<HashMap<String, BucketEpochs>>,
+    /// Serializes `BeginStage` and `PromoteBucket`, which read, advance and
+    /// persist a bucket's marker.
+    changes: Mutex<()>,
+}
+
+impl EpochTable {
+    pub fn get(&self, bucket: &str) -> Option<BucketEpochs> {
+        self.cached.read().unwrap().get(bucket).copied()
+    }
+
+    /// Caches epochs just read from a bucket's marker and returns the cached
+    /// epochs. A change cached while the marker was being read is newer, so
+    /// it is kept.
+    pub fn insert_loaded(&self, bucket: &str, loaded: BucketEpochs) -> BucketEpochs {
+        *self
+            .cached
+            .write()
+            .unwrap()
+            .entry(bucket.to_string())
+            .or_insert(loaded)
+    }
+
+    /// Caches epochs after they have been persisted to the bucket's marker.
+    pub fn set(&self, bucket: &str, epochs: BucketEpochs) {
+        self.cached
+            .write()
+            .unwrap()
+            .insert(bucket.to_string(), epochs);
+    }
+
+    /// Waits for any other staging or promotion to finish.
+    pub async fn lock_changes(&self) -> MutexGuard<'_, ()> {
+        self.changes.lock().await
+    }
+}
+
+#[test]
+fn test_bucket_epochs_round_trip_through_marker() {
+    let epochs = BucketEpochs { live: 3, next: 7 };
+    assert_eq!(BucketEpochs::decode(&epochs.encode()), Some(epochs));
+    assert_eq!(BucketEpochs::decode(&[]), Some(BucketEpochs::default()));
+    assert_eq!(BucketEpochs::decode(&[0; 5]), None);
+    assert_eq!(BucketEpochs::decode(&[0; 17]), None);
+}
+
+#[test]
+fn test_only_handed_out_epochs_are_staged() {
+    let epochs = BucketEpochs { live: 2, next: 5 };
+    assert!(!epochs.is_staged(2));
+    assert!(epochs.is_staged(3));
+    assert!(epochs.is_staged(4));
+    assert!(!epochs.is_staged(5));
+    assert!(!BucketEpochs::default().is_staged(0));
+}
+
+#[test]
+fn test_epoch_table_keeps_changes_made_while_loading() {
+    let table = EpochTable::default();
+    assert_eq!(table.get("users"), None);
+    let loaded = BucketEpochs { live: 0, next: 2 };
+    assert_eq!(table.insert_loaded("users", loaded), loaded);
+
+    // A stage persisted while another marker read was in flight wins over it
+    let staged = BucketEpochs { live: 0, next: 3 };
+    table.set("users", staged);
+    assert_eq!(table.insert_loaded("users", loaded), staged);
+    assert_eq!(table.get("users"), Some(staged));
+    assert_eq!(table.get("orders"), None);
+}
+
+#[tokio::test]
+async fn test_epoch_table_serializes_changes() {
+    let table = EpochTable::default();
+    let changes = table.lock_changes().await;
+    assert!(
+        tokio::time::timeout(std::time::Duration::from_millis(10), table.lock_changes())
+            .await
+            .is_err()
+    );
+    // Lookups do not wait on a change in progress
+    table.set("users", BucketEpochs::default());
+    assert_eq!(table.get("users"), Some(BucketEpochs::default()));
+    drop(changes);
+    drop(table.lock_changes().await);
+}
@@ -1,34 +1,133 @@
+mod audit;
+mod compute;
+mod config;
 mod connection;
+mod epoch;
+mod metrics;
 mod rate_limit;
+mod result_cache;
+mod retry_budget;
+mod ring;
 mod service;
-mod validation;
+mod web;
 
-use conhash::ConsistentHash;
+use crate::audit::AuditLog;
+use crate::compute::LeaseTable;
+use crate::config::Config;
+use crate::connection::{HealthCheck, NodeConns};
+use crate::epoch::EpochTable;
+use crate::result_cache::ResultCache;
+use crate::ring::Ring;
+use milena_protos::rejections::{RejectedRequest, RejectionLayer};
 use milena_protos::router_server::router_server::RouterServer;
+use prometheus::{Encoder, Registry, TextEncoder};
 use service::RouterServiceImpl;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{Mutex, Semaphore};
+use tonic::codec::CompressionEncoding;
 use tonic::transport::Server;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use tracing_subscriber::EnvFilter;
+use warp::Filter;
+use warp::http::StatusCode;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Initialize configuration
+    let config = Config::from_env()?;
+    config.validate()?;
+
     // Initialize logging
     tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::new("info"))
+        .with_env_filter(EnvFilter::new(&config.log_level))
         .init();
 
     info!("Starting router service...");
+    if config.read_only {
+        info!("Read-only mode: calls that write to cache nodes will be rejected");
+    }
+
+    // Initialize rate limiter
+    let rate_limiter = Arc::new(rate_limit::RateLimiterMiddleware::new(config.rate_limit));
+
+    // Initialize metrics
+    let metrics = Arc::new(metrics::Metrics::new()?);
+
+    let retry_budget = Arc::new(retry_budget::RetryBudget::new(
+        config.retry_budget_max_tokens,
+        config.retry_budget_token_ratio,
+        metrics.retry_budget_tokens.clone(),
+    ));
 
-    // Initialize rate limiter (100 requests per second)
-    let rate_limiter = Arc::new(rate_limit::RateLimiterMiddleware::new(100));
+    // Initialize audit log
+    let audit_log = match &config.audit_log {
+        Some(target) => {
+            info!("Writing audit log to {}", target);
+            Some(Arc::new(
+                AuditLog::open(target, metrics.audit_dropped.clone()).await?,
+            ))
+        }
+        None => None,
+    };
+
+    // Initialize result cache
+    let result_cache = NonZeroUsize::new(config.result_cache_size).map(|size| {
+        info!(
+            "Caching up to {} GET results for {}ms",
+            size, config.result_cache_ttl_ms
+        );
+        Arc::new(ResultCache::new(
+            size,
+            Duration::from_millis(config.result_cache_ttl_ms),
+        ))
+    });
+
+    let bucket_node_groups = config.bucket_node_groups()?;
+    for (bucket, group) in &bucket_node_groups {
+        info!("Placing bucket {} on node group {}", bucket, group);
+    }
 
     // Initialize router service
     let router_service = RouterServiceImpl {
-        nodes: Arc::new(Mutex::new(ConsistentHash::new())),
-        node_conns: Arc::new(Mutex::new(std::collections::HashMap::new())),
+        ring: Arc::new(Ring::default()),
+        bucket_node_groups,
+        node_conns: Arc::new(NodeConns::default()),
         rate_limiter,
+        metrics: metrics.clone(),
+        routing_keys_enabled: config.routing_keys_enabled,
+        routing_keys_optional: config.routing_keys_optional,
+        membership_permits: Arc::new(Semaphore::new(config.max_concurrent_membership_changes)),
+        max_nodes: match config.max_nodes {
+            0 => usize::MAX,
+            limit => limit,
+        },
+        max_message_bytes: config.max_message_bytes,
+        grpc_compression: config.grpc_compression,
+        max_in_flight_per_node: match config.max_in_flight_per_node {
+            0 => Semaphore::MAX_PERMITS,
+            limit => limit,
+        },
+        pool_wait_timeout: Duration::from_millis(config.pool_wait_timeout_ms),
+        pool_health_check: HealthCheck {
+            interval: Duration::from_millis(config.pool_health_check_interval_ms),
+            timeout: Duration::from_millis(config.pool_health_check_timeout_ms),
+        },
+        bucket_epochs: Arc::new(EpochTable::default()),
+        max_retries: config.max_retries,
+        retry_budget,
+        compute_leases: Arc::new(LeaseTable::default()),
+        compute_lease_timeout: Duration::from_millis(config.compute_lease_timeout_ms),
+        bucket_name_rules: config.bucket_name_rules(),
+        key_normalization: config.key_normalization(),
+        audit_log,
+        protect_last_node: config.protect_last_node,
+        canonicalize_node_addresses: config.canonicalize_node_addresses,
+        read_only: config.read_only,
+        result_cache,
+        replication_factor: config.replication_factor,
     };
 
     // Setup graceful shutdown
@@ -48,10 +147,51 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
-    // Start gRPC server
-    let addr = "[::1]:50052".parse()?;
+    // Start metrics server
+    let metrics_addr = SocketAddr::from(([0, 0, 0, 0], config.metrics_port));
+    let registry = metrics.registry.clone();
+    let metrics_server = warp::serve(
+        warp::path("metrics")
+            .and(warp::get())
+            .map(move || scrape_metrics(&registry)),
+    )
+    .run(metrics_addr);
+    info!("Metrics server listening on {}", metrics_addr);
+
+    // Start gRPC server, optionally accepting gRPC-Web from browsers
+    let addr = config.listen_addr;
+    let grpc_web = config.grpc_web_enabled.then(|| {
+        info!("gRPC-Web enabled for read-only methods");
+        tower::ServiceBuilder::new()
+            .layer(web::cors_layer(&config.grpc_web_allowed_origins))
+            .layer(web::ReadOnlyWebLayer)
+            .layer(tonic_web::GrpcWebLayer::new())
+    });
+    let mut router_server = RouterServer::new(router_service)
+        .max_decoding_message_size(config.max_message_bytes)
+        .max_encoding_message_size(config.max_message_bytes)
+        .accept_compressed(CompressionEncoding::Gzip);
+    if config.grpc_compression {
+        // Only used for clients that advertise gzip in grpc-accept-encoding
+        router_server = router_server.send_compressed(CompressionEncoding::Gzip);
+    }
     let grpc_server = Server::builder()
-        .add_service(RouterServer::new(router_service))
+        .accept_http1(config.grpc_web_enabled)
+        .layer(tower::util::option_layer(grpc_web))
+        .layer(RejectionLayer::new(move |rejected: RejectedRequest| {
+            warn!(
+                "Rejected {} request to {} from {:?}: {}",
+                rejected.rejection.as_str(),
+                rejected.method,
+                rejected.peer,
+                rejected.message
+            );
+            metrics
+                .rejected_requests
+                .with_label_values(&[rejected.rejection.as_str()])
+                .inc();
+        }))
+        .add_service(router_server)
         .serve(addr);
 
     info!("Router service listening on {}", addr);
@@ -64,7 +204,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         _ = grpc_server => {
             error!("gRPC server error");
         }
+        _ = metrics_server => {
+            error!("Metrics server error");
+        }
     }
 
     Ok(())
 }
+
+// Encodes the registry for a Prometheus scrape, answering a failure with a 500.
+fn scrape_metrics(registry: &Registry) -> Box<dyn warp::Reply> {
+    let mut buffer = Vec::new();
+    match TextEncoder::new().encode(&registry.gather(), &mut buffer) {
+        Ok(()) => Box::new(warp::reply::with_header(
+            buffer,
+            "Content-Type",
+            "text/plain; version=0.0.4; charset=utf-8",
+        )),
+        Err(e) => {
+            warn!("Failed to encode metrics: {}", e);
+            Box::new(warp::reply::with_status(
+                format!("Failed to encode metrics: {}", e),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
+}
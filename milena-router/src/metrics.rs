@@ -0,0 +1,219 @@
+use prometheus::{
+    Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry,
+};
+use std::sync::Arc;
+use tonic::Code;
+
+#[derive(Clone)]
+pub struct Metrics {
+    pub registry: Arc<Registry>,
+    pub downstream_errors: IntCounterVec,
+    pub node_in_flight: IntGaugeVec,
+    pub node_saturated: IntCounterVec,
+    pub retry_budget_tokens: Gauge,
+    pub retries: IntCounterVec,
+    pub audit_dropped: IntCounter,
+    pub result_cache_lookups: IntCounterVec,
+    pub rejected_requests: IntCounterVec,
+    pub requests: IntCounterVec,
+    pub request_errors: IntCounterVec,
+    pub rate_limited: IntCounter,
+    pub downstream_duration: HistogramVec,
+    pub replica_copy_failures: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let registry = Registry::new();
+
+        let downstream_errors = IntCounterVec::new(
+            Opts::new(
+                "router_downstream_errors_total",
+                "Total number of failed cache node calls by gRPC code",
+            ),
+            &["code"],
+        )?;
+        registry.register(Box::new(downstream_errors.clone()))?;
+
+        let node_in_flight = IntGaugeVec::new(
+            Opts::new(
+                "router_node_in_flight_requests",
+                "Requests currently in flight to each cache node",
+            ),
+            &["node"],
+        )?;
+        registry.register(Box::new(node_in_flight.clone()))?;
+
+        let node_saturated = IntCounterVec::new(
+            Opts::new(
+                "router_node_saturated_total",
+                "Requests rejected because the cache node was at its in-flight limit",
+            ),
+            &["node"],
+        )?;
+        registry.register(Box::new(node_saturated.clone()))?;
+
+        let retry_budget_tokens = Gauge::new(
+            "router_retry_budget_tokens",
+            "Tokens left in the router-wide retry budget",
+        )?;
+        registry.register(Box::new(retry_budget_tokens.clone()))?;
+
+        let retries = IntCounterVec::new(
+            Opts::new(
+                "router_retries_total",
+                "Retries of failed cache node calls, by whether the budget allowed them",
+            ),
+            &["outcome"],
+        )?;
+        registry.register(Box::new(retries.clone()))?;
+
+        let audit_dropped = IntCounter::new(
+            "router_audit_records_dropped_total",
+            "Audit records dropped because the audit log writer fell behind",
+        )?;
+        registry.register(Box::new(audit_dropped.clone()))?;
+
+        let result_cache_lookups = IntCounterVec::new(
+            Opts::new(
+                "router_result_cache_lookups_total",
+                "GETs looked up in the router's result cache, by whether they hit",
+            ),
+            &["outcome"],
+        )?;
+        registry.register(Box::new(result_cache_lookups.clone()))?;
+
+        let rejected_requests = IntCounterVec::new(
+            Opts::new(
+                "router_rejected_requests_total",
+                "gRPC requests rejected before reaching a handler, by reason",
+            ),
+            &["reason"],
+        )?;
+        registry.register(Box::new(rejected_requests.clone()))?;
+
+        let requests = IntCounterVec::new(
+            Opts::new("router_requests_total", "Requests received, by gRPC method"),
+            &["method"],
+        )?;
+        registry.register(Box::new(requests.clone()))?;
+
+        let request_errors = IntCounterVec::new(
+            Opts::new(
+                "router_request_errors_total",
+                "Requests that failed after validation, by gRPC method and code",
+            ),
+            &["method", "code"],
+        )?;
+        registry.register(Box::new(request_errors.clone()))?;
+
+        let rate_limited = IntCounter::new(
+            "router_rate_limited_total",
+            "Requests rejected by the router's rate limit",
+        )?;
+        registry.register(Box::new(rate_limited.clone()))?;
+
+        let downstream_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "router_downstream_duration_seconds",
+                "Duration of each cache node call attempt, by node",
+            )
+            .buckets(vec![
+                0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+            ]),
+            &["node"],
+        )?;
+        registry.register(Box::new(downstream_duration.clone()))?;
+
+        let replica_copy_failures = IntCounterVec::new(
+            Opts::new(
+                "router_replica_copy_failures_total",
+                "Replica copies of a put that failed to reach their node, by node",
+            ),
+            &["node"],
+        )?;
+        registry.register(Box::new(replica_copy_failures.clone()))?;
+
+        let build_info = IntGaugeVec::new(
+            Opts::new(
+                "router_build_info",
+                "Always 1, labelled with the version, commit and compiler of this build",
+            ),
+            &["version", "git_sha", "rustc_version"],
+        )?;
+        build_info
+            .with_label_values(&[
+                env!("CARGO_PKG_VERSION"),
+                env!("MILENA_GIT_SHA"),
+                env!("MILENA_RUSTC_VERSION"),
+            ])
+            .set(1);
+        registry.register(Box::new(build_info))?;
+
+        Ok(Self {
+            registry: Arc::new(registry),
+            downstream_errors,
+            node_in_flight,
+            node_saturated,
+            retry_budget_tokens,
+            retries,
+            audit_dropped,
+            result_cache_lookups,
+            rejected_requests,
+            requests,
+            request_errors,
+            rate_limited,
+            downstream_duration,
+            replica_copy_failures,
+        })
+    }
+
+    pub fn record_result_cache_lookup(&self, hit: bool) {
+        self.result_cache_lookups
+            .with_label_values(&[if hit { "hit" } else { "miss" }])
+            .inc();
+    }
+
+    pub fn record_downstream_error(&self, code: Code) {
+        self.downstream_errors
+            .with_label_values(&[&format!("{:?}", code)])
+            .inc();
+    }
+
+    pub fn record_request(&self, method: &str) {
+        self.requests.with_label_values(&[method]).inc();
+    }
+
+    pub fn record_request_error(&self, method: &str, code: Code) {
+        self.request_errors
+            .with_label_values(&[method, &format!("{:?}", code)])
+            .inc();
+    }
+}
+
+#[test]
+fn test_registry_gathers_request_metrics() {
+    let metrics = Metrics::new().unwrap();
+    metrics.record_request("get");
+    metrics.record_request_error("get", Code::Unavailable);
+    metrics.rate_limited.inc();
+    metrics
+        .downstream_duration
+        .with_label_values(&["http://a:50051"])
+        .observe(0.002);
+
+    let names: Vec<String> = metrics
+        .registry
+        .gather()
+        .iter()
+        .map(|family| family.get_name().to_string())
+        .collect();
+    for name in [
+        "router_requests_total",
+        "router_request_errors_total",
+        "router_rate_limited_total",
+        "router_downstream_duration_seconds",
+    ] {
+        assert!(names.iter().any(|n| n == name), "{name} not gathered");
+    }
+}
@@ -0,0 +1,75 @@
+use lru::LruCache;
+use milena_protos::router_server::GetResponse;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Bucket, epoch and key of a cached read.
+type ResultKey = (String, u64, Vec<u8>);
+
+/// Recent GET hits kept by the router itself, so repeated reads of a very hot
+/// key within `ttl` are answered without a cache node call. Writes through
+/// this router drop the key; writes through other routers do not, so a read
+/// may trail them by up to `ttl`.
+pub struct ResultCache {
+    entries: Mutex<LruCache<ResultKey, (GetResponse, Instant)>>,
+    ttl: Duration,
+}
+
+impl ResultCache {
+    pub fn new(capacity: NonZeroUsize, ttl: Duration) -> Self {
+        ResultCache {
+            entries: Mutex::new(LruCache::new(capacity)),
+            ttl,
+        }
+    }
+
+    pub fn get(&self, bucket: &str, epoch: u64, key: &[u8]) -> Option<GetResponse> {
+        let result_key = (bucket.to_string(), epoch, key.to_vec());
+        let mut entries = self.entries.lock().unwrap();
+        let fresh = entries
+            .get(&result_key)
+            .filter(|(_, stored_at)| stored_at.elapsed() < self.ttl)
+            .map(|(response, _)| response.clone());
+        if fresh.is_none() {
+            entries.pop(&result_key);
+        }
+        fresh
+    }
+
+    pub fn insert(&self, bucket: &str, epoch: u64, key: &[u8], response: GetResponse) {
+        self.entries.lock().unwrap().put(
+            (bucket.to_string(), epoch, key.to_vec()),
+            (response, Instant::now()),
+        );
+    }
+
+    pub fn invalidate(&self, bucket: &str, epoch: u64, key: &[u8]) {
+        self.entries
+            .lock()
+            .unwrap()
+            .pop(&(bucket.to_string(), epoch, key.to_vec()));
+    }
+}
+
+#[test]
+fn test_result_cache_expires_and_invalidates() {
+    let cache = ResultCache::new(NonZeroUsize::new(2).unwrap(), Duration::from_millis(20));
+    let response = GetResponse {
+        successful: true,
+        value: b"value".to_vec(),
+        ..Default::default()
+    };
+
+    cache.insert("users", 0, b"a", response.clone());
+    assert_eq!(cache.get("users", 0, b"a"), Some(response.clone()));
+    // Each epoch of a bucket is cached separately
+    assert_eq!(cache.get("users", 1, b"a"), None);
+
+    cache.invalidate("users", 0, b"a");
+    assert_eq!(cache.get("users", 0, b"a"), None);
+
+    cache.insert("users", 0, b"a", response);
+    std::thread::sleep(Duration::from_millis(30));
+    assert_eq!(cache.get("users", 0, b"a"), None);
+}
@@ -0,0 +1,77 @@
+use prometheus::Gauge;
+use std::sync::Mutex;
+
+/// Router-wide token bucket limiting retries to cache nodes, modelled on gRPC
+/// retry throttling. Each retry spends a whole token and each successful call
+/// earns back `token_ratio` of one, so once failures outpace successes the
+/// bucket drains and requests fail fast instead of multiplying load on a
+/// struggling cluster.
+pub struct RetryBudget {
+    tokens: Mutex<f64>,
+    max_tokens: f64,
+    token_ratio: f64,
+    gauge: Gauge,
+}
+
+impl RetryBudget {
+    /// Starts full, so a router can retry straight after startup.
+    pub fn new(max_tokens: u32, token_ratio: f64, gauge: Gauge) -> Self {
+        let max_tokens = f64::from(max_tokens);
+        gauge.set(max_tokens);
+        Self {
+            tokens: Mutex::new(max_tokens),
+            max_tokens,
+            token_ratio,
+            gauge,
+        }
+    }
+
+    /// Takes a token for one retry, or returns false if the budget is spent.
+    pub fn try_withdraw(&self) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        self.gauge.set(*tokens);
+        true
+    }
+
+    pub fn record_success(&self) {
+        let mut tokens = self.tokens.lock().unwrap();
+        if *tokens < self.max_tokens {
+            *tokens = (*tokens + self.token_ratio).min(self.max_tokens);
+            self.gauge.set(*tokens);
+        }
+    }
+}
+
+#[test]
+fn test_retry_budget_fails_fast_once_spent() {
+    let gauge = Gauge::new("retry_budget_tokens", "Retry tokens left").unwrap();
+    let budget = RetryBudget::new(2, 0.5, gauge.clone());
+    assert_eq!(gauge.get(), 2.0);
+    assert!(budget.try_withdraw());
+    assert!(budget.try_withdraw());
+    assert!(!budget.try_withdraw());
+    assert_eq!(gauge.get(), 0.0);
+}
+
+#[test]
+fn test_retry_budget_refills_from_successes_up_to_its_size() {
+    let gauge = Gauge::new("retry_budget_tokens", "Retry tokens left").unwrap();
+    let budget = RetryBudget::new(2, 0.5, gauge.clone());
+    assert!(budget.try_withdraw());
+    assert!(budget.try_withdraw());
+
+    // Half a token per success, so one success is not enough for a retry
+    budget.record_success();
+    assert!(!budget.try_withdraw());
+    budget.record_success();
+    assert!(budget.try_withdraw());
+
+    for _ in 0..10 {
+        budget.record_success();
+    }
+    assert_eq!(gauge.get(), 2.0);
+}
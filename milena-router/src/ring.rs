@@ -0,0 +1,284 @@
+use arc_swap::{ArcSwap, Guard};
+use conhash::{ConsistentHash, Node};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Virtual nodes each cache node gets on the ring.
+const REPLICAS: usize = 2;
+// Re-hashes of a key tried per node wanted when picking its replicas.
+const PROBES_PER_NODE: usize = 32;
+
+/// Group of nodes that join without a label, serving every bucket not mapped
+/// to a group of its own.
+pub const DEFAULT_GROUP: &str = "";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ServerNode {
+    host: String,
+}
+
+impl ServerNode {
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+}
+
+impl Node for ServerNode {
+    fn name(&self) -> String {
+        self.host.to_string()
+    }
+}
+
+/// The ring of every node group as of one membership change.
+#[derive(Default)]
+pub struct Rings {
+    groups: HashMap<String, ConsistentHash<ServerNode>>,
+}
+
+impl Rings {
+    /// The ring of `group`, or `None` when no node has joined it.
+    pub fn group(&self, group: &str) -> Option<&ConsistentHash<ServerNode>> {
+        self.groups.get(group)
+    }
+
+    /// Up to `n` distinct nodes of `group` to hold `key`, its owner first.
+    /// The rest are found by re-hashing the key with a counter, so the choice
+    /// is stable for a given key and ring. This is best effort: fewer are
+    /// returned when the group has fewer nodes, and also, rarely, when every
+    /// one of the bounded re-hashes lands on a node already picked.
+    pub fn get_n_nodes(&self, group: &str, key: &[u8], n: usize) -> Vec<&ServerNode> {
+        let Some(ring) = self.groups.get(group) else {
+            return Vec::new();
+        };
+        let mut nodes: Vec<&ServerNode> = ring.get(key).into_iter().collect();
+        let mut probe = key.to_vec();
+        for attempt in 0..n * PROBES_PER_NODE {
+            if nodes.len() >= n {
+                break;
+            }
+            probe.truncate(key.len());
+            probe.extend((attempt as u32).to_be_bytes());
+            if let Some(node) = ring.get(&probe)
+                && !nodes.contains(&node)
+            {
+                nodes.push(node);
+            }
+        }
+        nodes.truncate(n);
+        nodes
+    }
+}
+
+/// Placement rings consulted on every request, one per node group. Lookups
+/// read the current snapshot without taking a lock; joins and leaves rebuild
+/// the rings from the member list and publish them as a new snapshot, so
+/// readers never wait on a membership change and writers never wait on readers.
+pub struct Ring {
+    snapshot: ArcSwap<Rings>,
+    /// Nodes on the rings and their groups. Writers serialize on this lock
+    /// while rebuilding.
+    members: Mutex<Vec<(String, String)>>,
+}
+
+impl Default for Ring {
+    fn default() -> Self {
+        Ring {
+            snapshot: ArcSwap::from_pointee(Rings::default()),
+            members: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Ring {
+    /// The rings as of the last membership change. Lookups that must agree
+    /// with each other should share one snapshot.
+    pub fn snapshot(&self) -> Guard<Arc<Rings>> {
+        self.snapshot.load()
+    }
+
+    /// Adds `host` to `group`'s ring, moving it there if it is on another.
+    pub fn add(&self, host: &str, group: &str) {
+        let mut members = self.members.lock().unwrap();
+        match members.iter_mut().find(|(member, _)| member == host) {
+            Some((_, current)) if current == group => return,
+            Some((_, current)) => *current = group.to_string(),
+            None => members.push((host.to_string(), group.to_string())),
+        }
+        self.publish(&members);
+    }
+
+    pub fn remove(&self, host: &str) {
+        let mut members = self.members.lock().unwrap();
+        members.retain(|(member, _)| member != host);
+        self.publish(&members);
+    }
+
+    // A node's positions depend only on its name, so a rebuilt ring places
+    // every key exactly where the previous one did.
+    fn publish(&self, members: &[(String, String)]) {
+        let mut rings = Rings::default();
+        for (host, group) in members {
+            rings
+                .groups
+                .entry(group.clone())
+                .or_insert_with(ConsistentHash::new)
+                .add(&ServerNode { host: host.clone() }, REPLICAS);
+        }
+        self.snapshot.store(Arc::new(rings));
+    }
+}
+
+#[cfg(test)]
+fn owner(ring: &Ring, group: &str, key: &[u8]) -> Option<String> {
+    let rings = ring.snapshot();
+    let node = rings.group(group)?.get(key)?;
+    Some(node.host().to_string())
+}
+
+#[test]
+fn test_ring_membership() {
+    let ring = Ring::default();
+    assert!(owner(&ring, DEFAULT_GROUP, b"key").is_none());
+
+    ring.add("http://a:50051", DEFAULT_GROUP);
+    ring.add("http://b:50051", DEFAULT_GROUP);
+    ring.add("http://a:50051", DEFAULT_GROUP);
+    let first = owner(&ring, DEFAULT_GROUP, b"key").unwrap();
+
+    // Rebuilding for an unrelated change keeps existing placements
+    ring.add("http://c:50051", DEFAULT_GROUP);
+    ring.remove("http://c:50051");
+    assert_eq!(owner(&ring, DEFAULT_GROUP, b"key").unwrap(), first);
+
+    ring.remove(&first);
+    let remaining = owner(&ring, DEFAULT_GROUP, b"key").unwrap();
+    assert_ne!(remaining, first);
+    ring.remove(&remaining);
+    assert!(owner(&ring, DEFAULT_GROUP, b"key").is_none());
+}
+
+#[test]
+fn test_ring_groups() {
+    let ring = Ring::default();
+    ring.add("http://a:50051", DEFAULT_GROUP);
+    ring.add("http://premium:50051", "premium");
+
+    // Each group's keys only land on its own nodes
+    for key in [&b"one"[..], b"two", b"three"] {
+        assert_eq!(owner(&ring, DEFAULT_GROUP, key).unwrap(), "http://a:50051");
+        assert_eq!(
+            owner(&ring, "premium", key).unwrap(),
+            "http://premium:50051"
+        );
+    }
+    assert!(owner(&ring, "batch", b"one").is_none());
+
+    // Rejoining under another label moves the node
+    ring.add("http://premium:50051", "batch");
+    assert!(owner(&ring, "premium", b"one").is_none());
+    assert_eq!(
+        owner(&ring, "batch", b"one").unwrap(),
+        "http://premium:50051"
+    );
+}
+
+#[test]
+fn test_ring_get_n_nodes() {
+    let ring = Ring::default();
+    for host in ["http://a:50051", "http://b:50051", "http://c:50051"] {
+        ring.add(host, DEFAULT_GROUP);
+    }
+    let hosts = |key: &[u8], n| -> Vec<String> {
+        let rings = ring.snapshot();
+        rings
+            .get_n_nodes(DEFAULT_GROUP, key, n)
+            .into_iter()
+            .map(|node| node.host().to_string())
+            .collect()
+    };
+
+    for i in 0..32u32 {
+        let key = i.to_be_bytes();
+        let replicas = hosts(&key, 2);
+        assert_eq!(replicas.len(), 2);
+        assert_ne!(replicas[0], replicas[1]);
+        assert_eq!(replicas[0], owner(&ring, DEFAULT_GROUP, &key).unwrap());
+        assert_eq!(hosts(&key, 1), replicas[..1]);
+    }
+
+    // Losing one replica leaves the other among the key's replicas
+    let replicas = hosts(b"key", 2);
+    ring.remove(&replicas[0]);
+    assert!(hosts(b"key", 2).contains(&replicas[1]));
+
+    // Never more than the group has
+    ring.add("http://premium:50051", "premium");
+    assert_eq!(ring.snapshot().get_n_nodes("premium", b"key", 2).len(), 1);
+    assert!(ring.snapshot().get_n_nodes("batch", b"key", 2).is_empty());
+}
+
+// Compares contended lookups against the mutex-guarded ring this replaced:
+// cargo test -p milena-router --release -- --ignored --nocapture bench_ring_lookups
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+#[ignore]
+async fn bench_ring_lookups() {
+    const TASKS: usize = 8;
+    const LOOKUPS: usize = 200_000;
+
+    let ring = Arc::new(Ring::default());
+    let mut locked = ConsistentHash::new();
+    for i in 0..16 {
+        let host = format!("http://node-{i}:50051");
+        ring.add(&host, DEFAULT_GROUP);
+        locked.add(&ServerNode { host }, REPLICAS);
+    }
+    let locked = Arc::new(tokio::sync::Mutex::new(locked));
+
+    let start = std::time::Instant::now();
+    let tasks: Vec<_> = (0..TASKS)
+        .map(|task| {
+            let locked = locked.clone();
+            tokio::spawn(async move {
+                for i in 0..LOOKUPS {
+                    let key = (task * LOOKUPS + i).to_be_bytes();
+                    let host = locked.lock().await.get(&key).map(Node::name);
+                    std::hint::black_box(host);
+                }
+            })
+        })
+        .collect();
+    futures::future::join_all(tasks).await;
+    let mutex_elapsed = start.elapsed();
+
+    let start = std::time::Instant::now();
+    let tasks: Vec<_> = (0..TASKS)
+        .map(|task| {
+            let ring = ring.clone();
+            tokio::spawn(async move {
+                for i in 0..LOOKUPS {
+                    let key = (task * LOOKUPS + i).to_be_bytes();
+                    let rings = ring.snapshot();
+                    let host = rings
+                        .group(DEFAULT_GROUP)
+                        .unwrap()
+                        .get(&key)
+                        .map(Node::name);
+                    std::hint::black_box(host);
+                }
+            })
+        })
+        .collect();
+    futures::future::join_all(tasks).await;
+    let snapshot_elapsed = start.elapsed();
+
+    let per_lookup = |elapsed: std::time::Duration| elapsed / (TASKS * LOOKUPS) as u32;
+    println!(
+        "{} tasks x {} lookups: mutex {:?} ({:?}/lookup), snapshot {:?} ({:?}/lookup)",
+        TASKS,
+        LOOKUPS,
+        mutex_elapsed,
+        per_lookup(mutex_elapsed),
+        snapshot_elapsed,
+        per_lookup(snapshot_elapsed)
+    );
+}
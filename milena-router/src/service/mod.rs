@@ -1,19 +1,36 @@
 use crate::{
-    connection::{CacheClientManager, Pool, PooledClient},
+    audit::{AuditLog, AuditRecord, Caller},
+    compute::{Claim, LeaseTable},
+    connection::{HealthCheck, InFlight, NodeConn, NodeConns, PooledClient, create_pool},
+    epoch::{BucketEpochs, EPOCH_MARKER_KEY, EpochTable},
+    metrics::Metrics,
     rate_limit::{RateLimitError, RateLimiterMiddleware},
-    validation::{
-        validate_address, validate_bucket_name, validate_key, validate_value, ValidationError,
-    },
+    result_cache::ResultCache,
+    retry_budget::RetryBudget,
+    ring::{DEFAULT_GROUP, Ring},
 };
-use conhash::{ConsistentHash, Node};
+use deadpool::managed::PoolError;
+use futures::Stream;
+use futures::future::{join_all, try_join_all};
 use milena_protos::cache_server::{self};
+use milena_protos::normalization::KeyNormalization;
 use milena_protos::router_server::{router_server::Router, *};
+use milena_protos::validation::{
+    canonical_address, validate_address, validate_bucket_name_with, validate_key, validate_routing_key,
+    validate_value, BucketNameRules, ValidationError,
+};
 use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tokio::sync::Mutex;
-use tonic::{Code, Request, Response, Status};
-use tracing::{error, info};
+use tokio::sync::{Semaphore, SemaphorePermit, mpsc};
+use tonic::{Code, Request, Response, Status, Streaming};
+use tracing::{error, info, warn};
+
+// How many lease holders a GetOrCompute caller waits out before giving up.
+const MAX_LEASE_WAITS: usize = 3;
 
 #[derive(Debug, Error)]
 pub enum RouterError {
@@ -21,8 +38,21 @@ pub enum RouterError {
     NodeNotFound(String),
     #[error("Connection error: {0}")]
     ConnectionError(String),
+    #[error("Node saturated: {0}")]
+    NodeSaturated(String),
+    #[error("Connection pool exhausted: {0}")]
+    PoolExhausted(String),
     #[error("Internal error: {0}")]
     InternalError(String),
+    #[error("Invalid epoch: {0}")]
+    InvalidEpoch(String),
+    #[error("Refusing to remove the last node: {0}")]
+    LastNode(String),
+    #[error("Too many nodes: {0}")]
+    TooManyNodes(String),
+    /// Boxed, since a `Status` would make every `RouterResult` several times larger.
+    #[error("Cache node error: {0}")]
+    DownstreamError(Box<Status>),
     #[error("Validation error: {0}")]
     ValidationError(#[from] ValidationError),
     #[error("Rate limit error: {0}")]
@@ -32,97 +62,957 @@ pub enum RouterError {
 // Define a helper type for our result to avoid confusion with Status
 pub type RouterResult<T> = std::result::Result<T, RouterError>;
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub struct ServerNode {
-    host: String,
+/// A mutating call made to a read-only router.
+#[derive(Debug, Error)]
+#[error("{0} is not available on a read-only router")]
+pub struct ReadOnly(&'static str);
+
+impl From<ReadOnly> for Status {
+    fn from(e: ReadOnly) -> Self {
+        Status::permission_denied(e.to_string())
+    }
 }
 
-impl Node for ServerNode {
-    fn name(&self) -> String {
-        self.host.to_string()
+impl RouterError {
+    fn code(&self) -> Code {
+        match self {
+            RouterError::NodeSaturated(_) | RouterError::PoolExhausted(_) => {
+                Code::ResourceExhausted
+            }
+            RouterError::ValidationError(_) | RouterError::InvalidEpoch(_) => Code::InvalidArgument,
+            RouterError::LastNode(_) => Code::FailedPrecondition,
+            RouterError::TooManyNodes(_) => Code::ResourceExhausted,
+            _ => Code::Internal,
+        }
+    }
+
+    // Failures that may clear up on another attempt. Saturation is left alone
+    // since retrying would defeat the backpressure it signals.
+    fn is_retryable(&self) -> bool {
+        match self {
+            RouterError::ConnectionError(_) => true,
+            RouterError::DownstreamError(status) => status.code() == Code::Unavailable,
+            _ => false,
+        }
     }
 }
 
+#[derive(Clone)]
 pub struct RouterServiceImpl {
-    pub nodes: Arc<Mutex<ConsistentHash<ServerNode>>>,
-    pub node_conns: Arc<Mutex<HashMap<String, Pool>>>,
+    pub ring: Arc<Ring>,
+    /// Node group serving each listed bucket; other buckets use the default group.
+    pub bucket_node_groups: HashMap<String, String>,
+    pub node_conns: Arc<NodeConns>,
     pub rate_limiter: Arc<RateLimiterMiddleware>,
+    pub metrics: Arc<Metrics>,
+    pub routing_keys_enabled: bool,
+    /// Place requests that carry a routing key by it, and the rest by their key.
+    pub routing_keys_optional: bool,
+    pub membership_permits: Arc<Semaphore>,
+    /// Joins beyond this many nodes are rejected.
+    pub max_nodes: usize,
+    pub max_message_bytes: usize,
+    /// Gzip requests sent to cache nodes.
+    pub grpc_compression: bool,
+    pub max_in_flight_per_node: usize,
+    /// Longest a request waits for a free connection in a node's pool.
+    pub pool_wait_timeout: Duration,
+    /// How pooled connections are checked before reuse.
+    pub pool_health_check: HealthCheck,
+    /// Epochs of every bucket seen so far, loaded from each bucket's marker on first use.
+    pub bucket_epochs: Arc<EpochTable>,
+    pub max_retries: u32,
+    pub retry_budget: Arc<RetryBudget>,
+    /// Keys a `GetOrCompute` caller is currently fetching from origin.
+    pub compute_leases: Arc<LeaseTable>,
+    pub compute_lease_timeout: Duration,
+    pub bucket_name_rules: BucketNameRules,
+    /// Applied to every key before placement, so keys it maps together share a node.
+    pub key_normalization: KeyNormalization,
+    /// Receives a record of every put and delete sent to a cache node.
+    pub audit_log: Option<Arc<AuditLog>>,
+    /// Reject unforced leaves that would empty the ring.
+    pub protect_last_node: bool,
+    /// Key membership by each node's canonical address, so one node spelled
+    /// two ways gets a single ring entry.
+    pub canonicalize_node_addresses: bool,
+    /// Refuse data writes before they reach a cache node.
+    pub read_only: bool,
+    /// Recent GET hits served without a cache node call; disabled when unset.
+    pub result_cache: Option<Arc<ResultCache>>,
+    /// Distinct nodes each key is written to; gets fall back through them.
+    pub replication_factor: usize,
 }
 
 impl RouterServiceImpl {
-    async fn get_connection_for_key(&self, key: &Vec<u8>) -> RouterResult<PooledClient> {
-        let nodes_guard = self.nodes.lock().await;
-        let node = nodes_guard.get(key).ok_or_else(|| {
-            RouterError::NodeNotFound(format!("No node found for key: {:?}", key))
+    // Placement on the ring uses the routing key when enabled so that related
+    // keys co-locate; the full key is still what gets stored on the node.
+    fn placement_key(&self, key: &[u8], routing_key: &[u8]) -> Result<Vec<u8>, ValidationError> {
+        if self.routing_keys_enabled || (self.routing_keys_optional && !routing_key.is_empty()) {
+            validate_routing_key(routing_key)?;
+            Ok(routing_key.to_vec())
+        } else {
+            Ok(key.to_vec())
+        }
+    }
+
+    async fn check_rate_limit(&self) -> Result<(), Status> {
+        self.rate_limiter.check_rate_limit().await.map_err(|e| {
+            self.metrics.rate_limited.inc();
+            Status::new(
+                Code::ResourceExhausted,
+                format!("Rate limit exceeded: {}", e),
+            )
+        })
+    }
+
+    fn check_writable(&self, method: &'static str) -> Result<(), ReadOnly> {
+        if self.read_only {
+            return Err(ReadOnly(method));
+        }
+        Ok(())
+    }
+
+    // Starts an audit record for a write about to be sent to a cache node, or
+    // returns None when auditing is off so the key is never hashed.
+    fn audit(
+        &self,
+        operation: &'static str,
+        caller: &Caller,
+        bucket: &str,
+        key: &[u8],
+    ) -> Option<AuditRecord> {
+        self.audit_log
+            .as_ref()
+            .map(|_| AuditRecord::new(operation, caller.clone(), bucket, key))
+    }
+
+    fn finish_audit(&self, record: Option<AuditRecord>, successful: bool) {
+        if let (Some(audit_log), Some(mut record)) = (&self.audit_log, record) {
+            record.successful = successful;
+            audit_log.record(record);
+        }
+    }
+
+    fn node_group(&self, bucket: &str) -> &str {
+        self.bucket_node_groups
+            .get(bucket)
+            .map_or(DEFAULT_GROUP, String::as_str)
+    }
+
+    async fn node_for_key(&self, bucket: &str, key: &[u8]) -> RouterResult<String> {
+        let rings = self.ring.snapshot();
+        let group = self.node_group(bucket);
+        let node = rings
+            .group(group)
+            .and_then(|ring| ring.get(key))
+            .ok_or_else(|| {
+                RouterError::NodeNotFound(format!(
+                    "No node in group {:?} found for key: {:?}",
+                    group, key
+                ))
+            })?;
+        Ok(node.host().to_string())
+    }
+
+    // The nodes holding copies of a key, its owner first.
+    async fn replica_nodes(&self, bucket: &str, key: &[u8]) -> RouterResult<Vec<String>> {
+        let rings = self.ring.snapshot();
+        let group = self.node_group(bucket);
+        let nodes: Vec<String> = rings
+            .get_n_nodes(group, key, self.replication_factor)
+            .into_iter()
+            .map(|node| node.host().to_string())
+            .collect();
+        if nodes.is_empty() {
+            return Err(RouterError::NodeNotFound(format!(
+                "No node in group {:?} found for key: {:?}",
+                group, key
+            )));
+        }
+        Ok(nodes)
+    }
+
+    // Nodes that report themselves full keep serving reads, but their writes go
+    // to the first writable node found by re-hashing the key with a suffix, so
+    // the choice is stable for a given key. When every node is full the owner
+    // is used anyway.
+    async fn write_node_for_key(&self, bucket: &str, key: &[u8], owner: &str) -> String {
+        let node_conns = self.node_conns.load();
+        let rings = self.ring.snapshot();
+        let is_writable = |host: &str| node_conns.get(host).is_none_or(|conn| conn.writable);
+        if is_writable(owner) {
+            return owner.to_string();
+        }
+        // Writes stay within the bucket's group
+        let Some(ring) = rings.group(self.node_group(bucket)) else {
+            return owner.to_string();
+        };
+        let mut probe = key.to_vec();
+        for attempt in 0..node_conns.len() * 2 {
+            probe.truncate(key.len());
+            probe.extend((attempt as u32).to_be_bytes());
+            if let Some(node) = ring.get(&probe)
+                && node.host() != owner
+                && is_writable(node.host())
+            {
+                return node.host().to_string();
+            }
+        }
+        owner.to_string()
+    }
+
+    async fn get_connection_for_key(&self, bucket: &str, key: &[u8]) -> RouterResult<PooledClient> {
+        let node = self.node_for_key(bucket, key).await?;
+        self.get_connection(&node).await
+    }
+
+    async fn get_connection(&self, node: &str) -> RouterResult<PooledClient> {
+        let node_conns = self.node_conns.load();
+        let node_conn = node_conns.get(node).ok_or_else(|| {
+            RouterError::NodeNotFound(format!("No connection found for node: {}", node))
         })?;
 
-        let node_conns_guard = self.node_conns.lock().await;
-        let pool = node_conns_guard.get(&node.host).ok_or_else(|| {
-            RouterError::NodeNotFound(format!("No connection found for node: {}", node.host))
+        // Fail fast rather than queue behind a node that is already saturated
+        let permit = node_conn
+            .in_flight
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| {
+                self.metrics.node_saturated.with_label_values(&[node]).inc();
+                RouterError::NodeSaturated(format!("Node {} has too many requests in flight", node))
+            })?;
+        let in_flight = InFlight::new(
+            permit,
+            self.metrics.node_in_flight.with_label_values(&[node]),
+        );
+
+        // Wait for a connection without holding up every other node
+        let pool = node_conn.pool.clone();
+        drop(node_conns);
+        let connection = pool.get().await.map_err(|e| match e {
+            PoolError::Timeout(_) => RouterError::PoolExhausted(format!(
+                "No connection to {} freed up within {:?}",
+                node, self.pool_wait_timeout
+            )),
+            e => RouterError::ConnectionError(e.to_string()),
         })?;
+        Ok(PooledClient::new(connection, in_flight))
+    }
 
-        // Get connection from pool
-        let connection = pool
-            .get()
+    // Sends a request to `node`, retrying transient failures up to
+    // `max_retries` times while the retry budget has tokens.
+    async fn call_node<T, F, Fut>(&self, node: &str, mut call: F) -> RouterResult<T>
+    where
+        F: FnMut(PooledClient) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Status>>,
+    {
+        let mut retries = 0;
+        loop {
+            let result = match self.get_connection(node).await {
+                Ok(pooled_client) => {
+                    let timer = Instant::now();
+                    let result = call(pooled_client).await;
+                    self.metrics
+                        .downstream_duration
+                        .with_label_values(&[node])
+                        .observe(timer.elapsed().as_secs_f64());
+                    result.map_err(|e| {
+                        self.metrics.record_downstream_error(e.code());
+                        RouterError::DownstreamError(Box::new(e))
+                    })
+                }
+                Err(e) => Err(e),
+            };
+            let error = match result {
+                Ok(response) => {
+                    self.retry_budget.record_success();
+                    return Ok(response.into_inner());
+                }
+                Err(e) => e,
+            };
+            if !error.is_retryable() || retries >= self.max_retries {
+                return Err(error);
+            }
+            if !self.retry_budget.try_withdraw() {
+                self.metrics.retries.with_label_values(&["throttled"]).inc();
+                return Err(error);
+            }
+            self.metrics.retries.with_label_values(&["attempted"]).inc();
+            info!("Retrying cache node call after: {}", error);
+            retries += 1;
+        }
+    }
+
+    // Sends a request to each of a key's replicas in turn until one answers.
+    async fn call_replicas<T, F, Fut>(&self, replicas: &[String], mut call: F) -> RouterResult<T>
+    where
+        F: FnMut(PooledClient) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Status>>,
+    {
+        let mut result = self.call_node(&replicas[0], &mut call).await;
+        for node in &replicas[1..] {
+            let Err(e) = &result else {
+                break;
+            };
+            warn!("Cache node call failed, trying replica {}: {}", node, e);
+            result = self.call_node(node, &mut call).await;
+        }
+        result
+    }
+
+    // Returns the cached epochs for `bucket`, reading its marker on first use.
+    // No lock is held while the marker is read.
+    async fn load_epochs(&self, bucket: &str) -> RouterResult<BucketEpochs> {
+        if let Some(epochs) = self.bucket_epochs.get(bucket) {
+            return Ok(epochs);
+        }
+        let mut pooled_client = self
+            .get_connection_for_key(bucket, EPOCH_MARKER_KEY)
+            .await?;
+        let marker = pooled_client
+            .client()
+            .get(Request::new(cache_server::GetRequest {
+                key: EPOCH_MARKER_KEY.to_vec(),
+                bucket: bucket.to_string(),
+                prefetch_keys: vec![],
+                epoch: 0,
+            }))
             .await
-            .map_err(|e| RouterError::ConnectionError(e.to_string()))?;
-        Ok(PooledClient(connection))
+            .map_err(|e| {
+                self.metrics.record_downstream_error(e.code());
+                RouterError::ConnectionError(e.to_string())
+            })?
+            .into_inner()
+            .value;
+        let loaded = BucketEpochs::decode(&marker).ok_or_else(|| {
+            RouterError::InternalError(format!("Malformed epoch marker for bucket {}", bucket))
+        })?;
+        Ok(self.bucket_epochs.insert_loaded(bucket, loaded))
+    }
+
+    async fn store_epochs(&self, bucket: &str, epochs: BucketEpochs) -> RouterResult<()> {
+        let mut pooled_client = self
+            .get_connection_for_key(bucket, EPOCH_MARKER_KEY)
+            .await?;
+        pooled_client
+            .client()
+            .put(Request::new(cache_server::PutRequest {
+                key: EPOCH_MARKER_KEY.to_vec(),
+                bucket: bucket.to_string(),
+                value: epochs.encode(),
+                epoch: 0,
+                durable: false,
+                idempotency_key: String::new(),
+                return_value: false,
+                ttl_seconds: 0,
+                replica: false,
+            }))
+            .await
+            .map_err(|e| {
+                self.metrics.record_downstream_error(e.code());
+                RouterError::ConnectionError(e.to_string())
+            })?;
+        Ok(())
+    }
+
+    // Data requests target the live epoch unless they name a staged one.
+    async fn resolve_epoch(&self, bucket: &str, staged_epoch: u64) -> RouterResult<u64> {
+        let bucket_epochs = self.load_epochs(bucket).await?;
+        if staged_epoch == 0 {
+            return Ok(bucket_epochs.live);
+        }
+        if !bucket_epochs.is_staged(staged_epoch) {
+            return Err(RouterError::InvalidEpoch(format!(
+                "Epoch {} is not staged for bucket {}",
+                staged_epoch, bucket
+            )));
+        }
+        Ok(staged_epoch)
+    }
+
+    async fn begin_stage(&self, bucket: &str) -> RouterResult<u64> {
+        let _changes = self.bucket_epochs.lock_changes().await;
+        let bucket_epochs = self.load_epochs(bucket).await?;
+        let staged = BucketEpochs {
+            live: bucket_epochs.live,
+            next: bucket_epochs.next + 1,
+        };
+        // Persist before handing the epoch out so it is never reused after a restart
+        self.store_epochs(bucket, staged).await?;
+        self.bucket_epochs.set(bucket, staged);
+        info!("Staging epoch {} for bucket {}", staged.next - 1, bucket);
+        Ok(staged.next - 1)
+    }
+
+    async fn promote_bucket(&self, bucket: &str, epoch: u64) -> RouterResult<()> {
+        let _changes = self.bucket_epochs.lock_changes().await;
+        let bucket_epochs = self.load_epochs(bucket).await?;
+        if !bucket_epochs.is_staged(epoch) {
+            return Err(RouterError::InvalidEpoch(format!(
+                "Epoch {} is not staged for bucket {}",
+                epoch, bucket
+            )));
+        }
+        let promoted = BucketEpochs {
+            live: epoch,
+            next: bucket_epochs.next,
+        };
+        self.store_epochs(bucket, promoted).await?;
+        // Published in one write, so every reader switches sets at once
+        self.bucket_epochs.set(bucket, promoted);
+        info!("Promoted epoch {} for bucket {}", epoch, bucket);
+        Ok(())
+    }
+
+    // Membership changes rebuild the ring and update the connection table, so
+    // they are bounded separately from the data-plane rate limit and queue here
+    // instead.
+    async fn acquire_membership_permit(&self, address: &str) -> SemaphorePermit<'_> {
+        match self.membership_permits.try_acquire() {
+            Ok(permit) => permit,
+            Err(_) => {
+                info!("Membership change for {} queued", address);
+                self.membership_permits
+                    .acquire()
+                    .await
+                    .expect("membership semaphore is never closed")
+            }
+        }
+    }
+
+    // The address a node is tracked under. Addresses that fail to parse are
+    // kept as given, so a leave still matches a node that joined that way.
+    fn node_address(&self, address: String) -> String {
+        if !self.canonicalize_node_addresses {
+            return address;
+        }
+        canonical_address(&address).unwrap_or(address)
     }
 
-    async fn join_node(&self, address: String) -> RouterResult<()> {
-        info!("Joining node: {}", address);
+    async fn join_node(&self, address: String, group: String) -> RouterResult<()> {
         validate_address(&address)?;
+        let address = self.node_address(address);
+        let _permit = self.acquire_membership_permit(&address).await;
+        if group == DEFAULT_GROUP {
+            info!("Joining node: {}", address);
+        } else {
+            info!("Joining node {} to group {}", address, group);
+        }
 
-        self.nodes.lock().await.add(
-            &ServerNode {
-                host: address.clone(),
-            },
-            2,
-        );
+        // Held until the node is added, so concurrent joins can't both pass
+        // the checks and a leave of the same address sees the ring entry and
+        // pool change together
+        let mut node_conns = self.node_conns.edit().await;
+        if let Some(node_conn) = node_conns.get_mut(&address) {
+            // A node restarted without leaving keeps its ring entry and pool,
+            // but nodes join writable and may have been relabelled
+            info!("Node {} already joined", address);
+            node_conn.writable = true;
+            let moved = node_conn.group != group;
+            node_conn.group = group.clone();
+            node_conns.publish();
+            if moved {
+                info!("Moving node {} to group {:?}", address, group);
+                self.ring.add(&address, &group);
+            }
+            return Ok(());
+        }
+        if node_conns.len() >= self.max_nodes {
+            error!(
+                "Rejecting join of {}: the ring already has the maximum of {} nodes",
+                address, self.max_nodes
+            );
+            return Err(RouterError::TooManyNodes(format!(
+                "{} (limit {})",
+                address, self.max_nodes
+            )));
+        }
 
-        // Create a connection pool for the new node
-        let pool = Pool::builder(CacheClientManager::new(address.clone()))
-            .max_size(10)
-            .build()
-            .map_err(|e| RouterError::ConnectionError(e.to_string()))?;
+        // Create a connection pool for the new node before it goes on the
+        // ring, so a failure leaves neither
+        let pool = create_pool(
+            address.clone(),
+            10,
+            self.max_message_bytes,
+            self.grpc_compression,
+            self.pool_health_check,
+            self.pool_wait_timeout,
+        )
+        .map_err(|e| RouterError::ConnectionError(e.to_string()))?;
 
-        self.node_conns.lock().await.insert(address, pool);
+        // The pool is published before the ring entry, so a request never
+        // finds the node without a connection to it
+        node_conns.insert(
+            address.clone(),
+            NodeConn {
+                pool,
+                in_flight: Arc::new(Semaphore::new(self.max_in_flight_per_node)),
+                writable: true,
+                group: group.clone(),
+            },
+        );
+        node_conns.publish();
+        self.ring.add(&address, &group);
         info!("Successfully joined node");
         Ok(())
     }
 
-    async fn leave_node(&self, address: String) {
+    async fn leave_node(&self, address: String, force: bool) -> RouterResult<()> {
+        let address = self.node_address(address);
+        let _permit = self.acquire_membership_permit(&address).await;
         info!("Leaving node: {}", address);
-        self.nodes.lock().await.remove(&ServerNode {
-            host: address.clone(),
-        });
-        self.node_conns.lock().await.remove(&address);
+        // Held until the node is gone, so a concurrent join is seen either
+        // before the check or after the removal
+        let mut node_conns = self.node_conns.edit().await;
+        if node_conns.len() == 1 && node_conns.contains_key(&address) {
+            if self.protect_last_node && !force {
+                error!(
+                    "Refusing to remove {}: it is the last node and every request would fail \
+                     with NodeNotFound. Retry with force to remove it anyway",
+                    address
+                );
+                return Err(RouterError::LastNode(address));
+            }
+            warn!(
+                "Removing the last node {}; every request will fail with NodeNotFound \
+                 until a node joins",
+                address
+            );
+        }
+        self.ring.remove(&address);
+        node_conns.remove(&address);
+        node_conns.publish();
+        let _ = self.metrics.node_in_flight.remove_label_values(&[&address]);
+        let _ = self
+            .metrics
+            .downstream_duration
+            .remove_label_values(&[&address]);
         info!("Successfully removed node");
+        Ok(())
+    }
+
+    // Drops the router's cached result for a key this router is writing.
+    fn invalidate_result(&self, bucket: &str, epoch: u64, key: &[u8]) {
+        if let Some(result_cache) = &self.result_cache {
+            result_cache.invalidate(bucket, epoch, key);
+        }
+    }
+
+    async fn put_key(
+        &self,
+        placement_key: &[u8],
+        cache_request: cache_server::PutRequest,
+    ) -> RouterResult<cache_server::PutResponse> {
+        let bucket = &cache_request.bucket;
+        let replicas = self.replica_nodes(bucket, placement_key).await?;
+        let owner = replicas[0].clone();
+        let node = self.write_node_for_key(bucket, placement_key, &owner).await;
+        let response = self
+            .call_node(&node, |mut pooled_client| {
+                let cache_request = cache_request.clone();
+                async move {
+                    pooled_client
+                        .client()
+                        .put(Request::new(cache_request))
+                        .await
+                }
+            })
+            .await;
+        if response.is_ok() {
+            let copies = replicas
+                .into_iter()
+                .skip(1)
+                .filter(|replica| *replica != node)
+                .map(|replica| (replica, cache_request.clone()))
+                .collect();
+            self.put_copies(copies).await;
+        }
+        // Even a failed put may have reached the nodes
+        self.invalidate_result(
+            &cache_request.bucket,
+            cache_request.epoch,
+            &cache_request.key,
+        );
+        let response = response?;
+
+        if node != owner {
+            self.drop_owner_copy(
+                &owner,
+                cache_request.key,
+                cache_request.bucket,
+                cache_request.epoch,
+            )
+            .await?;
+        }
+        Ok(response)
+    }
+
+    // Writes copies of written keys to their replicas beyond the owner, one
+    // batch per node. Copies are marked as replicas so their nodes skip S3,
+    // which the original write already reached. A failed copy is logged and
+    // counted instead of failing the put.
+    async fn put_copies(&self, copies: Vec<(String, cache_server::PutRequest)>) {
+        let copies = copies
+            .into_iter()
+            .map(|(replica, put)| {
+                let put = cache_server::PutRequest {
+                    replica: true,
+                    return_value: false,
+                    ..put
+                };
+                (replica, put)
+            })
+            .collect();
+        join_all(split_by_node(copies).into_iter().map(|batch| async move {
+            let count = batch.items.len() as u64;
+            let cache_request = cache_server::BatchPutRequest {
+                requests: batch.items,
+            };
+            let result = self
+                .call_node(&batch.node, |mut pooled_client| {
+                    let cache_request = cache_request.clone();
+                    async move {
+                        pooled_client
+                            .client()
+                            .batch_put(Request::new(cache_request))
+                            .await
+                    }
+                })
+                .await;
+            if let Err(e) = result {
+                warn!(
+                    "Failed to write {} replica copies to {}: {}",
+                    count, batch.node, e
+                );
+                self.metrics
+                    .replica_copy_failures
+                    .with_label_values(&[batch.node.as_str()])
+                    .inc_by(count);
+            }
+        }))
+        .await;
+    }
+
+    // Sends batched gets to each key's owner, one batch per node. The keys of a
+    // batch that fails are sent on to their next replicas, as `call_replicas`
+    // does for a single get, until every key is answered or one runs out of
+    // replicas.
+    async fn batch_get_from_replicas(
+        &self,
+        targets: &[(Vec<String>, cache_server::GetRequest)],
+    ) -> RouterResult<Vec<cache_server::GetResponse>> {
+        let mut answered = Vec::new();
+        let mut pending: Vec<usize> = (0..targets.len()).collect();
+        let mut attempt = 0;
+        while !pending.is_empty() {
+            let attempts = pending
+                .drain(..)
+                .map(|index| (targets[index].0[attempt].clone(), index))
+                .collect();
+            let results = join_all(split_by_node(attempts).into_iter().map(|batch| async move {
+                let cache_request = cache_server::BatchGetRequest {
+                    requests: batch
+                        .items
+                        .iter()
+                        .map(|&index| targets[index].1.clone())
+                        .collect(),
+                };
+                let result = self
+                    .call_node(&batch.node, |mut pooled_client| {
+                        let cache_request = cache_request.clone();
+                        async move {
+                            pooled_client
+                                .client()
+                                .batch_get(Request::new(cache_request))
+                                .await
+                        }
+                    })
+                    .await;
+                (batch, result)
+            }))
+            .await;
+            attempt += 1;
+            for (batch, result) in results {
+                match result {
+                    Ok(response) => answered.push((batch.items, response.responses)),
+                    Err(e) => {
+                        if batch
+                            .items
+                            .iter()
+                            .any(|&index| targets[index].0.len() <= attempt)
+                        {
+                            return Err(e);
+                        }
+                        warn!(
+                            "Batched cache node call to {} failed, trying replicas: {}",
+                            batch.node, e
+                        );
+                        pending.extend(batch.items);
+                    }
+                }
+            }
+        }
+        reassemble(targets.len(), answered)
+    }
+
+    // Drops a full owner's copy of a key written to another node, so the
+    // owner's reads fall through to S3 and see the write.
+    async fn drop_owner_copy(
+        &self,
+        owner: &str,
+        key: Vec<u8>,
+        bucket: String,
+        epoch: u64,
+    ) -> RouterResult<()> {
+        let delete_request = cache_server::DeleteRequest {
+            key,
+            bucket,
+            epoch,
+            idempotency_key: String::new(),
+        };
+        self.call_node(owner, |mut pooled_client| {
+            let delete_request = delete_request.clone();
+            async move {
+                pooled_client
+                    .client()
+                    .delete(Request::new(delete_request))
+                    .await
+            }
+        })
+        .await?;
+        Ok(())
+    }
+
+    // Validates a request naming a single key and resolves the node that owns
+    // it and the epoch to address it under.
+    async fn key_owner(
+        &self,
+        bucket: &str,
+        key: &[u8],
+        routing_key: &[u8],
+        staged_epoch: u64,
+    ) -> Result<(String, u64), Status> {
+        if let Err(e) = validate_bucket_name_with(bucket, &self.bucket_name_rules) {
+            return Err(Status::new(Code::InvalidArgument, format!("{}", e)));
+        }
+        if let Err(e) = validate_key(key) {
+            return Err(Status::new(Code::InvalidArgument, format!("{}", e)));
+        }
+        if key == EPOCH_MARKER_KEY {
+            return Err(Status::new(
+                Code::InvalidArgument,
+                "Key is reserved for bucket epochs",
+            ));
+        }
+        let placement_key = self
+            .placement_key(key, routing_key)
+            .map_err(|e| Status::new(Code::InvalidArgument, format!("{}", e)))?;
+        let epoch = self
+            .resolve_epoch(bucket, staged_epoch)
+            .await
+            .map_err(|e| Status::new(e.code(), format!("{e}")))?;
+        let node = self
+            .node_for_key(bucket, &placement_key)
+            .await
+            .map_err(|e| Status::new(e.code(), format!("{e}")))?;
+        Ok((node, epoch))
+    }
+
+    // Serves one GetOrCompute exchange. A hit is answered straight away; on a
+    // miss the first caller gets a lease and must send the value back, while
+    // later callers wait for the lease to be released and read again.
+    async fn get_or_compute_session(
+        &self,
+        mut requests: Streaming<GetOrComputeRequest>,
+        caller: Caller,
+        responses: &mpsc::Sender<Result<GetOrComputeResponse, Status>>,
+    ) -> Result<(), Status> {
+        let mut lookup = match requests.message().await? {
+            Some(GetOrComputeRequest {
+                request: Some(get_or_compute_request::Request::Lookup(lookup)),
+            }) => lookup,
+            _ => {
+                return Err(Status::new(
+                    Code::InvalidArgument,
+                    "Exchange must start with a lookup",
+                ));
+            }
+        };
+        lookup.key = self.key_normalization.apply(lookup.key);
+        if let Err(e) = validate_bucket_name_with(&lookup.bucket, &self.bucket_name_rules) {
+            return Err(Status::new(Code::InvalidArgument, format!("{}", e)));
+        }
+        if let Err(e) = validate_key(&lookup.key) {
+            return Err(Status::new(Code::InvalidArgument, format!("{}", e)));
+        }
+        if lookup.key == EPOCH_MARKER_KEY {
+            return Err(Status::new(
+                Code::InvalidArgument,
+                "Key is reserved for bucket epochs",
+            ));
+        }
+        let placement_key = match self.placement_key(&lookup.key, &lookup.routing_key) {
+            Ok(k) => k,
+            Err(e) => return Err(Status::new(Code::InvalidArgument, format!("{}", e))),
+        };
+        let epoch = self
+            .resolve_epoch(&lookup.bucket, 0)
+            .await
+            .map_err(|e| Status::new(e.code(), format!("{e}")))?;
+        let get_request = cache_server::GetRequest {
+            key: lookup.key.clone(),
+            bucket: lookup.bucket.clone(),
+            prefetch_keys: vec![],
+            epoch,
+        };
+
+        let mut waits = 0;
+        let lease = loop {
+            let replicas = self
+                .replica_nodes(&lookup.bucket, &placement_key)
+                .await
+                .map_err(|e| Status::new(e.code(), format!("{e}")))?;
+            let cached = self
+                .call_replicas(&replicas, |mut pooled_client| {
+                    let get_request = get_request.clone();
+                    async move { pooled_client.client().get(Request::new(get_request)).await }
+                })
+                .await
+                .map_err(|e| Status::new(e.code(), format!("{e}")))?;
+            // Nodes that predate `found` never set it, but stamp every value they return
+            if cached.found || cached.written_at_millis != 0 {
+                let _ = responses
+                    .send(Ok(GetOrComputeResponse {
+                        response: Some(get_or_compute_response::Response::Value(GetResponse {
+                            value: cached.value,
+                            successful: cached.successful,
+                            written_at_millis: cached.written_at_millis,
+                            served_from: cached.served_from,
+                            found: cached.found,
+                        })),
+                    }))
+                    .await;
+                return Ok(());
+            }
+            match self.compute_leases.claim(&lookup.bucket, &lookup.key) {
+                Claim::Leader(lease) => break lease,
+                Claim::Follower(mut released) => {
+                    if waits == MAX_LEASE_WAITS {
+                        return Err(Status::new(
+                            Code::Unavailable,
+                            "Timed out waiting for another caller to compute the key",
+                        ));
+                    }
+                    waits += 1;
+                    // Errors once the holder drops its lease, which is what we wait for
+                    let _ = tokio::time::timeout(self.compute_lease_timeout, released.changed())
+                        .await;
+                }
+            }
+        };
+
+        let _ = responses
+            .send(Ok(GetOrComputeResponse {
+                response: Some(get_or_compute_response::Response::Lease(ComputeLease {
+                    token: lease.token(),
+                    timeout_millis: self.compute_lease_timeout.as_millis() as u64,
+                })),
+            }))
+            .await;
+        let fill = match tokio::time::timeout(self.compute_lease_timeout, requests.message()).await {
+            Err(_) => {
+                return Err(Status::new(
+                    Code::DeadlineExceeded,
+                    "Lease expired before a value was sent",
+                ));
+            }
+            Ok(Err(status)) => return Err(status),
+            // The caller gave up; dropping the lease lets a waiter take over
+            Ok(Ok(None)) => return Ok(()),
+            Ok(Ok(Some(GetOrComputeRequest {
+                request: Some(get_or_compute_request::Request::Fill(fill)),
+            }))) => fill,
+            Ok(Ok(Some(_))) => {
+                return Err(Status::new(
+                    Code::InvalidArgument,
+                    "Lease holder must send a fill",
+                ));
+            }
+        };
+        if fill.lease_token != lease.token() {
+            return Err(Status::new(Code::InvalidArgument, "Unknown lease token"));
+        }
+        if let Err(e) = validate_value(&fill.value) {
+            return Err(Status::new(Code::InvalidArgument, format!("{}", e)));
+        }
+        let audit = self.audit("put", &caller, &lookup.bucket, &lookup.key);
+        let put_request = cache_server::PutRequest {
+            key: lookup.key,
+            bucket: lookup.bucket,
+            value: fill.value,
+            epoch,
+            durable: false,
+            idempotency_key: String::new(),
+            return_value: false,
+            ttl_seconds: 0,
+            replica: false,
+        };
+        let stored = self.put_key(&placement_key, put_request).await;
+        self.finish_audit(audit, stored.is_ok());
+        let stored = stored.map_err(|e| Status::new(e.code(), format!("{e}")))?;
+        // Release waiters only once the value is readable
+        drop(lease);
+        let _ = responses
+            .send(Ok(GetOrComputeResponse {
+                response: Some(get_or_compute_response::Response::Stored(PutResponse {
+                    successful: stored.successful,
+                    value: Vec::new(),
+                })),
+            }))
+            .await;
+        Ok(())
+    }
+
+    async fn set_node_writable(&self, address: &str, writable: bool) -> RouterResult<()> {
+        let address = self.node_address(address.to_string());
+        let mut node_conns = self.node_conns.edit().await;
+        let node_conn = node_conns.get_mut(&address).ok_or_else(|| {
+            RouterError::NodeNotFound(format!("No connection found for node: {}", address))
+        })?;
+        if node_conn.writable != writable {
+            info!(
+                "Node {} is now {}",
+                address,
+                if writable { "writable" } else { "full" }
+            );
+            node_conn.writable = writable;
+            node_conns.publish();
+        }
+        Ok(())
     }
 }
 
 #[tonic::async_trait]
 impl Router for RouterServiceImpl {
+    type GetOrComputeStream =
+        Pin<Box<dyn Stream<Item = Result<GetOrComputeResponse, Status>> + Send + 'static>>;
+    type WatchStream = Pin<Box<dyn Stream<Item = Result<WatchEvent, Status>> + Send + 'static>>;
+
     async fn join(
         &self,
         request: tonic::Request<JoinRequest>,
     ) -> std::result::Result<Response<JoinResponse>, Status> {
-        match self.rate_limiter.check_rate_limit().await {
-            Ok(_) => {}
-            Err(e) => {
-                return Err(Status::new(
-                    Code::ResourceExhausted,
-                    format!("Rate limit exceeded: {}", e),
-                ));
-            }
-        }
+        self.metrics.record_request("join");
+        self.check_rate_limit().await?;
 
         let request_ref = request.into_inner();
-        match self.join_node(request_ref.address).await {
+        match self.join_node(request_ref.address, request_ref.group).await {
             Ok(_) => Ok(Response::new(JoinResponse { successful: true })),
             Err(e) => {
                 error!("Failed to join node: {}", e);
-                Err(Status::new(Code::Internal, format!("{e}")))
+                self.metrics.record_request_error("join", e.code());
+                Err(Status::new(e.code(), format!("{e}")))
             }
         }
     }
@@ -131,76 +1021,122 @@ impl Router for RouterServiceImpl {
         &self,
         request: tonic::Request<LeaveRequest>,
     ) -> std::result::Result<Response<LeaveResponse>, Status> {
-        match self.rate_limiter.check_rate_limit().await {
-            Ok(_) => {}
+        self.metrics.record_request("leave");
+        self.check_rate_limit().await?;
+
+        let request_ref = request.into_inner();
+        match self
+            .leave_node(request_ref.address, request_ref.force)
+            .await
+        {
+            Ok(_) => Ok(Response::new(LeaveResponse { successful: true })),
             Err(e) => {
-                return Err(Status::new(
-                    Code::ResourceExhausted,
-                    format!("Rate limit exceeded: {}", e),
-                ));
+                self.metrics.record_request_error("leave", e.code());
+                Err(Status::new(e.code(), format!("{e}")))
             }
         }
-
-        let request_ref = request.into_inner();
-        self.leave_node(request_ref.address).await;
-        Ok(Response::new(LeaveResponse { successful: true }))
     }
 
     async fn get(
         &self,
         request: tonic::Request<GetRequest>,
     ) -> std::result::Result<Response<GetResponse>, Status> {
-        match self.rate_limiter.check_rate_limit().await {
+        self.metrics.record_request("get");
+        self.check_rate_limit().await?;
+
+        let mut request_ref = request.into_inner();
+        request_ref.key = self.key_normalization.apply(request_ref.key);
+        request_ref.prefetch_keys = request_ref
+            .prefetch_keys
+            .into_iter()
+            .map(|key| self.key_normalization.apply(key))
+            .collect();
+        match validate_bucket_name_with(&request_ref.bucket, &self.bucket_name_rules) {
             Ok(_) => {}
             Err(e) => {
-                return Err(Status::new(
-                    Code::ResourceExhausted,
-                    format!("Rate limit exceeded: {}", e),
-                ));
+                return Err(Status::new(Code::InvalidArgument, format!("{}", e)));
             }
         }
 
-        let request_ref = request.into_inner();
-        match validate_bucket_name(&request_ref.bucket) {
+        match validate_key(&request_ref.key) {
             Ok(_) => {}
             Err(e) => {
                 return Err(Status::new(Code::InvalidArgument, format!("{}", e)));
             }
         }
 
-        match validate_key(&request_ref.key) {
-            Ok(_) => {}
+        let placement_key = match self.placement_key(&request_ref.key, &request_ref.routing_key) {
+            Ok(k) => k,
             Err(e) => {
                 return Err(Status::new(Code::InvalidArgument, format!("{}", e)));
             }
+        };
+        let epoch = match self
+            .resolve_epoch(&request_ref.bucket, request_ref.staged_epoch)
+            .await
+        {
+            Ok(epoch) => epoch,
+            Err(e) => return Err(Status::new(e.code(), format!("{e}"))),
+        };
+
+        if let Some(result_cache) = &self.result_cache {
+            let cached = result_cache.get(&request_ref.bucket, epoch, &request_ref.key);
+            self.metrics.record_result_cache_lookup(cached.is_some());
+            if let Some(response) = cached {
+                return Ok(Response::new(response));
+            }
         }
 
-        match self.get_connection_for_key(&request_ref.key).await {
-            Ok(mut pooled_client) => {
-                match pooled_client
-                    .client()
-                    .get(Request::new(cache_server::GetRequest {
-                        key: request_ref.key,
-                        bucket: request_ref.bucket,
-                    }))
-                    .await
+        let replicas = match self
+            .replica_nodes(&request_ref.bucket, &placement_key)
+            .await
+        {
+            Ok(replicas) => replicas,
+            Err(e) => return Err(Status::new(e.code(), format!("{e}"))),
+        };
+
+        let cache_request = cache_server::GetRequest {
+            key: request_ref.key,
+            bucket: request_ref.bucket,
+            prefetch_keys: request_ref.prefetch_keys,
+            epoch,
+        };
+        match self
+            .call_replicas(&replicas, |mut pooled_client| {
+                let cache_request = cache_request.clone();
+                async move {
+                    pooled_client
+                        .client()
+                        .get(Request::new(cache_request))
+                        .await
+                }
+            })
+            .await
+        {
+            Ok(response) => {
+                let response = GetResponse {
+                    value: response.value,
+                    successful: response.successful,
+                    written_at_millis: response.written_at_millis,
+                    served_from: response.served_from,
+                    found: response.found,
+                };
+                if let Some(result_cache) = &self.result_cache
+                    && is_hit(&response)
                 {
-                    Ok(x) => {
-                        let response = x.into_inner();
-                        Ok(Response::new(GetResponse {
-                            value: response.value,
-                            successful: response.successful,
-                        }))
-                    }
-                    Err(e) => {
-                        error!("Failed to get key: {}", e);
-                        Err(Status::new(Code::Internal, format!("{e}")))
-                    }
+                    result_cache.insert(
+                        &cache_request.bucket,
+                        epoch,
+                        &cache_request.key,
+                        response.clone(),
+                    );
                 }
+                Ok(Response::new(response))
             }
             Err(e) => {
-                error!("Failed to get connection: {}", e);
-                Err(Status::new(Code::Internal, format!("{e}")))
+                error!("Failed to get key: {}", e);
+                self.metrics.record_request_error("get", e.code());
+                Err(Status::new(e.code(), format!("{e}")))
             }
         }
     }
@@ -209,18 +1145,14 @@ impl Router for RouterServiceImpl {
         &self,
         request: tonic::Request<PutRequest>,
     ) -> std::result::Result<Response<PutResponse>, Status> {
-        match self.rate_limiter.check_rate_limit().await {
-            Ok(_) => {}
-            Err(e) => {
-                return Err(Status::new(
-                    Code::ResourceExhausted,
-                    format!("Rate limit exceeded: {}", e),
-                ));
-            }
-        }
+        self.metrics.record_request("put");
+        self.check_writable("Put")?;
+        self.check_rate_limit().await?;
 
-        let request_ref = request.into_inner();
-        if let Err(e) = validate_bucket_name(&request_ref.bucket) {
+        let caller = Caller::of(&request);
+        let mut request_ref = request.into_inner();
+        request_ref.key = self.key_normalization.apply(request_ref.key);
+        if let Err(e) = validate_bucket_name_with(&request_ref.bucket, &self.bucket_name_rules) {
             return Err(Status::new(Code::InvalidArgument, format!("{}", e)));
         }
         if let Err(e) = validate_key(&request_ref.key) {
@@ -229,33 +1161,230 @@ impl Router for RouterServiceImpl {
         if let Err(e) = validate_value(&request_ref.value) {
             return Err(Status::new(Code::InvalidArgument, format!("{}", e)));
         }
+        if request_ref.key == EPOCH_MARKER_KEY {
+            return Err(Status::new(
+                Code::InvalidArgument,
+                "Key is reserved for bucket epochs",
+            ));
+        }
+        let placement_key = match self.placement_key(&request_ref.key, &request_ref.routing_key) {
+            Ok(k) => k,
+            Err(e) => return Err(Status::new(Code::InvalidArgument, format!("{}", e))),
+        };
+        let epoch = match self
+            .resolve_epoch(&request_ref.bucket, request_ref.staged_epoch)
+            .await
+        {
+            Ok(epoch) => epoch,
+            Err(e) => return Err(Status::new(e.code(), format!("{e}"))),
+        };
 
-        match self.get_connection_for_key(&request_ref.key).await {
-            Ok(mut pooled_client) => {
-                match pooled_client
-                    .client()
-                    .put(Request::new(cache_server::PutRequest {
-                        key: request_ref.key,
-                        bucket: request_ref.bucket,
-                        value: request_ref.value,
-                    }))
-                    .await
-                {
-                    Ok(x) => {
-                        let response = x.into_inner();
-                        Ok(Response::new(PutResponse {
-                            successful: response.successful,
-                        }))
-                    }
-                    Err(e) => {
-                        error!("Failed to put key: {}", e);
-                        Err(Status::new(Code::Internal, format!("{e}")))
+        let audit = self.audit("put", &caller, &request_ref.bucket, &request_ref.key);
+        let cache_request = cache_server::PutRequest {
+            key: request_ref.key,
+            bucket: request_ref.bucket,
+            value: request_ref.value,
+            epoch,
+            durable: request_ref.durable,
+            idempotency_key: request_ref.idempotency_key,
+            return_value: request_ref.return_value,
+            ttl_seconds: request_ref.ttl_seconds,
+            replica: false,
+        };
+        let result = self.put_key(&placement_key, cache_request).await;
+        self.finish_audit(audit, result.is_ok());
+        match result {
+            Ok(response) => Ok(Response::new(PutResponse {
+                successful: response.successful,
+                value: response.value,
+            })),
+            Err(e) => {
+                error!("Failed to put key: {}", e);
+                self.metrics.record_request_error("put", e.code());
+                Err(Status::new(e.code(), format!("{e}")))
+            }
+        }
+    }
+
+    async fn batch_get(
+        &self,
+        request: tonic::Request<BatchGetRequest>,
+    ) -> std::result::Result<Response<BatchGetResponse>, Status> {
+        self.metrics.record_request("batch_get");
+        self.check_rate_limit().await?;
+
+        let mut targets = Vec::new();
+        for mut request_ref in request.into_inner().requests {
+            request_ref.key = self.key_normalization.apply(request_ref.key);
+            let (_, epoch) = self
+                .key_owner(
+                    &request_ref.bucket,
+                    &request_ref.key,
+                    &request_ref.routing_key,
+                    request_ref.staged_epoch,
+                )
+                .await?;
+            let placement_key = self
+                .placement_key(&request_ref.key, &request_ref.routing_key)
+                .map_err(|e| Status::new(Code::InvalidArgument, format!("{}", e)))?;
+            let replicas = self
+                .replica_nodes(&request_ref.bucket, &placement_key)
+                .await
+                .map_err(|e| Status::new(e.code(), format!("{e}")))?;
+            let cache_request = cache_server::GetRequest {
+                key: request_ref.key,
+                bucket: request_ref.bucket,
+                prefetch_keys: request_ref
+                    .prefetch_keys
+                    .into_iter()
+                    .map(|key| self.key_normalization.apply(key))
+                    .collect(),
+                epoch,
+            };
+            targets.push((replicas, cache_request));
+        }
+
+        match self.batch_get_from_replicas(&targets).await {
+            Ok(responses) => Ok(Response::new(BatchGetResponse {
+                responses: responses
+                    .into_iter()
+                    .map(|response| GetResponse {
+                        value: response.value,
+                        successful: response.successful,
+                        written_at_millis: response.written_at_millis,
+                        served_from: response.served_from,
+                        found: response.found,
+                    })
+                    .collect(),
+            })),
+            Err(e) => {
+                error!("Failed to batch get keys: {}", e);
+                self.metrics.record_request_error("batch_get", e.code());
+                Err(Status::new(e.code(), format!("{e}")))
+            }
+        }
+    }
+
+    async fn batch_put(
+        &self,
+        request: tonic::Request<BatchPutRequest>,
+    ) -> std::result::Result<Response<BatchPutResponse>, Status> {
+        self.metrics.record_request("batch_put");
+        self.check_writable("BatchPut")?;
+        self.check_rate_limit().await?;
+
+        let caller = Caller::of(&request);
+        let mut targets = Vec::new();
+        // Keys written away from their full owner, whose copies must be dropped
+        let mut displaced = Vec::new();
+        // Copies for replicas beyond each key's owner
+        let mut copies = Vec::new();
+        let mut audits = Vec::new();
+        for mut request_ref in request.into_inner().requests {
+            request_ref.key = self.key_normalization.apply(request_ref.key);
+            if let Err(e) = validate_value(&request_ref.value) {
+                return Err(Status::new(Code::InvalidArgument, format!("{}", e)));
+            }
+            let (owner, epoch) = self
+                .key_owner(
+                    &request_ref.bucket,
+                    &request_ref.key,
+                    &request_ref.routing_key,
+                    request_ref.staged_epoch,
+                )
+                .await?;
+            let placement_key = self
+                .placement_key(&request_ref.key, &request_ref.routing_key)
+                .map_err(|e| Status::new(Code::InvalidArgument, format!("{}", e)))?;
+            let node = self
+                .write_node_for_key(&request_ref.bucket, &placement_key, &owner)
+                .await;
+            let replicas = self
+                .replica_nodes(&request_ref.bucket, &placement_key)
+                .await
+                .map_err(|e| Status::new(e.code(), format!("{e}")))?;
+            if node != owner {
+                displaced.push((
+                    owner,
+                    request_ref.key.clone(),
+                    request_ref.bucket.clone(),
+                    epoch,
+                ));
+            }
+            audits.push(self.audit("put", &caller, &request_ref.bucket, &request_ref.key));
+            let cache_request = cache_server::PutRequest {
+                key: request_ref.key,
+                bucket: request_ref.bucket,
+                value: request_ref.value,
+                epoch,
+                durable: request_ref.durable,
+                idempotency_key: request_ref.idempotency_key,
+                return_value: request_ref.return_value,
+                ttl_seconds: request_ref.ttl_seconds,
+                replica: false,
+            };
+            for replica in replicas.into_iter().skip(1) {
+                if replica != node {
+                    copies.push((replica, cache_request.clone()));
+                }
+            }
+            targets.push((node, cache_request));
+        }
+
+        let written: Vec<_> = targets
+            .iter()
+            .map(|(_, put)| (put.bucket.clone(), put.epoch, put.key.clone()))
+            .collect();
+        let len = targets.len();
+        let answered = try_join_all(split_by_node(targets).into_iter().map(|batch| async move {
+            let cache_request = cache_server::BatchPutRequest {
+                requests: batch.items,
+            };
+            let response = self
+                .call_node(&batch.node, |mut pooled_client| {
+                    let cache_request = cache_request.clone();
+                    async move {
+                        pooled_client
+                            .client()
+                            .batch_put(Request::new(cache_request))
+                            .await
                     }
+                })
+                .await?;
+            Ok::<_, RouterError>((batch.indexes, response.responses))
+        }))
+        .await;
+        // Even a failed batch may have reached its nodes
+        for (bucket, epoch, key) in &written {
+            self.invalidate_result(bucket, *epoch, key);
+        }
+        let mut result = answered.and_then(|answered| reassemble(len, answered));
+        if result.is_ok() {
+            self.put_copies(copies).await;
+            for (owner, key, bucket, epoch) in displaced {
+                if let Err(e) = self.drop_owner_copy(&owner, key, bucket, epoch).await {
+                    result = Err(e);
+                    break;
                 }
             }
+        }
+        for audit in audits {
+            self.finish_audit(audit, result.is_ok());
+        }
+        match result {
+            Ok(responses) => Ok(Response::new(BatchPutResponse {
+                responses: responses
+                    .into_iter()
+                    .map(|response| PutResponse {
+                        successful: response.successful,
+                        value: response.value,
+                    })
+                    .collect(),
+            })),
             Err(e) => {
-                error!("Failed to get connection: {}", e);
-                Err(Status::new(Code::Internal, format!("{e}")))
+                error!("Failed to batch put keys: {}", e);
+                self.metrics.record_request_error("batch_put", e.code());
+                Err(Status::new(e.code(), format!("{e}")))
             }
         }
     }
@@ -264,50 +1393,1029 @@ impl Router for RouterServiceImpl {
         &self,
         request: tonic::Request<DeleteRequest>,
     ) -> std::result::Result<Response<DeleteResponse>, Status> {
-        match self.rate_limiter.check_rate_limit().await {
-            Ok(_) => {}
+        self.metrics.record_request("delete");
+        self.check_writable("Delete")?;
+        self.check_rate_limit().await?;
+
+        let caller = Caller::of(&request);
+        let mut request_ref = request.into_inner();
+        request_ref.key = self.key_normalization.apply(request_ref.key);
+        if let Err(e) = validate_bucket_name_with(&request_ref.bucket, &self.bucket_name_rules) {
+            return Err(Status::new(Code::InvalidArgument, format!("{}", e)));
+        }
+        if let Err(e) = validate_key(&request_ref.key) {
+            return Err(Status::new(Code::InvalidArgument, format!("{}", e)));
+        }
+        if request_ref.key == EPOCH_MARKER_KEY {
+            return Err(Status::new(
+                Code::InvalidArgument,
+                "Key is reserved for bucket epochs",
+            ));
+        }
+        let placement_key = match self.placement_key(&request_ref.key, &request_ref.routing_key) {
+            Ok(k) => k,
+            Err(e) => return Err(Status::new(Code::InvalidArgument, format!("{}", e))),
+        };
+        let epoch = match self
+            .resolve_epoch(&request_ref.bucket, request_ref.staged_epoch)
+            .await
+        {
+            Ok(epoch) => epoch,
+            Err(e) => return Err(Status::new(e.code(), format!("{e}"))),
+        };
+
+        let replicas = match self
+            .replica_nodes(&request_ref.bucket, &placement_key)
+            .await
+        {
+            Ok(replicas) => replicas,
+            Err(e) => return Err(Status::new(e.code(), format!("{e}"))),
+        };
+
+        let audit = self.audit("delete", &caller, &request_ref.bucket, &request_ref.key);
+        let cache_request = cache_server::DeleteRequest {
+            key: request_ref.key,
+            bucket: request_ref.bucket,
+            epoch,
+            idempotency_key: request_ref.idempotency_key,
+        };
+        // Every replica drops its copy
+        let result = try_join_all(replicas.iter().map(|node| {
+            self.call_node(node, |mut pooled_client| {
+                let cache_request = cache_request.clone();
+                async move {
+                    pooled_client
+                        .client()
+                        .delete(Request::new(cache_request))
+                        .await
+                }
+            })
+        }))
+        .await
+        .map(|responses| DeleteResponse {
+            successful: responses.iter().all(|response| response.successful),
+            existed: responses.iter().any(|response| response.existed),
+        });
+        self.invalidate_result(
+            &cache_request.bucket,
+            cache_request.epoch,
+            &cache_request.key,
+        );
+        self.finish_audit(audit, result.is_ok());
+        match result {
+            Ok(response) => Ok(Response::new(response)),
             Err(e) => {
-                return Err(Status::new(
-                    Code::ResourceExhausted,
-                    format!("Rate limit exceeded: {}", e),
-                ));
+                error!("Failed to delete key: {}", e);
+                self.metrics.record_request_error("delete", e.code());
+                Err(Status::new(e.code(), format!("{e}")))
             }
         }
+    }
+
+    async fn exists(
+        &self,
+        request: tonic::Request<ExistsRequest>,
+    ) -> std::result::Result<Response<ExistsResponse>, Status> {
+        self.metrics.record_request("exists");
+        self.check_rate_limit().await?;
+
+        let mut request_ref = request.into_inner();
+        request_ref.key = self.key_normalization.apply(request_ref.key);
+        let (_, epoch) = self
+            .key_owner(
+                &request_ref.bucket,
+                &request_ref.key,
+                &request_ref.routing_key,
+                request_ref.staged_epoch,
+            )
+            .await?;
+        let placement_key = self
+            .placement_key(&request_ref.key, &request_ref.routing_key)
+            .map_err(|e| Status::new(Code::InvalidArgument, format!("{}", e)))?;
+        let replicas = self
+            .replica_nodes(&request_ref.bucket, &placement_key)
+            .await
+            .map_err(|e| Status::new(e.code(), format!("{e}")))?;
+
+        let cache_request = cache_server::ExistsRequest {
+            key: request_ref.key,
+            bucket: request_ref.bucket,
+            epoch,
+        };
+        match self
+            .call_replicas(&replicas, |mut pooled_client| {
+                let cache_request = cache_request.clone();
+                async move {
+                    pooled_client
+                        .client()
+                        .exists(Request::new(cache_request))
+                        .await
+                }
+            })
+            .await
+        {
+            Ok(response) => Ok(Response::new(ExistsResponse {
+                exists: response.exists,
+            })),
+            Err(e) => {
+                error!("Failed to check key: {}", e);
+                self.metrics.record_request_error("exists", e.code());
+                Err(Status::new(e.code(), format!("{e}")))
+            }
+        }
+    }
+
+    async fn begin_stage(
+        &self,
+        request: tonic::Request<BeginStageRequest>,
+    ) -> std::result::Result<Response<BeginStageResponse>, Status> {
+        self.metrics.record_request("begin_stage");
+        self.check_writable("BeginStage")?;
+        self.check_rate_limit().await?;
 
         let request_ref = request.into_inner();
-        if let Err(e) = validate_bucket_name(&request_ref.bucket) {
+        if let Err(e) = validate_bucket_name_with(&request_ref.bucket, &self.bucket_name_rules) {
             return Err(Status::new(Code::InvalidArgument, format!("{}", e)));
         }
-        if let Err(e) = validate_key(&request_ref.key) {
+
+        match RouterServiceImpl::begin_stage(self, &request_ref.bucket).await {
+            Ok(epoch) => Ok(Response::new(BeginStageResponse { epoch })),
+            Err(e) => {
+                error!("Failed to begin stage: {}", e);
+                self.metrics.record_request_error("begin_stage", e.code());
+                Err(Status::new(e.code(), format!("{e}")))
+            }
+        }
+    }
+
+    async fn promote_bucket(
+        &self,
+        request: tonic::Request<PromoteBucketRequest>,
+    ) -> std::result::Result<Response<PromoteBucketResponse>, Status> {
+        self.metrics.record_request("promote_bucket");
+        self.check_writable("PromoteBucket")?;
+        self.check_rate_limit().await?;
+
+        let request_ref = request.into_inner();
+        if let Err(e) = validate_bucket_name_with(&request_ref.bucket, &self.bucket_name_rules) {
             return Err(Status::new(Code::InvalidArgument, format!("{}", e)));
         }
 
-        match self.get_connection_for_key(&request_ref.key).await {
-            Ok(mut pooled_client) => {
-                match pooled_client
-                    .client()
-                    .delete(Request::new(cache_server::DeleteRequest {
-                        key: request_ref.key,
-                        bucket: request_ref.bucket,
-                    }))
-                    .await
-                {
-                    Ok(x) => {
-                        let response = x.into_inner();
-                        Ok(Response::new(DeleteResponse {
-                            successful: response.successful,
-                        }))
-                    }
-                    Err(e) => {
-                        error!("Failed to delete key: {}", e);
-                        Err(Status::new(Code::Internal, format!("{e}")))
-                    }
-                }
+        match RouterServiceImpl::promote_bucket(self, &request_ref.bucket, request_ref.epoch).await
+        {
+            Ok(()) => Ok(Response::new(PromoteBucketResponse { successful: true })),
+            Err(e) => {
+                error!("Failed to promote bucket: {}", e);
+                self.metrics
+                    .record_request_error("promote_bucket", e.code());
+                Err(Status::new(e.code(), format!("{e}")))
+            }
+        }
+    }
+
+    async fn set_node_writable(
+        &self,
+        request: tonic::Request<SetNodeWritableRequest>,
+    ) -> std::result::Result<Response<SetNodeWritableResponse>, Status> {
+        self.metrics.record_request("set_node_writable");
+        let request_ref = request.into_inner();
+        match RouterServiceImpl::set_node_writable(self, &request_ref.address, request_ref.writable)
+            .await
+        {
+            Ok(()) => Ok(Response::new(SetNodeWritableResponse { successful: true })),
+            Err(e) => {
+                error!("Failed to set node writable state: {}", e);
+                self.metrics
+                    .record_request_error("set_node_writable", Code::NotFound);
+                Err(Status::new(Code::NotFound, format!("{e}")))
+            }
+        }
+    }
+
+    async fn list_nodes(
+        &self,
+        _request: tonic::Request<ListNodesRequest>,
+    ) -> std::result::Result<Response<ListNodesResponse>, Status> {
+        self.metrics.record_request("list_nodes");
+        let mut nodes: Vec<NodeInfo> = self
+            .node_conns
+            .load()
+            .iter()
+            .map(|(address, conn)| NodeInfo {
+                address: address.clone(),
+                writable: conn.writable,
+                group: conn.group.clone(),
+            })
+            .collect();
+        nodes.sort_by(|a, b| a.address.cmp(&b.address));
+        Ok(Response::new(ListNodesResponse { nodes }))
+    }
+
+    async fn get_or_compute(
+        &self,
+        request: tonic::Request<Streaming<GetOrComputeRequest>>,
+    ) -> std::result::Result<Response<Self::GetOrComputeStream>, Status> {
+        self.metrics.record_request("get_or_compute");
+        // Misses are filled with the caller's value, so the whole call writes
+        self.check_writable("GetOrCompute")?;
+        self.check_rate_limit().await?;
+
+        let caller = Caller::of(&request);
+        let requests = request.into_inner();
+        let (tx, rx) = mpsc::channel(1);
+        let service = self.clone();
+        tokio::spawn(async move {
+            if let Err(status) = service.get_or_compute_session(requests, caller, &tx).await {
+                error!("Failed to get or compute key: {}", status.message());
+                service
+                    .metrics
+                    .record_request_error("get_or_compute", status.code());
+                let _ = tx.send(Err(status)).await;
+            }
+        });
+        let responses = futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|response| (response, rx))
+        });
+        Ok(Response::new(Box::pin(responses)))
+    }
+
+    async fn pin(
+        &self,
+        request: tonic::Request<PinRequest>,
+    ) -> std::result::Result<Response<PinResponse>, Status> {
+        self.metrics.record_request("pin");
+        self.check_writable("Pin")?;
+        let mut request_ref = request.into_inner();
+        request_ref.key = self.key_normalization.apply(request_ref.key);
+        let (node, epoch) = self
+            .key_owner(
+                &request_ref.bucket,
+                &request_ref.key,
+                &request_ref.routing_key,
+                request_ref.staged_epoch,
+            )
+            .await?;
+
+        let cache_request = cache_server::PinRequest {
+            key: request_ref.key,
+            bucket: request_ref.bucket,
+            epoch,
+        };
+        match self
+            .call_node(&node, |mut pooled_client| {
+                let cache_request = cache_request.clone();
+                async move { pooled_client.client().pin(Request::new(cache_request)).await }
+            })
+            .await
+        {
+            Ok(response) => Ok(Response::new(PinResponse {
+                successful: response.successful,
+                resident: response.resident,
+            })),
+            Err(e) => {
+                error!("Failed to pin key: {}", e);
+                self.metrics.record_request_error("pin", e.code());
+                Err(Status::new(e.code(), format!("{e}")))
             }
+        }
+    }
+
+    async fn unpin(
+        &self,
+        request: tonic::Request<UnpinRequest>,
+    ) -> std::result::Result<Response<UnpinResponse>, Status> {
+        self.metrics.record_request("unpin");
+        self.check_writable("Unpin")?;
+        let mut request_ref = request.into_inner();
+        request_ref.key = self.key_normalization.apply(request_ref.key);
+        let (node, epoch) = self
+            .key_owner(
+                &request_ref.bucket,
+                &request_ref.key,
+                &request_ref.routing_key,
+                request_ref.staged_epoch,
+            )
+            .await?;
+
+        let cache_request = cache_server::UnpinRequest {
+            key: request_ref.key,
+            bucket: request_ref.bucket,
+            epoch,
+        };
+        match self
+            .call_node(&node, |mut pooled_client| {
+                let cache_request = cache_request.clone();
+                async move {
+                    pooled_client
+                        .client()
+                        .unpin(Request::new(cache_request))
+                        .await
+                }
+            })
+            .await
+        {
+            Ok(response) => Ok(Response::new(UnpinResponse {
+                successful: response.successful,
+                was_pinned: response.was_pinned,
+            })),
             Err(e) => {
-                error!("Failed to get connection: {}", e);
-                Err(Status::new(Code::Internal, format!("{e}")))
+                error!("Failed to unpin key: {}", e);
+                self.metrics.record_request_error("unpin", e.code());
+                Err(Status::new(e.code(), format!("{e}")))
+            }
+        }
+    }
+
+    // Relays the owning node's stream; the connection is returned to the pool
+    // once the stream is open, so long-lived watches hold no in-flight slot.
+    async fn watch(
+        &self,
+        request: tonic::Request<WatchRequest>,
+    ) -> std::result::Result<Response<Self::WatchStream>, Status> {
+        self.metrics.record_request("watch");
+        let mut request_ref = request.into_inner();
+        request_ref.key = self.key_normalization.apply(request_ref.key);
+        let (node, epoch) = self
+            .key_owner(
+                &request_ref.bucket,
+                &request_ref.key,
+                &request_ref.routing_key,
+                request_ref.staged_epoch,
+            )
+            .await?;
+
+        let cache_request = cache_server::WatchRequest {
+            key: request_ref.key,
+            bucket: request_ref.bucket,
+            epoch,
+        };
+        let changes = self
+            .call_node(&node, |mut pooled_client| {
+                let cache_request = cache_request.clone();
+                async move {
+                    pooled_client
+                        .client()
+                        .watch(Request::new(cache_request))
+                        .await
+                }
+            })
+            .await
+            .map_err(|e| {
+                error!("Failed to watch key: {}", e);
+                self.metrics.record_request_error("watch", e.code());
+                Status::new(e.code(), format!("{e}"))
+            })?;
+        let events = futures::TryStreamExt::map_ok(changes, |event| WatchEvent {
+            deleted: event.deleted,
+            value: event.value,
+            written_at_millis: event.written_at_millis,
+        });
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+// Only found values are cached. Nodes that predate `served_from` leave it
+// unspecified, and their misses cannot be told apart from hits.
+fn is_hit(response: &GetResponse) -> bool {
+    matches!(
+        ServedFrom::try_from(response.served_from),
+        Ok(ServedFrom::Memory | ServedFrom::Disk | ServedFrom::Cloud)
+    )
+}
+
+// The requests of a batch bound for one node, in batch order, with the
+// position of each in the batch.
+struct NodeBatch<T> {
+    node: String,
+    indexes: Vec<usize>,
+    items: Vec<T>,
+}
+
+fn split_by_node<T>(targets: Vec<(String, T)>) -> Vec<NodeBatch<T>> {
+    let mut batches: Vec<NodeBatch<T>> = Vec::new();
+    for (index, (node, item)) in targets.into_iter().enumerate() {
+        match batches.iter_mut().find(|batch| batch.node == node) {
+            Some(batch) => {
+                batch.indexes.push(index);
+                batch.items.push(item);
+            }
+            None => batches.push(NodeBatch {
+                node,
+                indexes: vec![index],
+                items: vec![item],
+            }),
+        }
+    }
+    batches
+}
+
+// Puts each node's responses back at the positions of its requests.
+fn reassemble<T>(len: usize, answered: Vec<(Vec<usize>, Vec<T>)>) -> RouterResult<Vec<T>> {
+    let mut responses: Vec<Option<T>> = (0..len).map(|_| None).collect();
+    for (indexes, node_responses) in answered {
+        if indexes.len() != node_responses.len() {
+            return Err(RouterError::InternalError(format!(
+                "Cache node answered {} of {} batched requests",
+                node_responses.len(),
+                indexes.len()
+            )));
+        }
+        for (index, response) in indexes.into_iter().zip(node_responses) {
+            responses[index] = Some(response);
+        }
+    }
+    responses
+        .into_iter()
+        .map(|response| {
+            response.ok_or_else(|| {
+                RouterError::InternalError("Batched request went unanswered".to_string())
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn test_service() -> RouterServiceImpl {
+    let metrics = Arc::new(Metrics::new().unwrap());
+    RouterServiceImpl {
+        ring: Arc::new(Ring::default()),
+        bucket_node_groups: HashMap::new(),
+        node_conns: Arc::new(NodeConns::default()),
+        rate_limiter: Arc::new(RateLimiterMiddleware::new(1000)),
+        retry_budget: Arc::new(RetryBudget::new(
+            10,
+            0.1,
+            metrics.retry_budget_tokens.clone(),
+        )),
+        metrics,
+        routing_keys_enabled: false,
+        routing_keys_optional: false,
+        membership_permits: Arc::new(Semaphore::new(4)),
+        max_nodes: usize::MAX,
+        max_message_bytes: 8 * 1024 * 1024,
+        grpc_compression: false,
+        max_in_flight_per_node: Semaphore::MAX_PERMITS,
+        pool_wait_timeout: Duration::from_millis(100),
+        pool_health_check: HealthCheck {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_millis(200),
+        },
+        bucket_epochs: Arc::new(EpochTable::default()),
+        max_retries: 0,
+        compute_leases: Arc::new(LeaseTable::default()),
+        compute_lease_timeout: Duration::from_secs(1),
+        bucket_name_rules: BucketNameRules::default(),
+        key_normalization: KeyNormalization::default(),
+        audit_log: None,
+        protect_last_node: false,
+        canonicalize_node_addresses: true,
+        read_only: false,
+        result_cache: None,
+        replication_factor: 1,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_join_and_leave_keep_ring_and_pools_consistent() {
+    let service = test_service();
+    let address = "http://cache-1:50051";
+    for round in 0..20 {
+        let mut changes = Vec::new();
+        for i in 0..8 {
+            let service = service.clone();
+            changes.push(tokio::spawn(async move {
+                // Spelled two ways, as a restarting node and an operator might
+                let spelling = if i % 3 == 0 {
+                    "HTTP://Cache-1:50051/"
+                } else {
+                    address
+                };
+                if (round + i) % 2 == 0 {
+                    service
+                        .join_node(spelling.to_string(), DEFAULT_GROUP.to_string())
+                        .await
+                } else {
+                    service.leave_node(spelling.to_string(), true).await
+                }
+            }));
+        }
+        for change in changes {
+            change.await.unwrap().unwrap();
+        }
+
+        let on_ring = service
+            .ring
+            .snapshot()
+            .group(DEFAULT_GROUP)
+            .and_then(|ring| ring.get(b"key").map(|node| node.host().to_string()));
+        let node_conns = service.node_conns.load();
+        match on_ring {
+            Some(host) => {
+                assert_eq!(host, address);
+                assert!(node_conns.contains_key(address));
             }
+            None => assert!(node_conns.is_empty()),
+        }
+        assert!(node_conns.len() <= 1);
+    }
+}
+
+#[tokio::test]
+async fn test_batch_is_split_by_node_and_reassembled_in_order() {
+    let service = test_service();
+    for address in ["http://cache-1:50051", "http://cache-2:50051"] {
+        service
+            .join_node(address.to_string(), DEFAULT_GROUP.to_string())
+            .await
+            .unwrap();
+    }
+    let mut targets = Vec::new();
+    for i in 0..16 {
+        let key = format!("key-{i}");
+        let node = service.node_for_key("users", key.as_bytes()).await.unwrap();
+        targets.push((node, key));
+    }
+
+    let batches = split_by_node(targets.clone());
+    assert_eq!(batches.len(), 2);
+    for batch in &batches {
+        for (index, key) in batch.indexes.iter().zip(&batch.items) {
+            assert_eq!(targets[*index], (batch.node.clone(), key.clone()));
+        }
+        assert!(batch.indexes.is_sorted());
+    }
+
+    // Each node answers its own keys, and the answers come back in batch order
+    let answered = batches
+        .into_iter()
+        .map(|batch| {
+            let responses = batch.items.iter().map(|key| key.to_uppercase()).collect();
+            (batch.indexes, responses)
+        })
+        .collect();
+    let expected: Vec<_> = targets.iter().map(|(_, key)| key.to_uppercase()).collect();
+    assert_eq!(reassemble(targets.len(), answered).unwrap(), expected);
+
+    // A node that answers short fails the batch
+    let short = vec![(vec![0, 1], vec!["only".to_string()])];
+    assert!(reassemble(2, short).is_err());
+}
+
+#[tokio::test]
+async fn test_join_beyond_max_nodes_is_rejected_but_rejoin_succeeds() {
+    let service = RouterServiceImpl {
+        max_nodes: 1,
+        ..test_service()
+    };
+    let address = "http://cache-1:50051";
+    service
+        .join_node(address.to_string(), DEFAULT_GROUP.to_string())
+        .await
+        .unwrap();
+    service.set_node_writable(address, false).await.unwrap();
+
+    // Joining again is not counted against the limit and makes the node writable
+    service
+        .join_node(address.to_string(), DEFAULT_GROUP.to_string())
+        .await
+        .unwrap();
+    assert!(service.node_conns.load()[address].writable);
+
+    let rejected = service
+        .join_node(
+            "http://cache-2:50051".to_string(),
+            DEFAULT_GROUP.to_string(),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(rejected.code(), Code::ResourceExhausted);
+    assert_eq!(service.node_conns.load().len(), 1);
+    assert_eq!(
+        service.node_for_key("users", b"key").await.unwrap(),
+        address
+    );
+}
+
+#[tokio::test]
+async fn test_leave_keeps_the_last_node_unless_forced() {
+    let service = RouterServiceImpl {
+        protect_last_node: true,
+        ..test_service()
+    };
+    let address = "http://cache-1:50051";
+    service
+        .join_node(address.to_string(), DEFAULT_GROUP.to_string())
+        .await
+        .unwrap();
+
+    // Leaving a node that never joined changes nothing, even with one node left
+    service
+        .leave_node("http://cache-2:50051".to_string(), false)
+        .await
+        .unwrap();
+    assert!(service.node_conns.load().contains_key(address));
+
+    let refused = service
+        .leave_node(address.to_string(), false)
+        .await
+        .unwrap_err();
+    assert_eq!(refused.code(), Code::FailedPrecondition);
+    assert!(service.node_conns.load().contains_key(address));
+
+    service.leave_node(address.to_string(), true).await.unwrap();
+    assert!(service.node_conns.load().is_empty());
+    assert!(service.node_for_key("users", b"key").await.is_err());
+}
+
+#[tokio::test]
+async fn test_membership_changes_queue_and_move_only_the_leaving_nodes_keys() {
+    let service = RouterServiceImpl {
+        membership_permits: Arc::new(Semaphore::new(1)),
+        ..test_service()
+    };
+    let addresses = [
+        "http://cache-1:50051",
+        "http://cache-2:50051",
+        "http://cache-3:50051",
+    ];
+
+    // A join waits while another membership change holds the only permit
+    let held = service
+        .membership_permits
+        .clone()
+        .try_acquire_owned()
+        .unwrap();
+    let queued = tokio::spawn({
+        let service = service.clone();
+        async move {
+            service
+                .join_node(addresses[0].to_string(), DEFAULT_GROUP.to_string())
+                .await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(service.node_conns.load().is_empty());
+    drop(held);
+    queued.await.unwrap().unwrap();
+    for address in &addresses[1..] {
+        service
+            .join_node(address.to_string(), DEFAULT_GROUP.to_string())
+            .await
+            .unwrap();
+    }
+
+    let keys: Vec<String> = (0..64).map(|i| format!("key-{i}")).collect();
+    let mut owners = Vec::new();
+    for key in &keys {
+        owners.push(service.node_for_key("users", key.as_bytes()).await.unwrap());
+    }
+    let used: std::collections::HashSet<_> = owners.iter().collect();
+    assert_eq!(used.len(), addresses.len());
+
+    service
+        .leave_node(addresses[1].to_string(), false)
+        .await
+        .unwrap();
+    for (key, owner) in keys.iter().zip(&owners) {
+        let node = service.node_for_key("users", key.as_bytes()).await.unwrap();
+        if owner == addresses[1] {
+            assert_ne!(node, addresses[1]);
+        } else {
+            assert_eq!(&node, owner);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_saturated_node_fails_fast() {
+    let service = RouterServiceImpl {
+        max_in_flight_per_node: 1,
+        ..test_service()
+    };
+    let address = "http://cache-1:50051";
+    service
+        .join_node(address.to_string(), DEFAULT_GROUP.to_string())
+        .await
+        .unwrap();
+    let in_flight_gauge = service.metrics.node_in_flight.with_label_values(&[address]);
+
+    // Stands in for a request still waiting on the node
+    let semaphore = service.node_conns.load()[address].in_flight.clone();
+    let in_flight = InFlight::new(
+        semaphore.clone().try_acquire_owned().unwrap(),
+        in_flight_gauge.clone(),
+    );
+    assert_eq!(in_flight_gauge.get(), 1);
+
+    let Err(saturated) = service.get_connection(address).await else {
+        panic!("a saturated node should not hand out a connection");
+    };
+    assert_eq!(saturated.code(), Code::ResourceExhausted);
+    let saturations = service.metrics.node_saturated.with_label_values(&[address]);
+    assert_eq!(saturations.get(), 1);
+
+    drop(in_flight);
+    assert_eq!(in_flight_gauge.get(), 0);
+    assert_eq!(semaphore.available_permits(), 1);
+}
+
+#[tokio::test]
+async fn test_exhausted_pool_fails_after_the_wait_timeout() {
+    let service = RouterServiceImpl {
+        pool_wait_timeout: Duration::from_millis(50),
+        ..test_service()
+    };
+    let address = "http://cache-1:50051";
+    service
+        .join_node(address.to_string(), DEFAULT_GROUP.to_string())
+        .await
+        .unwrap();
+    // A pool with no connections to give stays exhausted
+    let mut node_conns = service.node_conns.edit().await;
+    node_conns.get_mut(address).unwrap().pool = create_pool(
+        address.to_string(),
+        0,
+        service.max_message_bytes,
+        false,
+        service.pool_health_check,
+        service.pool_wait_timeout,
+    )
+    .unwrap();
+    node_conns.publish();
+    drop(node_conns);
+
+    let started = Instant::now();
+    let Err(exhausted) = service.get_connection(address).await else {
+        panic!("an exhausted pool should not hand out a connection");
+    };
+    assert!(matches!(exhausted, RouterError::PoolExhausted(_)));
+    assert_eq!(exhausted.code(), Code::ResourceExhausted);
+    assert!(started.elapsed() >= service.pool_wait_timeout);
+}
+
+// An in-process cache node keeping values in a map, with the replica flag of
+// the put that wrote each. A node that is down fails every call.
+#[cfg(test)]
+type FakeValues = HashMap<Vec<u8>, (Vec<u8>, bool)>;
+
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct FakeNode {
+    values: Arc<std::sync::Mutex<FakeValues>>,
+    down: Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[cfg(test)]
+impl FakeNode {
+    fn is_down(&self) -> bool {
+        self.down.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    fn get_one(&self, request: cache_server::GetRequest) -> cache_server::GetResponse {
+        match self.values.lock().unwrap().get(&request.key) {
+            Some((value, _)) => cache_server::GetResponse {
+                successful: true,
+                value: value.clone(),
+                written_at_millis: 1,
+                served_from: cache_server::ServedFrom::Memory as i32,
+                found: true,
+            },
+            None => cache_server::GetResponse {
+                successful: true,
+                served_from: cache_server::ServedFrom::Miss as i32,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn put_one(&self, request: cache_server::PutRequest) -> cache_server::PutResponse {
+        self.values
+            .lock()
+            .unwrap()
+            .insert(request.key, (request.value, request.replica));
+        cache_server::PutResponse {
+            successful: true,
+            value: Vec::new(),
+        }
+    }
+
+    async fn serve(&self) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(cache_server::cache_server::CacheServer::new(self.clone()))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        format!("http://{addr}")
+    }
+}
+
+#[cfg(test)]
+#[tonic::async_trait]
+impl cache_server::cache_server::Cache for FakeNode {
+    type WatchStream =
+        futures::stream::BoxStream<'static, Result<cache_server::WatchEvent, Status>>;
+
+    async fn get(
+        &self,
+        request: Request<cache_server::GetRequest>,
+    ) -> Result<Response<cache_server::GetResponse>, Status> {
+        if self.is_down() {
+            return Err(Status::unavailable("node is down"));
+        }
+        Ok(Response::new(self.get_one(request.into_inner())))
+    }
+
+    async fn put(
+        &self,
+        request: Request<cache_server::PutRequest>,
+    ) -> Result<Response<cache_server::PutResponse>, Status> {
+        if self.is_down() {
+            return Err(Status::unavailable("node is down"));
+        }
+        Ok(Response::new(self.put_one(request.into_inner())))
+    }
+
+    async fn batch_get(
+        &self,
+        request: Request<cache_server::BatchGetRequest>,
+    ) -> Result<Response<cache_server::BatchGetResponse>, Status> {
+        if self.is_down() {
+            return Err(Status::unavailable("node is down"));
         }
+        let responses = request
+            .into_inner()
+            .requests
+            .into_iter()
+            .map(|get| self.get_one(get))
+            .collect();
+        Ok(Response::new(cache_server::BatchGetResponse { responses }))
+    }
+
+    async fn batch_put(
+        &self,
+        request: Request<cache_server::BatchPutRequest>,
+    ) -> Result<Response<cache_server::BatchPutResponse>, Status> {
+        if self.is_down() {
+            return Err(Status::unavailable("node is down"));
+        }
+        let responses = request
+            .into_inner()
+            .requests
+            .into_iter()
+            .map(|put| self.put_one(put))
+            .collect();
+        Ok(Response::new(cache_server::BatchPutResponse { responses }))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<cache_server::DeleteRequest>,
+    ) -> Result<Response<cache_server::DeleteResponse>, Status> {
+        if self.is_down() {
+            return Err(Status::unavailable("node is down"));
+        }
+        let existed = self
+            .values
+            .lock()
+            .unwrap()
+            .remove(&request.into_inner().key)
+            .is_some();
+        Ok(Response::new(cache_server::DeleteResponse {
+            successful: true,
+            existed,
+        }))
+    }
+
+    async fn exists(
+        &self,
+        _: Request<cache_server::ExistsRequest>,
+    ) -> Result<Response<cache_server::ExistsResponse>, Status> {
+        Err(Status::unimplemented("exists"))
+    }
+
+    async fn self_test(
+        &self,
+        _: Request<cache_server::SelfTestRequest>,
+    ) -> Result<Response<cache_server::SelfTestResponse>, Status> {
+        Err(Status::unimplemented("self_test"))
+    }
+
+    async fn key_shards(
+        &self,
+        _: Request<cache_server::KeyShardsRequest>,
+    ) -> Result<Response<cache_server::KeyShardsResponse>, Status> {
+        Err(Status::unimplemented("key_shards"))
+    }
+
+    async fn list_buckets(
+        &self,
+        _: Request<cache_server::ListBucketsRequest>,
+    ) -> Result<Response<cache_server::ListBucketsResponse>, Status> {
+        Err(Status::unimplemented("list_buckets"))
+    }
+
+    async fn pin(
+        &self,
+        _: Request<cache_server::PinRequest>,
+    ) -> Result<Response<cache_server::PinResponse>, Status> {
+        Err(Status::unimplemented("pin"))
+    }
+
+    async fn unpin(
+        &self,
+        _: Request<cache_server::UnpinRequest>,
+    ) -> Result<Response<cache_server::UnpinResponse>, Status> {
+        Err(Status::unimplemented("unpin"))
+    }
+
+    async fn watch(
+        &self,
+        _: Request<cache_server::WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        Err(Status::unimplemented("watch"))
+    }
+
+    async fn inspect(
+        &self,
+        _: Request<cache_server::InspectRequest>,
+    ) -> Result<Response<cache_server::InspectResponse>, Status> {
+        Err(Status::unimplemented("inspect"))
+    }
+
+    async fn health(
+        &self,
+        _: Request<cache_server::HealthRequest>,
+    ) -> Result<Response<cache_server::HealthResponse>, Status> {
+        if self.is_down() {
+            return Err(Status::unavailable("node is down"));
+        }
+        Ok(Response::new(cache_server::HealthResponse {
+            status: cache_server::ServingStatus::Serving as i32,
+        }))
+    }
+}
+
+#[tokio::test]
+async fn test_replicated_keys_survive_a_node_going_down() {
+    let service = RouterServiceImpl {
+        replication_factor: 2,
+        ..test_service()
+    };
+    let nodes = [
+        FakeNode::default(),
+        FakeNode::default(),
+        FakeNode::default(),
+    ];
+    for node in &nodes {
+        service
+            .join_node(node.serve().await, DEFAULT_GROUP.to_string())
+            .await
+            .unwrap();
+    }
+
+    service
+        .put(Request::new(PutRequest {
+            key: b"key".to_vec(),
+            bucket: "users".to_string(),
+            value: b"value".to_vec(),
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+    // The owner's write goes to S3, while the other copy is cache-only
+    let copies: Vec<_> = nodes
+        .iter()
+        .filter_map(|node| node.values.lock().unwrap().get(b"key".as_slice()).cloned())
+        .collect();
+    assert_eq!(copies.len(), 2);
+    assert!(copies.iter().all(|(value, _)| value == b"value"));
+    assert_eq!(copies.iter().filter(|(_, replica)| *replica).count(), 1);
+
+    // With the owner down, reads fall back to the replica
+    let owner = nodes
+        .iter()
+        .find(|node| {
+            matches!(
+                node.values.lock().unwrap().get(b"key".as_slice()),
+                Some((_, false))
+            )
+        })
+        .unwrap();
+    owner.down.store(true, std::sync::atomic::Ordering::SeqCst);
+    let get = GetRequest {
+        key: b"key".to_vec(),
+        bucket: "users".to_string(),
+        ..Default::default()
+    };
+    let response = service.get(Request::new(get.clone())).await.unwrap();
+    assert_eq!(response.into_inner().value, b"value");
+    let batch = BatchGetRequest {
+        requests: vec![get.clone(), get],
+    };
+    let responses = service.batch_get(Request::new(batch)).await.unwrap();
+    for response in responses.into_inner().responses {
+        assert_eq!(response.value, b"value");
     }
 }
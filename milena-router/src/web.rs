@@ -0,0 +1,176 @@
+use futures::future::BoxFuture;
+use http::header::{HeaderValue, CONTENT_TYPE};
+use http::{Request, Response};
+use std::task::{Context, Poll};
+use tonic::body::BoxBody;
+use tonic::Status;
+use tower::{Layer, Service};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Methods browsers may call over gRPC-Web; everything else is rejected so the
+/// dashboard cannot mutate data or cluster membership.
+const READ_ONLY_METHODS: &[&str] = &[
+    "/router_server.Router/Get",
+    "/router_server.Router/ListNodes",
+];
+
+/// CORS policy for gRPC-Web callers. An empty origin list allows any origin.
+pub fn cors_layer(allowed_origins: &str) -> CorsLayer {
+    let origins: Vec<HeaderValue> = allowed_origins
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .filter_map(|origin| origin.parse().ok())
+        .collect();
+
+    let allow_origin = if origins.is_empty() {
+        AllowOrigin::mirror_request()
+    } else {
+        AllowOrigin::list(origins)
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_headers([
+            CONTENT_TYPE,
+            "x-grpc-web".parse().unwrap(),
+            "x-user-agent".parse().unwrap(),
+            "grpc-timeout".parse().unwrap(),
+        ])
+        .expose_headers([
+            "grpc-status".parse().unwrap(),
+            "grpc-message".parse().unwrap(),
+        ])
+}
+
+#[derive(Clone, Default)]
+pub struct ReadOnlyWebLayer;
+
+impl<S> Layer<S> for ReadOnlyWebLayer {
+    type Service = ReadOnlyWeb<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ReadOnlyWeb { inner }
+    }
+}
+
+/// Answers gRPC-Web calls to methods outside `READ_ONLY_METHODS` with
+/// `PermissionDenied`; native gRPC calls pass through untouched.
+#[derive(Clone)]
+pub struct ReadOnlyWeb<S> {
+    inner: S,
+}
+
+impl<S, B> Service<Request<B>> for ReadOnlyWeb<S>
+where
+    S: Service<Request<B>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let content_type = request.headers().get(CONTENT_TYPE).cloned();
+        let is_grpc_web = content_type
+            .as_ref()
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/grpc-web"));
+
+        if is_grpc_web && !READ_ONLY_METHODS.contains(&request.uri().path()) {
+            let mut response = Status::permission_denied(format!(
+                "{} is not available over gRPC-Web",
+                request.uri().path()
+            ))
+            .to_http();
+            if let Some(content_type) = content_type {
+                response.headers_mut().insert(CONTENT_TYPE, content_type);
+            }
+            return Box::pin(async move { Ok(response) });
+        }
+
+        Box::pin(self.inner.call(request))
+    }
+}
+
+/// Answers every call with an empty response and no gRPC status.
+#[cfg(test)]
+#[derive(Clone)]
+struct Answer;
+
+#[cfg(test)]
+impl<B> Service<Request<B>> for Answer {
+    type Response = Response<BoxBody>;
+    type Error = std::convert::Infallible;
+    type Future = std::future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _request: Request<B>) -> Self::Future {
+        std::future::ready(Ok(Response::new(tonic::body::empty_body())))
+    }
+}
+
+#[cfg(test)]
+async fn call_read_only_web(path: &str, content_type: &str) -> Response<BoxBody> {
+    let request = Request::post(path)
+        .header(CONTENT_TYPE, content_type)
+        .body(())
+        .unwrap();
+    ReadOnlyWebLayer.layer(Answer).call(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_read_only_web_rejects_grpc_web_writes() {
+    let response =
+        call_read_only_web("/router_server.Router/Put", "application/grpc-web+proto").await;
+    assert_eq!(response.headers()["grpc-status"], "7");
+    assert_eq!(
+        response.headers()[CONTENT_TYPE],
+        "application/grpc-web+proto"
+    );
+}
+
+#[tokio::test]
+async fn test_read_only_web_passes_reads_and_native_grpc() {
+    let read = call_read_only_web("/router_server.Router/Get", "application/grpc-web").await;
+    assert!(!read.headers().contains_key("grpc-status"));
+    let native = call_read_only_web("/router_server.Router/Put", "application/grpc").await;
+    assert!(!native.headers().contains_key("grpc-status"));
+}
+
+#[tokio::test]
+async fn test_cors_layer_allows_only_listed_origins() {
+    let mut cors = cors_layer("https://dash.example, https://ops.example").layer(Answer);
+    for (origin, allowed) in [
+        ("https://ops.example", true),
+        ("https://evil.example", false),
+    ] {
+        let request = Request::post("/router_server.Router/Get")
+            .header(http::header::ORIGIN, origin)
+            .body(())
+            .unwrap();
+        let response = cors.call(request).await.unwrap();
+        let allow_origin = response
+            .headers()
+            .get(http::header::ACCESS_CONTROL_ALLOW_ORIGIN);
+        assert_eq!(allow_origin.is_some_and(|o| o == origin), allowed);
+    }
+
+    // Without a list the caller's origin is mirrored back
+    let request = Request::post("/router_server.Router/Get")
+        .header(http::header::ORIGIN, "https://any.example")
+        .body(())
+        .unwrap();
+    let response = cors_layer("").layer(Answer).call(request).await.unwrap();
+    assert_eq!(
+        response.headers()[http::header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "https://any.example"
+    );
+}